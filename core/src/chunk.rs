@@ -6,7 +6,12 @@
 //! multiple sessions to share a TCP connection, and for large messages
 //! to be sent efficiently while not blocking other messages that share
 //! the same connection, or even the same MSRP session.
+//!
+//! Chunks of a message are reassembled in memory, so its size is limited. Larger data should
+//! be transferred as a stream, whose chunks are read from a [ChunkSource] on demand, see
+//! [crate::swarm::stream].
 
+use async_trait::async_trait;
 use bytes::Bytes;
 use itertools::Itertools;
 use serde::Deserialize;
@@ -94,6 +99,17 @@ pub trait ChunkManager {
     fn handle(&mut self, chunk: Chunk) -> Option<Bytes>;
 }
 
+/// Source of a stream, which reads chunks by index on demand.
+/// A chunk may be read more than once, if it's lost and requested again by receiver.
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+pub trait ChunkSource {
+    /// Number of chunks.
+    fn total(&self) -> usize;
+    /// Read data of chunk at index.
+    async fn read(&self, index: usize) -> Result<Bytes>;
+}
+
 /// List of Chunk, simply wrapped `Vec<Chunk>`
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChunkList<const MTU: usize>(Vec<Chunk>);
@@ -209,6 +225,21 @@ impl<const MTU: usize> From<Vec<Chunk>> for ChunkList<MTU> {
     }
 }
 
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl<const MTU: usize> ChunkSource for ChunkList<MTU> {
+    fn total(&self) -> usize {
+        self.0.len()
+    }
+
+    async fn read(&self, index: usize) -> Result<Bytes> {
+        self.0
+            .get(index)
+            .map(|c| c.data.clone())
+            .ok_or(Error::InvalidChunkIndex(index))
+    }
+}
+
 impl<const MTU: usize> ChunkManager for ChunkList<MTU> {
     fn list_completed(&self) -> Vec<Uuid> {
         // group by msg uuid and chunk size
@@ -5,8 +5,80 @@ pub const DEFAULT_TTL_MS: u64 = 300 * 1000;
 pub const MAX_TTL_MS: u64 = DEFAULT_TTL_MS * 10;
 pub const TS_OFFSET_TOLERANCE_MS: u128 = 3000;
 pub const DEFAULT_SESSION_TTL_MS: u64 = 30 * 24 * 3600 * 1000;
+/// default capacity of replay window, see [crate::swarm::replay::ReplayWindow]
+pub const DEFAULT_REPLAY_WINDOW_SIZE: usize = 100_000;
 /// 60k
 pub const TRANSPORT_MTU: usize = 60000;
 /// 60M
 pub const TRANSPORT_MAX_SIZE: usize = TRANSPORT_MTU * 1000;
 pub const VNODE_DATA_MAX_LEN: usize = 1024;
+/// max ttl of virtual node, expired vnodes are kept as tombstones for this long before purged,
+/// so that no stale copy with ttl can outlive them
+pub const MAX_VNODE_TTL_MS: u64 = 24 * 3600 * 1000;
+/// max time a payload is parked for its offline destination in ms, older ones are dropped
+pub const MAX_RELAY_MESSAGE_PARKED_MS: u64 = MAX_VNODE_TTL_MS;
+/// lease of topic subscription, renewed on every stabilization
+pub const TOPIC_SUBSCRIPTION_TTL_MS: u64 = 60 * 1000;
+/// capacity of received messages of a subscribed topic, newer messages are dropped when full
+pub const TOPIC_INBOX_CAPACITY: usize = 1024;
+/// max hops of a ring-wide broadcast, it takes `O(log N)` hops to reach all nodes
+pub const MAX_BROADCAST_HOPS: u8 = 32;
+/// max subring broadcasts waiting for the members of their subrings to be fetched
+pub const MAX_PARKED_SUBRING_BROADCASTS: usize = 256;
+/// how many nodes are queried in parallel on each hop of iterative lookup
+pub const DEFAULT_LOOKUP_ALPHA: usize = 3;
+/// timeout of each hop of iterative lookup in ms
+pub const DEFAULT_LOOKUP_HOP_TIMEOUT_MS: u64 = 3000;
+/// how many disjoint paths are looked up iteratively, their results must agree
+pub const DEFAULT_LOOKUP_DISJOINT_PATHS: usize = 2;
+/// max hops of iterative lookup, it takes `O(log N)` hops to find the successor
+pub const MAX_LOOKUP_HOPS: usize = 32;
+/// timeout of measuring round-trip time of a connected peer in ms
+pub const DEFAULT_PING_TIMEOUT_MS: u64 = 3000;
+/// timeout of querying topological info of each node when inspecting the ring in ms
+pub const RING_INSPECT_QUERY_TIMEOUT_MS: u64 = 3000;
+/// max nodes visited when inspecting the ring
+pub const MAX_RING_INSPECT_NODES: usize = 1024;
+/// max positions operated by a node on the ring, including its own did
+pub const MAX_VIRTUAL_POSITIONS: u16 = 16;
+/// max bytes of vnodes handed off in a message when leaving, each batch is acknowledged
+pub const VNODE_HANDOFF_BATCH_SIZE: usize = TRANSPORT_MTU;
+/// timeout of waiting for the ack of a batch of vnodes handed off when leaving in ms
+pub const DEFAULT_HANDOFF_ACK_TIMEOUT_MS: u64 = 3000;
+/// lifetime of the session public key published to DHT, it's republished when half expired
+pub const SESSION_PUBKEY_TTL_MS: u64 = 3600 * 1000;
+/// timeout of fetching the published session public key of a peer in ms
+pub const DEFAULT_SESSION_PUBKEY_FETCH_TIMEOUT_MS: u64 = 3000;
+/// timeout of waiting for the delivery ack of each attempt in ms
+pub const DEFAULT_DELIVERY_ACK_TIMEOUT_MS: u64 = 3000;
+/// max attempts of sending a message that should be acknowledged, including the first one
+pub const MAX_DELIVERY_ATTEMPTS: usize = 3;
+/// max deliveries whose status are remembered, the oldest ones are forgotten when full
+pub const MAX_TRACKED_DELIVERIES: usize = 10_000;
+/// max bytes of data in a chunk of stream, small enough to be sent without further chunking
+pub const STREAM_CHUNK_SIZE: usize = TRANSPORT_MTU / 2;
+/// max bytes of data in a chunk relayed to destination, small enough to be relayed without further chunking
+pub const RELAY_CHUNK_SIZE: usize = TRANSPORT_MTU / 2;
+/// how many chunks of stream can be received but not read, granted to sender as credits
+pub const DEFAULT_STREAM_WINDOW: usize = 64;
+/// timeout of waiting for credits or chunks of stream in ms
+pub const DEFAULT_STREAM_TIMEOUT_MS: u64 = 3000;
+/// max consecutive timeouts of waiting for credits or chunks, before a stream is given up
+pub const MAX_STREAM_ATTEMPTS: usize = 5;
+/// max finished streams remembered by receiver, the oldest ones are forgotten when full
+pub const MAX_TRACKED_STREAMS: usize = 1024;
+/// max streams being received at the same time, new offers are refused when full
+pub const MAX_INCOMING_STREAMS: usize = 256;
+/// max streams being received from the same peer at the same time
+pub const MAX_INCOMING_STREAMS_PER_PEER: usize = 16;
+/// incoming streams without any offer, chunk or read longer than this are dropped, in ms
+pub const INCOMING_STREAM_IDLE_TIMEOUT_MS: u64 =
+    DEFAULT_STREAM_TIMEOUT_MS * MAX_STREAM_ATTEMPTS as u64 * 2;
+/// version of protocol, increased when messages are changed incompatibly
+pub const PROTOCOL_VERSION: u32 = 1;
+/// the lowest version of protocol that current node can talk with
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// optional features of protocol supported by current node
+pub const PROTOCOL_FEATURES: &[&str] = &["compression", "encryption", "chunking", "chunk-relaying"];
+/// id of the network that nodes belong to by default
+pub const DEFAULT_NETWORK_ID: &str = "rings";
@@ -5,6 +5,7 @@ use std::sync::Mutex;
 use std::sync::MutexGuard;
 
 use async_trait::async_trait;
+use dashmap::DashMap;
 use num_bigint::BigUint;
 use serde::Deserialize;
 use serde::Serialize;
@@ -14,11 +15,18 @@ use super::successor::SuccessorSeq;
 use super::types::Chord;
 use super::types::ChordStorage;
 use super::types::ChordStorageCache;
+use super::types::ChordStorageReplica;
 use super::types::ChordStorageSync;
 use super::types::CorrectChord;
+use super::vnode::VNodeDigest;
 use super::vnode::VNodeOperation;
+use super::vnode::VNodeType;
 use super::vnode::VirtualNode;
 use super::FingerTable;
+use super::HybridClock;
+use super::TopicSubscriptions;
+use crate::consts::MAX_VNODE_TTL_MS;
+use crate::dht::validation::SuccessorQueries;
 use crate::dht::Did;
 use crate::dht::LiveDid;
 use crate::dht::SuccessorReader;
@@ -29,6 +37,7 @@ use crate::storage::MemStorage;
 use crate::storage::PersistenceStorage;
 use crate::storage::PersistenceStorageReadAndWrite;
 use crate::storage::PersistenceStorageRemove;
+use crate::utils::get_epoch_ms;
 
 /// PeerRing is used to help a node interact with other nodes.
 /// All nodes in rings network form a clockwise ring in the order of Did.
@@ -51,6 +60,25 @@ pub struct PeerRing {
     pub storage: Arc<PersistenceStorage>,
     /// Local cache for [ChordStorage].
     pub cache: Arc<MemStorage<Did, VirtualNode>>,
+    /// Replicas of vnodes stored on predecessors, see [ChordStorageReplica].
+    /// They are kept in memory and lost on restart, then repaired by anti-entropy of
+    /// the predecessors, so a vnode survives as long as its holders are not all restarted.
+    pub replicas: Arc<MemStorage<Did, VirtualNode>>,
+    /// How many successors should keep replicas of vnodes stored on current node.
+    /// Zero means replication is disabled.
+    pub replication_factor: u8,
+    /// Clock to generate versions of vnodes written by current node.
+    pub clock: Arc<HybridClock>,
+    /// Subscriptions of topics that current node is responsible for.
+    pub subscriptions: Arc<TopicSubscriptions>,
+    /// Round-trip time in milliseconds of connected nodes, used for proximity neighbor selection.
+    pub rtts: Arc<DashMap<Did, u64>>,
+    /// Endpoints to reach nodes out of band, used to reconnect them after restart.
+    pub endpoints: Arc<DashMap<Did, String>>,
+    /// Estimations of network size gossiped by neighbours, see [crate::dht::estimate].
+    pub size_estimates: Arc<DashMap<Did, u64>>,
+    /// Successor lookups sent by current node and waiting for reports.
+    pub successor_queries: Arc<SuccessorQueries>,
 }
 
 /// Type alias is just for making the code easy to read.
@@ -90,6 +118,10 @@ pub enum RemoteAction {
     Notify(Did),
     /// Let `did_a` sync data with it's successor.
     SyncVNodeWithSuccessor(Vec<VirtualNode>),
+    /// Let `did_a` keep replicas of virtual nodes, which are paired with the dids they stored at.
+    ReplicateVNode(Vec<(Did, VirtualNode)>),
+    /// Let `did_a` compare digests of virtual nodes in range (current node, `did_b`] with its replicas.
+    SyncVNodeDigest(Did, Vec<(Did, VNodeDigest)>),
 
     /// Need `did_a` to find `did_b` then send back with `for connect` flag.
     FindSuccessorForConnect(Did),
@@ -192,10 +224,57 @@ impl PeerRing {
             finger: Arc::new(Mutex::new(FingerTable::new(did, 160))),
             storage: Arc::new(storage),
             cache: Arc::new(MemStorage::<Did, VirtualNode>::new()),
+            replicas: Arc::new(MemStorage::<Did, VirtualNode>::new()),
+            replication_factor: 0,
+            clock: Arc::new(HybridClock::default()),
+            subscriptions: Arc::new(TopicSubscriptions::default()),
+            rtts: Arc::new(DashMap::new()),
+            endpoints: Arc::new(DashMap::new()),
+            size_estimates: Arc::new(DashMap::new()),
+            successor_queries: Arc::new(SuccessorQueries::default()),
             did,
         }
     }
 
+    /// Set how many successors should keep replicas of vnodes stored on current node.
+    pub fn with_replication_factor(mut self, replication_factor: u8) -> Self {
+        self.replication_factor = replication_factor;
+        self
+    }
+
+    /// Get a vnode from local storage, expired vnode is treated as not existed.
+    pub async fn storage_get(&self, vid: Did) -> Result<Option<VirtualNode>> {
+        let vnode: Option<VirtualNode> = self.storage.get(&vid).await?;
+        Ok(vnode.filter(|v| !v.is_expired(get_epoch_ms() as u64)))
+    }
+
+    /// Purge vnodes expired at `now` from cache, and vnodes expired for [MAX_VNODE_TTL_MS]
+    /// from storage and replicas. Expired vnodes are kept as tombstones until then, so that
+    /// their versions keep stale copies from reviving them.
+    /// Return the number of purged vnodes in storage.
+    pub async fn purge_expired_vnodes(&self, now: u64) -> Result<usize> {
+        let mut purged = 0;
+        let tombstone_now = now.saturating_sub(MAX_VNODE_TTL_MS);
+        let items: Vec<(Did, VirtualNode)> = self.storage.get_all().await?;
+        for (vid, vnode) in items {
+            if vnode.is_expired(tombstone_now) {
+                self.storage.remove(&vid).await?;
+                purged += 1;
+            }
+        }
+        for (vid, vnode) in self.cache.items() {
+            if vnode.is_expired(now) {
+                self.cache.remove(&vid);
+            }
+        }
+        for (vid, vnode) in self.replicas.items() {
+            if vnode.is_expired(tombstone_now) {
+                self.replicas.remove(&vid);
+            }
+        }
+        Ok(purged)
+    }
+
     /// Return successor sequence. This function is deprecated, please use [chord.successors] instead.
     #[deprecated]
     pub fn lock_successor(&self) -> Result<SuccessorSeq> {
@@ -231,6 +310,8 @@ impl PeerRing {
         }
         finger.remove(did);
         successor.remove(did)?;
+        self.rtts.remove(&did);
+        self.size_estimates.remove(&did);
         if successor.is_empty()? {
             if let Some(x) = finger.first() {
                 successor.update(x)?;
@@ -239,10 +320,169 @@ impl PeerRing {
         Ok(())
     }
 
+    /// Record the round-trip time in milliseconds of a connected node.
+    pub fn set_rtt(&self, did: Did, rtt: u64) {
+        self.rtts.insert(did, rtt);
+    }
+
+    /// Get the round-trip time in milliseconds of a connected node, if measured.
+    pub fn rtt(&self, did: Did) -> Option<u64> {
+        self.rtts.get(&did).map(|v| *v)
+    }
+
+    /// The measured node with lowest rtt in the interval of finger `index`, if any.
+    fn closest_by_rtt_in_interval(&self, finger: &FingerTable, index: usize) -> Option<Did> {
+        self.rtts
+            .iter()
+            .filter(|e| finger.in_interval(index, *e.key()))
+            .min_by_key(|e| (*e.value(), *e.key() - self.did))
+            .map(|e| *e.key())
+    }
+
     /// Calculate bias of the Did on the ring.
     pub fn bias(&self, did: Did) -> BiasId {
         BiasId::new(self.did, did)
     }
+
+    /// Next hops of a ring-wide broadcast which current node is responsible for the range
+    /// (current node, `limit`). If `limit` is current node, the range is the whole ring.
+    ///
+    /// Each known node in the range, from finger table and successor sequence, is paired with
+    /// the next one as its limit, and the last one takes `limit`. So that the ranges are not
+    /// overlapped and every node on the ring receives the broadcast once, in `O(log N)` hops.
+    pub fn broadcast_routes(&self, limit: Did) -> Result<Vec<(Did, Did)>> {
+        let bound = limit - self.did;
+        let whole_ring = bound == Did::from(0u32);
+
+        let mut hops = self.known_nodes()?;
+        hops.retain(|did| *did != self.did && (whole_ring || *did - self.did < bound));
+        hops.sort_by_key(|did| *did - self.did);
+        hops.dedup();
+
+        let limits = hops.iter().skip(1).copied().chain(std::iter::once(limit));
+        Ok(hops.iter().copied().zip(limits).collect())
+    }
+
+    /// Known nodes preceding `did`, from finger table and successor sequence, sorted by
+    /// the distance to `did`, closest first. At most `count` nodes are returned.
+    /// It's used to answer next hops of iterative lookup, see [crate::swarm::lookup].
+    pub fn closest_preceding_nodes(&self, did: Did, count: usize) -> Result<Vec<Did>> {
+        let bound = did - self.did;
+
+        let mut nodes = self.known_nodes()?;
+        nodes.retain(|n| *n != self.did && *n - self.did < bound);
+        nodes.sort_by_key(|n| did - *n);
+        nodes.dedup();
+        nodes.truncate(count);
+        Ok(nodes)
+    }
+
+    fn known_nodes(&self) -> Result<Vec<Did>> {
+        let mut nodes: Vec<Did> = self
+            .lock_finger()?
+            .list()
+            .iter()
+            .flatten()
+            .copied()
+            .collect();
+        nodes.extend(self.successors().list()?);
+        Ok(nodes)
+    }
+
+    /// Check if `origin` may send replicas of vnode `vid` to current node.
+    /// The replica holders of current node, which are the successors, restore vnodes to it.
+    /// The node responsible for `vid` replicates it to its successors, so it should precede
+    /// both `vid` and current node, and no known node is closer to `vid` than it.
+    pub fn is_replica_source(&self, origin: Did, vid: Did) -> Result<bool> {
+        if origin == self.did || self.successors().list()?.contains(&origin) {
+            return Ok(true);
+        }
+        let reach = BiasId::new(origin, vid);
+        if reach >= BiasId::new(origin, self.did) {
+            return Ok(false);
+        }
+        let mut known = self.known_nodes()?;
+        known.extend(*self.lock_predecessor()?);
+        Ok(!known
+            .into_iter()
+            .any(|did| did != origin && BiasId::new(origin, did) < reach))
+    }
+
+    /// Check if `origin` may hand off vnode `vid` to current node, see [ChordStorageSync].
+    /// It should be the predecessor, or closer to current node than the predecessor, such as
+    /// a node that just joined. Otherwise it should be the previous holder of `vid`, which
+    /// precedes `vid` and no known node other than current node is closer to `vid` than it.
+    pub fn is_handoff_source(&self, origin: Did, vid: Did) -> Result<bool> {
+        if origin == self.did {
+            return Ok(false);
+        }
+        let predecessor = *self.lock_predecessor()?;
+        if let Some(pred) = predecessor {
+            if origin == pred || self.bias(origin) > self.bias(pred) {
+                return Ok(true);
+            }
+        }
+        let reach = BiasId::new(origin, vid);
+        let mut known = self.known_nodes()?;
+        known.extend(predecessor);
+        Ok(!known
+            .into_iter()
+            .any(|did| did != origin && did != self.did && BiasId::new(origin, did) < reach))
+    }
+
+    /// Apply [VNodeOperation] to the local copy of vnode `vid`, and return actions to
+    /// replicate the result to successors.
+    async fn operate_local(
+        &self,
+        vid: Did,
+        op: VNodeOperation,
+        writer: Did,
+    ) -> Result<PeerRingAction> {
+        let stored: Option<VirtualNode> = self.storage.get(&vid).await.ok().flatten();
+        let now = get_epoch_ms() as u64;
+        if matches!(op, VNodeOperation::LeaveSubring(..))
+            && !matches!(&stored, Some(this) if !this.is_expired(now))
+        {
+            // Nothing to leave.
+            return Ok(PeerRingAction::None);
+        }
+        let this = match stored {
+            Some(this) if this.is_expired(now) => {
+                // Keep the version of expired vnode, so that stale writes are ignored.
+                let mut vnode = op.clone().gen_default_vnode()?;
+                vnode.version = this.version;
+                vnode
+            }
+            Some(this) => this,
+            None => op.clone().gen_default_vnode()?,
+        };
+        let vnode = this.operate(op, writer)?;
+        if vnode == this {
+            // Nothing changed, such as a stale overwrite.
+            return Ok(PeerRingAction::None);
+        }
+        self.storage.put(&vid, &vnode).await?;
+        self.replicate_action(vid, vnode)
+    }
+
+    /// Return actions to replicate vnode `vid` stored on current node to successors.
+    fn replicate_action(&self, vid: Did, vnode: VirtualNode) -> Result<PeerRingAction> {
+        if vnode.kind == VNodeType::RelayMessage {
+            // Relay messages are drained once delivered, never replicate them.
+            return Ok(PeerRingAction::None);
+        }
+        Ok(self
+            .replica_targets()?
+            .into_iter()
+            .map(|target| {
+                PeerRingAction::RemoteAction(
+                    target,
+                    RemoteAction::ReplicateVNode(vec![(vid, vnode.clone())]),
+                )
+            })
+            .collect::<Vec<_>>()
+            .into())
+    }
 }
 
 impl Chord<PeerRingAction> for PeerRing {
@@ -282,7 +522,7 @@ impl Chord<PeerRingAction> for PeerRing {
                 Ok(PeerRingAction::Some(successor.min()?))
             } else {
                 // Otherwise, find the closest preceding node and ask it to find the successor.
-                let closest_predecessor = finger.closest_predecessor(did);
+                let closest_predecessor = finger.closest_predecessor_by_rtt(did, |v| self.rtt(v));
                 Ok(PeerRingAction::RemoteAction(
                     closest_predecessor,
                     RemoteAction::FindSuccessor(did),
@@ -338,6 +578,17 @@ impl Chord<PeerRingAction> for PeerRing {
         // Get finger did.
         let finger_did = Did::from(BigUint::from(2u16).pow(fix_finger_index as u32));
 
+        // Proximity neighbor selection: any node in the interval of finger works,
+        // prefer the one with lowest rtt among measured nodes.
+        {
+            let mut finger = self.lock_finger()?;
+            if let Some(did) = self.closest_by_rtt_in_interval(&finger, fix_finger_index as usize) {
+                finger.fix_finger_index = fix_finger_index;
+                finger.set_fix(did);
+                return Ok(PeerRingAction::None);
+            }
+        }
+
         // Caution here that there are also locks in find_successor.
         // You cannot lock finger table before calling find_successor.
         // Have to lock_finger in each branch of the match.
@@ -386,7 +637,7 @@ impl<const REDUNDANT: u16> ChordStorage<PeerRingAction, REDUNDANT> for PeerRing
         for vid in vid.rotate_affine(REDUNDANT) {
             let maybe_act = match self.find_successor(vid) {
                 // Resource should be stored in current node.
-                Ok(PeerRingAction::Some(succ)) => match self.storage.get(&vid).await {
+                Ok(PeerRingAction::Some(succ)) => match self.storage_get(vid).await {
                     Ok(Some(v)) => Ok(PeerRingAction::SomeVNode(v)),
                     Ok(None) => {
                         tracing::debug!(
@@ -430,21 +681,18 @@ impl<const REDUNDANT: u16> ChordStorage<PeerRingAction, REDUNDANT> for PeerRing
     /// Handle [VNodeOperation] if the target vnode between current node and the
     /// successor of current node, otherwise find the responsible node and return
     /// as Action.
-    async fn vnode_operate(&self, op: VNodeOperation) -> Result<PeerRingAction> {
+    async fn vnode_operate(&self, op: VNodeOperation, writer: Did) -> Result<PeerRingAction> {
+        let op = op.with_bounded_expiry(get_epoch_ms() as u64);
         let vid = op.did()?;
+        if let Some(version) = op.version() {
+            self.clock.observe(&version)?;
+        }
         let mut ret = vec![];
         for vid in vid.rotate_affine(REDUNDANT) {
             let maybe_act = match self.find_successor(vid) {
                 // `vnode` should be on current node.
                 Ok(PeerRingAction::Some(_)) => {
-                    let this = if let Ok(Some(this)) = self.storage.get(&vid).await {
-                        Ok(this)
-                    } else {
-                        op.clone().gen_default_vnode()
-                    }?;
-                    let vnode = this.operate(op.clone())?;
-                    self.storage.put(&vid, &vnode).await?;
-                    Ok(PeerRingAction::None)
+                    Ok(self.operate_local(vid, op.clone(), writer).await?)
                 }
                 // `vnode` should be on other nodes.
                 // Return an action to describe how to store it.
@@ -455,7 +703,7 @@ impl<const REDUNDANT: u16> ChordStorage<PeerRingAction, REDUNDANT> for PeerRing
                 Err(e) => Err(e),
             };
             if let Ok(act) = maybe_act {
-                if act.is_remote() {
+                if act.is_remote() || act.is_multi() {
                     ret.push(act);
                 }
             }
@@ -491,19 +739,167 @@ impl ChordStorageSync<PeerRingAction> for PeerRing {
             Ok(PeerRingAction::None)
         }
     }
+
+    /// Store the vnode on current node directly, since the range of a leaving predecessor
+    /// is taken over by current node before the predecessor is removed.
+    /// The owner of vnode should be proved by [VirtualNode::verify_owner]. It's merged with
+    /// the local copy or replica by [VirtualNode::merge_replica], so the ownership is kept.
+    async fn vnode_take_over(&self, vnode: VirtualNode) -> Result<PeerRingAction> {
+        vnode.verify_owner()?;
+        self.clock.observe(&vnode.version)?;
+        let vid = vnode.did;
+        let stored: Option<VirtualNode> = self.storage.get(&vid).await?;
+        let latest = match stored.clone().or_else(|| self.replicas.get(&vid)) {
+            Some(this) => this.merge_replica(vnode)?,
+            None => vnode,
+        };
+        self.replicas.remove(&vid);
+        if stored.as_ref() == Some(&latest) {
+            return Ok(PeerRingAction::None);
+        }
+        self.storage.put(&vid, &latest).await?;
+        self.replicate_action(vid, latest)
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl ChordStorageReplica<PeerRingAction> for PeerRing {
+    /// The first `replication_factor` nodes of successor list.
+    fn replica_targets(&self) -> Result<Vec<Did>> {
+        let mut targets = self.successors().list()?;
+        targets.retain(|did| *did != self.did);
+        targets.truncate(self.replication_factor.into());
+        Ok(targets)
+    }
+
+    /// Restore vnodes that current node is responsible for, and keep others as replicas.
+    /// Vnodes from nodes that cannot hold them, see [PeerRing::is_replica_source], or with
+    /// unproved owner, see [VirtualNode::verify_owner], are dropped.
+    /// A vnode is only restored if it's missing in local storage, since the local copy is
+    /// written by operations checked by [VirtualNode::operate]. Replicas are merged by
+    /// [VirtualNode::merge_replica], so ownership is kept.
+    async fn vnode_replicate(&self, origin: Did, data: Vec<(Did, VirtualNode)>) -> Result<()> {
+        for (vid, vnode) in data {
+            if vnode.kind == VNodeType::RelayMessage {
+                continue;
+            }
+            if !self.is_replica_source(origin, vid)? {
+                tracing::warn!(
+                    "Drop replica of vnode {} from unrelated node {}",
+                    vid,
+                    origin
+                );
+                continue;
+            }
+            if let Err(e) = self
+                .clock
+                .observe(&vnode.version)
+                .and_then(|_| vnode.verify_owner())
+            {
+                tracing::warn!("Drop replica of vnode {}: {:?}", vid, e);
+                continue;
+            }
+            if let PeerRingAction::Some(_) = self.find_successor(vid)? {
+                let this: Option<VirtualNode> = self.storage.get(&vid).await?;
+                if this.is_none() {
+                    tracing::info!("Restore vnode {} from replica", vid);
+                    self.storage.put(&vid, &vnode).await?;
+                }
+                self.replicas.remove(&vid);
+            } else {
+                let latest = match self.replicas.get(&vid) {
+                    Some(replica) => match replica.merge_replica(vnode) {
+                        Ok(latest) => latest,
+                        Err(e) => {
+                            tracing::warn!("Drop replica of vnode {}: {:?}", vid, e);
+                            continue;
+                        }
+                    },
+                    None => vnode,
+                };
+                self.replicas.set(&vid, latest);
+            }
+        }
+        Ok(())
+    }
+
+    /// Send digests of all stored vnodes, except relay messages, to replica targets.
+    /// The range end is the successor of current node, since the replica targets
+    /// may hold replicas of the range that current node just took over.
+    async fn vnode_anti_entropy(&self) -> Result<PeerRingAction> {
+        let targets = self.replica_targets()?;
+        if targets.is_empty() {
+            return Ok(PeerRingAction::None);
+        }
+
+        let mut digests = vec![];
+        let items: Vec<(Did, VirtualNode)> = self.storage.get_all().await?;
+        for (vid, vnode) in items {
+            if vnode.kind != VNodeType::RelayMessage {
+                digests.push((vid, vnode.digest()?));
+            }
+        }
+
+        let range_end = self.successors().min()?;
+        Ok(targets
+            .into_iter()
+            .map(|target| {
+                PeerRingAction::RemoteAction(
+                    target,
+                    RemoteAction::SyncVNodeDigest(range_end, digests.clone()),
+                )
+            })
+            .collect::<Vec<_>>()
+            .into())
+    }
+
+    /// Compare digests with local replicas.
+    async fn vnode_diff_digests(
+        &self,
+        origin: Did,
+        range_end: Did,
+        digests: &[(Did, VNodeDigest)],
+    ) -> Result<(Vec<Did>, Vec<(Did, VirtualNode)>)> {
+        let mut missing = vec![];
+        for (vid, digest) in digests {
+            match self.replicas.get(vid) {
+                Some(replica) if replica.digest()? == *digest => {}
+                _ => missing.push(*vid),
+            }
+        }
+
+        let mut restore = vec![];
+        for (vid, replica) in self.replicas.items() {
+            let in_range =
+                vid != origin && BiasId::new(origin, vid) <= BiasId::new(origin, range_end);
+            if in_range && !digests.iter().any(|(d, _)| *d == vid) {
+                restore.push((vid, replica));
+            }
+        }
+
+        Ok((missing, restore))
+    }
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl ChordStorageCache<PeerRingAction> for PeerRing {
     /// Cache fetched `vnode` locally.
+    /// If the vnode is already cached, keep the latest one, see [VirtualNode::latest].
     fn local_cache_set(&self, vnode: VirtualNode) {
+        let vnode = match self.cache.get(&vnode.did) {
+            Some(cached) => cached.latest(vnode.clone()).unwrap_or(vnode),
+            None => vnode,
+        };
         self.cache.set(&vnode.did.clone(), vnode);
     }
 
-    /// Get vnode from local cache.
+    /// Get vnode from local cache, expired vnode is ignored.
     fn local_cache_get(&self, vid: Did) -> Option<VirtualNode> {
-        self.cache.get(&vid)
+        self.cache
+            .get(&vid)
+            .filter(|v| !v.is_expired(get_epoch_ms() as u64))
     }
 }
 
@@ -587,8 +983,8 @@ impl CorrectChord<PeerRingAction> for PeerRing {
     fn stabilize(&self, info: TopoInfo) -> Result<PeerRingAction> {
         let mut ret = vec![];
         let successors = self.successors();
-        let succ_len = info.successors.len();
-        let but_last = &info.successors[..succ_len - 1].to_vec();
+        let succ_len = info.successors.len().saturating_sub(1);
+        let but_last = &info.successors[..succ_len].to_vec();
         if let Some(new_succ) = info.predecessor {
             successors.update(new_succ)?;
         }
@@ -626,6 +1022,8 @@ mod tests {
 
     use super::*;
     use crate::ecc::SecretKey;
+    use crate::session::SessionSk;
+    use crate::storage::PersistenceStorageOperation;
     use crate::tests::default::gen_sorted_dht;
 
     #[tokio::test]
@@ -814,6 +1212,265 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_remove_and_purge_vnodes() -> Result<()> {
+        let session_sk = SessionSk::new_with_seckey(&SecretKey::random())?;
+        let did = session_sk.account_did();
+        let stranger: Did = SecretKey::random().address().into();
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node = PeerRing::new_with_storage(did, 3, db);
+
+        let vnode: VirtualNode = "test_remove".to_string().try_into()?;
+        let vid = vnode.did;
+        let overwrite = VNodeOperation::Overwrite(vnode.owned_by(&session_sk, vec![])?);
+        <PeerRing as ChordStorage<_, 1>>::vnode_operate(&node, overwrite.clone(), did).await?;
+        assert!(node.storage_get(vid).await?.is_some());
+
+        // Only owner can remove.
+        assert!(matches!(
+            <PeerRing as ChordStorage<_, 1>>::vnode_operate(&node, VNodeOperation::Remove(vid), stranger).await,
+            Err(Error::VNodeNotWritable(d)) if d == stranger
+        ));
+        <PeerRing as ChordStorage<_, 1>>::vnode_operate(&node, VNodeOperation::Remove(vid), did)
+            .await?;
+        assert_eq!(node.storage_get(vid).await?, None);
+        assert_eq!(
+            <PeerRing as ChordStorage<_, 1>>::vnode_lookup(&node, vid).await?,
+            PeerRingAction::None
+        );
+
+        // A stale overwrite cannot revive the removed vnode.
+        assert!(matches!(
+            <PeerRing as ChordStorage<_, 1>>::vnode_operate(&node, overwrite, did).await,
+            Err(Error::VNodeVersionStale)
+        ));
+        assert_eq!(node.storage_get(vid).await?, None);
+
+        // Vnodes are purged after expired.
+        let expiring: VirtualNode = "test_expiry".to_string().try_into()?;
+        let expiring = expiring.with_ttl(std::time::Duration::from_secs(60));
+        node.local_cache_set(expiring.clone());
+        <PeerRing as ChordStorage<_, 1>>::vnode_operate(
+            &node,
+            VNodeOperation::Overwrite(expiring.clone()),
+            did,
+        )
+        .await?;
+        // The tombstone is kept until max ttl passed, so it still blocks stale copies.
+        let now = get_epoch_ms() as u64;
+        assert_eq!(node.purge_expired_vnodes(now).await?, 0);
+        assert!(node.storage_get(expiring.did).await?.is_some());
+        assert!(node.local_cache_get(expiring.did).is_some());
+        assert_eq!(node.purge_expired_vnodes(now + 60_000).await?, 0);
+        assert!(node.cache.is_empty());
+        assert_eq!(node.storage.count().await?, 2);
+        assert_eq!(node.purge_expired_vnodes(now + MAX_VNODE_TTL_MS).await?, 1);
+        assert_eq!(
+            node.purge_expired_vnodes(now + 60_000 + MAX_VNODE_TTL_MS)
+                .await?,
+            1
+        );
+        assert_eq!(node.storage.count().await?, 0);
+        assert!(node.cache.is_empty());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replicate_only_from_related_nodes() -> Result<()> {
+        let topic = "test_replicate".to_string();
+        let vid = VirtualNode::try_from(topic.clone())?.did;
+        let at = |offset: u32, forward: bool| {
+            let offset = Did::from(BigUint::from(offset));
+            if forward {
+                vid + offset
+            } else {
+                vid - offset
+            }
+        };
+        // Clockwise: stranger -> pred -> vid -> did -> succ.
+        let (stranger, pred, did, succ) = (at(2, false), at(1, false), at(10, true), at(20, true));
+        let owner_sk = SessionSk::new_with_seckey(&SecretKey::random())?;
+        let (owner, thief): (Did, Did) = (
+            owner_sk.account_did(),
+            SecretKey::random().address().into(),
+        );
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node = PeerRing::new_with_storage(did, 3, db);
+        node.join(succ)?;
+        node.notify(pred)?;
+
+        let clock = HybridClock::default();
+        let vnode: VirtualNode = (topic.clone(), "owned".to_string()).try_into()?;
+        let mut vnode = vnode.owned_by(&owner_sk, vec![])?;
+        vnode.version = clock.tick(owner)?;
+
+        // The predecessor is closer to the vnode than the stranger.
+        assert!(node.is_replica_source(pred, vid)?);
+        assert!(!node.is_replica_source(stranger, vid)?);
+        node.vnode_replicate(stranger, vec![(vid, vnode.clone())])
+            .await?;
+        assert_eq!(node.storage_get(vid).await?, None);
+        assert_eq!(node.replicas.get(&vid), None);
+
+        // A copy with unproved owner is dropped.
+        let mut hijacked: VirtualNode = (topic.clone(), "hijacked".to_string()).try_into()?;
+        hijacked.owner = Some(thief);
+        hijacked.version = clock.tick(thief)?;
+        node.vnode_replicate(succ, vec![(vid, hijacked.clone())])
+            .await?;
+        assert_eq!(node.replicas.get(&vid), None);
+
+        node.vnode_replicate(succ, vec![(vid, vnode.clone())])
+            .await?;
+        assert_eq!(node.replicas.get(&vid), Some(vnode.clone()));
+
+        // A newer copy of the owner is merged.
+        let mut updated: VirtualNode = (topic, "updated".to_string()).try_into()?;
+        updated.owner = Some(owner);
+        updated.owner_proof = vnode.owner_proof.clone();
+        updated.version = clock.tick(owner)?;
+        node.vnode_replicate(succ, vec![(vid, updated.clone())])
+            .await?;
+        assert_eq!(node.replicas.get(&vid), Some(updated.clone()));
+
+        // The node responsible for vnode only restores it when it's missing, a restored
+        // vnode is never changed by replicas, even a newer copy of the owner.
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let holder = PeerRing::new_with_storage(at(5, false), 3, db);
+        holder.join(succ)?;
+        holder
+            .vnode_replicate(succ, vec![(vid, hijacked)])
+            .await?;
+        assert_eq!(holder.storage_get(vid).await?, None);
+        holder
+            .vnode_replicate(succ, vec![(vid, vnode.clone())])
+            .await?;
+        assert_eq!(holder.storage_get(vid).await?, Some(vnode.clone()));
+        holder.vnode_replicate(succ, vec![(vid, updated)]).await?;
+        assert_eq!(holder.storage_get(vid).await?, Some(vnode));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handoff_only_from_predecessor_or_previous_holder() -> Result<()> {
+        let vid = VirtualNode::try_from("test_handoff".to_string())?.did;
+        let at = |offset: u32| vid - Did::from(BigUint::from(offset));
+        // Clockwise: stranger -> pred -> joined -> vid -> did.
+        let (stranger, pred, joined) = (at(30), at(20), at(10));
+        let did = vid + Did::from(BigUint::from(10u32));
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node = PeerRing::new_with_storage(did, 3, db);
+
+        // Without predecessor, the previous holder of vnode is accepted.
+        assert!(node.is_handoff_source(stranger, vid)?);
+
+        node.notify(pred)?;
+        assert!(node.is_handoff_source(pred, vid)?);
+        assert!(node.is_handoff_source(joined, vid)?);
+        assert!(!node.is_handoff_source(stranger, vid)?);
+        assert!(!node.is_handoff_source(did, vid)?);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_routes_reach_all_nodes_once() -> Result<()> {
+        let dhts = gen_sorted_dht(32).await;
+        for dht in dhts.iter() {
+            for other in dhts.iter().filter(|d| d.did != dht.did) {
+                dht.join(other.did)?;
+            }
+        }
+        let origin = &dhts[0];
+        let nodes: std::collections::HashMap<Did, &PeerRing> =
+            dhts.iter().map(|d| (d.did, d)).collect();
+
+        let mut received = vec![];
+        let mut hops = 0;
+        let mut queue: Vec<(Did, Did, usize)> = origin
+            .broadcast_routes(origin.did)?
+            .into_iter()
+            .map(|(next, limit)| (next, limit, 1))
+            .collect();
+        while let Some((did, limit, hop)) = queue.pop() {
+            received.push(did);
+            hops = hops.max(hop);
+            for (next, limit) in nodes[&did].broadcast_routes(limit)? {
+                queue.push((next, limit, hop + 1));
+            }
+        }
+
+        received.sort();
+        let expected: Vec<Did> = dhts.iter().skip(1).map(|d| d.did).collect();
+        assert_eq!(received, expected);
+        assert!(hops <= 6, "too many hops: {}", hops);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fix_fingers_by_rtt() -> Result<()> {
+        let dht = gen_sorted_dht(1).await.remove(0);
+        let near = dht.did + Did::from(9u32);
+        let far = dht.did + Did::from(8u32);
+        let out = dht.did + Did::from(16u32);
+        dht.set_rtt(near, 10);
+        dht.set_rtt(far, 50);
+        dht.set_rtt(out, 1);
+
+        // The next finger to fix is 3, whose interval is [8, 16).
+        dht.lock_finger()?.fix_finger_index = 2;
+        assert_eq!(dht.fix_fingers()?, PeerRingAction::None);
+        assert_eq!(dht.lock_finger()?.get(3), Some(near));
+
+        // Rtt is dropped with the node.
+        dht.remove(near)?;
+        assert_eq!(dht.rtt(near), None);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_closest_preceding_nodes() -> Result<()> {
+        let dhts = gen_sorted_dht(4).await;
+        let origin = &dhts[0];
+        for other in dhts.iter().skip(1) {
+            origin.join(other.did)?;
+        }
+
+        // Target itself and nodes after it are excluded, the closest one comes first.
+        assert_eq!(origin.closest_preceding_nodes(dhts[3].did, 3)?, vec![
+            dhts[2].did,
+            dhts[1].did
+        ]);
+        assert_eq!(origin.closest_preceding_nodes(dhts[3].did, 1)?, vec![
+            dhts[2].did
+        ]);
+        assert!(origin.closest_preceding_nodes(dhts[1].did, 3)?.is_empty());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_two_node_finger() -> Result<()> {
         let mut key1 = SecretKey::random();
@@ -44,7 +44,10 @@ use ethereum_types::H160;
 use num_bigint::BigUint;
 use serde::Deserialize;
 use serde::Serialize;
+use sha1::Digest;
+use sha1::Sha1;
 
+use crate::consts::MAX_VIRTUAL_POSITIONS;
 use crate::ecc::HashStr;
 use crate::error::Error;
 use crate::error::Result;
@@ -178,6 +181,29 @@ impl Did {
         let angle = 360 / scalar;
         (0..scalar).map(|i| (*self).rotate(i * angle)).collect()
     }
+
+    /// The `index`-th position of a node on the ring, see [crate::swarm::positions].
+    /// Position 0 is the did itself. Others are hashed from the did and index, so they are
+    /// spread over the ring independently of [Did::rotate_affine], and anyone can verify them.
+    pub fn virtual_position(&self, index: u16) -> Did {
+        if index == 0 {
+            return *self;
+        }
+        let mut hasher = Sha1::new();
+        hasher.update(self.as_bytes());
+        hasher.update(index.to_be_bytes());
+        Self(H160::from_slice(&hasher.finalize()))
+    }
+
+    /// The first `count` positions of a node on the ring, starting from the did itself.
+    pub fn virtual_positions(&self, count: u16) -> Vec<Did> {
+        (0..count).map(|i| self.virtual_position(i)).collect()
+    }
+
+    /// Test if current did is one of the positions that can be operated by `did`.
+    pub fn is_virtual_position_of(&self, did: Did) -> bool {
+        (0..MAX_VIRTUAL_POSITIONS).any(|i| did.virtual_position(i) == *self)
+    }
 }
 
 /// Ordering with a did reference
@@ -274,6 +300,8 @@ impl Sub for Did {
 mod tests {
     use std::str::FromStr;
 
+    use itertools::Itertools;
+
     use super::*;
 
     #[test]
@@ -335,6 +363,24 @@ mod tests {
         assert_eq!(affine_dids[3], did.rotate(270));
     }
 
+    #[test]
+    fn test_virtual_positions() {
+        let did = Did::from_str("0x11E807fcc88dD319270493fB2e822e388Fe36ab0").unwrap();
+        let positions = did.virtual_positions(4);
+        assert_eq!(positions.len(), 4);
+        assert_eq!(positions[0], did);
+        assert_eq!(positions[1], did.virtual_position(1));
+        assert_eq!(positions.iter().unique().count(), 4);
+        assert!(positions.iter().all(|p| p.is_virtual_position_of(did)));
+
+        let other = Did::from_str("0x999999cf1046e68e36E1aA2E0E07105eDDD1f08E").unwrap();
+        assert!(!other.is_virtual_position_of(did));
+        assert!(!positions[1].is_virtual_position_of(other));
+        assert!(!did
+            .virtual_position(MAX_VIRTUAL_POSITIONS)
+            .is_virtual_position_of(did));
+    }
+
     #[test]
     fn test_dump_and_load() {
         // The length must be 40.
@@ -0,0 +1,167 @@
+#![warn(missing_docs)]
+//! Estimation of network size by [PeerRing].
+//!
+//! Dids of nodes are uniformly distributed on the ring, so the size of network can be told by
+//! the density of known nodes. The successors of current node, together with its predecessor,
+//! are consecutive nodes on the ring, which means there is no unknown node on the arc they
+//! cover. The estimation is the number of gaps between them, scaled from the length of the arc
+//! to the whole ring. It's never less than the number of distinct nodes known by successor
+//! sequence, predecessor and finger table.
+//!
+//! The local estimation varies from node to node, it's refined by averaging with the ones
+//! gossiped by neighbours, see [crate::message::GossipNetworkSize].
+use itertools::Itertools;
+use num_bigint::BigUint;
+
+use crate::dht::successor::SuccessorReader;
+use crate::dht::Did;
+use crate::dht::PeerRing;
+use crate::error::Result;
+
+impl PeerRing {
+    /// Estimate the number of nodes on the ring, including current node, by the density of
+    /// successors and predecessor. If the successor sequence is not full, or the predecessor
+    /// is one of successors, the whole ring is known and the size is exact.
+    pub fn local_size_estimate(&self) -> Result<u64> {
+        let successors = self.successors().list()?;
+        let predecessor = *self.lock_predecessor()?;
+        let fingers: Vec<Did> = self
+            .lock_finger()?
+            .list()
+            .iter()
+            .flatten()
+            .copied()
+            .collect();
+
+        let known = successors
+            .iter()
+            .chain(predecessor.iter())
+            .chain(fingers.iter())
+            .filter(|did| **did != self.did)
+            .unique()
+            .count() as u64
+            + 1;
+
+        let Some(last) = successors.last().copied() else {
+            return Ok(known);
+        };
+        let wrapped = predecessor.map_or(false, |p| successors.contains(&p));
+        if wrapped || successors.len() < self.successors().capacity() {
+            return Ok(known.max(successors.len() as u64 + 1));
+        }
+
+        let (start, gaps) = match predecessor {
+            Some(p) if p != self.did => (p, successors.len() as u64 + 1),
+            _ => (self.did, successors.len() as u64),
+        };
+        let arc = BigUint::from(last - start);
+        if arc == BigUint::from(0u8) {
+            return Ok(known);
+        }
+        let ring = BigUint::from(1u8) << 160;
+        let size = (ring * gaps + &arc / 2u8) / arc;
+        Ok(u64::try_from(size).unwrap_or(u64::MAX).max(known))
+    }
+
+    /// Record the estimation of network size gossiped by a node.
+    pub fn set_size_estimate(&self, did: Did, size: u64) {
+        self.size_estimates.insert(did, size);
+    }
+
+    /// Estimations gossiped by neighbours, which are the successors and predecessor.
+    /// Estimations of other nodes are outdated and ignored.
+    pub fn neighbour_size_estimates(&self) -> Result<Vec<(Did, u64)>> {
+        let successors = self.successors().list()?;
+        let predecessor = *self.lock_predecessor()?;
+        Ok(successors
+            .iter()
+            .chain(predecessor.iter())
+            .unique()
+            .filter_map(|did| self.size_estimates.get(did).map(|v| (*did, *v)))
+            .collect())
+    }
+
+    /// Estimate the number of nodes on the ring. It's the average of local estimation and the
+    /// ones gossiped by neighbours.
+    pub fn size_estimate(&self) -> Result<u64> {
+        let local = self.local_size_estimate()?;
+        let neighbours = self.neighbour_size_estimates()?;
+        let count = neighbours.len() as u128 + 1;
+        let sum = neighbours
+            .iter()
+            .fold(local as u128, |acc, (_, size)| acc + *size as u128);
+        Ok(((sum + count / 2) / count) as u64)
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dht::tests::gen_ordered_dids;
+    use crate::dht::Chord;
+    use crate::tests::default::gen_pure_dht;
+
+    /// Dids evenly spaced on the ring, starting from zero.
+    fn gen_even_dids(n: u32) -> Vec<Did> {
+        let ring = BigUint::from(1u8) << 160;
+        (0..n).map(|i| Did::from(&ring * i / n)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_size_estimate_of_even_ring() -> Result<()> {
+        for n in [8, 16, 100] {
+            let dids = gen_even_dids(n);
+            let dht = gen_pure_dht(dids[0]).await?;
+            for did in dids.iter().skip(1) {
+                dht.join(*did)?;
+            }
+            // Without predecessor, gaps between current node and successors are counted.
+            assert_eq!(dht.local_size_estimate()?, n as u64);
+            dht.notify(dids[n as usize - 1])?;
+            assert_eq!(dht.local_size_estimate()?, n as u64);
+        }
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_size_estimate_of_small_ring() -> Result<()> {
+        let dids = gen_ordered_dids(3);
+        let dht = gen_pure_dht(dids[0]).await?;
+        assert_eq!(dht.local_size_estimate()?, 1);
+
+        dht.join(dids[1])?;
+        assert_eq!(dht.local_size_estimate()?, 2);
+        dht.join(dids[2])?;
+        assert_eq!(dht.local_size_estimate()?, 3);
+        dht.notify(dids[2])?;
+        assert_eq!(dht.local_size_estimate()?, 3);
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_size_estimate_refined_by_neighbours() -> Result<()> {
+        let dids = gen_even_dids(8);
+        let dht = gen_pure_dht(dids[0]).await?;
+        for did in dids.iter().skip(1) {
+            dht.join(*did)?;
+        }
+        let successors = dht.successors().list()?;
+
+        // Estimations of nodes which are not neighbours are ignored.
+        dht.set_size_estimate(dids[5], 1000);
+        assert_eq!(dht.size_estimate()?, 8);
+
+        dht.set_size_estimate(successors[0], 10);
+        dht.set_size_estimate(successors[1], 12);
+        assert_eq!(dht.neighbour_size_estimates()?.len(), 2);
+        assert_eq!(dht.size_estimate()?, 10);
+
+        dht.remove(successors[1])?;
+        assert_eq!(dht.neighbour_size_estimates()?, vec![(successors[0], 10)]);
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
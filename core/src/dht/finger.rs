@@ -31,6 +31,11 @@ impl FingerTable {
         }
     }
 
+    /// The did finger table starts from
+    pub fn did(&self) -> Did {
+        self.did
+    }
+
     /// is empty
     pub fn is_empty(&self) -> bool {
         self.len() == 0
@@ -157,6 +162,35 @@ impl FingerTable {
         self.did
     }
 
+    /// get closest predecessor, using rtt as the tie-breaker.
+    /// Fingers preceding `did` are tied if their distances to `did` are in the same
+    /// power-of-two interval, which take the same hops to reach `did` by expectation.
+    /// The one with lowest rtt is chosen among them, fingers without rtt come last.
+    pub fn closest_predecessor_by_rtt(&self, did: Did, rtt: impl Fn(Did) -> Option<u64>) -> Did {
+        let closest = self.closest_predecessor(did);
+        if closest == self.did {
+            return closest;
+        }
+
+        let bias = did.bias(self.did);
+        let level = |v: Did| BigUint::from(did - v).bits();
+        let closest_level = level(closest);
+        self.finger
+            .iter()
+            .flatten()
+            .filter(|v| v.bias(self.did) < bias && level(**v) == closest_level)
+            .min_by_key(|v| (rtt(**v).unwrap_or(u64::MAX), did - **v))
+            .copied()
+            .unwrap_or(closest)
+    }
+
+    /// Check if `did` is in the interval of finger `index`, whose distance from current node
+    /// is in `[2^index, 2^(index+1))`. Any node in the interval can be the finger without
+    /// losing `O(log N)` hops of lookup, so it can be chosen by proximity.
+    pub fn in_interval(&self, index: usize, did: Did) -> bool {
+        did != self.did && BigUint::from(did - self.did).bits() == index as u64 + 1
+    }
+
     /// get length of finger
     pub fn len(&self) -> usize {
         self.finger.iter().flatten().count()
@@ -394,4 +428,33 @@ mod test {
             None
         ]);
     }
+
+    #[test]
+    fn test_finger_table_proximity() {
+        let mut table = FingerTable::new(Did::from(0u32), 8);
+        let (did2, did8, did9) = (Did::from(2u32), Did::from(8u32), Did::from(9u32));
+        table.set(1, did2);
+        table.set(3, did8);
+        table.set(4, did9);
+
+        assert!(table.in_interval(3, did8));
+        assert!(table.in_interval(3, did9));
+        assert!(table.in_interval(3, Did::from(15u32)));
+        assert!(!table.in_interval(3, Did::from(16u32)));
+        assert!(!table.in_interval(3, Did::from(7u32)));
+
+        // Distances from did8 and did9 to 20 are both in [8, 16), they are tied.
+        let target = Did::from(20u32);
+        assert_eq!(table.closest_predecessor(target), did9);
+        assert_eq!(table.closest_predecessor_by_rtt(target, |_| None), did9);
+        let rtt = |did: Did| if did == did8 { Some(10) } else { Some(50) };
+        assert_eq!(table.closest_predecessor_by_rtt(target, rtt), did8);
+
+        // did2 and did8 are not tied with did9 for 12.
+        let rtt = |did: Did| if did == did9 { Some(50) } else { Some(10) };
+        assert_eq!(
+            table.closest_predecessor_by_rtt(Did::from(12u32), rtt),
+            did9
+        );
+    }
 }
@@ -0,0 +1,207 @@
+#![warn(missing_docs)]
+//! Migration of vnodes persisted by older versions.
+//!
+//! The [PersistenceStorage](crate::storage::PersistenceStorage) of native build encodes
+//! vnodes by bincode, which has neither field names nor defaults, so a record written before
+//! new fields are added to [VirtualNode] cannot be read anymore. The format of records is
+//! versioned by [VNODE_FORMAT_VERSION], which is written to storage under [VNODE_FORMAT_KEY]
+//! once [PeerRing::migrate_vnodes] rewrites older records to current format.
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::dht::vnode::VNodeType;
+use crate::dht::vnode::VirtualNode;
+use crate::dht::Did;
+use crate::dht::PeerRing;
+use crate::dht::VNodeVersion;
+use crate::error::Result;
+use crate::message::Encoded;
+use crate::message::MessageVerification;
+use crate::storage::PersistenceStorageReadAndWrite;
+
+/// Key of vnode format version in storage. It's not a valid [Did], so it never conflicts with vnodes.
+pub const VNODE_FORMAT_KEY: &str = "vnode_format";
+
+/// Version of current vnode format.
+/// * 0: did, data and kind only.
+/// * 1: owner, ACL, version and expiry are added.
+/// * 2: writers of entries are added.
+pub const VNODE_FORMAT_VERSION: u32 = 2;
+
+/// [VirtualNode] of format version 0.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct VirtualNodeV0 {
+    did: Did,
+    data: Vec<Encoded>,
+    kind: VNodeType,
+}
+
+impl From<VirtualNodeV0> for VirtualNode {
+    fn from(vnode: VirtualNodeV0) -> Self {
+        Self {
+            did: vnode.did,
+            data: vnode.data,
+            kind: vnode.kind,
+            owner: None,
+            owner_proof: None,
+            acl: vec![],
+            version: VNodeVersion::default(),
+            expires_at: None,
+            entry_expires_at: vec![],
+            entry_writers: vec![],
+        }
+    }
+}
+
+/// [VirtualNode] of format version 1.
+/// Fields added by it have defaults, so that self-describing storages, such as IndexedDB,
+/// can read records of version 0 as it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct VirtualNodeV1 {
+    did: Did,
+    data: Vec<Encoded>,
+    kind: VNodeType,
+    #[serde(default)]
+    owner: Option<Did>,
+    #[serde(default)]
+    owner_proof: Option<Box<MessageVerification>>,
+    #[serde(default)]
+    acl: Vec<Did>,
+    #[serde(default)]
+    version: VNodeVersion,
+    #[serde(default)]
+    expires_at: Option<u64>,
+    #[serde(default)]
+    entry_expires_at: Vec<Option<u64>>,
+}
+
+impl From<VirtualNodeV1> for VirtualNode {
+    fn from(vnode: VirtualNodeV1) -> Self {
+        Self {
+            did: vnode.did,
+            data: vnode.data,
+            kind: vnode.kind,
+            owner: vnode.owner,
+            owner_proof: vnode.owner_proof,
+            acl: vnode.acl,
+            version: vnode.version,
+            expires_at: vnode.expires_at,
+            entry_expires_at: vnode.entry_expires_at,
+            entry_writers: vec![],
+        }
+    }
+}
+
+impl PeerRing {
+    /// Rewrite vnodes of older format in storage to current format, and record the
+    /// [VNODE_FORMAT_VERSION]. It should be called before the storage is used.
+    /// Return the number of migrated vnodes.
+    pub async fn migrate_vnodes(&self) -> Result<usize> {
+        let version: Option<u32> = self.storage.get(&VNODE_FORMAT_KEY.to_string()).await?;
+        if version >= Some(VNODE_FORMAT_VERSION) {
+            return Ok(0);
+        }
+
+        // Records of newer format can be read as older ones, since bincode ignores trailing
+        // bytes, but not the reverse. So a record is migrated from the newest format it can be
+        // read as, and only the records unreadable as current are migrated.
+        let current: Vec<(Did, VirtualNode)> = self.storage.get_all().await?;
+        let v1: Vec<(Did, VirtualNodeV1)> = self.storage.get_all().await?;
+        let v0: Vec<(Did, VirtualNodeV0)> = self.storage.get_all().await?;
+        let is_current = |vid: &Did| current.iter().any(|(did, _)| did == vid);
+        let legacy = v1
+            .into_iter()
+            .map(|(vid, vnode)| (vid, VirtualNode::from(vnode)))
+            .filter(|(vid, _)| !is_current(vid))
+            .collect::<Vec<_>>();
+        let legacy = v0
+            .into_iter()
+            .map(|(vid, vnode)| (vid, VirtualNode::from(vnode)))
+            .filter(|(vid, _)| !is_current(vid) && !legacy.iter().any(|(did, _)| did == vid))
+            .chain(legacy.clone())
+            .collect::<Vec<_>>();
+        let mut migrated = 0;
+        for (vid, vnode) in legacy {
+            self.storage.put(&vid, &vnode).await?;
+            migrated += 1;
+        }
+        tracing::info!("Migrated {} vnodes of {}", migrated, self.did);
+
+        self.storage
+            .put(&VNODE_FORMAT_KEY.to_string(), &VNODE_FORMAT_VERSION)
+            .await?;
+        Ok(migrated)
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::PersistenceStorageOperation;
+    use crate::tests::default::gen_sorted_dht;
+
+    #[tokio::test]
+    async fn test_migrate_vnodes() -> Result<()> {
+        let dht = gen_sorted_dht(1).await.remove(0);
+
+        let legacy: VirtualNode = "test_migrate_legacy".to_string().try_into()?;
+        let owned: VirtualNode = "test_migrate_owned".to_string().try_into()?;
+        let owned = owned.with_ttl(std::time::Duration::from_secs(60));
+        let current: VirtualNode = "test_migrate_current".to_string().try_into()?;
+        let current = current.with_ttl(std::time::Duration::from_secs(60));
+        let v0 = VirtualNodeV0 {
+            did: legacy.did,
+            data: legacy.data.clone(),
+            kind: legacy.kind,
+        };
+        let v1 = VirtualNodeV1 {
+            did: owned.did,
+            data: owned.data.clone(),
+            kind: owned.kind,
+            owner: owned.owner,
+            owner_proof: owned.owner_proof.clone(),
+            acl: owned.acl.clone(),
+            version: owned.version,
+            expires_at: owned.expires_at,
+            entry_expires_at: owned.entry_expires_at.clone(),
+        };
+        dht.storage.put(&legacy.did, &v0).await?;
+        dht.storage.put(&owned.did, &v1).await?;
+        dht.storage.put(&current.did, &current).await?;
+
+        // The legacy records cannot be read before migration.
+        let all: Vec<(Did, VirtualNode)> = dht.storage.get_all().await?;
+        assert_eq!(all, vec![(current.did, current.clone())]);
+
+        assert_eq!(dht.migrate_vnodes().await?, 2);
+        assert_eq!(dht.storage_get(legacy.did).await?, Some(legacy));
+        assert_eq!(dht.storage_get(owned.did).await?, Some(owned));
+        assert_eq!(dht.storage_get(current.did).await?, Some(current));
+
+        // Migrated only once.
+        assert_eq!(dht.migrate_vnodes().await?, 0);
+        assert_eq!(dht.storage.count().await?, 4);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_v0_vnode_as_v1() -> Result<()> {
+        let legacy: VirtualNode = "test_migrate_json".to_string().try_into()?;
+        let v0 = serde_json::to_value(VirtualNodeV0 {
+            did: legacy.did,
+            data: legacy.data.clone(),
+            kind: legacy.kind,
+        })
+        .unwrap();
+
+        // Records of version 0 in self-describing storages can be read as any later version.
+        let v1: VirtualNodeV1 = serde_json::from_value(v0.clone()).unwrap();
+        assert_eq!(VirtualNode::from(v1), legacy);
+        let current: VirtualNode = serde_json::from_value(v0).unwrap();
+        assert_eq!(current, legacy);
+        Ok(())
+    }
+}
@@ -20,16 +20,27 @@ pub use finger::FingerTable;
 pub use types::Chord;
 pub use types::ChordStorage;
 pub use types::ChordStorageCache;
+pub use types::ChordStorageReplica;
 pub use types::ChordStorageSync;
 pub use types::CorrectChord;
 pub use types::LiveDid;
 mod stabilization;
 pub use stabilization::Stabilization;
 pub use stabilization::TStabilize;
+pub mod snapshot;
 /// Implement Subring with VNode
 pub mod subring;
+pub use snapshot::RoutingSnapshot;
+pub mod estimate;
+pub mod migration;
+pub mod pubsub;
+pub use pubsub::TopicSubscriptions;
+pub mod validation;
+pub mod version;
 /// VNode is a special node that only has virtual address
 pub mod vnode;
+pub use version::HybridClock;
+pub use version::VNodeVersion;
 
 #[cfg(test)]
 pub mod tests {
@@ -0,0 +1,98 @@
+#![warn(missing_docs)]
+//! Subscriptions of topics.
+//!
+//! A topic is identified by the did of its name, and its subscriptions are kept by the node
+//! responsible for that did. Subscriptions are leases, subscribers renew them periodically
+//! on [Stabilization](super::Stabilization), so that they are re-established on the new
+//! responsible node when the ring changes. Stale leases are purged after expired.
+use std::collections::HashMap;
+
+use dashmap::DashMap;
+
+use crate::dht::Did;
+
+/// Subscribers of topics that current node is responsible for.
+#[derive(Debug, Default)]
+pub struct TopicSubscriptions {
+    /// Topic -> subscriber -> timestamp in milliseconds when the lease is expired.
+    inner: DashMap<Did, HashMap<Did, u64>>,
+}
+
+impl TopicSubscriptions {
+    /// Add or renew a subscription until `expires_at`.
+    pub fn subscribe(&self, topic: Did, subscriber: Did, expires_at: u64) {
+        self.inner
+            .entry(topic)
+            .or_default()
+            .insert(subscriber, expires_at);
+    }
+
+    /// Remove a subscription.
+    pub fn unsubscribe(&self, topic: Did, subscriber: Did) {
+        let emptied = match self.inner.get_mut(&topic) {
+            Some(mut subscribers) => {
+                subscribers.remove(&subscriber);
+                subscribers.is_empty()
+            }
+            None => false,
+        };
+        if emptied {
+            self.inner
+                .remove_if(&topic, |_, subscribers| subscribers.is_empty());
+        }
+    }
+
+    /// List subscribers of a topic whose leases are not expired at `now`.
+    pub fn subscribers(&self, topic: Did, now: u64) -> Vec<Did> {
+        let Some(subscribers) = self.inner.get(&topic) else {
+            return vec![];
+        };
+        let mut dids: Vec<Did> = subscribers
+            .iter()
+            .filter(|(_, expires_at)| **expires_at > now)
+            .map(|(did, _)| *did)
+            .collect();
+        dids.sort();
+        dids
+    }
+
+    /// Purge subscriptions expired at `now`, return the number of purged ones.
+    pub fn purge_expired(&self, now: u64) -> usize {
+        let mut purged = 0;
+        self.inner.retain(|_, subscribers| {
+            let len = subscribers.len();
+            subscribers.retain(|_, expires_at| *expires_at > now);
+            purged += len - subscribers.len();
+            !subscribers.is_empty()
+        });
+        purged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dht::tests::gen_ordered_dids;
+
+    #[test]
+    fn test_topic_subscriptions_lease() {
+        let dids = gen_ordered_dids(3);
+        let (topic, a, b) = (dids[0], dids[1], dids[2]);
+        let subscriptions = TopicSubscriptions::default();
+
+        subscriptions.subscribe(topic, b, 100);
+        subscriptions.subscribe(topic, a, 200);
+        assert_eq!(subscriptions.subscribers(topic, 50), vec![a, b]);
+        assert_eq!(subscriptions.subscribers(topic, 100), vec![a]);
+
+        // Renewed lease is kept.
+        subscriptions.subscribe(topic, b, 300);
+        assert_eq!(subscriptions.purge_expired(150), 0);
+        assert_eq!(subscriptions.subscribers(topic, 150), vec![a, b]);
+
+        subscriptions.unsubscribe(topic, b);
+        assert_eq!(subscriptions.purge_expired(250), 1);
+        assert!(subscriptions.subscribers(topic, 0).is_empty());
+        assert!(subscriptions.inner.is_empty());
+    }
+}
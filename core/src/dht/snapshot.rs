@@ -0,0 +1,163 @@
+#![warn(missing_docs)]
+//! Routing state of [PeerRing] persisted for warm restart.
+//!
+//! A restarted node starts with an empty finger table and successor sequence, so it has to
+//! be reconnected to the ring manually. The [RoutingSnapshot] is written to the local
+//! [PersistenceStorage](crate::storage::PersistenceStorage) during stabilization. On startup,
+//! the nodes in it can be reconnected by their endpoints, and the routing state is rebuilt
+//! by joining them as usual.
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::dht::Did;
+use crate::dht::PeerRing;
+use crate::dht::TopoInfo;
+use crate::error::Result;
+use crate::storage::PersistenceStorageReadAndWrite;
+
+/// Key of [RoutingSnapshot] in storage. It's not a valid [Did], so it never conflicts with vnodes.
+pub const ROUTING_SNAPSHOT_KEY: &str = "routing_snapshot";
+
+/// Routing state of [PeerRing].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RoutingSnapshot {
+    /// Successors and predecessor.
+    pub topo: TopoInfo,
+    /// Nodes in finger table.
+    pub fingers: Vec<Did>,
+    /// Last known endpoints of nodes above, see [PeerRing::set_endpoint].
+    pub endpoints: BTreeMap<Did, String>,
+}
+
+impl RoutingSnapshot {
+    /// Dids of all nodes without duplication, in the order of successors, predecessor and fingers.
+    pub fn dids(&self) -> Vec<Did> {
+        self.topo
+            .successors
+            .iter()
+            .chain(self.topo.predecessor.iter())
+            .chain(self.fingers.iter())
+            .copied()
+            .unique()
+            .collect()
+    }
+
+    /// Returns `true` if there is no node in the snapshot.
+    pub fn is_empty(&self) -> bool {
+        self.dids().is_empty()
+    }
+}
+
+impl PeerRing {
+    /// Record the endpoint to reach a node out of band, such as the url of its jsonrpc server.
+    pub fn set_endpoint(&self, did: Did, endpoint: &str) {
+        self.endpoints.insert(did, endpoint.to_string());
+    }
+
+    /// Get the last known endpoint of a node.
+    pub fn endpoint(&self, did: Did) -> Option<String> {
+        self.endpoints.get(&did).map(|v| v.clone())
+    }
+
+    /// Take a snapshot of current routing state.
+    pub fn routing_snapshot(&self) -> Result<RoutingSnapshot> {
+        let topo = TopoInfo::try_from(self)?;
+        let fingers = self
+            .lock_finger()?
+            .list()
+            .iter()
+            .flatten()
+            .copied()
+            .unique()
+            .collect();
+
+        let mut snapshot = RoutingSnapshot {
+            topo,
+            fingers,
+            endpoints: BTreeMap::new(),
+        };
+        snapshot.endpoints = snapshot
+            .dids()
+            .into_iter()
+            .filter_map(|did| self.endpoint(did).map(|e| (did, e)))
+            .collect();
+        Ok(snapshot)
+    }
+
+    /// Persist current routing state to storage.
+    /// An empty routing state is not written, so that the last one survives leaving the ring.
+    pub async fn store_routing_snapshot(&self) -> Result<()> {
+        let snapshot = self.routing_snapshot()?;
+        if snapshot.is_empty() {
+            return Ok(());
+        }
+        self.storage
+            .put(&ROUTING_SNAPSHOT_KEY.to_string(), &snapshot)
+            .await
+    }
+
+    /// Load the routing state persisted by [PeerRing::store_routing_snapshot].
+    pub async fn load_routing_snapshot(&self) -> Result<Option<RoutingSnapshot>> {
+        self.storage.get(&ROUTING_SNAPSHOT_KEY.to_string()).await
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dht::successor::SuccessorWriter;
+    use crate::dht::vnode::VirtualNode;
+    use crate::storage::PersistenceStorageOperation;
+    use crate::tests::default::gen_sorted_dht;
+
+    #[tokio::test]
+    async fn test_routing_snapshot() -> Result<()> {
+        let mut dhts = gen_sorted_dht(4).await;
+        let dht = dhts.remove(0);
+        let dids = dhts.iter().map(|d| d.did).collect::<Vec<_>>();
+
+        // Empty routing state is not persisted.
+        dht.store_routing_snapshot().await?;
+        assert_eq!(dht.load_routing_snapshot().await?, None);
+
+        dht.successors().update(dids[0])?;
+        dht.successors().update(dids[1])?;
+        dht.lock_finger()?.set(0, dids[0]);
+        dht.lock_finger()?.set(1, dids[1]);
+        dht.lock_finger()?.set(159, dids[2]);
+        *dht.lock_predecessor()? = Some(dids[2]);
+        dht.set_endpoint(dids[0], "http://127.0.0.1:50001");
+        dht.set_endpoint(Did::from(1u32), "http://127.0.0.1:50002");
+
+        dht.store_routing_snapshot().await?;
+        let snapshot = dht.load_routing_snapshot().await?.unwrap();
+        assert_eq!(snapshot.topo.successors, vec![dids[0], dids[1]]);
+        assert_eq!(snapshot.topo.predecessor, Some(dids[2]));
+        assert_eq!(snapshot.fingers, vec![dids[0], dids[1], dids[2]]);
+        assert_eq!(snapshot.dids(), dids);
+        // Only endpoints of nodes in routing state are kept.
+        assert_eq!(
+            snapshot.endpoints,
+            BTreeMap::from([(dids[0], "http://127.0.0.1:50001".to_string())])
+        );
+
+        // The snapshot is ignored by vnode storage.
+        let vnodes: Vec<(Did, VirtualNode)> = dht.storage.get_all().await?;
+        assert!(vnodes.is_empty());
+        assert_eq!(dht.storage.count().await?, 1);
+
+        // Leaving the ring doesn't overwrite the last snapshot.
+        for did in dids.iter() {
+            dht.remove(*did)?;
+        }
+        dht.store_routing_snapshot().await?;
+        assert_eq!(dht.load_routing_snapshot().await?, Some(snapshot));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
@@ -5,6 +5,7 @@ use async_trait::async_trait;
 use rings_transport::core::transport::ConnectionInterface;
 
 use crate::dht::successor::SuccessorReader;
+use crate::dht::types::ChordStorageReplica;
 use crate::dht::types::CorrectChord;
 use crate::dht::Chord;
 use crate::dht::PeerRing;
@@ -15,12 +16,16 @@ use crate::message::handlers::MessageHandlerEvent;
 use crate::message::FindSuccessorReportHandler;
 use crate::message::FindSuccessorSend;
 use crate::message::FindSuccessorThen;
+use crate::message::GossipNetworkSize;
 use crate::message::Message;
 use crate::message::MessagePayload;
 use crate::message::NotifyPredecessorSend;
 use crate::message::PayloadSender;
 use crate::message::QueryForTopoInfoSend;
+use crate::message::SyncVNodeDigestSend;
+use crate::swarm::LookupMode;
 use crate::swarm::Swarm;
+use crate::utils::get_epoch_ms;
 
 /// A combination contains chord and swarm, use to run stabilize.
 /// - swarm: transports communicate with each others.
@@ -86,11 +91,12 @@ impl Stabilization {
         if self.chord.did != successor_min {
             for s in successor_list {
                 tracing::debug!("STABILIZATION notify_predecessor: {:?}", s);
-                let payload = MessagePayload::new_send(
+                let payload = MessagePayload::new_send_by(
                     msg.clone(),
                     self.swarm.session_sk(),
+                    self.chord.did,
                     s,
-                    self.swarm.did(),
+                    self.chord.did,
                 )?;
                 self.swarm.send_payload(payload).await?;
             }
@@ -110,17 +116,31 @@ impl Stabilization {
                     PeerRingRemoteAction::FindSuccessorForFix(finger_did),
                 ) => {
                     tracing::debug!("STABILIZATION fix_fingers: {:?}", finger_did);
+                    // Look up the finger through disjoint paths if iterative lookup is enabled,
+                    // the successor is joined into finger table once connected.
+                    if let LookupMode::Iterative(config) = self.swarm.lookup_mode() {
+                        let route = self.swarm.lookup_iterative(finger_did, config).await?;
+                        let successor = route.successor;
+                        if successor != self.chord.did && !self.swarm.is_local_position(successor) {
+                            self.swarm.connect(successor).await?;
+                        }
+                        return Ok(());
+                    }
                     let msg = Message::FindSuccessorSend(FindSuccessorSend {
                         did: finger_did,
                         then: FindSuccessorThen::Report(FindSuccessorReportHandler::FixFingerTable),
                         strict: false,
                     });
-                    let payload = MessagePayload::new_send(
+                    let payload = MessagePayload::new_send_by(
                         msg.clone(),
                         self.swarm.session_sk(),
+                        self.chord.did,
                         closest_predecessor,
                         closest_predecessor,
                     )?;
+                    self.chord
+                        .successor_queries
+                        .record(payload.transaction.tx_id, finger_did);
                     self.swarm.send_payload(payload).await?;
                     Ok(())
                 }
@@ -137,6 +157,104 @@ impl Stabilization {
     }
 }
 
+impl Stabilization {
+    /// Compare stored vnodes with the replicas on successors, this is a DHT operation.
+    /// Missing or stale replicas will be sent again, and replicas of the range that
+    /// current node took over will be restored.
+    pub async fn anti_entropy(&self) -> Result<()> {
+        let act = self.chord.vnode_anti_entropy().await?;
+        let PeerRingAction::MultiActions(acts) = act else {
+            return Ok(());
+        };
+        for act in acts {
+            if let PeerRingAction::RemoteAction(
+                target,
+                PeerRingRemoteAction::SyncVNodeDigest(range_end, digests),
+            ) = act
+            {
+                tracing::debug!("STABILIZATION anti_entropy: {:?}", target);
+                let msg = Message::SyncVNodeDigestSend(SyncVNodeDigestSend { range_end, digests });
+                self.swarm
+                    .position_sender(&self.chord)
+                    .send_direct_message(msg, target)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Stabilization {
+    /// Purge expired vnodes from storage, cache and replicas of local DHT,
+    /// and expired topic subscriptions held by local DHT.
+    pub async fn purge_expired(&self) -> Result<()> {
+        let now = get_epoch_ms() as u64;
+        let purged = self.chord.purge_expired_vnodes(now).await?;
+        if purged > 0 {
+            tracing::debug!("STABILIZATION purge_expired: {} vnodes", purged);
+        }
+        let purged = self.chord.subscriptions.purge_expired(now);
+        if purged > 0 {
+            tracing::debug!("STABILIZATION purge_expired: {} subscriptions", purged);
+        }
+        Ok(())
+    }
+}
+
+impl Stabilization {
+    /// Renew subscriptions of topics on their responsible nodes, which may be changed.
+    pub async fn renew_subscriptions(&self) -> Result<()> {
+        self.swarm.topic_renew_all().await
+    }
+}
+
+impl Stabilization {
+    /// Publish the session public key to DHT when it's half expired, see
+    /// [crate::swarm::encryption].
+    pub async fn renew_session_pubkey(&self) -> Result<()> {
+        if self.chord.successors().is_empty()? {
+            return Ok(());
+        }
+        self.swarm.renew_session_pubkey().await
+    }
+}
+
+impl Stabilization {
+    /// Measure round-trip time of connected peers, which is used to fix fingers by proximity.
+    pub async fn measure_rtt(&self) -> Result<()> {
+        self.swarm.ping_connections().await;
+        Ok(())
+    }
+}
+
+impl Stabilization {
+    /// Gossip the estimation of network size with successors and predecessor,
+    /// see [crate::dht::estimate].
+    pub async fn gossip_network_size(&self) -> Result<()> {
+        let size = self.chord.size_estimate()?;
+        let mut neighbours = self.chord.successors().list()?;
+        if let Some(p) = *self.chord.lock_predecessor()? {
+            if !neighbours.contains(&p) {
+                neighbours.push(p);
+            }
+        }
+        let sender = self.swarm.position_sender(&self.chord);
+        for did in neighbours.into_iter().filter(|did| *did != self.chord.did) {
+            tracing::debug!("STABILIZATION gossip_network_size: {:?}", did);
+            let msg = Message::GossipNetworkSize(GossipNetworkSize { size });
+            sender.send_direct_message(msg, did).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Stabilization {
+    /// Persist routing state, which is used to reconnect the ring after restart.
+    pub async fn store_routing_snapshot(&self) -> Result<()> {
+        self.chord.store_routing_snapshot().await
+    }
+}
+
 impl Stabilization {
     /// Call stabilization from correct chord implementation
     pub async fn correct_stabilize(&self) -> Result<()> {
@@ -156,6 +274,45 @@ impl Stabilization {
 }
 
 impl Stabilization {
+    /// Stabilization of positions operated by swarm besides its did, which only run
+    /// DHT operations on their own chord, see [crate::swarm::positions].
+    fn virtual_positions(&self) -> Vec<Self> {
+        self.swarm
+            .virtual_positions
+            .iter()
+            .map(|p| Self {
+                chord: p.dht.clone(),
+                swarm: self.swarm.clone(),
+                timeout: self.timeout,
+            })
+            .collect()
+    }
+
+    /// Stabilize a position operated by swarm besides its did.
+    async fn stabilize_virtual_position(&self) {
+        let did = self.chord.did;
+        tracing::debug!("STABILIZATION virtual position {:?} start", did);
+        if let Err(e) = self.notify_predecessor().await {
+            tracing::error!("[stabilize] Failed on notify predecessor {:?}", e);
+        }
+        if let Err(e) = self.fix_fingers().await {
+            tracing::error!("[stabilize] Failed on fix_finger {:?}", e);
+        }
+        if let Err(e) = self.anti_entropy().await {
+            tracing::error!("[stabilize] Failed on anti entropy {:?}", e);
+        }
+        if let Err(e) = self.purge_expired().await {
+            tracing::error!("[stabilize] Failed on purge expired vnodes {:?}", e);
+        }
+        if let Err(e) = self.store_routing_snapshot().await {
+            tracing::error!("[stabilize] Failed on store routing snapshot {:?}", e);
+        }
+        if let Err(e) = self.gossip_network_size().await {
+            tracing::error!("[stabilize] Failed on gossip network size {:?}", e);
+        }
+        tracing::debug!("STABILIZATION virtual position {:?} end", did);
+    }
+
     /// Call stabilize periodly.
     pub async fn stabilize(&self) -> Result<()> {
         tracing::debug!("STABILIZATION notify_predecessor start");
@@ -163,6 +320,11 @@ impl Stabilization {
             tracing::error!("[stabilize] Failed on notify predecessor {:?}", e);
         }
         tracing::debug!("STABILIZATION notify_predecessor end");
+        tracing::debug!("STABILIZATION measure_rtt start");
+        if let Err(e) = self.measure_rtt().await {
+            tracing::error!("[stabilize] Failed on measure rtt {:?}", e);
+        }
+        tracing::debug!("STABILIZATION measure_rtt end");
         tracing::debug!("STABILIZATION fix_fingers start");
         if let Err(e) = self.fix_fingers().await {
             tracing::error!("[stabilize] Failed on fix_finger {:?}", e);
@@ -176,6 +338,39 @@ impl Stabilization {
             );
         }
         tracing::debug!("STABILIZATION clean_unavailable_connections end");
+        tracing::debug!("STABILIZATION anti_entropy start");
+        if let Err(e) = self.anti_entropy().await {
+            tracing::error!("[stabilize] Failed on anti entropy {:?}", e);
+        }
+        tracing::debug!("STABILIZATION anti_entropy end");
+        tracing::debug!("STABILIZATION purge_expired start");
+        if let Err(e) = self.purge_expired().await {
+            tracing::error!("[stabilize] Failed on purge expired vnodes {:?}", e);
+        }
+        tracing::debug!("STABILIZATION purge_expired end");
+        tracing::debug!("STABILIZATION renew_subscriptions start");
+        if let Err(e) = self.renew_subscriptions().await {
+            tracing::error!("[stabilize] Failed on renew subscriptions {:?}", e);
+        }
+        tracing::debug!("STABILIZATION renew_subscriptions end");
+        tracing::debug!("STABILIZATION renew_session_pubkey start");
+        if let Err(e) = self.renew_session_pubkey().await {
+            tracing::error!("[stabilize] Failed on renew session pubkey {:?}", e);
+        }
+        tracing::debug!("STABILIZATION renew_session_pubkey end");
+        tracing::debug!("STABILIZATION store_routing_snapshot start");
+        if let Err(e) = self.store_routing_snapshot().await {
+            tracing::error!("[stabilize] Failed on store routing snapshot {:?}", e);
+        }
+        tracing::debug!("STABILIZATION store_routing_snapshot end");
+        tracing::debug!("STABILIZATION gossip_network_size start");
+        if let Err(e) = self.gossip_network_size().await {
+            tracing::error!("[stabilize] Failed on gossip network size {:?}", e);
+        }
+        tracing::debug!("STABILIZATION gossip_network_size end");
+        for position in self.virtual_positions() {
+            position.stabilize_virtual_position().await;
+        }
         #[cfg(feature = "experimental")]
         {
             tracing::debug!("STABILIZATION correct_stabilize start");
@@ -7,8 +7,13 @@ use super::vnode::VNodeType;
 use super::vnode::VirtualNode;
 use super::FingerTable;
 use crate::dht::Did;
+use crate::dht::VNodeVersion;
 use crate::error::Error;
 use crate::error::Result;
+use crate::message::Encoder;
+
+/// Size of the finger table of a subring, same as [super::PeerRing].
+pub const SUBRING_FINGER_SIZE: usize = 160;
 
 /// A Subring is like a [super::PeerRing] without storage functional.
 /// Subring also have two extra fields: `name` and `creator`.
@@ -18,10 +23,13 @@ use crate::error::Result;
 pub struct Subring {
     /// name of subring
     pub name: String,
-    /// finger table
+    /// finger table, starts from the did of subring
     pub finger: FingerTable,
     /// creator
     pub creator: Did,
+    /// all members of subring, sorted
+    #[serde(default)]
+    pub members: Vec<Did>,
 }
 
 impl Subring {
@@ -30,10 +38,78 @@ impl Subring {
         let did = VirtualNode::gen_did(name)?;
         Ok(Self {
             name: name.to_string(),
-            finger: FingerTable::new(did, 1),
+            finger: FingerTable::new(did, SUBRING_FINGER_SIZE),
             creator,
+            members: vec![],
         })
     }
+
+    /// The did of subring, which is the hash of its name.
+    pub fn did(&self) -> Result<Did> {
+        VirtualNode::gen_did(&self.name)
+    }
+
+    /// Add a member into subring.
+    pub fn join(&mut self, did: Did) {
+        if let Err(i) = self.members.binary_search(&did) {
+            self.members.insert(i, did);
+            self.finger.join(did);
+        }
+    }
+
+    /// Remove a member from subring, the finger table is rebuilt from remaining members.
+    pub fn leave(&mut self, did: Did) -> Result<()> {
+        if let Ok(i) = self.members.binary_search(&did) {
+            self.members.remove(i);
+            self.finger = FingerTable::new(self.did()?, SUBRING_FINGER_SIZE);
+            for member in self.members.iter() {
+                self.finger.join(*member);
+            }
+        }
+        Ok(())
+    }
+
+    /// Routes of broadcasting from `did` to `members`. A finger table starting from `did`
+    /// is built from `members`, see [split_by_finger]. The broadcast is started from the
+    /// did of subring, and forwarded from each member to the members it is responsible for.
+    pub fn routes(did: Did, members: &[Did]) -> Vec<(Did, Vec<Did>)> {
+        let mut finger = FingerTable::new(did, SUBRING_FINGER_SIZE);
+        for member in members {
+            finger.join(*member);
+        }
+        split_by_finger(&finger, members)
+    }
+}
+
+/// Split members into ranges by fingers, each finger is responsible for the members
+/// from it to the next finger, clockwise. Returns the fingers paired with the
+/// members they are responsible for, excluding themselves.
+/// The receivers split their ranges again by their own finger tables, so that a
+/// broadcast reaches all members in `O(log N)` hops.
+pub fn split_by_finger(finger: &FingerTable, members: &[Did]) -> Vec<(Did, Vec<Did>)> {
+    let start = finger.did();
+    let mut routes: Vec<(Did, Vec<Did>)> = vec![];
+    for did in finger.list().iter().flatten() {
+        if routes.last().map(|(last, _)| last) != Some(did) {
+            routes.push((*did, vec![]));
+        }
+    }
+
+    let mut members: Vec<Did> = members.iter().filter(|m| **m != start).copied().collect();
+    members.sort_by_key(|m| *m - start);
+    for member in members {
+        match routes
+            .iter_mut()
+            .rev()
+            .find(|(did, _)| *did - start <= member - start)
+        {
+            Some((did, range)) if *did != member => range.push(member),
+            Some(_) => {}
+            // Not covered by finger table, deliver to it directly.
+            None => routes.insert(0, (member, vec![])),
+        }
+    }
+    routes
 }
 
 impl TryFrom<Subring> for VirtualNode {
@@ -42,8 +118,15 @@ impl TryFrom<Subring> for VirtualNode {
         let data = serde_json::to_string(&ring).map_err(|_| Error::SerializeToString)?;
         Ok(Self {
             did: Self::gen_did(&ring.name)?,
-            data: vec![data.into()],
+            data: vec![data.encode()?],
             kind: VNodeType::Subring,
+            owner: None,
+            owner_proof: None,
+            acl: vec![],
+            version: VNodeVersion::default(),
+            expires_at: None,
+            entry_expires_at: vec![],
+            entry_writers: vec![],
         })
     }
 }
@@ -62,3 +145,53 @@ impl TryFrom<VirtualNode> for Subring {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dht::tests::gen_ordered_dids;
+
+    #[test]
+    fn test_subring_join_and_leave() -> Result<()> {
+        let dids = gen_ordered_dids(3);
+        let mut subring = Subring::new("test_membership", dids[0])?;
+        for did in [dids[2], dids[0], dids[1], dids[0]] {
+            subring.join(did);
+        }
+        assert_eq!(subring.members, dids);
+
+        subring.leave(dids[1])?;
+        assert_eq!(subring.members, vec![dids[0], dids[2]]);
+        assert!(!subring.finger.contains(Some(dids[1])));
+
+        let vnode: VirtualNode = subring.clone().try_into()?;
+        assert_eq!(Subring::try_from(vnode)?, subring);
+        Ok(())
+    }
+
+    #[test]
+    fn test_subring_routes_reach_all_members_once() -> Result<()> {
+        let dids = gen_ordered_dids(64);
+        let subring = VirtualNode::gen_did("test_routes")?;
+
+        let mut received = vec![];
+        let mut hops = 0;
+        let mut queue: Vec<(Did, Vec<Did>, usize)> = Subring::routes(subring, &dids)
+            .into_iter()
+            .map(|(next, members)| (next, members, 1))
+            .collect();
+        while let Some((did, members, hop)) = queue.pop() {
+            received.push(did);
+            hops = hops.max(hop);
+            for (next, members) in Subring::routes(did, &members) {
+                assert!(next != did);
+                queue.push((next, members, hop + 1));
+            }
+        }
+
+        received.sort();
+        assert_eq!(received, dids);
+        assert!(hops <= 8, "too many hops: {}", hops);
+        Ok(())
+    }
+}
@@ -73,6 +73,11 @@ impl SuccessorSeq {
             .map_err(|_| Error::FailedToReadSuccessors)
     }
 
+    /// The maximum number of successors.
+    pub fn capacity(&self) -> usize {
+        self.max as usize
+    }
+
     /// Calculate bias of a node on the ring.
     pub fn bias(&self, did: Did) -> BiasId {
         BiasId::new(self.did, did)
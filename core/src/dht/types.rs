@@ -4,6 +4,7 @@ use async_trait::async_trait;
 
 use super::chord::TopoInfo;
 use super::did::Did;
+use super::vnode::VNodeDigest;
 use super::vnode::VNodeOperation;
 use super::vnode::VirtualNode;
 use crate::error::Result;
@@ -68,7 +69,8 @@ pub trait ChordStorage<Action, const REDUNDANT: u16>: Chord<Action> {
     async fn vnode_lookup(&self, vid: Did) -> Result<Action>;
     /// Store `vnode` if it's between current node and the successor of current node,
     /// otherwise find the responsible node and return as Action.
-    async fn vnode_operate(&self, op: VNodeOperation) -> Result<Action>;
+    /// The `writer` is the signer of operation, which is checked by [VirtualNode::operate].
+    async fn vnode_operate(&self, op: VNodeOperation, writer: Did) -> Result<Action>;
 }
 
 /// ChordStorageSync defines the synchronous vnode storage behavior.
@@ -79,6 +81,39 @@ pub trait ChordStorageSync<Action>: Chord<Action> {
     /// `VirtualNode`s that are no longer between current node and `new_successor`,
     /// and sync them to the new successor.
     async fn sync_vnode_with_successor(&self, new_successor: Did) -> Result<Action>;
+
+    /// Store a vnode synced from predecessor on current node, without looking up
+    /// the responsible node of it. The owner of vnode should be proved.
+    async fn vnode_take_over(&self, vnode: VirtualNode) -> Result<Action>;
+}
+
+/// ChordStorageReplica defines how vnodes are replicated to successors and repaired.
+///
+/// The node responsible for a vnode (see [ChordStorage]) keeps copies of it on the first
+/// `r` nodes of its successor list. When the responsible node is lost, its predecessor
+/// takes over the range, and restores the vnodes from the replicas by anti-entropy.
+/// Replicas are not persisted, a restarted node gets them again from anti-entropy, so
+/// a vnode is lost if the responsible node and all replica holders restart together.
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+pub trait ChordStorageReplica<Action>: Chord<Action> {
+    /// The successors that should keep replicas of vnodes stored on current node.
+    fn replica_targets(&self) -> Result<Vec<Did>>;
+    /// Save vnodes received from `origin`. If current node is responsible for a vnode,
+    /// restore it to storage when it's missing there, otherwise keep it as a replica.
+    /// Vnodes are accepted only from the successors, or the nodes responsible for them.
+    async fn vnode_replicate(&self, origin: Did, data: Vec<(Did, VirtualNode)>) -> Result<()>;
+    /// Send digests of stored vnodes to replica targets for comparing.
+    async fn vnode_anti_entropy(&self) -> Result<Action>;
+    /// Compare digests from `origin`, which is responsible for range `(origin, range_end]`,
+    /// with local replicas. Return the dids of missing or stale replicas, and the replicas
+    /// in that range which `origin` does not have.
+    async fn vnode_diff_digests(
+        &self,
+        origin: Did,
+        range_end: Did,
+        digests: &[(Did, VNodeDigest)],
+    ) -> Result<(Vec<Did>, Vec<(Did, VirtualNode)>)>;
 }
 
 /// ChordStorageCache defines the basic API for getting and setting DHT cache storage.
@@ -0,0 +1,145 @@
+#![warn(missing_docs)]
+//! Sanity checks of routing information reported by remote nodes.
+//!
+//! Reports of lookups and topological info are written by remote nodes, so a single lying
+//! node can fill the successor sequence and finger table with dids controlled by attackers.
+//! The checks here only use the positions of dids on the ring, they can't tell whether a
+//! reported node exists, but they reject reports that can't be produced by an honest node.
+//! Offenders should be recorded by [crate::swarm::impls::Judegement::record_invalid_report].
+use dashmap::DashMap;
+use uuid::Uuid;
+
+use crate::consts::DEFAULT_TTL_MS;
+use crate::dht::Did;
+use crate::dht::TopoInfo;
+use crate::utils::get_epoch_ms;
+
+/// Targets of [crate::message::FindSuccessorSend] sent by current node, keyed by tx_id.
+/// A report is checked against the target we asked for, rather than the target written in
+/// the report by the reporter, and a report nobody asked for is dropped.
+#[derive(Debug, Default)]
+pub struct SuccessorQueries {
+    queries: DashMap<Uuid, (Did, u128)>,
+}
+
+impl SuccessorQueries {
+    /// Record the target of a query. Queries not reported in [DEFAULT_TTL_MS] are dropped.
+    pub fn record(&self, tx_id: Uuid, target: Did) {
+        let now = get_epoch_ms();
+        self.queries
+            .retain(|_, (_, at)| *at + DEFAULT_TTL_MS as u128 > now);
+        self.queries.insert(tx_id, (target, now));
+    }
+
+    /// Take the target of a query by the tx_id of its report.
+    pub fn take(&self, tx_id: Uuid) -> Option<Did> {
+        let now = get_epoch_ms();
+        self.queries
+            .remove(&tx_id)
+            .map(|(_, v)| v)
+            .filter(|(_, at)| *at + DEFAULT_TTL_MS as u128 > now)
+            .map(|(target, _)| target)
+    }
+}
+
+/// Check the successor of `target` reported by `reporter`.
+/// An honest reporter answers only when target is between itself and its successor,
+/// so the successor must be closer to target than the reporter.
+/// A node looking up itself reports its own successor, which can't be checked.
+pub fn is_valid_successor_report(target: Did, reporter: Did, successor: Did) -> bool {
+    reporter == target || successor - target <= reporter - target
+}
+
+/// Check the next hops of looking up `target` reported by `reporter`.
+/// Next hops must precede target and be closer to target than the reporter.
+pub fn is_valid_next_hops(target: Did, reporter: Did, nodes: &[Did]) -> bool {
+    let bound = target - reporter;
+    nodes
+        .iter()
+        .all(|n| *n != target && *n != reporter && target - *n < bound)
+}
+
+/// Check the topological info reported by `reporter`.
+/// Its successor list must be in clockwise order from the reporter without duplication,
+/// and neither the list nor the predecessor can be the reporter itself: a node never adds
+/// itself to its [crate::dht::successor::SuccessorSeq] or takes itself as predecessor, so
+/// such a report is forged, e.g. to make the receiver fill its successors with one node.
+pub fn is_valid_topo_info(reporter: Did, info: &TopoInfo) -> bool {
+    let ordered = info
+        .successors
+        .windows(2)
+        .all(|w| w[0].bias(reporter) < w[1].bias(reporter));
+    ordered && !info.successors.contains(&reporter) && info.predecessor != Some(reporter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dht::tests::gen_ordered_dids;
+
+    #[test]
+    fn test_successor_report() {
+        let dids = gen_ordered_dids(4);
+
+        // dids[0] finds dids[2] is the successor of dids[1].
+        assert!(is_valid_successor_report(dids[1], dids[0], dids[2]));
+        assert!(is_valid_successor_report(dids[1], dids[0], dids[1]));
+        // A successor after the reporter is never reported.
+        assert!(!is_valid_successor_report(dids[2], dids[3], dids[0]));
+        // A node looking up itself.
+        assert!(is_valid_successor_report(dids[1], dids[1], dids[3]));
+    }
+
+    #[test]
+    fn test_successor_queries() {
+        let dids = gen_ordered_dids(2);
+        let queries = SuccessorQueries::default();
+        let tx_id = Uuid::new_v4();
+
+        queries.record(tx_id, dids[0]);
+        assert_eq!(queries.take(Uuid::new_v4()), None);
+        assert_eq!(queries.take(tx_id), Some(dids[0]));
+        // A query is reported only once.
+        assert_eq!(queries.take(tx_id), None);
+    }
+
+    #[test]
+    fn test_next_hops() {
+        let dids = gen_ordered_dids(4);
+
+        assert!(is_valid_next_hops(dids[3], dids[0], &[dids[2], dids[1]]));
+        assert!(is_valid_next_hops(dids[3], dids[0], &[]));
+        assert!(!is_valid_next_hops(dids[2], dids[1], &[dids[0]]));
+        assert!(!is_valid_next_hops(dids[2], dids[1], &[dids[2]]));
+        assert!(!is_valid_next_hops(dids[2], dids[1], &[dids[3]]));
+    }
+
+    #[test]
+    fn test_topo_info() {
+        let dids = gen_ordered_dids(4);
+
+        let info = TopoInfo {
+            successors: vec![dids[2], dids[3], dids[0]],
+            predecessor: Some(dids[0]),
+        };
+        assert!(is_valid_topo_info(dids[1], &info));
+
+        let info = TopoInfo {
+            successors: vec![dids[3], dids[2]],
+            predecessor: None,
+        };
+        assert!(!is_valid_topo_info(dids[1], &info));
+
+        let info = TopoInfo {
+            successors: vec![dids[2], dids[2]],
+            predecessor: None,
+        };
+        assert!(!is_valid_topo_info(dids[1], &info));
+
+        let info = TopoInfo {
+            successors: vec![dids[1], dids[2]],
+            predecessor: None,
+        };
+        assert!(!is_valid_topo_info(dids[1], &info));
+    }
+}
@@ -0,0 +1,125 @@
+#![warn(missing_docs)]
+//! Versioning of [VirtualNode](super::vnode::VirtualNode).
+//!
+//! Versions are generated by a hybrid logical clock, which is the physical time in milliseconds
+//! plus a logical counter. The counter keeps versions increasing when the physical clock is
+//! behind the versions observed from other nodes, so causally later writes always win.
+//! Concurrent writes with the same time and counter are ordered by writer did.
+use std::sync::Mutex;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::consts::TS_OFFSET_TOLERANCE_MS;
+use crate::dht::Did;
+use crate::error::Error;
+use crate::error::Result;
+use crate::utils::get_epoch_ms;
+
+/// Version of a [VirtualNode](super::vnode::VirtualNode), compared by timestamp, counter
+/// and writer in order. The default version is the oldest one.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct VNodeVersion {
+    /// Physical time in milliseconds.
+    pub timestamp: u64,
+    /// Logical counter for versions with the same timestamp.
+    pub counter: u32,
+    /// The node who generated the version.
+    pub writer: Option<Did>,
+}
+
+impl VNodeVersion {
+    /// The smallest version that is greater than current version, used when merging a write
+    /// into a newer vnode.
+    pub fn successor(&self, writer: Did) -> Self {
+        Self {
+            timestamp: self.timestamp,
+            counter: self.counter.saturating_add(1),
+            writer: Some(writer),
+        }
+    }
+}
+
+/// Hybrid logical clock of a node.
+#[derive(Debug, Default)]
+pub struct HybridClock {
+    last: Mutex<(u64, u32)>,
+}
+
+impl HybridClock {
+    /// Generate a new version for a local write, which is greater than all versions
+    /// generated or observed before.
+    pub fn tick(&self, writer: Did) -> Result<VNodeVersion> {
+        let now = get_epoch_ms() as u64;
+        let mut last = self.last.lock().map_err(|_| Error::DHTSyncLockError)?;
+        *last = if now > last.0 {
+            (now, 0)
+        } else {
+            (last.0, last.1.saturating_add(1))
+        };
+        Ok(VNodeVersion {
+            timestamp: last.0,
+            counter: last.1,
+            writer: Some(writer),
+        })
+    }
+
+    /// Merge a version received from other node into the clock.
+    /// A version ahead of local time more than [TS_OFFSET_TOLERANCE_MS] is rejected,
+    /// otherwise it would win all later writes.
+    pub fn observe(&self, version: &VNodeVersion) -> Result<()> {
+        if version.timestamp as u128 > get_epoch_ms() + TS_OFFSET_TOLERANCE_MS {
+            return Err(Error::VNodeVersionFromFuture(version.timestamp));
+        }
+        let mut last = self.last.lock().map_err(|_| Error::DHTSyncLockError)?;
+        if (version.timestamp, version.counter) > *last {
+            *last = (version.timestamp, version.counter);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dht::tests::gen_ordered_dids;
+
+    #[test]
+    fn test_clock_tick_after_observed() -> Result<()> {
+        let dids = gen_ordered_dids(2);
+        let clock = HybridClock::default();
+
+        let v1 = clock.tick(dids[1])?;
+        let v2 = clock.tick(dids[0])?;
+        assert!(v2 > v1);
+
+        // A version too far from the future is rejected.
+        let remote = VNodeVersion {
+            timestamp: v2.timestamp + 60_000,
+            counter: 3,
+            writer: Some(dids[1]),
+        };
+        assert!(matches!(
+            clock.observe(&remote),
+            Err(Error::VNodeVersionFromFuture(ts)) if ts == remote.timestamp
+        ));
+
+        // A version from the future, generated by a node with slightly faster clock.
+        let remote = VNodeVersion {
+            timestamp: v2.timestamp + 2_000,
+            counter: 3,
+            writer: Some(dids[1]),
+        };
+        clock.observe(&remote)?;
+        let v3 = clock.tick(dids[0])?;
+        assert!(v3 > remote);
+        assert_eq!(v3.timestamp, remote.timestamp);
+        assert_eq!(v3.counter, 4);
+
+        assert!(VNodeVersion::default() < v1);
+        assert!(remote.successor(dids[0]) > remote);
+        Ok(())
+    }
+}
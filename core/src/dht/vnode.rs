@@ -1,21 +1,37 @@
 #![warn(missing_docs)]
 use std::cmp::max;
 use std::str::FromStr;
+use std::time::Duration;
 
 use num_bigint::BigUint;
 use serde::Deserialize;
 use serde::Serialize;
 
 use super::subring::Subring;
+use crate::consts::MAX_VNODE_TTL_MS;
 use crate::consts::VNODE_DATA_MAX_LEN;
 use crate::dht::Did;
+use crate::dht::VNodeVersion;
+use crate::ecc::keccak256;
 use crate::ecc::HashStr;
 use crate::error::Error;
 use crate::error::Result;
 use crate::message::Encoded;
 use crate::message::Encoder;
 use crate::message::MessagePayload;
-use crate::message::MessageVerificationExt;
+use crate::message::MessageVerification;
+use crate::session::SessionSk;
+use crate::utils::get_epoch_ms;
+
+/// Digest of a [VirtualNode], see [VirtualNode::digest].
+pub type VNodeDigest = [u8; 32];
+
+/// Prefixes of topics reserved for accounts. The topic `{prefix}:{did}` belongs to the account
+/// of `did`, such as the session public key published by it, see [VirtualNode::is_reserved_for].
+pub const RESERVED_TOPIC_PREFIXES: &[&str] = &["session_pubkey"];
+
+/// An entry of data with its expiry and writer.
+type Entry = (Encoded, Option<u64>, Option<Did>);
 
 /// VNode Types
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -43,6 +59,12 @@ pub enum VNodeOperation {
     Touch(VirtualNode),
     /// Join subring.
     JoinSubring(String, Did),
+    /// Leave subring, only the member itself is allowed.
+    LeaveSubring(String, Did),
+    /// Remove a Data type VirtualNode, only its owner is allowed.
+    /// The vnode is replaced by an expired tombstone, which is purged later.
+    /// For a vnode without owner, only the entries written by the writer are removed.
+    Remove(Did),
 }
 
 /// A `VirtualNode` is a piece of data with [VNodeType] and [Did]. You can save it to
@@ -62,6 +84,41 @@ pub struct VirtualNode {
     pub data: Vec<Encoded>,
     /// The type indicates how the data is encoded and how the Did is generated.
     pub kind: VNodeType,
+    /// The owner of a [VNodeType::Data] vnode. Ownership is opt-in, a writer claims it by
+    /// [VirtualNode::owned_by] when the vnode is created, otherwise the vnode has no owner
+    /// and is open to everyone, such as a topic appended by many publishers.
+    /// The storing node only accepts the claim of the signer of [VNodeOperation].
+    #[serde(default)]
+    pub owner: Option<Did>,
+    /// Claim of owner signed by its session, see [VirtualNode::verify_owner].
+    /// It's carried with the vnode, so that other nodes can check the owner of a synced copy.
+    #[serde(default)]
+    pub owner_proof: Option<Box<MessageVerification>>,
+    /// Dids allowed to overwrite or extend the vnode besides owner.
+    /// Only owner can change it by [VNodeOperation::Overwrite].
+    #[serde(default)]
+    pub acl: Vec<Did>,
+    /// Version of the last write, see [VNodeVersion].
+    /// Replicas and caches of the same vnode converge to the one with greatest version.
+    #[serde(default)]
+    pub version: VNodeVersion,
+    /// Timestamp in milliseconds after which the vnode is expired.
+    /// Expired vnodes are treated as not existed, and purged by
+    /// [Stabilization](super::Stabilization). None means never expire.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// Timestamps in milliseconds after which each entry of data is expired, in the same
+    /// order with data. A missing one means the entry never expires.
+    /// Expired entries are dropped when the vnode is extended or touched, and skipped by
+    /// [VirtualNode::live_data].
+    #[serde(default)]
+    pub entry_expires_at: Vec<Option<u64>>,
+    /// Writers of each entry of data, in the same order with data. They are set by the
+    /// storing node with the signer of [VNodeOperation]. A missing one means the writer is
+    /// unknown, such as entries written by older versions.
+    /// Only the writer can refresh, expire or remove its entries of a vnode without owner.
+    #[serde(default)]
+    pub entry_writers: Vec<Option<Did>>,
 }
 
 impl VirtualNode {
@@ -72,6 +129,21 @@ impl VirtualNode {
         tracing::debug!("gen_did: topic: {}, did: {:?}", topic, did);
         did
     }
+
+    /// Check if the vnode is of a topic reserved for the account of `did`, see
+    /// [RESERVED_TOPIC_PREFIXES].
+    pub fn is_reserved_for(&self, did: Did) -> bool {
+        RESERVED_TOPIC_PREFIXES
+            .iter()
+            .any(|prefix| matches!(Self::gen_did(&format!("{}:{}", prefix, did)), Ok(vid) if vid == self.did))
+    }
+
+    /// Generate the did of [VNodeType::RelayMessage] vnode for a destination.
+    /// It's the destination Did plus 1, so that it will be stored on the
+    /// successor of destination when destination is offline.
+    pub fn gen_relay_did(destination: Did) -> Did {
+        (BigUint::from(destination) + BigUint::from(1u16)).into()
+    }
 }
 
 impl VNodeOperation {
@@ -82,9 +154,56 @@ impl VNodeOperation {
             VNodeOperation::Extend(vnode) => vnode.did,
             VNodeOperation::Touch(vnode) => vnode.did,
             VNodeOperation::JoinSubring(name, _) => VirtualNode::gen_did(name)?,
+            VNodeOperation::LeaveSubring(name, _) => VirtualNode::gen_did(name)?,
+            VNodeOperation::Remove(did) => *did,
         })
     }
 
+    /// Extract the version of operation, which is generated by the writer.
+    pub fn version(&self) -> Option<VNodeVersion> {
+        match self {
+            VNodeOperation::Overwrite(vnode) => Some(vnode.version),
+            VNodeOperation::Extend(vnode) => Some(vnode.version),
+            VNodeOperation::Touch(vnode) => Some(vnode.version),
+            VNodeOperation::JoinSubring(..) => None,
+            VNodeOperation::LeaveSubring(..) => None,
+            VNodeOperation::Remove(_) => None,
+        }
+    }
+
+    /// Bound the expiry of vnode in operation to [MAX_VNODE_TTL_MS] from `now`, and apply
+    /// it to each entry of data, since the entries are written together.
+    pub fn with_bounded_expiry(self, now: u64) -> Self {
+        let bound = |mut vnode: VirtualNode| {
+            vnode.expires_at = vnode
+                .expires_at
+                .map(|t| t.min(now.saturating_add(MAX_VNODE_TTL_MS)));
+            vnode.entry_expires_at = vec![vnode.expires_at; vnode.data.len()];
+            vnode
+        };
+        match self {
+            VNodeOperation::Overwrite(vnode) => VNodeOperation::Overwrite(bound(vnode)),
+            VNodeOperation::Extend(vnode) => VNodeOperation::Extend(bound(vnode)),
+            VNodeOperation::Touch(vnode) => VNodeOperation::Touch(bound(vnode)),
+            op => op,
+        }
+    }
+
+    /// Mark each entry of data in operation as written by `writer`.
+    /// The value given by writers is ignored.
+    pub fn written_by(self, writer: Did) -> Self {
+        let mark = |mut vnode: VirtualNode| {
+            vnode.entry_writers = vec![Some(writer); vnode.data.len()];
+            vnode
+        };
+        match self {
+            VNodeOperation::Overwrite(vnode) => VNodeOperation::Overwrite(mark(vnode)),
+            VNodeOperation::Extend(vnode) => VNodeOperation::Extend(mark(vnode)),
+            VNodeOperation::Touch(vnode) => VNodeOperation::Touch(mark(vnode)),
+            op => op,
+        }
+    }
+
     /// Extract the kind of target VirtualNode.
     pub fn kind(&self) -> VNodeType {
         match self {
@@ -92,17 +211,29 @@ impl VNodeOperation {
             VNodeOperation::Extend(vnode) => vnode.kind,
             VNodeOperation::Touch(vnode) => vnode.kind,
             VNodeOperation::JoinSubring(..) => VNodeType::Subring,
+            VNodeOperation::LeaveSubring(..) => VNodeType::Subring,
+            VNodeOperation::Remove(_) => VNodeType::Data,
         }
     }
 
     /// Generate a target VirtualNode when it is not existed.
+    /// There is nothing to leave if the subring is not existed, so it's an error for
+    /// [VNodeOperation::LeaveSubring].
     pub fn gen_default_vnode(self) -> Result<VirtualNode> {
         match self {
             VNodeOperation::JoinSubring(name, did) => Subring::new(&name, did)?.try_into(),
+            VNodeOperation::LeaveSubring(..) => Err(Error::SubringNotFound),
             _ => Ok(VirtualNode {
                 did: self.did()?,
                 data: vec![],
                 kind: self.kind(),
+                owner: None,
+                owner_proof: None,
+                acl: vec![],
+                version: VNodeVersion::default(),
+                expires_at: None,
+                entry_expires_at: vec![],
+                entry_writers: vec![],
             }),
         }
     }
@@ -111,12 +242,19 @@ impl VNodeOperation {
 impl TryFrom<MessagePayload> for VirtualNode {
     type Error = Error;
     fn try_from(msg: MessagePayload) -> Result<Self> {
-        let did = BigUint::from(msg.signer()) + BigUint::from(1u16);
+        let did = Self::gen_relay_did(msg.transaction.destination);
         let data = msg.encode()?;
         Ok(Self {
-            did: did.into(),
+            did,
             data: vec![data],
             kind: VNodeType::RelayMessage,
+            owner: None,
+            owner_proof: None,
+            acl: vec![],
+            version: VNodeVersion::default(),
+            expires_at: None,
+            entry_expires_at: vec![],
+            entry_writers: vec![],
         })
     }
 }
@@ -128,6 +266,13 @@ impl TryFrom<(String, Encoded)> for VirtualNode {
             did: Self::gen_did(&topic)?,
             data: vec![e],
             kind: VNodeType::Data,
+            owner: None,
+            owner_proof: None,
+            acl: vec![],
+            version: VNodeVersion::default(),
+            expires_at: None,
+            entry_expires_at: vec![],
+            entry_writers: vec![],
         })
     }
 }
@@ -157,6 +302,43 @@ impl VirtualNode {
             .collect()
     }
 
+    /// Keccak256 digest of the whole vnode, used to compare replicas.
+    pub fn digest(&self) -> Result<VNodeDigest> {
+        let bytes = bincode::serialize(self).map_err(Error::BincodeSerialize)?;
+        Ok(keccak256(&bytes))
+    }
+
+    /// Pick the winner between two copies of a vnode, which is the one with greater version.
+    /// Copies with the same version are compared by digest, so that all nodes pick the same one.
+    pub fn latest(self, other: Self) -> Result<Self> {
+        if (other.version, other.digest()?) > (self.version, self.digest()?) {
+            Ok(other)
+        } else {
+            Ok(self)
+        }
+    }
+
+    /// Merge a replica of current vnode received from other node.
+    /// The writer of a replica is not proved, so a replica of [VNodeType::Data] vnode never
+    /// changes the owner, ACL or owner proof of current vnode. A replica of different owner
+    /// is ignored, unless the vnode is reserved for its proved owner, see
+    /// [VirtualNode::is_reserved_for]. Otherwise the latest copy is kept, see
+    /// [VirtualNode::latest].
+    pub fn merge_replica(self, other: Self) -> Result<Self> {
+        if self.kind != VNodeType::Data || other.kind != VNodeType::Data {
+            return self.latest(other);
+        }
+        if self.owner != other.owner {
+            let reserved = matches!(other.owner, Some(owner) if self.is_reserved_for(owner))
+                && other.verify_owner().is_ok();
+            return Ok(if reserved { other } else { self });
+        }
+        let mut latest = self.clone().latest(other)?;
+        latest.owner_proof = self.owner_proof;
+        latest.acl = self.acl;
+        Ok(latest)
+    }
+
     /// Clone and setup with new DID
     pub fn clone_with_did(&self, did: Did) -> Self {
         let mut vnode = self.clone();
@@ -164,21 +346,177 @@ impl VirtualNode {
         vnode
     }
 
-    /// The entry point of [VNodeOperation].
+    /// Set the vnode to be expired after `ttl` from now.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.expires_at = Some(get_epoch_ms() as u64 + ttl.as_millis() as u64);
+        self.entry_expires_at = vec![self.expires_at; self.data.len()];
+        self
+    }
+
+    /// Check if the vnode is expired at `now`, which is a timestamp in milliseconds.
+    pub fn is_expired(&self, now: u64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+    }
+
+    /// The message signed by owner to claim the vnode of `did`.
+    fn owner_claim(did: Did) -> String {
+        format!("owner:{}", did)
+    }
+
+    /// Claim the ownership of vnode for the account of `session_sk`, and allow the dids
+    /// in `acl` to write it besides the owner.
+    /// The claim is only accepted when the vnode is created by the owner, see [VirtualNode::operate].
+    pub fn owned_by(mut self, session_sk: &SessionSk, acl: Vec<Did>) -> Result<Self> {
+        let claim = Self::owner_claim(self.did);
+        self.owner = Some(session_sk.account_did());
+        self.owner_proof = Some(Box::new(MessageVerification::new(
+            claim.as_bytes(),
+            session_sk,
+        )?));
+        self.acl = acl;
+        Ok(self)
+    }
+
+    /// Check the owner of vnode is proved by [VirtualNode::owner_proof].
+    /// A vnode without owner needs no proof.
+    pub fn verify_owner(&self) -> Result<()> {
+        let Some(owner) = self.owner else {
+            return Ok(());
+        };
+        let claim = Self::owner_claim(self.did);
+        match &self.owner_proof {
+            Some(proof)
+                if proof.session.account_did() == owner && proof.verify(claim.as_bytes()) =>
+            {
+                Ok(())
+            }
+            _ => Err(Error::VNodeOwnerUnverified(owner)),
+        }
+    }
+
+    /// Check if `writer` is the owner of vnode or in its ACL.
+    /// A vnode without owner is writable by anyone.
+    pub fn is_writable_by(&self, writer: Did) -> bool {
+        match self.owner {
+            Some(owner) => owner == writer || self.acl.contains(&writer),
+            None => true,
+        }
+    }
+
+    /// The entry point of [VNodeOperation], `writer` is the signer of operation.
     /// Will dispatch to different operation handlers according to the variant.
-    pub fn operate(&self, op: VNodeOperation) -> Result<Self> {
-        match op {
+    ///
+    /// A [VNodeType::Data] vnode is owned only if it's created by [VNodeOperation::Overwrite]
+    /// or [VNodeOperation::Extend] of a vnode claimed by writer, see [VirtualNode::owned_by].
+    /// Claims of other writers, or on a vnode already existed, are ignored, except the claim
+    /// of a vnode reserved for writer, see [VirtualNode::is_reserved_for], which replaces the
+    /// vnode written by anyone else.
+    /// An owned vnode rejects [VNodeOperation::Overwrite], [VNodeOperation::Extend] and
+    /// [VNodeOperation::Touch] if writer is not allowed by [VirtualNode::is_writable_by],
+    /// only owner can change the ACL or remove it.
+    ///
+    /// A vnode without owner is open to everyone, such as a topic appended by many publishers
+    /// or services registered by different nodes, but each entry belongs to its writer, see
+    /// [VirtualNode::entry_writers]. [VNodeOperation::Overwrite] is rejected if it drops the
+    /// entries of others. [VNodeOperation::Touch] only refreshes the entries of writer, and
+    /// [VNodeOperation::Remove] only removes them.
+    ///
+    /// Concurrent writes are resolved by [VNodeVersion], which should be generated by writer.
+    /// [VNodeOperation::Overwrite] is last-writer-wins, an overwrite older than current vnode
+    /// is rejected by [Error::VNodeVersionStale]. Other operations are merged into current
+    /// vnode, and the result is always newer than current vnode.
+    pub fn operate(&self, op: VNodeOperation, writer: Did) -> Result<Self> {
+        let op = op.written_by(writer);
+
+        // The claim of writer, with the ACL given along with it.
+        let claim = match &op {
+            VNodeOperation::Overwrite(other) | VNodeOperation::Extend(other)
+                if other.owner == Some(writer) && other.verify_owner().is_ok() =>
+            {
+                Some((other.owner_proof.clone(), other.acl.clone()))
+            }
+            _ => None,
+        };
+        let is_created = self.owner.is_none() && self.data.is_empty();
+        if self.kind == VNodeType::Data
+            && claim.is_some()
+            && !is_created
+            && self.owner != Some(writer)
+            && self.is_reserved_for(writer)
+        {
+            tracing::warn!("Take back vnode {} reserved for {}", self.did, writer);
+            let mut vnode = op.clone().gen_default_vnode()?.operate(op, writer)?;
+            vnode.version = max(vnode.version, self.version.successor(writer));
+            return Ok(vnode);
+        }
+
+        if self.kind == VNodeType::Data && !self.is_writable_by(writer) {
+            return Err(Error::VNodeNotWritable(writer));
+        }
+        if matches!(op, VNodeOperation::Remove(_))
+            && matches!(self.owner, Some(owner) if owner != writer)
+        {
+            return Err(Error::VNodeNotWritable(writer));
+        }
+        if self.kind == VNodeType::Data
+            && self.owner.is_none()
+            && matches!(op, VNodeOperation::Overwrite(_))
+            && self
+                .live_entries(get_epoch_ms() as u64)
+                .iter()
+                .any(|(_, _, w)| matches!(w, Some(w) if *w != writer))
+        {
+            return Err(Error::VNodeNotWritable(writer));
+        }
+        if matches!(op, VNodeOperation::LeaveSubring(_, did) if did != writer) {
+            return Err(Error::VNodeNotWritable(writer));
+        }
+
+        if let Some(version) = op.version() {
+            if matches!(version.writer, Some(did) if did != writer) {
+                return Err(Error::VNodeVersionWriterMismatch(writer));
+            }
+        }
+        if let VNodeOperation::Overwrite(other) = &op {
+            if other.version < self.version {
+                tracing::debug!("Reject stale overwrite of vnode {}", self.did);
+                return Err(Error::VNodeVersionStale);
+            }
+        }
+        let is_merge = !matches!(op, VNodeOperation::Overwrite(_));
+        let acl = match (&op, self.owner) {
+            (VNodeOperation::Overwrite(other), Some(owner)) if owner == writer => other.acl.clone(),
+            _ => self.acl.clone(),
+        };
+
+        let mut vnode = match op {
             VNodeOperation::Overwrite(vnode) => self.overwrite(vnode),
             VNodeOperation::Extend(vnode) => self.extend(vnode),
             VNodeOperation::Touch(vnode) => self.touch(vnode),
             VNodeOperation::JoinSubring(_, did) => self.join_subring(did),
+            VNodeOperation::LeaveSubring(_, did) => self.leave_subring(did),
+            VNodeOperation::Remove(_) if self.owner == Some(writer) => self.remove(),
+            VNodeOperation::Remove(_) => self.remove_entries(writer),
+        }?;
+
+        if vnode.kind == VNodeType::Data {
+            (vnode.owner, vnode.owner_proof, vnode.acl) = match claim {
+                Some((proof, acl)) if is_created => (Some(writer), proof, acl),
+                // Owner renews the proof, such as the session of last one is expired.
+                Some((proof, _)) if self.owner == Some(writer) => (self.owner, proof, acl),
+                _ => (self.owner, self.owner_proof.clone(), acl),
+            };
         }
+        if is_merge && vnode.version <= self.version {
+            vnode.version = self.version.successor(writer);
+        }
+        Ok(vnode)
     }
 
     /// Overwrite current data with new data.
     /// The handler of [VNodeOperation::Overwrite].
     pub fn overwrite(&self, other: Self) -> Result<Self> {
-        if self.kind != VNodeType::Data {
+        if !matches!(self.kind, VNodeType::Data | VNodeType::RelayMessage) {
             return Err(Error::VNodeNotOverwritable);
         }
         if self.kind != other.kind {
@@ -190,10 +528,11 @@ impl VirtualNode {
         Ok(other)
     }
 
-    /// This method is used to extend data to a Data type VirtualNode.
+    /// This method is used to extend data to a Data or RelayMessage type VirtualNode.
+    /// Expired entries are dropped.
     /// The handler of [VNodeOperation::Extend].
     pub fn extend(&self, other: Self) -> Result<Self> {
-        if self.kind != VNodeType::Data {
+        if !matches!(self.kind, VNodeType::Data | VNodeType::RelayMessage) {
             return Err(Error::VNodeNotAppendable);
         }
         if self.kind != other.kind {
@@ -203,23 +542,15 @@ impl VirtualNode {
             return Err(Error::VNodeDidNotEqual);
         }
 
-        let trim_num = max(
-            0,
-            (self.data.len() + other.data.len()) as i64 - VNODE_DATA_MAX_LEN as i64,
-        ) as usize;
-
-        let mut data = self.data.iter().skip(trim_num).cloned().collect::<Vec<_>>();
-        data.extend_from_slice(&other.data);
-
-        Ok(Self {
-            did: self.did,
-            data,
-            kind: self.kind,
-        })
+        let now = get_epoch_ms() as u64;
+        let mut entries = self.live_entries(now);
+        entries.extend(other.live_entries(now));
+        Ok(self.with_entries(entries, other.version))
     }
 
     /// This method is used to extend data to a Data type VirtualNode uniquely.
-    /// If any element is already existed, move it to the end of the data vector.
+    /// If any element is already written by the same writer, move it to the end of the
+    /// data vector, and its expiry is refreshed by the new one. Expired entries are dropped.
     /// The handler of [VNodeOperation::Touch].
     pub fn touch(&self, other: Self) -> Result<Self> {
         if self.kind != VNodeType::Data {
@@ -232,31 +563,128 @@ impl VirtualNode {
             return Err(Error::VNodeDidNotEqual);
         }
 
-        let remains = self
-            .data
-            .iter()
-            .filter(|e| !other.data.contains(e))
+        let now = get_epoch_ms() as u64;
+        let touched = other.entries().collect::<Vec<_>>();
+        let mut entries = self
+            .live_entries(now)
+            .into_iter()
+            .filter(|(e, _, w)| {
+                !touched
+                    .iter()
+                    .any(|(te, _, tw)| te == e && (w.is_none() || w == tw))
+            })
             .collect::<Vec<_>>();
+        entries.extend(other.live_entries(now));
+        Ok(self.with_entries(entries, other.version))
+    }
+
+    /// Entries of data with their expiry and writer, see [VirtualNode::entry_expires_at]
+    /// and [VirtualNode::entry_writers].
+    fn entries(&self) -> impl Iterator<Item = Entry> + '_ {
+        self.data.iter().enumerate().map(|(i, e)| {
+            (
+                e.clone(),
+                self.entry_expires_at.get(i).copied().flatten(),
+                self.entry_writers.get(i).copied().flatten(),
+            )
+        })
+    }
 
-        let trim_num = max(
-            0,
-            (remains.len() + other.data.len()) as i64 - VNODE_DATA_MAX_LEN as i64,
-        ) as usize;
+    fn live_entries(&self, now: u64) -> Vec<Entry> {
+        self.entries()
+            .filter(|(_, expires_at, _)| !matches!(expires_at, Some(t) if *t <= now))
+            .collect()
+    }
 
-        let mut data = remains
+    /// Data entries which are not expired at `now`.
+    pub fn live_data(&self, now: u64) -> Vec<Encoded> {
+        self.live_entries(now)
             .into_iter()
-            .skip(trim_num)
-            .cloned()
-            .collect::<Vec<_>>();
-        data.extend_from_slice(&other.data);
+            .map(|(e, _, _)| e)
+            .collect()
+    }
 
-        Ok(Self {
+    /// Drop the entries in `data`, such as relay messages which are delivered.
+    pub fn without_data(&self, data: &[Encoded]) -> Self {
+        let entries = self.entries().filter(|(e, _, _)| !data.contains(e));
+        self.with_entries(entries.collect(), self.version)
+    }
+
+    /// Replace data with entries, the oldest entries are trimmed if exceeding
+    /// [VNODE_DATA_MAX_LEN]. The vnode expires with its last entry.
+    fn with_entries(&self, entries: Vec<Entry>, version: VNodeVersion) -> Self {
+        let trim_num = entries.len().saturating_sub(VNODE_DATA_MAX_LEN);
+        let mut data = vec![];
+        let mut entry_expires_at = vec![];
+        let mut entry_writers = vec![];
+        for (e, expires_at, writer) in entries.into_iter().skip(trim_num) {
+            data.push(e);
+            entry_expires_at.push(expires_at);
+            entry_writers.push(writer);
+        }
+        let expires_at = entry_expires_at
+            .iter()
+            .try_fold(0, |last, expires_at| expires_at.map(|t| max(last, t)))
+            .filter(|_| !data.is_empty());
+
+        Self {
             did: self.did,
             data,
             kind: self.kind,
+            owner: self.owner,
+            owner_proof: self.owner_proof.clone(),
+            acl: self.acl.clone(),
+            version: max(self.version, version),
+            expires_at,
+            entry_expires_at,
+            entry_writers,
+        }
+    }
+
+    /// Replace a Data type VirtualNode with an expired tombstone.
+    /// The tombstone keeps the version, so that stale writes will not revive the vnode
+    /// before it's purged, see [MAX_VNODE_TTL_MS].
+    /// The handler of [VNodeOperation::Remove].
+    pub fn remove(&self) -> Result<Self> {
+        if self.kind != VNodeType::Data {
+            return Err(Error::VNodeNotRemovable);
+        }
+        Ok(Self {
+            did: self.did,
+            data: vec![],
+            kind: self.kind,
+            owner: self.owner,
+            owner_proof: self.owner_proof.clone(),
+            acl: self.acl.clone(),
+            version: self.version,
+            expires_at: Some(get_epoch_ms() as u64),
+            entry_expires_at: vec![],
+            entry_writers: vec![],
         })
     }
 
+    /// Remove the entries written by `writer` from a Data type VirtualNode. The vnode is
+    /// replaced by a tombstone if no entry is left, see [VirtualNode::remove].
+    /// The handler of [VNodeOperation::Remove] for a vnode without owner.
+    pub fn remove_entries(&self, writer: Did) -> Result<Self> {
+        if self.kind != VNodeType::Data {
+            return Err(Error::VNodeNotRemovable);
+        }
+        let entries = self.live_entries(get_epoch_ms() as u64);
+        let kept = entries
+            .iter()
+            .filter(|(_, _, w)| *w != Some(writer))
+            .cloned()
+            .collect::<Vec<_>>();
+        if kept.len() == entries.len() {
+            return Err(Error::VNodeNotWritable(writer));
+        }
+        if kept.is_empty() {
+            return self.remove();
+        }
+        Ok(self.with_entries(kept, self.version))
+    }
+
     /// This method is used to join a subring.
     /// The handler of [VNodeOperation::JoinSubring].
     pub fn join_subring(&self, did: Did) -> Result<Self> {
@@ -265,7 +693,19 @@ impl VirtualNode {
         }
 
         let mut subring: Subring = self.clone().try_into()?;
-        subring.finger.join(did);
+        subring.join(did);
+        subring.try_into()
+    }
+
+    /// This method is used to leave a subring.
+    /// The handler of [VNodeOperation::LeaveSubring].
+    pub fn leave_subring(&self, did: Did) -> Result<Self> {
+        if self.kind != VNodeType::Subring {
+            return Err(Error::VNodeNotJoinable);
+        }
+
+        let mut subring: Subring = self.clone().try_into()?;
+        subring.leave(did)?;
         subring.try_into()
     }
 }
@@ -273,6 +713,7 @@ impl VirtualNode {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ecc::SecretKey;
 
     #[test]
     fn test_vnode_extend_over_max_len() {
@@ -315,4 +756,391 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_vnode_operate_with_acl() {
+        let owner_sk = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let owner = owner_sk.account_did();
+        let writer: Did = SecretKey::random().address().into();
+        let stranger: Did = SecretKey::random().address().into();
+
+        let topic = "test_acl".to_string();
+        let vnode: VirtualNode = topic.clone().try_into().unwrap();
+        let default = VNodeOperation::Overwrite(vnode.clone())
+            .gen_default_vnode()
+            .unwrap();
+
+        // A vnode without claim has no owner, and is open to everyone.
+        let unowned = default
+            .operate(VNodeOperation::Overwrite(vnode.clone()), owner)
+            .unwrap();
+        assert_eq!(unowned.owner, None);
+        let other: VirtualNode = (topic, "writer".to_string()).try_into().unwrap();
+        let extended = unowned
+            .operate(VNodeOperation::Extend(other.clone()), writer)
+            .unwrap();
+        assert_eq!(extended.data.len(), 2);
+
+        // The claim is ignored if the vnode is existed or it's given by others.
+        let claimed = vnode.owned_by(&owner_sk, vec![]).unwrap();
+        assert_eq!(
+            unowned
+                .operate(VNodeOperation::Overwrite(claimed.clone()), owner)
+                .unwrap()
+                .owner,
+            None
+        );
+        assert_eq!(
+            default
+                .operate(VNodeOperation::Overwrite(claimed.clone()), stranger)
+                .unwrap()
+                .owner,
+            None
+        );
+
+        let vnode = default
+            .operate(VNodeOperation::Overwrite(claimed), owner)
+            .unwrap();
+        assert_eq!(vnode.owner, Some(owner));
+        assert!(vnode.verify_owner().is_ok());
+
+        // Only owner can write before it's in ACL, touching cannot take the vnode either.
+        for op in [
+            VNodeOperation::Overwrite(other.clone()),
+            VNodeOperation::Extend(other.clone()),
+            VNodeOperation::Touch(other.clone()),
+            VNodeOperation::Remove(other.did),
+        ] {
+            assert!(matches!(
+                vnode.operate(op, writer),
+                Err(Error::VNodeNotWritable(did)) if did == writer
+            ));
+        }
+
+        // Owner grants writer.
+        let mut granted = vnode.clone();
+        granted.acl = vec![writer];
+        let vnode = vnode
+            .operate(VNodeOperation::Overwrite(granted), owner)
+            .unwrap();
+        assert_eq!(vnode.acl, vec![writer]);
+
+        // Writer in ACL can write data, but cannot change owner or ACL.
+        let mut other = other;
+        other.owner = Some(writer);
+        other.acl = vec![stranger];
+        let vnode = vnode
+            .operate(VNodeOperation::Overwrite(other.clone()), writer)
+            .unwrap();
+        assert_eq!(vnode.data, other.data);
+        assert_eq!(vnode.owner, Some(owner));
+        assert_eq!(vnode.acl, vec![writer]);
+        assert!(vnode.verify_owner().is_ok());
+        assert!(vnode
+            .operate(VNodeOperation::Extend(other), stranger)
+            .is_err());
+
+        // A forged owner is not proved.
+        let mut forged = vnode;
+        forged.owner = Some(stranger);
+        assert!(matches!(
+            forged.verify_owner(),
+            Err(Error::VNodeOwnerUnverified(did)) if did == stranger
+        ));
+    }
+
+    #[test]
+    fn test_vnode_merge_replica() {
+        let owner_sk = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let owner = owner_sk.account_did();
+        let thief: Did = SecretKey::random().address().into();
+        let clock = crate::dht::HybridClock::default();
+
+        let topic = "test_merge_replica".to_string();
+        let mut vnode: VirtualNode = (topic.clone(), "owned".to_string()).try_into().unwrap();
+        vnode = vnode.owned_by(&owner_sk, vec![]).unwrap();
+        vnode.version = clock.tick(owner).unwrap();
+
+        // A newer copy of the owner is merged, keeping the ACL and proof.
+        let mut updated: VirtualNode = (topic.clone(), "updated".to_string()).try_into().unwrap();
+        updated.owner = Some(owner);
+        updated.acl = vec![thief];
+        updated.version = clock.tick(owner).unwrap();
+        let merged = vnode.merge_replica(updated.clone()).unwrap();
+        assert_eq!(merged.data, updated.data);
+        assert_eq!(merged.acl, vec![]);
+        assert!(merged.verify_owner().is_ok());
+
+        // A newer copy cannot take over or drop the ownership.
+        for owner in [Some(thief), None] {
+            let mut hijacked: VirtualNode =
+                (topic.clone(), "hijacked".to_string()).try_into().unwrap();
+            hijacked.owner = owner;
+            hijacked.version = clock.tick(thief).unwrap();
+            assert_eq!(merged.clone().merge_replica(hijacked).unwrap(), merged);
+        }
+    }
+
+    #[test]
+    fn test_vnode_operate_with_version() {
+        let dids = crate::dht::tests::gen_ordered_dids(2);
+        let (a, b) = (dids[0], dids[1]);
+        let clock = crate::dht::HybridClock::default();
+
+        let topic = "test_version".to_string();
+        let mut early: VirtualNode = (topic.clone(), "early".to_string()).try_into().unwrap();
+        early.version = clock.tick(a).unwrap();
+        let mut late: VirtualNode = (topic, "late".to_string()).try_into().unwrap();
+        late.version = clock.tick(a).unwrap();
+        let default = VNodeOperation::Overwrite(early.clone())
+            .gen_default_vnode()
+            .unwrap();
+
+        // Concurrent overwrites converge whatever the order is, the stale one is rejected.
+        let v1 = default
+            .operate(VNodeOperation::Overwrite(early.clone()), a)
+            .unwrap()
+            .operate(VNodeOperation::Overwrite(late.clone()), a)
+            .unwrap();
+        let v2 = default
+            .operate(VNodeOperation::Overwrite(late.clone()), a)
+            .unwrap();
+        assert!(matches!(
+            v2.operate(VNodeOperation::Overwrite(early.clone()), a),
+            Err(Error::VNodeVersionStale)
+        ));
+        assert_eq!(v1, v2);
+
+        // The version should be generated by the writer.
+        assert!(matches!(
+            default.operate(VNodeOperation::Overwrite(late.clone()), b),
+            Err(Error::VNodeVersionWriterMismatch(w)) if w == b
+        ));
+        assert_eq!(v1.data, late.data);
+        assert_eq!(v1.version, late.version);
+
+        // Stale extend is merged, and the result is newer than both.
+        let extended = v1.operate(VNodeOperation::Extend(early), a).unwrap();
+        assert_eq!(extended.data.len(), 2);
+        assert!(extended.version > v1.version);
+        assert_eq!(extended.clone().latest(v1.clone()).unwrap(), extended);
+        assert_eq!(v1.clone().latest(extended.clone()).unwrap(), extended);
+
+        // Copies with the same version are resolved by digest.
+        let mut other = v1.clone();
+        other.data = late.data.iter().chain(late.data.iter()).cloned().collect();
+        assert_eq!(
+            v1.clone().latest(other.clone()).unwrap(),
+            other.latest(v1).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_vnode_expiry_and_remove() {
+        let writer_sk = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let writer = writer_sk.account_did();
+        let topic = "test_expiry".to_string();
+        let vnode: VirtualNode = topic.clone().try_into().unwrap();
+        assert!(!vnode.is_expired(u64::MAX));
+        let now = get_epoch_ms() as u64;
+        let with_expiry = |data: &str, expires_at: Option<u64>| {
+            let mut vnode: VirtualNode = (topic.clone(), data.to_string()).try_into().unwrap();
+            vnode.expires_at = expires_at;
+            vnode.entry_expires_at = vec![expires_at];
+            vnode
+        };
+
+        // Each entry expires on its own, the vnode expires with the last one.
+        let vnode = VNodeOperation::Extend(with_expiry("a", Some(now + 100)))
+            .gen_default_vnode()
+            .unwrap()
+            .operate(
+                VNodeOperation::Extend(
+                    with_expiry("a", Some(now + 100))
+                        .owned_by(&writer_sk, vec![])
+                        .unwrap(),
+                ),
+                writer,
+            )
+            .unwrap()
+            .operate(
+                VNodeOperation::Extend(with_expiry("b", Some(now + 200))),
+                writer,
+            )
+            .unwrap();
+        assert_eq!(vnode.expires_at, Some(now + 200));
+        assert_eq!(vnode.live_data(now + 150), vec!["b"
+            .to_string()
+            .encode()
+            .unwrap()]);
+        assert!(vnode.is_expired(now + 200));
+
+        // Touching an entry refreshes its expiry.
+        let vnode = vnode
+            .operate(
+                VNodeOperation::Touch(with_expiry("a", Some(now + 300))),
+                writer,
+            )
+            .unwrap();
+        assert_eq!(vnode.live_data(now + 250), vec!["a"
+            .to_string()
+            .encode()
+            .unwrap()]);
+        assert_eq!(vnode.expires_at, Some(now + 300));
+
+        // An entry never expires keeps the vnode alive.
+        let vnode = vnode
+            .operate(VNodeOperation::Extend(with_expiry("c", None)), writer)
+            .unwrap();
+        assert_eq!(vnode.expires_at, None);
+        assert_eq!(vnode.live_data(u64::MAX), vec!["c"
+            .to_string()
+            .encode()
+            .unwrap()]);
+
+        // The expiry is bounded by max ttl.
+        let far = VNodeOperation::Touch(with_expiry("d", Some(u64::MAX))).with_bounded_expiry(now);
+        assert!(matches!(
+            far,
+            VNodeOperation::Touch(v) if v.expires_at == Some(now + MAX_VNODE_TTL_MS)
+                && v.entry_expires_at == vec![Some(now + MAX_VNODE_TTL_MS)]
+        ));
+
+        // Removed vnode is an expired tombstone with newer version.
+        let removed = vnode
+            .operate(VNodeOperation::Remove(vnode.did), writer)
+            .unwrap();
+        assert!(removed.data.is_empty());
+        assert!(removed.is_expired(get_epoch_ms() as u64));
+        assert!(removed.version > vnode.version);
+
+        let subring: VirtualNode = Subring::new("test_expiry", writer)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert!(matches!(subring.remove(), Err(Error::VNodeNotRemovable)));
+    }
+
+    #[test]
+    fn test_vnode_reserved_for_owner() {
+        let owner_sk = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let owner = owner_sk.account_did();
+        let squatter_sk = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let squatter = squatter_sk.account_did();
+        let topic = format!("session_pubkey:{}", owner);
+        let entry = |data: &str| -> VirtualNode {
+            (topic.clone(), data.to_string()).try_into().unwrap()
+        };
+        assert!(entry("key").is_reserved_for(owner));
+        assert!(!entry("key").is_reserved_for(squatter));
+        let default = VNodeOperation::Overwrite(entry("key"))
+            .gen_default_vnode()
+            .unwrap();
+
+        // Others occupied the vnode before the owner, by writing it or claiming it.
+        let extended = default
+            .operate(VNodeOperation::Extend(entry("junk")), squatter)
+            .unwrap();
+        let claimed = default
+            .operate(
+                VNodeOperation::Overwrite(entry("junk").owned_by(&squatter_sk, vec![]).unwrap()),
+                squatter,
+            )
+            .unwrap();
+        assert_eq!(claimed.owner, Some(squatter));
+
+        // The owner takes it back by a claim, a write without claim is not enough.
+        let published = entry("key").owned_by(&owner_sk, vec![]).unwrap();
+        for occupied in [extended, claimed.clone()] {
+            let vnode = occupied
+                .operate(VNodeOperation::Overwrite(published.clone()), owner)
+                .unwrap();
+            assert_eq!(vnode.owner, Some(owner));
+            assert_eq!(vnode.data, published.data);
+            assert!(vnode.version > occupied.version);
+            assert!(vnode
+                .operate(VNodeOperation::Extend(entry("junk")), squatter)
+                .is_err());
+        }
+        assert!(claimed
+            .operate(VNodeOperation::Overwrite(entry("key")), owner)
+            .is_err());
+
+        // Replicas of the proved owner replace the occupied one.
+        let taken = default
+            .operate(VNodeOperation::Overwrite(published), owner)
+            .unwrap();
+        assert_eq!(claimed.clone().merge_replica(taken.clone()).unwrap(), taken);
+        assert_eq!(taken.clone().merge_replica(claimed).unwrap(), taken);
+    }
+
+    #[test]
+    fn test_vnode_entries_of_writers() {
+        let a: Did = SecretKey::random().address().into();
+        let b: Did = SecretKey::random().address().into();
+        let topic = "test_writers".to_string();
+        let now = get_epoch_ms() as u64;
+        let entry = |data: &str, ttl: u64| {
+            let vnode: VirtualNode = (topic.clone(), data.to_string()).try_into().unwrap();
+            vnode.with_ttl(Duration::from_millis(ttl))
+        };
+        let encoded = |data: &str| data.to_string().encode().unwrap();
+
+        // Both writers register the same service.
+        let vnode = VNodeOperation::Touch(entry("a", 60_000))
+            .gen_default_vnode()
+            .unwrap()
+            .operate(VNodeOperation::Touch(entry("a", 60_000)), a)
+            .unwrap()
+            .operate(VNodeOperation::Touch(entry("b", 60_000)), b)
+            .unwrap()
+            .operate(VNodeOperation::Touch(entry("a", 60_000)), b)
+            .unwrap();
+        assert_eq!(vnode.owner, None);
+        assert_eq!(vnode.entry_writers, vec![Some(a), Some(b), Some(b)]);
+        assert_eq!(vnode.live_data(now), vec![
+            encoded("a"),
+            encoded("b"),
+            encoded("a")
+        ]);
+
+        // Expiring an entry only affects the one of writer.
+        let expired = vnode
+            .operate(VNodeOperation::Touch(entry("a", 0)), b)
+            .unwrap();
+        assert_eq!(expired.live_data(now), vec![encoded("a"), encoded("b")]);
+        assert_eq!(expired.entry_writers, vec![Some(a), Some(b)]);
+
+        // The writer given by operation is ignored.
+        let mut forged = entry("a", 0);
+        forged.entry_writers = vec![Some(a)];
+        assert_eq!(
+            expired
+                .operate(VNodeOperation::Touch(forged), b)
+                .unwrap()
+                .live_data(now),
+            expired.live_data(now)
+        );
+
+        // Overwriting cannot drop the entries of others.
+        assert!(matches!(
+            expired.operate(VNodeOperation::Overwrite(entry("c", 60_000)), b),
+            Err(Error::VNodeNotWritable(w)) if w == b
+        ));
+
+        // Removal only drops the entries of writer, the vnode is removed with the last one.
+        let removed = expired
+            .operate(VNodeOperation::Remove(expired.did), b)
+            .unwrap();
+        assert_eq!(removed.live_data(now), vec![encoded("a")]);
+        assert!(matches!(
+            removed.operate(VNodeOperation::Remove(removed.did), b),
+            Err(Error::VNodeNotWritable(w)) if w == b
+        ));
+        let removed = removed
+            .operate(VNodeOperation::Remove(removed.did), a)
+            .unwrap();
+        assert!(removed.data.is_empty());
+        assert!(removed.is_expired(get_epoch_ms() as u64));
+    }
 }
@@ -40,6 +40,30 @@ pub enum Error {
     #[error("The type of VirtualNode is not allowed to be joined as a subring")]
     VNodeNotJoinable,
 
+    #[error("Subring is not found")]
+    SubringNotFound,
+
+    #[error("VirtualNode is not allowed to be written by {0}")]
+    VNodeNotWritable(crate::dht::Did),
+
+    #[error("Owner of VirtualNode is not proved: {0}")]
+    VNodeOwnerUnverified(crate::dht::Did),
+
+    #[error("The type of VirtualNode is not allowed to be removed")]
+    VNodeNotRemovable,
+
+    #[error("VirtualNode handed off by {0} is not accepted")]
+    VNodeHandoffRefused(crate::dht::Did),
+
+    #[error("Version of VirtualNode is too far ahead of local time: {0}")]
+    VNodeVersionFromFuture(u64),
+
+    #[error("Version of VirtualNode is not generated by writer {0}")]
+    VNodeVersionWriterMismatch(crate::dht::Did),
+
+    #[error("Version of VirtualNode is older than the stored one")]
+    VNodeVersionStale,
+
     #[error("Encode a byte vector into a base58-check string, adds 4 bytes checksum")]
     Encode,
 
@@ -338,6 +362,11 @@ pub enum Error {
     #[error("Message decryption failed")]
     MessageDecryptionFailed(ecies::SecpError),
 
+    #[error(
+        "Session public key of {0} is unknown, it's neither learned from messages nor published"
+    )]
+    SessionPubkeyNotFound(crate::dht::Did),
+
     #[error("Message has {0} bytes which is too large")]
     MessageTooLarge(usize),
 
@@ -365,6 +394,30 @@ pub enum Error {
 
     #[error("External Javascript error: {0}")]
     JsError(String),
+
+    #[error("Request {0} timeout")]
+    RequestTimeout(uuid::Uuid),
+
+    #[error("Request {0} is cancelled")]
+    RequestCancelled(uuid::Uuid),
+
+    #[error("Failed to lookup {0} on the ring")]
+    LookupFailed(crate::dht::Did),
+
+    #[error("Disjoint lookups of {0} disagree on the successor")]
+    LookupDisagreed(crate::dht::Did),
+
+    #[error("Peer {0} does not support {1}")]
+    UnsupportedByPeer(crate::dht::Did, String),
+
+    #[error("Stream {0} timeout")]
+    StreamTimeout(uuid::Uuid),
+
+    #[error("Stream {0} not found")]
+    StreamNotFound(uuid::Uuid),
+
+    #[error("Invalid chunk index {0}")]
+    InvalidChunkIndex(usize),
 }
 
 #[cfg(feature = "wasm")]
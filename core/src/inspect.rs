@@ -23,6 +23,8 @@ pub struct SwarmInspect {
 pub struct ConnectionInspect {
     pub did: String,
     pub state: String,
+    #[serde(default)]
+    pub rtt: Option<u64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -34,6 +36,63 @@ pub struct DHTInspect {
     pub finger_table: Vec<(Option<String>, usize, usize)>,
 }
 
+/// Estimation of network size, see [crate::dht::estimate].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkSizeInspect {
+    /// Did of the node estimating.
+    pub did: String,
+    /// Number of nodes on the ring, averaged with estimations of neighbours.
+    pub estimate: u64,
+    /// Number of nodes on the ring, estimated by the density of successors and predecessor.
+    pub local: u64,
+    /// Estimations gossiped by neighbours.
+    pub neighbours: Vec<(String, u64)>,
+}
+
+/// Report of walking the ring along successors, see [crate::swarm::crawl].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RingInspect {
+    /// Did of the node started walking.
+    pub did: String,
+    /// Nodes visited along successors, starting from `did`.
+    pub nodes: Vec<String>,
+    /// Whether the walk returned to `did`.
+    pub complete: bool,
+    /// Number of nodes on the ring, estimated by the density of visited nodes if the walk is incomplete.
+    pub estimated_size: u64,
+    /// Violations of ring invariants found during the walk.
+    pub anomalies: Vec<RingAnomaly>,
+}
+
+/// Violation of ring invariants, see [crate::dht::CorrectChord].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RingAnomaly {
+    /// The node didn't report its topological info in time.
+    Unreachable { did: String },
+    /// The topological info reported by the node is not valid, see [crate::dht::validation].
+    InvalidTopoInfo { did: String },
+    /// The node has no successor.
+    NoSuccessor { did: String },
+    /// The successor of the node was visited, the walk is trapped in a loop without origin.
+    Loop { did: String, successor: String },
+    /// The successor of the node passed over origin, the ring is not ordered or origin is not on it.
+    Disordered { did: String, successor: String },
+    /// The predecessor of successor is not the node.
+    PredecessorMismatch {
+        did: String,
+        successor: String,
+        predecessor: Option<String>,
+    },
+    /// The successor list of the node doesn't agree with the one of its successor.
+    SuccessorListMismatch { did: String, successor: String },
+    /// The successor list of the node is shorter than expected.
+    ShortSuccessorList {
+        did: String,
+        len: usize,
+        expected: usize,
+    },
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StorageInspect {
     pub items: Vec<(String, VirtualNode)>,
@@ -50,6 +109,7 @@ impl SwarmInspect {
                 .map(|(did, c)| ConnectionInspect {
                     did: did.to_string(),
                     state: format!("{:?}", c.ice_connection_state()),
+                    rtt: swarm.dht().rtt(*did),
                 })
                 .collect()
         };
@@ -66,6 +126,26 @@ impl SwarmInspect {
     }
 }
 
+impl NetworkSizeInspect {
+    pub fn inspect(dht: &PeerRing) -> Self {
+        let local = dht.local_size_estimate().unwrap_or(1);
+        let estimate = dht.size_estimate().unwrap_or(local);
+        let neighbours = dht
+            .neighbour_size_estimates()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(did, size)| (did.to_string(), size))
+            .collect();
+
+        Self {
+            did: dht.did.to_string(),
+            estimate,
+            local,
+            neighbours,
+        }
+    }
+}
+
 impl DHTInspect {
     pub fn inspect(dht: &PeerRing) -> Self {
         let did = dht.did.to_string();
@@ -106,13 +186,10 @@ impl DHTInspect {
 
 impl StorageInspect {
     pub async fn inspect_persistence_storage(storage: &PersistenceStorage) -> Self {
+        // Keys of vnodes are dids, other entries such as routing snapshot are skipped.
+        let items: Vec<(Did, VirtualNode)> = storage.get_all().await.unwrap_or_default();
         Self {
-            items: storage
-                .get_all()
-                .await
-                .unwrap_or_default()
-                .into_iter()
-                .collect(),
+            items: items.into_iter().map(|(k, v)| (k.to_string(), v)).collect(),
         }
     }
     pub fn inspect_mem_storage(storage: &MemStorage<Did, VirtualNode>) -> Self {
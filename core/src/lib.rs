@@ -1,7 +1,7 @@
 //! Rings: Chord based P2P implementation over WebRTC and ElGamal.
 //! --------------
 //! - [Chord](crate::dht::PeerRing) is a structured p2p network based on Chord protocol and expanded with secp256k1 based Did support.
-//! - [ElGamal](crate::ecc::elgamal) provides End2End encryption based on Chord Did.
+//! - [Encryption](crate::swarm::encryption) provides End2End encryption of custom messages based on session keys.
 //! - [Swarm](crate::swarm) is a module for managing all transports.
 //! - [Connection](rings_transport::core::transport::ConnectionInterface) is used for connection handshaking, which supports all platforms, including browser (wasm) and native runtime.
 
@@ -20,7 +20,8 @@
 //!   (A successor is the closest node on the Ring for node A.)
 //!   If node B know another node X could be the successor of node A, it will respond with the `Did` of node X.
 //! 3. E2e encrypt
-//! - After joining Ring, should encrypt all direct messages with the ElGamal algorithm.
+//! - After joining Ring, custom messages can be sealed to the session public key of destination via
+//!   `swarm.send_encrypted_message(msg, destination)`, so relay nodes can only see the ciphertext.
 //!
 //! # MessagePayload
 //!
@@ -20,6 +20,10 @@ pub enum MeasureCounter {
     Connect,
     /// The number of disconnect.
     Disconnected,
+    /// The number of received duplicated messages.
+    Duplicated,
+    /// The number of received invalid routing reports.
+    InvalidReport,
 }
 
 /// `Measure` is used to assess the reliability of peers by counting their behaviour.
@@ -89,3 +93,16 @@ pub trait MessageRecvBehaviour<const THRESHOLD: i16>: Measure {
         (failed as i16) < THRESHOLD
     }
 }
+
+/// `RoutingBehaviour` trait provides a default implementation for the `good` method, judging a node's
+/// behavior based on the routing information it reported.
+/// The "goodness" of a node is measured by comparing the invalid report count against a given threshold.
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+pub trait RoutingBehaviour<const THRESHOLD: i16>: Measure {
+    /// This asynchronous method returns a boolean indicating whether the node identified by `did` has reported satisfactory routing information.
+    async fn good(&self, did: Did) -> bool {
+        let invalid = self.get_count(did, MeasureCounter::InvalidReport).await;
+        (invalid as i16) < THRESHOLD
+    }
+}
@@ -0,0 +1,169 @@
+#![warn(missing_docs)]
+use async_trait::async_trait;
+
+use crate::consts::MAX_BROADCAST_HOPS;
+use crate::error::Result;
+use crate::message::types::CustomMessage;
+use crate::message::types::RingBroadcast;
+use crate::message::Encoder;
+use crate::message::HandleMsg;
+use crate::message::Message;
+use crate::message::MessageHandler;
+use crate::message::MessageHandlerEvent;
+use crate::message::MessagePayload;
+use crate::message::PayloadSender;
+use crate::swarm::Swarm;
+
+impl Swarm {
+    /// Broadcast [CustomMessage] to all nodes on the ring except current node.
+    /// The message is signed once by current node, and forwarded along finger tables,
+    /// see [PeerRing::broadcast_routes](crate::dht::PeerRing::broadcast_routes).
+    /// Return the tx_id of the signed message.
+    pub async fn broadcast(&self, msg: &[u8]) -> Result<uuid::Uuid> {
+        let payload = MessagePayload::new_send(
+            Message::CustomMessage(CustomMessage(msg.to_vec())),
+            self.session_sk(),
+            self.did(),
+            self.did(),
+        )?;
+        // Drop it when it comes back to current node.
+        self.broadcast_window.check(&payload);
+        let tx_id = payload.transaction.tx_id;
+        let payload = payload.encode()?;
+
+        for (next, limit) in self.dht.broadcast_routes(self.did())? {
+            let msg = Message::RingBroadcast(RingBroadcast {
+                limit,
+                ttl: MAX_BROADCAST_HOPS - 1,
+                payload: payload.clone(),
+            });
+            self.send_message(msg, next).await?;
+        }
+        Ok(tx_id)
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<RingBroadcast> for MessageHandler {
+    /// The ttl is clamped to [MAX_BROADCAST_HOPS], so that a broadcast cannot be forwarded
+    /// further than the one sent by [Swarm::broadcast].
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &RingBroadcast,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.dht.did != ctx.relay.destination {
+            return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
+        }
+        Ok(vec![MessageHandlerEvent::DeliverRingBroadcast(
+            RingBroadcast {
+                ttl: msg.ttl.min(MAX_BROADCAST_HOPS - 1),
+                ..msg.clone()
+            },
+        )])
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::lock::Mutex;
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::ecc::tests::gen_ordered_keys;
+    use crate::swarm::callback::SwarmCallback;
+    use crate::tests::default::prepare_node;
+    use crate::tests::manually_establish_connection;
+
+    struct BroadcastCallback {
+        messages: Mutex<Vec<Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl SwarmCallback for BroadcastCallback {
+        async fn on_inbound(
+            &self,
+            payload: &MessagePayload,
+        ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+            if let Message::CustomMessage(msg) = payload.transaction.data()? {
+                self.messages.lock().await.push(msg.0);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ring_broadcast() -> Result<()> {
+        let mut nodes = vec![];
+        let mut callbacks = vec![];
+        for key in gen_ordered_keys(4) {
+            let (node, _path) = prepare_node(key).await;
+            let cb = Arc::new(BroadcastCallback {
+                messages: Mutex::new(vec![]),
+            });
+            node.set_callback(cb.clone()).unwrap();
+            nodes.push(node);
+            callbacks.push(cb);
+        }
+        for (i, node) in nodes.iter().enumerate() {
+            for other in nodes.iter().skip(i + 1) {
+                manually_establish_connection(node, other).await;
+            }
+        }
+        for node in nodes.iter() {
+            let n = node.clone();
+            tokio::spawn(async move { n.listen().await });
+        }
+        sleep(Duration::from_secs(3)).await;
+
+        // Every node except origin receives the message exactly once.
+        nodes[1].broadcast(b"hello").await?;
+        sleep(Duration::from_secs(3)).await;
+        assert!(callbacks[1].messages.lock().await.is_empty());
+        for (i, cb) in callbacks.iter().enumerate().filter(|(i, _)| *i != 1) {
+            assert_eq!(
+                cb.messages.lock().await.as_slice(),
+                &[b"hello".to_vec()],
+                "node {} should receive broadcast once",
+                i
+            );
+        }
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ring_broadcast_ttl_clamped() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (node, _path) = prepare_node(keys[0]).await;
+        let (sender, _path) = prepare_node(keys[1]).await;
+        let handler = MessageHandler::new(node.dht());
+
+        let msg = RingBroadcast {
+            limit: node.did(),
+            ttl: u8::MAX,
+            payload: "payload".into(),
+        };
+        let ctx = MessagePayload::new_send(
+            Message::RingBroadcast(msg.clone()),
+            sender.session_sk(),
+            node.did(),
+            node.did(),
+        )?;
+        let events = handler.handle(&ctx, &msg).await?;
+        assert!(matches!(
+            events.as_slice(),
+            [MessageHandlerEvent::DeliverRingBroadcast(RingBroadcast { ttl, .. })]
+                if *ttl == MAX_BROADCAST_HOPS - 1
+        ));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
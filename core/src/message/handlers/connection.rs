@@ -4,11 +4,14 @@ use async_trait::async_trait;
 
 use super::dht;
 use crate::dht::types::CorrectChord;
+use crate::dht::validation;
 use crate::dht::Chord;
 use crate::dht::PeerRingAction;
+use crate::dht::SuccessorReader;
 use crate::dht::TopoInfo;
 use crate::error::Error;
 use crate::error::Result;
+use crate::message::types::AnnouncePositions;
 use crate::message::types::ConnectNodeReport;
 use crate::message::types::ConnectNodeSend;
 use crate::message::types::FindSuccessorReport;
@@ -25,6 +28,10 @@ use crate::message::LeaveDHT;
 use crate::message::MessageHandler;
 use crate::message::MessageHandlerEvent;
 use crate::message::MessagePayload;
+use crate::message::MessageVerificationExt;
+use crate::message::NotifyLeaving;
+use crate::message::PingReport;
+use crate::message::PingSend;
 
 /// QueryForTopoInfoSend is direct message
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -56,6 +63,28 @@ impl HandleMsg<QueryForTopoInfoReport> for MessageHandler {
         ctx: &MessagePayload,
         msg: &QueryForTopoInfoReport,
     ) -> Result<Vec<MessageHandlerEvent>> {
+        // Reports of inspection are resolved by pending requests of origin,
+        // see [crate::swarm::Swarm::inspect_ring].
+        if let <QueryForTopoInfoReport as Then>::Then::Inspection = msg.then {
+            return Ok(vec![]);
+        }
+
+        // Only successors are asked for topological info, except inspection.
+        let reporter = ctx.origin_position();
+        if !self.dht.successors().contains(&reporter)? {
+            tracing::debug!(
+                "Ignore TopoInfo reported by {} which is not a successor",
+                reporter
+            );
+            return Ok(vec![]);
+        }
+        if !validation::is_valid_topo_info(reporter, &msg.info) {
+            tracing::warn!("Drop invalid TopoInfo reported by {}", reporter);
+            return Ok(vec![MessageHandlerEvent::RecordInvalidReport(
+                ctx.transaction.signer(),
+            )]);
+        }
+
         match msg.then {
             <QueryForTopoInfoReport as Then>::Then::SyncSuccessor => Ok(msg
                 .info
@@ -67,6 +96,7 @@ impl HandleMsg<QueryForTopoInfoReport> for MessageHandler {
                 let ev = self.dht.stabilize(msg.info.clone())?;
                 dht::handle_dht_events(&ev, ctx).await
             }
+            <QueryForTopoInfoReport as Then>::Then::Inspection => Ok(vec![]),
         }
     }
 }
@@ -83,6 +113,31 @@ impl HandleMsg<LeaveDHT> for MessageHandler {
     }
 }
 
+/// Remove the leaving node from DHT and connect to the replacement it suggested.
+/// The connection is kept, since the leaving node may hand off its vnodes through it,
+/// and it will be closed by the leaving node.
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<NotifyLeaving> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &NotifyLeaving,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if ctx.transaction.signer() != msg.did {
+            tracing::warn!("Ignore NotifyLeaving of {} not signed by itself", msg.did);
+            return Ok(vec![]);
+        }
+
+        self.dht.remove(msg.did)?;
+
+        match msg.replacement {
+            Some(did) if did != self.dht.did => Ok(vec![MessageHandlerEvent::Connect(did)]),
+            _ => Ok(vec![]),
+        }
+    }
+}
+
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl HandleMsg<JoinDHT> for MessageHandler {
@@ -155,6 +210,7 @@ impl HandleMsg<FindSuccessorSend> for MessageHandler {
                                 ctx.clone(),
                                 Message::FindSuccessorReport(FindSuccessorReport {
                                     did,
+                                    target: msg.did,
                                     handler: handler.clone(),
                                 }),
                             )])
@@ -190,6 +246,25 @@ impl HandleMsg<FindSuccessorReport> for MessageHandler {
             return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
         }
 
+        // Check the report against the target we asked for, see [validation::SuccessorQueries].
+        let Some(target) = self.dht.successor_queries.take(ctx.transaction.tx_id) else {
+            tracing::debug!("Ignore successor report of unknown query {}", ctx.transaction.tx_id);
+            return Ok(vec![]);
+        };
+        let reporter = ctx.origin_position();
+        if msg.target != target || !validation::is_valid_successor_report(target, reporter, msg.did)
+        {
+            tracing::warn!(
+                "Drop successor {} of {} reported by {}",
+                msg.did,
+                target,
+                reporter
+            );
+            return Ok(vec![MessageHandlerEvent::RecordInvalidReport(
+                ctx.transaction.signer(),
+            )]);
+        }
+
         match &msg.handler {
             FindSuccessorReportHandler::FixFingerTable => {
                 Ok(vec![MessageHandlerEvent::Connect(msg.did)])
@@ -200,6 +275,55 @@ impl HandleMsg<FindSuccessorReport> for MessageHandler {
     }
 }
 
+/// PingSend is direct message, answer it immediately.
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<PingSend> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        _msg: &PingSend,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.dht.did != ctx.relay.destination {
+            return Ok(vec![]);
+        }
+        Ok(vec![MessageHandlerEvent::SendReportMessage(
+            ctx.clone(),
+            Message::PingReport(PingReport {}),
+        )])
+    }
+}
+
+/// The report is resolved by pending requests of origin, see [crate::swarm::Swarm::ping].
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<PingReport> for MessageHandler {
+    async fn handle(
+        &self,
+        _ctx: &MessagePayload,
+        _msg: &PingReport,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        Ok(vec![])
+    }
+}
+
+/// AnnouncePositions is direct message, the positions are derived from its signer.
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<AnnouncePositions> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &AnnouncePositions,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        Ok(vec![MessageHandlerEvent::JoinPositions(
+            ctx.clone(),
+            ctx.transaction.signer(),
+            msg.count,
+        )])
+    }
+}
+
 #[cfg(not(feature = "wasm"))]
 #[cfg(test)]
 pub mod tests {
@@ -384,7 +508,7 @@ pub mod tests {
         assert_eq!(ev_3.relay.path, vec![node2.did()]);
         assert!(matches!(
             ev_3.transaction.data()?,
-            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect}) if did == node3.did()
+            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect, ..}) if did == node3.did()
         ));
         // dht3 won't set did3 as successor
         assert!(!node3.dht().successors().list()?.contains(&node3.did()));
@@ -397,7 +521,7 @@ pub mod tests {
         // node3 is only aware of node2, so it respond node2
         assert!(matches!(
             ev_2.transaction.data()?,
-            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect}) if did == node2.did()
+            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect, ..}) if did == node2.did()
         ));
         // dht2 won't set did2 as successor
         assert!(!node2.dht().successors().list()?.contains(&node2.did()));
@@ -437,7 +561,7 @@ pub mod tests {
         assert_eq!(ev_1.relay.path, vec![node3.did()]);
         assert!(matches!(
             ev_1.transaction.data()?,
-            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect}) if did == node1.did()
+            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect, ..}) if did == node1.did()
         ));
         // dht1 won't set did1 as successor
         assert!(!node1.dht().successors().list()?.contains(&node1.did()));
@@ -453,7 +577,7 @@ pub mod tests {
         assert_eq!(ev_3.relay.path, vec![node2.did(), node1.did()]);
         assert!(matches!(
             ev_3.transaction.data()?,
-            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect}) if did == node3.did()
+            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect, ..}) if did == node3.did()
         ));
         // dht3 won't set did3 as successor
         assert!(!node3.dht().successors().list()?.contains(&node3.did()));
@@ -541,7 +665,7 @@ pub mod tests {
         // node3 is only aware of node2, so it respond node2
         assert!(matches!(
             ev_2.transaction.data()?,
-            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect}) if did == node2.did()
+            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect, ..}) if did == node2.did()
         ));
         // dht2 won't set did2 as successor
         assert!(!node2.dht().successors().list()?.contains(&node2.did()));
@@ -554,7 +678,7 @@ pub mod tests {
         // node1 is only aware of node2, so it respond node2
         assert!(matches!(
             ev_2.transaction.data()?,
-            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect}) if did == node2.did()
+            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect, ..}) if did == node2.did()
         ));
 
         // 1->2->3 FindSuccessorReport
@@ -564,7 +688,7 @@ pub mod tests {
         assert_eq!(ev_3.relay.path, vec![node1.did(), node2.did()]);
         assert!(matches!(
             ev_3.transaction.data()?,
-            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect}) if did == node2.did()
+            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect, ..}) if did == node2.did()
         ));
 
         println!("=== Check state before connect via DHT ===");
@@ -602,7 +726,7 @@ pub mod tests {
         assert_eq!(ev_3.relay.path, vec![node1.did()]);
         assert!(matches!(
             ev_3.transaction.data()?,
-            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect}) if did == node3.did()
+            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect, ..}) if did == node3.did()
         ));
         // dht3 won't set did3 as successor
         assert!(!node3.dht.successors().list()?.contains(&node3.did()));
@@ -618,7 +742,7 @@ pub mod tests {
         assert_eq!(ev_1.relay.path, vec![node2.did(), node3.did()]);
         assert!(matches!(
             ev_1.transaction.data()?,
-            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect}) if did == node1.did()
+            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect, ..}) if did == node1.did()
         ));
         // dht1 won't set did1 as successor
         assert!(!node1.dht.successors().list()?.contains(&node1.did()));
@@ -699,7 +823,7 @@ pub mod tests {
         // node2 is only aware of node1, so it respond node1
         assert!(matches!(
             ev_1.transaction.data()?,
-            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect}) if did == node1.did()
+            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect, ..}) if did == node1.did()
         ));
         // dht1 won't set dhd1 as successor
         assert!(!node1.dht().successors().list()?.contains(&node1.did()));
@@ -712,7 +836,7 @@ pub mod tests {
         // node1 is only aware of node2, so it respond node2
         assert!(matches!(
             ev_2.transaction.data()?,
-            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect}) if did == node2.did()
+            Message::FindSuccessorReport(FindSuccessorReport{did, handler: FindSuccessorReportHandler::Connect, ..}) if did == node2.did()
         ));
         // dht2 won't set did2 as successor
         assert!(!node2.dht().successors().list()?.contains(&node2.did()));
@@ -1,11 +1,51 @@
 use async_trait::async_trait;
 
+use crate::chunk::Chunk;
+use crate::dht::Chord;
+use crate::dht::PeerRingAction;
 use crate::error::Result;
+use crate::message::types::AckedMessage;
 use crate::message::types::CustomMessage;
+use crate::message::types::DeliveryAck;
+use crate::message::types::EncryptedMessage;
+use crate::message::types::Message;
+use crate::message::types::StreamCredit;
+use crate::message::types::StreamOffer;
 use crate::message::HandleMsg;
 use crate::message::MessageHandler;
 use crate::message::MessageHandlerEvent;
 use crate::message::MessagePayload;
+use crate::message::MessageVerificationExt;
+
+impl MessageHandler {
+    pub(super) fn relay_custom_payload(
+        &self,
+        ctx: &MessagePayload,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.dht.did == ctx.relay.destination {
+            return Ok(vec![]);
+        }
+
+        // If the destination should be the successor of current node but it is not,
+        // the destination is offline. Park the payload and wait for it coming back.
+        if let Ok(PeerRingAction::Some(succ)) = self.dht.find_successor(ctx.relay.destination) {
+            if succ != ctx.relay.destination {
+                return Ok(vec![MessageHandlerEvent::StoreRelayMessage(ctx.clone())]);
+            }
+        }
+
+        Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)])
+    }
+
+    /// Messages of stream are consumed by swarm at destination, see [crate::swarm::stream].
+    /// They are never parked for an offline destination, the stream is resumed instead.
+    fn relay_stream_payload(&self, ctx: &MessagePayload) -> Result<Vec<MessageHandlerEvent>> {
+        if self.dht.did == ctx.relay.destination {
+            return Ok(vec![]);
+        }
+        Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)])
+    }
+}
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
@@ -14,11 +54,86 @@ impl HandleMsg<CustomMessage> for MessageHandler {
         &self,
         ctx: &MessagePayload,
         _: &CustomMessage,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        self.relay_custom_payload(ctx)
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<EncryptedMessage> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        _: &EncryptedMessage,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        self.relay_custom_payload(ctx)
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<AckedMessage> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        _: &AckedMessage,
     ) -> Result<Vec<MessageHandlerEvent>> {
         if self.dht.did != ctx.relay.destination {
-            Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)])
-        } else {
-            Ok(vec![])
+            return self.relay_custom_payload(ctx);
         }
+
+        // The ack is routed as a new message, since the relay path may be broken.
+        Ok(vec![MessageHandlerEvent::SendMessage(
+            Message::DeliveryAck(DeliveryAck {
+                tx_id: ctx.transaction.tx_id,
+            }),
+            ctx.transaction.signer(),
+        )])
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<DeliveryAck> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        _: &DeliveryAck,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        self.relay_custom_payload(ctx)
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<StreamOffer> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        _: &StreamOffer,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        self.relay_stream_payload(ctx)
+    }
+}
+
+/// Chunks of stream, which are sent by [Message::StreamChunk].
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<Chunk> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload, _: &Chunk) -> Result<Vec<MessageHandlerEvent>> {
+        self.relay_stream_payload(ctx)
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<StreamCredit> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        _: &StreamCredit,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        self.relay_stream_payload(ctx)
     }
 }
@@ -0,0 +1,62 @@
+#![warn(missing_docs)]
+use async_trait::async_trait;
+
+use crate::dht::Chord;
+use crate::dht::PeerRingAction;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::types::LookupStep;
+use crate::message::types::Message;
+use crate::message::types::QueryNextHopReport;
+use crate::message::types::QueryNextHopSend;
+use crate::message::HandleMsg;
+use crate::message::MessageHandler;
+use crate::message::MessageHandlerEvent;
+use crate::message::MessagePayload;
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<QueryNextHopSend> for MessageHandler {
+    /// Answer origin with the successor of target if current node knows it,
+    /// else with the closest preceding nodes in finger table and successor sequence.
+    /// Unlike [crate::message::FindSuccessorSend], the query is never forwarded
+    /// towards target, origin will ask the next hops by itself.
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &QueryNextHopSend,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.dht.did != ctx.relay.destination {
+            return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
+        }
+
+        let step = match self.dht.find_successor(msg.did)? {
+            PeerRingAction::Some(did) => LookupStep::Found(did),
+            PeerRingAction::RemoteAction(..) => LookupStep::Next(
+                self.dht
+                    .closest_preceding_nodes(msg.did, msg.count as usize)?,
+            ),
+            act => return Err(Error::PeerRingUnexpectedAction(act)),
+        };
+        Ok(vec![MessageHandlerEvent::SendReportMessage(
+            ctx.clone(),
+            Message::QueryNextHopReport(QueryNextHopReport { did: msg.did, step }),
+        )])
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<QueryNextHopReport> for MessageHandler {
+    /// The report is resolved by pending requests of origin, see [crate::swarm::lookup].
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        _msg: &QueryNextHopReport,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.dht.did != ctx.relay.destination {
+            return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
+        }
+        Ok(vec![])
+    }
+}
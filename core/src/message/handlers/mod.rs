@@ -13,6 +13,7 @@ use std::sync::Arc;
 use async_recursion::async_recursion;
 use async_trait::async_trait;
 
+use self::subring::ParkedSubringBroadcasts;
 use super::Message;
 use super::MessagePayload;
 use crate::dht::vnode::VirtualNode;
@@ -21,13 +22,22 @@ use crate::dht::PeerRing;
 use crate::error::Result;
 use crate::message::ConnectNodeReport;
 use crate::message::ConnectNodeSend;
+use crate::message::RingBroadcast;
+use crate::message::SubringBroadcast;
+use crate::message::TopicMessage;
 
+/// Operator and Handler for ring-wide broadcast
+pub mod broadcast;
 /// Operator and Handler for Connection
 pub mod connection;
 /// Operator and Handler for CustomMessage
 pub mod custom;
 /// For handle dht related actions
 pub mod dht;
+/// Handler for iterative lookup
+pub mod lookup;
+/// Operator and Handler for topic subscriptions
+pub mod pubsub;
 /// Operator and handler for DHT stablization
 pub mod stabilization;
 /// Operator and Handler for Storage
@@ -76,14 +86,44 @@ pub enum MessageHandlerEvent {
 
     /// Instructs the swarm to store vnode.
     StorageStore(VirtualNode),
+
+    /// Instructs the swarm to park an unreachable payload as a RelayMessage vnode,
+    /// which will be fetched by the destination when it joins the DHT.
+    StoreRelayMessage(MessagePayload),
+
+    /// Instructs the swarm to deliver the payloads inside a RelayMessage vnode
+    /// to the callback. If the vnode is found in a payload, the payload is acknowledged
+    /// by [Message::DeliveryAck] once all the payloads are delivered.
+    DeliverRelayMessage(VirtualNode, Option<MessagePayload>),
+
+    /// Instructs the swarm to deliver the payload inside a SubringBroadcast
+    /// to the callback if it's not seen before, then forward it to the members.
+    DeliverSubringMessage(SubringBroadcast),
+
+    /// Instructs the swarm to deliver a message of subscribed topic to its inbox.
+    DeliverTopicMessage(TopicMessage),
+
+    /// Instructs the swarm to deliver the payload inside a RingBroadcast to the callback
+    /// if it's not seen before, then forward it to the next hops.
+    DeliverRingBroadcast(RingBroadcast),
+
     /// Notify a node
     Notify(Did),
+
+    /// Instructs the swarm to record a peer that reported invalid routing information,
+    /// see [crate::dht::validation].
+    RecordInvalidReport(Did),
+
+    /// Instructs the swarm to join the positions operated by a connected peer,
+    /// by given context, Did of the peer and number of positions, see [crate::swarm::positions].
+    JoinPositions(MessagePayload, Did, u16),
 }
 
 /// MessageHandler will manage resources.
 #[derive(Clone)]
 pub struct MessageHandler {
     dht: Arc<PeerRing>,
+    parked_broadcasts: Arc<ParkedSubringBroadcasts>,
 }
 
 /// Generic trait for handle message ,inspired by Actor-Model.
@@ -97,7 +137,10 @@ pub trait HandleMsg<T> {
 impl MessageHandler {
     /// Create a new MessageHandler Instance.
     pub fn new(dht: Arc<PeerRing>) -> Self {
-        Self { dht }
+        Self {
+            dht,
+            parked_broadcasts: Arc::new(ParkedSubringBroadcasts::default()),
+        }
     }
 
     /// Handle builtin message.
@@ -135,7 +178,32 @@ impl MessageHandler {
             Message::CustomMessage(ref msg) => self.handle(payload, msg).await,
             Message::QueryForTopoInfoSend(ref msg) => self.handle(payload, msg).await,
             Message::QueryForTopoInfoReport(ref msg) => self.handle(payload, msg).await,
-            Message::Chunk(_) => Ok(vec![]),
+            // Chunks are reassembled by connection at destination, and relayed on the way.
+            Message::Chunk(_) => self.relay_custom_payload(payload),
+            Message::EncryptedMessage(ref msg) => self.handle(payload, msg).await,
+            Message::NotifyLeaving(ref msg) => self.handle(payload, msg).await,
+            Message::ReplicateVNode(ref msg) => self.handle(payload, msg).await,
+            Message::SyncVNodeDigestSend(ref msg) => self.handle(payload, msg).await,
+            Message::SyncVNodeDigestReport(ref msg) => self.handle(payload, msg).await,
+            Message::OperateVNodeReport(ref msg) => self.handle(payload, msg).await,
+            Message::SubringBroadcast(ref msg) => self.handle(payload, msg).await,
+            Message::SubscribeTopic(ref msg) => self.handle(payload, msg).await,
+            Message::UnsubscribeTopic(ref msg) => self.handle(payload, msg).await,
+            Message::PublishTopic(ref msg) => self.handle(payload, msg).await,
+            Message::TopicMessage(ref msg) => self.handle(payload, msg).await,
+            Message::RingBroadcast(ref msg) => self.handle(payload, msg).await,
+            Message::QueryNextHopSend(ref msg) => self.handle(payload, msg).await,
+            Message::QueryNextHopReport(ref msg) => self.handle(payload, msg).await,
+            Message::PingSend(ref msg) => self.handle(payload, msg).await,
+            Message::PingReport(ref msg) => self.handle(payload, msg).await,
+            Message::AnnouncePositions(ref msg) => self.handle(payload, msg).await,
+            Message::GossipNetworkSize(ref msg) => self.handle(payload, msg).await,
+            Message::AckedMessage(ref msg) => self.handle(payload, msg).await,
+            Message::DeliveryAck(ref msg) => self.handle(payload, msg).await,
+            Message::ProtocolHandshake(_) => Ok(vec![]),
+            Message::StreamOffer(ref msg) => self.handle(payload, msg).await,
+            Message::StreamChunk(ref msg) => self.handle(payload, msg).await,
+            Message::StreamCredit(ref msg) => self.handle(payload, msg).await,
         }?;
 
         tracing::debug!("FINISH HANDLE MESSAGE {}", &payload.transaction.tx_id);
@@ -151,10 +219,14 @@ pub mod tests {
     use tokio::time::Duration;
 
     use super::*;
+    use crate::consts::TRANSPORT_MTU;
     use crate::dht::Did;
+    use crate::ecc::tests::gen_ordered_keys;
     use crate::ecc::SecretKey;
+    use crate::message::handlers::connection::tests::test_only_two_nodes_establish_connection;
     use crate::message::MessageVerificationExt;
     use crate::message::PayloadSender;
+    use crate::message::ProtocolHandshake;
     use crate::swarm::callback::SwarmCallback;
     use crate::swarm::Swarm;
     use crate::tests::default::prepare_node;
@@ -270,6 +342,138 @@ pub mod tests {
         Ok(())
     }
 
+    #[derive(Default)]
+    struct ChunkRelayCallback {
+        validated: Mutex<Vec<String>>,
+        inbound: Mutex<Vec<MessagePayload>>,
+    }
+
+    #[async_trait]
+    impl SwarmCallback for ChunkRelayCallback {
+        async fn on_validate(
+            &self,
+            payload: &MessagePayload,
+        ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+            let msg: Message = payload.transaction.data().map_err(Box::new)?;
+            self.validated.lock().await.push(msg.kind().to_string());
+            Ok(())
+        }
+
+        async fn on_inbound(
+            &self,
+            payload: &MessagePayload,
+        ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+            self.inbound.lock().await.push(payload.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relay_chunks_of_large_message() -> Result<()> {
+        let keys = gen_ordered_keys(3);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        let (node3, _path3) = prepare_node(keys[2]).await;
+        let cb2 = Arc::new(ChunkRelayCallback::default());
+        let cb3 = Arc::new(ChunkRelayCallback::default());
+        node2.set_callback(cb2.clone()).unwrap();
+        node3.set_callback(cb3.clone()).unwrap();
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+        test_only_two_nodes_establish_connection(&node2, &node3).await?;
+        assert!(node1.get_connection(node3.did()).is_none());
+        for node in [&node1, &node2, &node3] {
+            let node = node.clone();
+            tokio::spawn(async move { node.listen().await });
+        }
+
+        let data: Vec<u8> = (0..TRANSPORT_MTU * 3).map(|i| (i % 251) as u8).collect();
+        node1
+            .send_message(Message::custom(&data)?, node3.did())
+            .await?;
+        sleep(Duration::from_secs(5)).await;
+
+        // The relay forwards chunks without reassembling the message.
+        let validated = cb2.validated.lock().await;
+        assert!(validated.iter().any(|k| k == "Chunk"));
+        assert!(validated.iter().all(|k| k != "CustomMessage"));
+
+        let inbound = cb3.inbound.lock().await;
+        let payload = inbound
+            .iter()
+            .find(|p| matches!(p.transaction.data(), Ok(Message::CustomMessage(_))))
+            .expect("message should be reassembled by destination");
+        assert!(matches!(
+            payload.transaction.data()?,
+            Message::CustomMessage(ref msg) if msg.0 == data
+        ));
+        assert_eq!(payload.transaction.signer(), node1.did());
+        assert_eq!(payload.relay.path, vec![node1.did(), node2.did()]);
+        assert_eq!(payload.relay.next_hop, node3.did());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reassemble_chunks_for_next_hop_without_chunk_relaying() -> Result<()> {
+        let keys = gen_ordered_keys(4);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        let (node3, _path3) = prepare_node(keys[2]).await;
+        let (node4, _path4) = prepare_node(keys[3]).await;
+        let cb2 = Arc::new(ChunkRelayCallback::default());
+        let cb4 = Arc::new(ChunkRelayCallback::default());
+        node2.set_callback(cb2.clone()).unwrap();
+        node4.set_callback(cb4.clone()).unwrap();
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+        test_only_two_nodes_establish_connection(&node2, &node3).await?;
+        test_only_two_nodes_establish_connection(&node3, &node4).await?;
+        for node in [&node1, &node2, &node3, &node4] {
+            let node = node.clone();
+            tokio::spawn(async move { node.listen().await });
+        }
+
+        // node3 is known by node2 as a node which cannot relay chunks.
+        let mut handshake = ProtocolHandshake::new(node2.network_id());
+        handshake.features.retain(|f| f != "chunk-relaying");
+        node2
+            .peer_protocols
+            .negotiate(node3.did(), &handshake)
+            .unwrap();
+
+        let data: Vec<u8> = (0..TRANSPORT_MTU * 3).map(|i| (i % 251) as u8).collect();
+        node1
+            .send_message(Message::custom(&data)?, node4.did())
+            .await?;
+        sleep(Duration::from_secs(5)).await;
+
+        // The relay reassembles the message, and chunks it again for node3.
+        let validated = cb2.validated.lock().await;
+        assert!(validated.iter().any(|k| k == "Chunk"));
+        assert!(validated.iter().all(|k| k != "CustomMessage"));
+        assert!(node2.relayed_chunks.lock().await.as_vec().is_empty());
+
+        let inbound = cb4.inbound.lock().await;
+        let payload = inbound
+            .iter()
+            .find(|p| matches!(p.transaction.data(), Ok(Message::CustomMessage(_))))
+            .expect("message should be reassembled by destination");
+        assert!(matches!(
+            payload.transaction.data()?,
+            Message::CustomMessage(ref msg) if msg.0 == data
+        ));
+        assert_eq!(payload.transaction.signer(), node1.did());
+        assert_eq!(payload.relay.path, vec![
+            node1.did(),
+            node2.did(),
+            node3.did()
+        ]);
+        assert_eq!(payload.relay.next_hop, node4.did());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
     pub async fn assert_no_more_msg(node1: &Swarm, node2: &Swarm, node3: &Swarm) {
         tokio::select! {
             _ = node1.listen_once() => unreachable!("node1 should not receive any message"),
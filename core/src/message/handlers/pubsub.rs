@@ -0,0 +1,374 @@
+#![warn(missing_docs)]
+//! Push based publish/subscribe of topics.
+//!
+//! Subscribers register themselves on the node responsible for the did of topic by
+//! [SubscribeTopic]. Published data is sent to the same node by [PublishTopic], which
+//! fans it out to all subscribers as [TopicMessage]. The data is also appended to the
+//! topic vnode by [ChordStorageInterface::storage_append_data] as history.
+//!
+//! Subscriptions are leases of [TOPIC_SUBSCRIPTION_TTL_MS], renewed on every
+//! [Stabilization](crate::dht::Stabilization), so that they are re-established when the
+//! responsible node is changed.
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::consts::TOPIC_SUBSCRIPTION_TTL_MS;
+use crate::dht::vnode::VirtualNode;
+use crate::dht::Chord;
+use crate::dht::Did;
+use crate::dht::PeerRing;
+use crate::dht::PeerRingAction;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::types::Message;
+use crate::message::types::PublishTopic;
+use crate::message::types::SubscribeTopic;
+use crate::message::types::TopicMessage;
+use crate::message::types::UnsubscribeTopic;
+use crate::message::ChordStorageInterface;
+use crate::message::Decoder;
+use crate::message::Encoded;
+use crate::message::Encoder;
+use crate::message::HandleMsg;
+use crate::message::MessageHandler;
+use crate::message::MessageHandlerEvent;
+use crate::message::MessagePayload;
+use crate::message::MessageVerificationExt;
+use crate::message::PayloadSender;
+use crate::swarm::Swarm;
+use crate::utils::get_epoch_ms;
+
+/// PubsubInterface should imply necessary operator for topic subscriptions
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+pub trait PubsubInterface<const REDUNDANT: u16> {
+    /// subscribe a topic, messages published after it will be pushed to current node.
+    async fn topic_subscribe(&self, topic: &str) -> Result<()>;
+    /// unsubscribe a topic, and drop received messages of it.
+    async fn topic_unsubscribe(&self, topic: &str) -> Result<()>;
+    /// publish data to a topic, and append it to the topic vnode as history.
+    async fn topic_publish(&self, topic: &str, data: Encoded) -> Result<()>;
+    /// take received messages of a subscribed topic, wait for the next one until timeout
+    /// if there is no message.
+    async fn topic_poll(&self, topic: &str, timeout: Duration) -> Result<Vec<TopicMessage>>;
+}
+
+/// Return the next hop to the node responsible for topic,
+/// or None if current node is responsible.
+fn topic_next_hop(dht: &PeerRing, topic: Did) -> Result<Option<Did>> {
+    match dht.find_successor(topic)? {
+        PeerRingAction::Some(_) => Ok(None),
+        PeerRingAction::RemoteAction(next, _) => Ok(Some(next)),
+        act => Err(Error::PeerRingUnexpectedAction(act)),
+    }
+}
+
+/// Fan out published data to all subscribers of topic, with the payload of [PublishTopic]
+/// signed by publisher.
+fn handle_topic_publish(
+    dht: &PeerRing,
+    publication: &MessagePayload,
+    msg: &PublishTopic,
+) -> Result<Vec<MessageHandlerEvent>> {
+    let now = get_epoch_ms() as u64;
+    let publisher = publication.transaction.signer();
+    let publication = publication.encode()?;
+    Ok(dht
+        .subscriptions
+        .subscribers(msg.topic, now)
+        .into_iter()
+        .map(|subscriber| {
+            let msg = TopicMessage {
+                topic: msg.topic,
+                publisher,
+                data: msg.data.clone(),
+                publication: publication.clone(),
+            };
+            if subscriber == dht.did {
+                MessageHandlerEvent::DeliverTopicMessage(msg)
+            } else {
+                MessageHandlerEvent::SendMessage(Message::TopicMessage(msg), subscriber)
+            }
+        })
+        .collect())
+}
+
+/// Check that the message is published by its publisher, see [TopicMessage::publication].
+fn is_valid_topic_message(msg: &TopicMessage) -> bool {
+    let Ok(publication) = MessagePayload::from_encoded(&msg.publication) else {
+        return false;
+    };
+    if !publication.transaction.verify() || publication.transaction.signer() != msg.publisher {
+        return false;
+    }
+    matches!(
+        publication.transaction.data(),
+        Ok(Message::PublishTopic(PublishTopic { topic, data })) if topic == msg.topic && data == msg.data
+    )
+}
+
+impl Swarm {
+    /// Subscribe a topic on the node responsible for it, or renew the lease.
+    async fn topic_renew(&self, topic: Did) -> Result<()> {
+        match topic_next_hop(&self.dht, topic)? {
+            None => {
+                let expires_at = get_epoch_ms() as u64 + TOPIC_SUBSCRIPTION_TTL_MS;
+                self.dht
+                    .subscriptions
+                    .subscribe(topic, self.did(), expires_at);
+            }
+            Some(next) => {
+                self.send_message(Message::SubscribeTopic(SubscribeTopic { topic }), next)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renew the leases of all subscribed topics.
+    /// The responsible nodes may be changed, so subscriptions are re-established on them.
+    pub(crate) async fn topic_renew_all(&self) -> Result<()> {
+        for topic in self.topic_inbox.topics(self.did()) {
+            if let Err(e) = self.topic_renew(topic).await {
+                tracing::warn!("Failed to renew subscription of topic {}: {:?}", topic, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl<const REDUNDANT: u16> PubsubInterface<REDUNDANT> for Swarm {
+    async fn topic_subscribe(&self, topic: &str) -> Result<()> {
+        let topic = VirtualNode::gen_did(topic)?;
+        self.topic_inbox.subscribe(self.did(), topic);
+        self.topic_renew(topic).await
+    }
+
+    async fn topic_unsubscribe(&self, topic: &str) -> Result<()> {
+        let topic = VirtualNode::gen_did(topic)?;
+        self.topic_inbox.unsubscribe(self.did(), topic);
+        match topic_next_hop(&self.dht, topic)? {
+            None => self.dht.subscriptions.unsubscribe(topic, self.did()),
+            Some(next) => {
+                self.send_message(Message::UnsubscribeTopic(UnsubscribeTopic { topic }), next)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn topic_publish(&self, topic: &str, data: Encoded) -> Result<()> {
+        <Swarm as ChordStorageInterface<REDUNDANT>>::storage_append_data(self, topic, data.clone())
+            .await?;
+
+        let msg = PublishTopic {
+            topic: VirtualNode::gen_did(topic)?,
+            data,
+        };
+        match topic_next_hop(&self.dht, msg.topic)? {
+            None => {
+                let publication = MessagePayload::new_send(
+                    Message::PublishTopic(msg.clone()),
+                    self.session_sk(),
+                    msg.topic,
+                    msg.topic,
+                )?;
+                let events = handle_topic_publish(&self.dht, &publication, &msg)?;
+                self.handle_message_handler_events(&events).await?;
+            }
+            Some(next) => {
+                self.send_message(Message::PublishTopic(msg), next).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn topic_poll(&self, topic: &str, timeout: Duration) -> Result<Vec<TopicMessage>> {
+        let topic = VirtualNode::gen_did(topic)?;
+        Ok(self.topic_inbox.poll(self.did(), topic, timeout).await)
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<SubscribeTopic> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &SubscribeTopic,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if let Some(next) = topic_next_hop(&self.dht, msg.topic)? {
+            return Ok(vec![MessageHandlerEvent::ResetDestination(
+                ctx.clone(),
+                next,
+            )]);
+        }
+        let expires_at = get_epoch_ms() as u64 + TOPIC_SUBSCRIPTION_TTL_MS;
+        self.dht
+            .subscriptions
+            .subscribe(msg.topic, ctx.transaction.signer(), expires_at);
+        Ok(vec![])
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<UnsubscribeTopic> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &UnsubscribeTopic,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if let Some(next) = topic_next_hop(&self.dht, msg.topic)? {
+            return Ok(vec![MessageHandlerEvent::ResetDestination(
+                ctx.clone(),
+                next,
+            )]);
+        }
+        self.dht
+            .subscriptions
+            .unsubscribe(msg.topic, ctx.transaction.signer());
+        Ok(vec![])
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<PublishTopic> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &PublishTopic,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if let Some(next) = topic_next_hop(&self.dht, msg.topic)? {
+            return Ok(vec![MessageHandlerEvent::ResetDestination(
+                ctx.clone(),
+                next,
+            )]);
+        }
+        handle_topic_publish(&self.dht, ctx, msg)
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<TopicMessage> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &TopicMessage,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.dht.did != ctx.relay.destination {
+            return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
+        }
+        if !is_valid_topic_message(msg) {
+            tracing::warn!("Drop topic message not published by {}", msg.publisher);
+            return Ok(vec![]);
+        }
+        Ok(vec![MessageHandlerEvent::DeliverTopicMessage(msg.clone())])
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::ecc::tests::gen_ordered_keys;
+    use crate::tests::default::prepare_node;
+    use crate::tests::manually_establish_connection;
+
+    #[tokio::test]
+    async fn test_topic_subscribe_and_publish() -> Result<()> {
+        let topic = "test_pubsub_topic";
+        let timeout = Duration::from_secs(3);
+        let mut nodes = vec![];
+        for key in gen_ordered_keys(3) {
+            let (node, _path) = prepare_node(key).await;
+            nodes.push(node);
+        }
+        manually_establish_connection(&nodes[0], &nodes[1]).await;
+        manually_establish_connection(&nodes[1], &nodes[2]).await;
+        manually_establish_connection(&nodes[0], &nodes[2]).await;
+        for node in nodes.iter() {
+            let n = node.clone();
+            tokio::spawn(async move { n.listen().await });
+        }
+        sleep(Duration::from_secs(3)).await;
+
+        for node in nodes.iter().skip(1) {
+            <Swarm as PubsubInterface<1>>::topic_subscribe(node, topic).await?;
+        }
+        sleep(Duration::from_secs(1)).await;
+
+        let data = "hello".to_string().encode()?;
+        <Swarm as PubsubInterface<1>>::topic_publish(&nodes[0], topic, data.clone()).await?;
+        let mut publications = vec![];
+        for node in nodes.iter().skip(1) {
+            let messages = <Swarm as PubsubInterface<1>>::topic_poll(node, topic, timeout).await?;
+            assert_eq!(messages.len(), 1);
+            assert_eq!(messages[0].topic, VirtualNode::gen_did(topic)?);
+            assert_eq!(messages[0].publisher, nodes[0].did());
+            assert_eq!(messages[0].data, data);
+            assert!(is_valid_topic_message(&messages[0]));
+            publications.push(messages[0].clone());
+        }
+        // Publisher did not subscribe the topic.
+        let messages =
+            <Swarm as PubsubInterface<1>>::topic_poll(&nodes[0], topic, Duration::ZERO).await?;
+        assert!(messages.is_empty());
+
+        // Messages not matching the publication are dropped by subscriber.
+        let forged = [
+            TopicMessage {
+                publisher: nodes[2].did(),
+                ..publications[0].clone()
+            },
+            TopicMessage {
+                data: "forged".to_string().encode()?,
+                ..publications[0].clone()
+            },
+        ];
+        for msg in forged {
+            assert!(!is_valid_topic_message(&msg));
+            nodes[0]
+                .send_message(Message::TopicMessage(msg), nodes[1].did())
+                .await?;
+        }
+        let messages =
+            <Swarm as PubsubInterface<1>>::topic_poll(&nodes[1], topic, Duration::from_millis(500))
+                .await?;
+        assert!(messages.is_empty());
+
+        // Published data is kept in topic vnode as history.
+        let vnode = <Swarm as ChordStorageInterface<1>>::storage_fetch_vnode(
+            &nodes[0],
+            VirtualNode::gen_did(topic)?,
+            timeout,
+        )
+        .await?
+        .unwrap();
+        assert_eq!(vnode.data, vec![data]);
+
+        // Unsubscribed node does not receive messages anymore.
+        <Swarm as PubsubInterface<1>>::topic_unsubscribe(&nodes[2], topic).await?;
+        sleep(Duration::from_secs(1)).await;
+        let data = "world".to_string().encode()?;
+        <Swarm as PubsubInterface<1>>::topic_publish(&nodes[0], topic, data.clone()).await?;
+        let messages = <Swarm as PubsubInterface<1>>::topic_poll(&nodes[1], topic, timeout).await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data, data);
+        <Swarm as PubsubInterface<1>>::topic_subscribe(&nodes[2], topic).await?;
+        let messages =
+            <Swarm as PubsubInterface<1>>::topic_poll(&nodes[2], topic, Duration::from_millis(500))
+                .await?;
+        assert!(messages.is_empty());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
@@ -5,6 +5,7 @@ use crate::dht::ChordStorageSync;
 use crate::dht::PeerRingAction;
 use crate::dht::PeerRingRemoteAction;
 use crate::error::Result;
+use crate::message::types::GossipNetworkSize;
 use crate::message::types::Message;
 use crate::message::types::NotifyPredecessorReport;
 use crate::message::types::NotifyPredecessorSend;
@@ -63,6 +64,19 @@ impl HandleMsg<NotifyPredecessorReport> for MessageHandler {
     }
 }
 
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<GossipNetworkSize> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &GossipNetworkSize,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        self.dht.set_size_estimate(ctx.origin_position(), msg.size);
+        Ok(vec![])
+    }
+}
+
 #[cfg(not(feature = "wasm"))]
 #[cfg(test)]
 mod test {
@@ -315,8 +329,14 @@ mod test {
             Message::NotifyPredecessorSend(NotifyPredecessorSend{did}) if did == node3.did()
         ));
 
-        // node1 report node3
-        let ev3 = node3.listen_once().await.unwrap().0;
+        // node1 and node2 report node3, through different connections in any order
+        let mut reports = vec![
+            node3.listen_once().await.unwrap().0,
+            node3.listen_once().await.unwrap().0,
+        ];
+        reports.sort_by_key(|ev| ev.signer() != node1.did());
+
+        let ev3 = &reports[0];
         assert_eq!(ev3.signer(), node1.did());
         assert_eq!(ev3.relay.path, vec![node1.did()]);
         assert!(matches!(
@@ -324,8 +344,7 @@ mod test {
             Message::NotifyPredecessorReport(NotifyPredecessorReport{did}) if did == node2.did()
         ));
 
-        // node2 report node3
-        let ev3 = node3.listen_once().await.unwrap().0;
+        let ev3 = &reports[1];
         assert_eq!(ev3.signer(), node2.did());
         assert_eq!(ev3.relay.path, vec![node2.did()]);
         assert!(matches!(
@@ -1,10 +1,15 @@
 #![warn(missing_docs)]
+use std::sync::Arc;
+use std::time::Duration;
+
 use async_recursion::async_recursion;
 use async_trait::async_trait;
 
 use crate::dht::vnode::VirtualNode;
 use crate::dht::ChordStorage;
 use crate::dht::ChordStorageCache;
+use crate::dht::ChordStorageReplica;
+use crate::dht::ChordStorageSync;
 use crate::dht::Did;
 use crate::dht::PeerRing;
 use crate::dht::PeerRingAction;
@@ -12,18 +17,30 @@ use crate::dht::PeerRingRemoteAction;
 use crate::error::Error;
 use crate::error::Result;
 use crate::handle_multi_actions;
+use crate::message::types::DeliveryAck;
 use crate::message::types::FoundVNode;
 use crate::message::types::Message;
+use crate::message::types::OperateVNodeReport;
+use crate::message::types::ReplicateVNode;
 use crate::message::types::SearchVNode;
+use crate::message::types::SyncVNodeDigestReport;
+use crate::message::types::SyncVNodeDigestSend;
 use crate::message::types::SyncVNodeWithSuccessor;
 use crate::message::Encoded;
 use crate::message::HandleMsg;
 use crate::message::MessageHandler;
 use crate::message::MessageHandlerEvent;
 use crate::message::MessagePayload;
+use crate::message::MessageVerificationExt;
 use crate::message::PayloadSender;
 use crate::prelude::vnode::VNodeOperation;
+use crate::prelude::vnode::VNodeType;
+use crate::storage::PersistenceStorageReadAndWrite;
+use crate::storage::PersistenceStorageRemove;
+use crate::swarm::positions::PositionSender;
+use crate::swarm::LookupMode;
 use crate::swarm::Swarm;
+use crate::utils::get_epoch_ms;
 
 /// ChordStorageInterface should imply necessary method for DHT storage
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -31,12 +48,26 @@ use crate::swarm::Swarm;
 pub trait ChordStorageInterface<const REDUNDANT: u16> {
     /// fetch virtual node from DHT
     async fn storage_fetch(&self, vid: Did) -> Result<()>;
+    /// fetch virtual node from DHT and wait for it until timeout.
+    /// All the affine copies are queried, and the latest one is returned and cached.
+    /// Return None if no copy is found before timeout.
+    async fn storage_fetch_vnode(&self, vid: Did, timeout: Duration)
+        -> Result<Option<VirtualNode>>;
     /// store virtual node on DHT
     async fn storage_store(&self, vnode: VirtualNode) -> Result<()>;
     /// append data to Data type virtual node
     async fn storage_append_data(&self, topic: &str, data: Encoded) -> Result<()>;
-    /// append data to Data type virtual node uniquely
-    async fn storage_touch_data(&self, topic: &str, data: Encoded) -> Result<()>;
+    /// append data to Data type virtual node uniquely, and refresh its expiry.
+    /// The entry expires after `ttl`, or never if `ttl` is None.
+    async fn storage_touch_data(
+        &self,
+        topic: &str,
+        data: Encoded,
+        ttl: Option<Duration>,
+    ) -> Result<()>;
+    /// remove Data type virtual node owned by current node, or the entries written by
+    /// current node if the virtual node has no owner
+    async fn storage_remove(&self, vid: Did) -> Result<()>;
 }
 
 /// ChordStorageInterfaceCacheChecker defines the interface for checking the local cache of the DHT.
@@ -60,13 +91,22 @@ async fn handle_storage_fetch_act(swarm: &Swarm, act: PeerRingAction) -> Result<
         }
         PeerRingAction::RemoteAction(next, dht_act) => {
             if let PeerRingRemoteAction::FindVNode(vid) = dht_act {
+                // Send to the responsible node directly if it's looked up iteratively.
+                let (next, next_hop) = match swarm.lookup_mode() {
+                    LookupMode::Iterative(config) => {
+                        let route = swarm.lookup_iterative(vid, config).await?;
+                        let holder = route.holder().unwrap_or(next);
+                        (holder, swarm.lookup_next_hop(holder)?)
+                    }
+                    LookupMode::Recursive => (next, swarm.infer_next_hop(None, next)?),
+                };
                 tracing::debug!(
                     "storage_fetch send_message: SearchVNode({:?}) to {:?}",
                     vid,
                     next
                 );
                 swarm
-                    .send_message(Message::SearchVNode(SearchVNode { vid }), next)
+                    .send_message_by_hop(Message::SearchVNode(SearchVNode { vid }), next, next_hop)
                     .await?;
             }
         }
@@ -80,20 +120,48 @@ async fn handle_storage_fetch_act(swarm: &Swarm, act: PeerRingAction) -> Result<
     Ok(())
 }
 
-/// Handle the storage store operations of the peer ring.
+/// Handle the storage store operations of the peer ring, looking up the target in the
+/// [LookupMode] of swarm.
+pub(super) async fn handle_storage_store_act(swarm: &Swarm, act: PeerRingAction) -> Result<()> {
+    handle_position_store_act(&swarm.position_sender(&swarm.dht), act, swarm.lookup_mode()).await
+}
+
+/// Handle the storage store operations of the peer ring, on behalf of a position operated
+/// by swarm, see [crate::swarm::positions].
+/// Stores triggered by incoming messages should use [LookupMode::Recursive], since they are
+/// handled in the listening loop of swarm, which cannot wait for the reports of a lookup.
 #[cfg_attr(feature = "wasm", async_recursion(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_recursion)]
-pub(super) async fn handle_storage_store_act(swarm: &Swarm, act: PeerRingAction) -> Result<()> {
+async fn handle_position_store_act(
+    sender: &PositionSender<'_>,
+    act: PeerRingAction,
+    mode: &LookupMode,
+) -> Result<()> {
     match act {
         PeerRingAction::None => (),
         PeerRingAction::RemoteAction(target, PeerRingRemoteAction::FindVNodeForOperate(op)) => {
-            swarm
-                .send_message(Message::OperateVNode(op), target)
+            // Send to the responsible node directly if it's looked up iteratively.
+            let swarm = sender.swarm();
+            let (target, next_hop) = match mode {
+                LookupMode::Iterative(config) => {
+                    let route = swarm.lookup_iterative(op.did()?, config).await?;
+                    let holder = route.holder().unwrap_or(target);
+                    (holder, swarm.lookup_next_hop(holder)?)
+                }
+                LookupMode::Recursive => (target, sender.infer_next_hop(None, target)?),
+            };
+            sender
+                .send_message_by_hop(Message::OperateVNode(op), target, next_hop)
+                .await?;
+        }
+        PeerRingAction::RemoteAction(target, PeerRingRemoteAction::ReplicateVNode(data)) => {
+            sender
+                .send_direct_message(Message::ReplicateVNode(ReplicateVNode { data }), target)
                 .await?;
         }
         PeerRingAction::MultiActions(acts) => {
             for act in acts {
-                handle_storage_store_act(swarm, act).await?;
+                handle_position_store_act(sender, act, mode).await?;
             }
         }
         act => return Err(Error::PeerRingUnexpectedAction(act)),
@@ -138,6 +206,12 @@ pub(super) async fn handle_storage_operate_act(
 ) -> Result<Vec<MessageHandlerEvent>> {
     match act {
         PeerRingAction::None => Ok(vec![]),
+        PeerRingAction::RemoteAction(target, PeerRingRemoteAction::ReplicateVNode(data)) => {
+            Ok(vec![MessageHandlerEvent::SendDirectMessage(
+                Message::ReplicateVNode(ReplicateVNode { data: data.clone() }),
+                *target,
+            )])
+        }
         PeerRingAction::RemoteAction(next, _) => Ok(vec![MessageHandlerEvent::ResetDestination(
             ctx.clone(),
             *next,
@@ -153,6 +227,108 @@ pub(super) async fn handle_storage_operate_act(
     }
 }
 
+/// Park a payload which cannot reach its destination as a [VNodeType::RelayMessage] vnode.
+/// The payload will be appended to the vnode at the successor of destination.
+pub(crate) async fn handle_relay_message_store(
+    swarm: &Swarm,
+    payload: &MessagePayload,
+) -> Result<()> {
+    let vnode: VirtualNode = payload.clone().try_into()?;
+    let op = VNodeOperation::Extend(vnode);
+    // For relay message, set redundant to 1
+    let act = <PeerRing as ChordStorage<_, 1>>::vnode_operate(&swarm.dht, op, swarm.did()).await?;
+    let sender = swarm.position_sender(&swarm.dht);
+    handle_position_store_act(&sender, act, &LookupMode::Recursive).await
+}
+
+/// Store a vnode synced from predecessor, see [SyncVNodeWithSuccessor].
+/// It's stored on current node directly, even if predecessor is still responsible for it,
+/// since the predecessor may be handing off its range before leaving, see [crate::swarm::leave].
+/// The owner of synced vnode should be proved, and the ownership of local copy or replica
+/// is kept, see [ChordStorageSync::vnode_take_over]. Otherwise an error is returned, so that
+/// the sync is not acknowledged and the predecessor keeps the vnode.
+/// The vnode is stored on the DHT of the position it was synced to, see [crate::swarm::positions].
+pub(crate) async fn handle_storage_sync_store(
+    swarm: &Swarm,
+    dht: &Arc<PeerRing>,
+    vnode: VirtualNode,
+) -> Result<()> {
+    let act = dht.vnode_take_over(vnode).await?;
+    let sender = swarm.position_sender(dht);
+    handle_position_store_act(&sender, act, &LookupMode::Recursive).await
+}
+
+/// Check parked payloads when a node joins the DHT of current node.
+/// If current node is holding the [VNodeType::RelayMessage] vnode of the joined node,
+/// send it to the joined node as [FoundVNode]. It's removed only after the joined node
+/// acknowledges it by [Message::DeliveryAck], see [handle_relay_message_ack].
+/// Parked payloads of current node, which may be synced from its predecessor, will be
+/// delivered directly, and removed after delivered.
+pub(crate) async fn handle_relay_message_join(
+    swarm: &Swarm,
+    did: Did,
+) -> Result<Vec<MessageHandlerEvent>> {
+    let mut events = vec![];
+
+    let vid = VirtualNode::gen_relay_did(did);
+    if let Some(vnode) = parked_relay_message(&swarm.dht, vid).await? {
+        let tx_id = swarm
+            .send_direct_message(
+                Message::FoundVNode(FoundVNode {
+                    data: vec![vnode.clone()],
+                }),
+                did,
+            )
+            .await?;
+        swarm.parked_deliveries.insert(tx_id, (did, vnode));
+    }
+
+    let vid = VirtualNode::gen_relay_did(swarm.did());
+    if let Some(vnode) = parked_relay_message(&swarm.dht, vid).await? {
+        events.push(MessageHandlerEvent::DeliverRelayMessage(vnode, None));
+    }
+
+    Ok(events)
+}
+
+/// Drain the parked payloads acknowledged by [Message::DeliveryAck] in payload.
+/// The ack should be sent by the destination of parked payloads.
+pub(crate) async fn handle_relay_message_ack(
+    swarm: &Swarm,
+    payload: &MessagePayload,
+) -> Result<()> {
+    let Ok(Message::DeliveryAck(ack)) = payload.transaction.data() else {
+        return Ok(());
+    };
+    let origin = payload.origin_position();
+    let Some((_, (_, vnode))) = swarm
+        .parked_deliveries
+        .remove_if(&ack.tx_id, |_, (did, _)| *did == origin)
+    else {
+        return Ok(());
+    };
+    drain_relay_message(&swarm.dht, &vnode).await
+}
+
+async fn parked_relay_message(dht: &PeerRing, vid: Did) -> Result<Option<VirtualNode>> {
+    let vnode: Option<VirtualNode> = dht.storage.get(&vid).await?;
+    Ok(vnode.filter(|v| v.kind == VNodeType::RelayMessage))
+}
+
+/// Remove delivered payloads from the parked [VNodeType::RelayMessage] vnode.
+/// Payloads parked after `delivered` was taken are kept.
+pub(crate) async fn drain_relay_message(dht: &PeerRing, delivered: &VirtualNode) -> Result<()> {
+    let Some(vnode) = parked_relay_message(dht, delivered.did).await? else {
+        return Ok(());
+    };
+    let vnode = vnode.without_data(&delivered.data);
+    if vnode.data.is_empty() {
+        dht.storage.remove(&delivered.did).await?;
+        return Ok(());
+    }
+    dht.storage.put(&delivered.did, &vnode).await
+}
+
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl ChordStorageInterfaceCacheChecker for Swarm {
@@ -174,26 +350,92 @@ impl<const REDUNDANT: u16> ChordStorageInterface<REDUNDANT> for Swarm {
         Ok(())
     }
 
-    /// Store VirtualNode, `TryInto<VirtualNode>` is implemented for alot of types
-    async fn storage_store(&self, vnode: VirtualNode) -> Result<()> {
+    /// Fetch all affine copies of virtual node, local copies are used directly,
+    /// and remote copies are collected from [FoundVNode] until timeout.
+    async fn storage_fetch_vnode(
+        &self,
+        vid: Did,
+        timeout: Duration,
+    ) -> Result<Option<VirtualNode>> {
+        // Register before sending, so that a fast response will not be missed.
+        let vids = vid.rotate_affine(REDUNDANT);
+        let fetch = self.pending_fetches.register(vids.clone());
+        let mut found = vec![];
+        let mut expected = 0;
+        for vid in vids {
+            match <PeerRing as ChordStorage<_, 1>>::vnode_lookup(&self.dht, vid).await? {
+                PeerRingAction::None => {}
+                PeerRingAction::SomeVNode(v) => found.push(v),
+                act => {
+                    expected += 1;
+                    handle_storage_fetch_act(self, act).await?;
+                }
+            }
+        }
+        found.extend(fetch.collect(expected, timeout).await);
+
+        let mut latest: Option<VirtualNode> = None;
+        for vnode in found {
+            latest = Some(match latest {
+                Some(v) => v.latest(vnode)?,
+                None => vnode,
+            });
+        }
+        if let Some(vnode) = latest.clone() {
+            self.dht.local_cache_set(vnode);
+        }
+        Ok(latest)
+    }
+
+    /// Store VirtualNode, `TryInto<VirtualNode>` is implemented for alot of types.
+    /// The version of vnode is generated by the clock of current node.
+    async fn storage_store(&self, mut vnode: VirtualNode) -> Result<()> {
+        vnode.version = self.dht.clock.tick(self.did())?;
         let op = VNodeOperation::Overwrite(vnode);
-        let act = <PeerRing as ChordStorage<_, REDUNDANT>>::vnode_operate(&self.dht, op).await?;
+        let act =
+            <PeerRing as ChordStorage<_, REDUNDANT>>::vnode_operate(&self.dht, op, self.did())
+                .await?;
         handle_storage_store_act(self, act).await?;
         Ok(())
     }
 
     async fn storage_append_data(&self, topic: &str, data: Encoded) -> Result<()> {
-        let vnode: VirtualNode = (topic.to_string(), data).try_into()?;
+        let mut vnode: VirtualNode = (topic.to_string(), data).try_into()?;
+        vnode.version = self.dht.clock.tick(self.did())?;
         let op = VNodeOperation::Extend(vnode);
-        let act = <PeerRing as ChordStorage<_, REDUNDANT>>::vnode_operate(&self.dht, op).await?;
+        let act =
+            <PeerRing as ChordStorage<_, REDUNDANT>>::vnode_operate(&self.dht, op, self.did())
+                .await?;
         handle_storage_store_act(self, act).await?;
         Ok(())
     }
 
-    async fn storage_touch_data(&self, topic: &str, data: Encoded) -> Result<()> {
-        let vnode: VirtualNode = (topic.to_string(), data).try_into()?;
+    async fn storage_touch_data(
+        &self,
+        topic: &str,
+        data: Encoded,
+        ttl: Option<Duration>,
+    ) -> Result<()> {
+        let mut vnode: VirtualNode = (topic.to_string(), data).try_into()?;
+        if let Some(ttl) = ttl {
+            vnode = vnode.with_ttl(ttl);
+        }
+        vnode.version = self.dht.clock.tick(self.did())?;
         let op = VNodeOperation::Touch(vnode);
-        let act = <PeerRing as ChordStorage<_, REDUNDANT>>::vnode_operate(&self.dht, op).await?;
+        let act =
+            <PeerRing as ChordStorage<_, REDUNDANT>>::vnode_operate(&self.dht, op, self.did())
+                .await?;
+        handle_storage_store_act(self, act).await?;
+        Ok(())
+    }
+
+    /// Remove VirtualNode, and drop it from local cache.
+    async fn storage_remove(&self, vid: Did) -> Result<()> {
+        self.dht.cache.remove(&vid);
+        let op = VNodeOperation::Remove(vid);
+        let act =
+            <PeerRing as ChordStorage<_, REDUNDANT>>::vnode_operate(&self.dht, op, self.did())
+                .await?;
         handle_storage_store_act(self, act).await?;
         Ok(())
     }
@@ -210,10 +452,19 @@ impl HandleMsg<SearchVNode> for MessageHandler {
         msg: &SearchVNode,
     ) -> Result<Vec<MessageHandlerEvent>> {
         // For relay message, set redundant to 1
-        match <PeerRing as ChordStorage<_, 1>>::vnode_lookup(&self.dht, msg.vid).await {
-            Ok(action) => handle_storage_search_act(ctx, action).await,
-            Err(e) => Err(e),
+        let action = <PeerRing as ChordStorage<_, 1>>::vnode_lookup(&self.dht, msg.vid).await?;
+        // Answer with replica if the vnode is not stored locally,
+        // which happens when the responsible node is lost.
+        // If both exist, answer with the latest one.
+        let replica = self.dht.replicas.get(&msg.vid);
+        if let Some(replica) = replica.filter(|v| !v.is_expired(get_epoch_ms() as u64)) {
+            let vnode = match action {
+                PeerRingAction::SomeVNode(vnode) => vnode.latest(replica)?,
+                _ => replica,
+            };
+            return handle_storage_search_act(ctx, PeerRingAction::SomeVNode(vnode)).await;
         }
+        handle_storage_search_act(ctx, action).await
     }
 }
 
@@ -228,10 +479,25 @@ impl HandleMsg<FoundVNode> for MessageHandler {
         if self.dht.did != ctx.relay.destination {
             return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
         }
+        let mut events = vec![];
         for data in msg.data.iter().cloned() {
+            if data.kind == VNodeType::RelayMessage
+                && data.did == VirtualNode::gen_relay_did(self.dht.did)
+            {
+                // Parked payloads are acknowledged after delivered, so that the holder can
+                // drain them.
+                events.push(MessageHandlerEvent::DeliverRelayMessage(
+                    data,
+                    Some(ctx.clone()),
+                ));
+                continue;
+            }
+            if data.kind == VNodeType::Subring {
+                events.extend(self.release_subring_broadcasts(&data)?);
+            }
             self.dht.local_cache_set(data);
         }
-        Ok(vec![])
+        Ok(events)
     }
 }
 
@@ -244,9 +510,34 @@ impl HandleMsg<VNodeOperation> for MessageHandler {
         msg: &VNodeOperation,
     ) -> Result<Vec<MessageHandlerEvent>> {
         // For relay message, set redundant to 1
-        let action =
-            <PeerRing as ChordStorage<_, 1>>::vnode_operate(&self.dht, msg.clone()).await?;
-        handle_storage_operate_act(ctx, &action).await
+        let writer = ctx.transaction.signer();
+        match <PeerRing as ChordStorage<_, 1>>::vnode_operate(&self.dht, msg.clone(), writer).await
+        {
+            Ok(action) => handle_storage_operate_act(ctx, &action).await,
+            Err(e) => {
+                tracing::warn!("Failed to operate vnode for {}: {:?}", writer, e);
+                Ok(vec![MessageHandlerEvent::SendReportMessage(
+                    ctx.clone(),
+                    Message::OperateVNodeReport(OperateVNodeReport {
+                        vid: msg.did()?,
+                        error: e.to_string(),
+                    }),
+                )])
+            }
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<OperateVNodeReport> for MessageHandler {
+    async fn handle(
+        &self,
+        _ctx: &MessagePayload,
+        msg: &OperateVNodeReport,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        tracing::warn!("Failed to operate vnode {}: {}", msg.vid, msg.error);
+        Ok(vec![])
     }
 }
 
@@ -256,29 +547,120 @@ impl HandleMsg<SyncVNodeWithSuccessor> for MessageHandler {
     // received remote sync vnode request
     async fn handle(
         &self,
-        _ctx: &MessagePayload,
+        ctx: &MessagePayload,
         msg: &SyncVNodeWithSuccessor,
     ) -> Result<Vec<MessageHandlerEvent>> {
+        // The new successor may not be connected yet, so the sync is relayed.
+        if self.dht.did != ctx.relay.destination {
+            return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
+        }
+        let origin = ctx.origin_position();
         let mut events = vec![];
         for data in msg.data.iter().cloned() {
+            if !self.dht.is_handoff_source(origin, data.did)? {
+                return Err(Error::VNodeHandoffRefused(origin));
+            }
             // only simply store here
             // For relay message, set redundant to 1
             events.push(MessageHandlerEvent::StorageStore(data));
         }
+        // Acknowledged after all vnodes are stored, since events are handled in order
+        // and stopped by the first failure.
+        events.push(MessageHandlerEvent::SendReportMessage(
+            ctx.clone(),
+            Message::DeliveryAck(DeliveryAck {
+                tx_id: ctx.transaction.tx_id,
+            }),
+        ));
         Ok(events)
     }
 }
 
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<ReplicateVNode> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &ReplicateVNode,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        self.dht
+            .vnode_replicate(ctx.origin_position(), msg.data.clone())
+            .await?;
+        Ok(vec![])
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<SyncVNodeDigestSend> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &SyncVNodeDigestSend,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        let (missing, restore) = self
+            .dht
+            .vnode_diff_digests(ctx.origin_position(), msg.range_end, &msg.digests)
+            .await?;
+        if missing.is_empty() && restore.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(vec![MessageHandlerEvent::SendReportMessage(
+            ctx.clone(),
+            Message::SyncVNodeDigestReport(SyncVNodeDigestReport { missing, restore }),
+        )])
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<SyncVNodeDigestReport> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &SyncVNodeDigestReport,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        self.dht
+            .vnode_replicate(ctx.origin_position(), msg.restore.clone())
+            .await?;
+
+        let mut data = vec![];
+        for vid in msg.missing.iter() {
+            let vnode: Option<VirtualNode> = self.dht.storage.get(vid).await?;
+            if let Some(vnode) = vnode {
+                data.push((*vid, vnode));
+            }
+        }
+        if data.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(vec![MessageHandlerEvent::SendDirectMessage(
+            Message::ReplicateVNode(ReplicateVNode { data }),
+            ctx.origin_position(),
+        )])
+    }
+}
+
 #[cfg(not(feature = "wasm"))]
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+
+    use futures::lock::Mutex;
+    use tokio::time::sleep;
+    use tokio::time::Duration;
+
     use super::*;
     use crate::ecc::tests::gen_ordered_keys;
     use crate::message::handlers::connection::tests::test_only_two_nodes_establish_connection;
     use crate::message::Encoder;
     use crate::prelude::vnode::VNodeType;
+    use crate::session::SessionSk;
     use crate::storage::PersistenceStorageOperation;
+    use crate::swarm::callback::SwarmCallback;
     use crate::tests::default::prepare_node;
+    use crate::tests::manually_establish_connection;
 
     #[tokio::test]
     async fn test_store_vnode() -> Result<()> {
@@ -306,7 +688,8 @@ mod test {
         assert!(node1.storage_check_cache(vid).await.is_none());
         assert!(node2.storage_check_cache(vid).await.is_none());
 
-        <Swarm as ChordStorageInterface<1>>::storage_store(&node1, vnode.clone())
+        let owned = vnode.clone().owned_by(node1.session_sk(), vec![])?;
+        <Swarm as ChordStorageInterface<1>>::storage_store(&node1, owned)
             .await
             .unwrap();
         let ev = node2.listen_once().await.unwrap().0;
@@ -340,14 +723,71 @@ mod test {
             Message::FoundVNode(x) if x.data[0].did == vid
         ));
 
-        assert_eq!(
+        assert!(matches!(
             node1.storage_check_cache(vid).await,
-            Some(VirtualNode {
-                did: vid,
-                data: vec![data.encode()?],
-                kind: VNodeType::Data
-            })
+            Some(VirtualNode { did, data: d, kind: VNodeType::Data, owner, version, .. })
+                if did == vid && d == vec![data.encode()?] && owner == Some(node1.did())
+                    && version.writer == Some(node1.did())
+        ));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_vnode_and_wait() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        let data = "Fetch me and wait".to_string();
+        let vnode: VirtualNode = data.clone().try_into().unwrap();
+        let vid = vnode.did;
+
+        // Make sure the data is stored on node2.
+        let (node1, node2) = if vid.in_range(node2.did(), node2.did(), node1.did()) {
+            (node1, node2)
+        } else {
+            (node2, node1)
+        };
+
+        <Swarm as ChordStorageInterface<1>>::storage_store(&node1, vnode).await?;
+        node2.listen_once().await.unwrap();
+        let stored: Option<VirtualNode> = node2.dht().storage.get(&vid).await?;
+
+        // Resolved once FoundVNode arrives.
+        let (found, _, _) = tokio::join!(
+            <Swarm as ChordStorageInterface<1>>::storage_fetch_vnode(
+                &node1,
+                vid,
+                Duration::from_secs(5)
+            ),
+            node2.listen_once(),
+            node1.listen_once(),
+        );
+        let found = found?;
+        assert!(found.is_some());
+        assert_eq!(found, stored);
+        assert_eq!(node1.storage_check_cache(vid).await, stored);
+
+        // Stored locally.
+        let found =
+            <Swarm as ChordStorageInterface<1>>::storage_fetch_vnode(&node2, vid, Duration::ZERO)
+                .await?;
+        assert_eq!(found, stored);
+
+        // Not found until timeout.
+        let missing = VirtualNode::gen_did("Nothing here")?;
+        let (found, _) = tokio::join!(
+            <Swarm as ChordStorageInterface<1>>::storage_fetch_vnode(
+                &node1,
+                missing,
+                Duration::from_millis(500)
+            ),
+            node2.listen_once(),
         );
+        assert_eq!(found?, None);
 
         tokio::fs::remove_dir_all("./tmp").await.ok();
         Ok(())
@@ -391,7 +831,7 @@ mod test {
         let ev = node2.listen_once().await.unwrap().0;
         assert!(matches!(
             ev.transaction.data()?,
-            Message::OperateVNode(VNodeOperation::Extend(VirtualNode { did, data, kind: VNodeType::Data }))
+            Message::OperateVNode(VNodeOperation::Extend(VirtualNode { did, data, kind: VNodeType::Data, .. }))
                 if did == vid && data == vec!["111".to_string().encode()?]
         ));
         <Swarm as ChordStorageInterface<1>>::storage_append_data(
@@ -404,7 +844,7 @@ mod test {
         let ev = node2.listen_once().await.unwrap().0;
         assert!(matches!(
             ev.transaction.data()?,
-            Message::OperateVNode(VNodeOperation::Extend(VirtualNode { did, data, kind: VNodeType::Data }))
+            Message::OperateVNode(VNodeOperation::Extend(VirtualNode { did, data, kind: VNodeType::Data, .. }))
                 if did == vid && data == vec!["222".to_string().encode()?]
         ));
         assert!(node1.storage_check_cache(vid).await.is_none());
@@ -432,14 +872,14 @@ mod test {
             Message::FoundVNode(x) if x.data[0].did == vid
         ));
 
-        assert_eq!(
-            node1.storage_check_cache(vid).await,
-            Some(VirtualNode {
-                did: vid,
-                data: vec!["111".to_string().encode()?, "222".to_string().encode()?],
-                kind: VNodeType::Data
-            })
-        );
+        let cached = node1.storage_check_cache(vid).await.unwrap();
+        assert!(matches!(
+            cached.clone(),
+            VirtualNode { did, data, kind: VNodeType::Data, owner, .. }
+                if did == vid
+                    && data == vec!["111".to_string().encode()?, "222".to_string().encode()?]
+                    && owner.is_none()
+        ));
 
         // Append more data
         <Swarm as ChordStorageInterface<1>>::storage_append_data(
@@ -453,7 +893,7 @@ mod test {
         let ev = node2.listen_once().await.unwrap().0;
         assert!(matches!(
             ev.transaction.data()?,
-            Message::OperateVNode(VNodeOperation::Extend(VirtualNode { did, data, kind: VNodeType::Data }))
+            Message::OperateVNode(VNodeOperation::Extend(VirtualNode { did, data, kind: VNodeType::Data, .. }))
                 if did == vid && data == vec!["333".to_string().encode()?]
         ));
 
@@ -477,18 +917,224 @@ mod test {
             Message::FoundVNode(x) if x.data[0].did == vid
         ));
 
-        assert_eq!(
+        // The cache is replaced by the newer version.
+        assert!(matches!(
             node1.storage_check_cache(vid).await,
-            Some(VirtualNode {
-                did: vid,
-                data: vec![
-                    "111".to_string().encode()?,
-                    "222".to_string().encode()?,
-                    "333".to_string().encode()?
-                ],
-                kind: VNodeType::Data
-            })
-        );
+            Some(VirtualNode { did, data, kind: VNodeType::Data, owner, version, .. })
+                if did == vid
+                    && data == vec![
+                        "111".to_string().encode()?,
+                        "222".to_string().encode()?,
+                        "333".to_string().encode()?
+                    ]
+                    && owner.is_none()
+                    && version > cached.version
+        ));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[cfg(not(feature = "redundant"))]
+    #[tokio::test]
+    async fn test_reject_overwrite_from_non_owner() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (key1, key2) = (keys[0], keys[1]);
+        let (node1, _path1) = prepare_node(key1).await;
+        let (node2, _path2) = prepare_node(key2).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        let topic = "Owned by someone else".to_string();
+        let vnode: VirtualNode = topic.clone().try_into().unwrap();
+        let vid = vnode.did;
+
+        // Make sure the data is stored on node2.
+        let (node1, node2) = if vid.in_range(node2.did(), node2.did(), node1.did()) {
+            (node1, node2)
+        } else {
+            (node2, node1)
+        };
+
+        let owner_sk = SessionSk::new_with_seckey(&crate::ecc::SecretKey::random())?;
+        let owned = VNodeOperation::Overwrite(vnode.clone())
+            .gen_default_vnode()?
+            .operate(
+                VNodeOperation::Overwrite(vnode.owned_by(&owner_sk, vec![])?),
+                owner_sk.account_did(),
+            )?;
+        node2.dht().storage.put(&vid, &owned).await?;
+
+        let other: VirtualNode = (topic, "wiped".to_string()).try_into()?;
+        <Swarm as ChordStorageInterface<1>>::storage_store(&node1, other).await?;
+        let ev = node2.listen_once().await.unwrap().0;
+        assert!(matches!(
+            ev.transaction.data()?,
+            Message::OperateVNode(VNodeOperation::Overwrite(x)) if x.did == vid
+        ));
+
+        // node1 gets the error report, and the data is not changed.
+        let ev = node1.listen_once().await.unwrap().0;
+        assert!(matches!(
+            ev.transaction.data()?,
+            Message::OperateVNodeReport(x) if x.vid == vid
+        ));
+        let stored: Option<VirtualNode> = node2.dht().storage.get(&vid).await?;
+        assert_eq!(stored, Some(owned));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_store_keeps_owner() -> Result<()> {
+        let keys = gen_ordered_keys(1);
+        let (node, _path) = prepare_node(keys[0]).await;
+        let dht = node.dht();
+        let owner_sk = SessionSk::new_with_seckey(&crate::ecc::SecretKey::random())?;
+        let owner = owner_sk.account_did();
+        let thief: Did = crate::ecc::SecretKey::random().address().into();
+
+        let topic = "test_sync_store".to_string();
+        let vnode: VirtualNode = topic.clone().try_into()?;
+        let vid = vnode.did;
+        let owned = VNodeOperation::Overwrite(vnode.clone())
+            .gen_default_vnode()?
+            .operate(
+                VNodeOperation::Overwrite(vnode.owned_by(&owner_sk, vec![])?),
+                owner,
+            )?;
+
+        // The owner is not proved, so it's not stored and the sync fails.
+        let mut synced: VirtualNode = (topic, "synced".to_string()).try_into()?;
+        synced.owner = Some(thief);
+        synced.version = dht.clock.tick(thief)?;
+        assert!(matches!(
+            handle_storage_sync_store(&node, &dht, synced.clone()).await,
+            Err(Error::VNodeOwnerUnverified(did)) if did == thief
+        ));
+        assert_eq!(dht.storage_get(vid).await?, None);
+
+        // A vnode without owner is stored, though there is neither local copy nor replica.
+        synced.owner = None;
+        handle_storage_sync_store(&node, &dht, synced.clone()).await?;
+        assert_eq!(dht.storage_get(vid).await?, Some(synced.clone()));
+        dht.storage.remove(&vid).await?;
+
+        // The owner of replica is kept.
+        dht.replicas.set(&vid, owned.clone());
+        synced.version = dht.clock.tick(thief)?;
+        handle_storage_sync_store(&node, &dht, synced.clone()).await?;
+        assert_eq!(dht.storage_get(vid).await?, Some(owned.clone()));
+
+        // A newer copy of the owner is merged.
+        synced.owner = Some(owner);
+        synced.owner_proof = owned.owner_proof.clone();
+        synced.version = dht.clock.tick(owner)?;
+        handle_storage_sync_store(&node, &dht, synced.clone()).await?;
+        let stored = dht.storage_get(vid).await?.unwrap();
+        assert_eq!(stored.owner, Some(owner));
+        assert_eq!(stored.data, synced.data);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    struct RelayMessageCallback {
+        messages: Mutex<Vec<Vec<u8>>>,
+        failing: bool,
+    }
+
+    #[async_trait]
+    impl SwarmCallback for RelayMessageCallback {
+        async fn on_inbound(
+            &self,
+            payload: &MessagePayload,
+        ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+            if self.failing {
+                return Err("Failed on handling".into());
+            }
+            if let Message::CustomMessage(msg) = payload.transaction.data()? {
+                self.messages.lock().await.push(msg.0);
+            }
+            Ok(())
+        }
+    }
+
+    /// Park a message for node3 on node2 while node3 is offline, then bring node3 online
+    /// with `callback`. Return node2 and the did of parked vnode.
+    async fn relay_message_for_offline_node(
+        callback: Arc<RelayMessageCallback>,
+    ) -> Result<(Arc<Swarm>, Did)> {
+        let keys = gen_ordered_keys(3);
+        let (key1, key2, key3) = (keys[0], keys[1], keys[2]);
+        let (node1, _path1) = prepare_node(key1).await;
+        let (node2, _path2) = prepare_node(key2).await;
+        let did3: Did = key3.address().into();
+        let vid = VirtualNode::gen_relay_did(did3);
+
+        manually_establish_connection(&node1, &node2).await;
+        let (n1, n2) = (node1.clone(), node2.clone());
+        tokio::spawn(async move { n1.listen().await });
+        tokio::spawn(async move { n2.listen().await });
+        sleep(Duration::from_secs(3)).await;
+
+        // node3 is offline, the message should be parked on node2,
+        // which is the successor of node3 in the ring.
+        node1
+            .send_message(Message::custom("hello node3".as_bytes())?, did3)
+            .await?;
+        sleep(Duration::from_secs(3)).await;
+
+        let parked: Option<VirtualNode> = node2.dht().storage.get(&vid).await?;
+        assert_eq!(parked.unwrap().kind, VNodeType::RelayMessage);
+
+        // node3 goes online, node2 should deliver the parked message to it.
+        let (node3, _path3) = prepare_node(key3).await;
+        node3.set_callback(callback).unwrap();
+
+        manually_establish_connection(&node3, &node2).await;
+        sleep(Duration::from_secs(3)).await;
+
+        // The parked message is kept until node3 acknowledges it.
+        let parked: Option<VirtualNode> = node2.dht().storage.get(&vid).await?;
+        assert!(parked.is_some());
+
+        let n3 = node3.clone();
+        tokio::spawn(async move { n3.listen().await });
+        sleep(Duration::from_secs(3)).await;
+        Ok((node2, vid))
+    }
+
+    #[tokio::test]
+    async fn test_relay_message_for_offline_node() -> Result<()> {
+        let cb3 = Arc::new(RelayMessageCallback {
+            messages: Mutex::new(vec![]),
+            failing: false,
+        });
+        let (node2, vid) = relay_message_for_offline_node(cb3.clone()).await?;
+
+        assert_eq!(cb3.messages.lock().await.as_slice(), &["hello node3"
+            .as_bytes()
+            .to_vec()]);
+        let parked: Option<VirtualNode> = node2.dht().storage.get(&vid).await?;
+        assert!(parked.is_none());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_keep_relay_message_not_delivered() -> Result<()> {
+        let cb3 = Arc::new(RelayMessageCallback {
+            messages: Mutex::new(vec![]),
+            failing: true,
+        });
+        let (node2, vid) = relay_message_for_offline_node(cb3.clone()).await?;
+
+        // The message is not acknowledged, so it's kept by node2.
+        assert!(cb3.messages.lock().await.is_empty());
+        let parked: Option<VirtualNode> = node2.dht().storage.get(&vid).await?;
+        assert!(parked.is_some());
 
         tokio::fs::remove_dir_all("./tmp").await.ok();
         Ok(())
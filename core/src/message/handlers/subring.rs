@@ -1,12 +1,37 @@
 #![warn(missing_docs)]
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
 use async_trait::async_trait;
 
 use super::storage::handle_storage_store_act;
+use crate::consts::DEFAULT_TTL_MS;
+use crate::consts::MAX_PARKED_SUBRING_BROADCASTS;
+use crate::dht::subring::Subring;
+use crate::dht::vnode::VirtualNode;
 use crate::dht::ChordStorage;
+use crate::dht::ChordStorageCache;
+use crate::dht::Did;
 use crate::dht::PeerRing;
+use crate::dht::PeerRingAction;
+use crate::dht::PeerRingRemoteAction;
 use crate::error::Result;
+use crate::message::types::CustomMessage;
+use crate::message::types::SearchVNode;
+use crate::message::types::SubringBroadcast;
+use crate::message::ChordStorageInterface;
+use crate::message::Encoder;
+use crate::message::HandleMsg;
+use crate::message::Message;
+use crate::message::MessageHandler;
+use crate::message::MessageHandlerEvent;
+use crate::message::MessagePayload;
+use crate::message::PayloadSender;
 use crate::prelude::vnode::VNodeOperation;
+use crate::prelude::vnode::VNodeType;
 use crate::swarm::Swarm;
+use crate::utils::get_epoch_ms;
 
 /// SubringInterface should imply necessary operator for DHT Subring
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -14,6 +39,28 @@ use crate::swarm::Swarm;
 pub trait SubringInterface<const REDUNDANT: u16> {
     /// join a subring
     async fn subring_join(&self, name: &str) -> Result<()>;
+    /// leave a subring
+    async fn subring_leave(&self, name: &str) -> Result<()>;
+    /// fetch subring from DHT and list its members.
+    /// Return empty list if subring is not found before timeout.
+    async fn subring_members(&self, name: &str, timeout: Duration) -> Result<Vec<Did>>;
+    /// send [CustomMessage] to all members of a subring except current node.
+    /// Return the number of members the message is sent to.
+    async fn send_to_subring(&self, name: &str, msg: &[u8], timeout: Duration) -> Result<usize>;
+}
+
+impl Swarm {
+    async fn subring_fetch<const REDUNDANT: u16>(
+        &self,
+        name: &str,
+        timeout: Duration,
+    ) -> Result<Option<Subring>> {
+        let did = VirtualNode::gen_did(name)?;
+        <Swarm as ChordStorageInterface<REDUNDANT>>::storage_fetch_vnode(self, did, timeout)
+            .await?
+            .map(Subring::try_from)
+            .transpose()
+    }
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -23,8 +70,345 @@ impl<const REDUNDANT: u16> SubringInterface<REDUNDANT> for Swarm {
     /// send direct message with `JoinSubring` type, which will handled by `next` node.
     async fn subring_join(&self, name: &str) -> Result<()> {
         let op = VNodeOperation::JoinSubring(name.to_string(), self.dht.did);
-        let act = <PeerRing as ChordStorage<_, REDUNDANT>>::vnode_operate(&self.dht, op).await?;
+        let act =
+            <PeerRing as ChordStorage<_, REDUNDANT>>::vnode_operate(&self.dht, op, self.dht.did)
+                .await?;
+        handle_storage_store_act(self, act).await?;
+        Ok(())
+    }
+
+    /// remove did from current chord subring.
+    /// send direct message with `LeaveSubring` type, which will handled by `next` node.
+    async fn subring_leave(&self, name: &str) -> Result<()> {
+        let op = VNodeOperation::LeaveSubring(name.to_string(), self.dht.did);
+        let act =
+            <PeerRing as ChordStorage<_, REDUNDANT>>::vnode_operate(&self.dht, op, self.dht.did)
+                .await?;
         handle_storage_store_act(self, act).await?;
         Ok(())
     }
+
+    async fn subring_members(&self, name: &str, timeout: Duration) -> Result<Vec<Did>> {
+        let subring = self.subring_fetch::<REDUNDANT>(name, timeout).await?;
+        Ok(subring.map(|s| s.members).unwrap_or_default())
+    }
+
+    /// The message is signed once by current node, and sent to the fingers of subring.
+    /// Each finger delivers it and forwards to the members it is responsible for.
+    async fn send_to_subring(&self, name: &str, msg: &[u8], timeout: Duration) -> Result<usize> {
+        let Some(subring) = self.subring_fetch::<REDUNDANT>(name, timeout).await? else {
+            return Ok(0);
+        };
+        let did = subring.did()?;
+        let payload = MessagePayload::new_send(
+            Message::CustomMessage(CustomMessage(msg.to_vec())),
+            self.session_sk(),
+            did,
+            did,
+        )?
+        .encode()?;
+
+        let members: Vec<Did> = subring
+            .members
+            .iter()
+            .filter(|m| **m != self.did())
+            .copied()
+            .collect();
+        for (next, range) in Subring::routes(did, &members) {
+            let msg = Message::SubringBroadcast(SubringBroadcast {
+                subring: did,
+                members: range,
+                payload: payload.clone(),
+            });
+            self.send_message(msg, next).await?;
+        }
+        Ok(members.len())
+    }
+}
+
+/// Subring broadcasts received before the members of their subrings are known.
+/// They are released when the subring is found, see [MessageHandler::release_subring_broadcasts].
+#[derive(Default)]
+pub struct ParkedSubringBroadcasts {
+    parked: Mutex<VecDeque<(u128, SubringBroadcast)>>,
+}
+
+impl ParkedSubringBroadcasts {
+    /// Park a broadcast, the oldest one is dropped if full. Broadcasts parked for longer
+    /// than [DEFAULT_TTL_MS] are dropped, since their payloads are expired.
+    fn park(&self, msg: SubringBroadcast) {
+        let now = get_epoch_ms();
+        let mut parked = self.parked.lock().unwrap_or_else(|e| e.into_inner());
+        parked.retain(|(at, _)| at + DEFAULT_TTL_MS as u128 > now);
+        if parked.len() >= MAX_PARKED_SUBRING_BROADCASTS {
+            parked.pop_front();
+        }
+        parked.push_back((now, msg));
+    }
+
+    /// Take the broadcasts parked for a subring.
+    fn take(&self, subring: Did) -> Vec<SubringBroadcast> {
+        let mut parked = self.parked.lock().unwrap_or_else(|e| e.into_inner());
+        let (taken, kept) = parked.drain(..).partition(|(_, m)| m.subring == subring);
+        *parked = kept;
+        taken.into_iter().map(|(_, m)| m).collect::<Vec<_>>()
+    }
+}
+
+impl MessageHandler {
+    /// Members of subring known by current node, from its local storage, replicas or cache.
+    async fn known_subring_members(&self, subring: Did) -> Result<Option<Vec<Did>>> {
+        let now = get_epoch_ms() as u64;
+        let vnode = match self.dht.storage_get(subring).await? {
+            Some(vnode) => Some(vnode),
+            None => self
+                .dht
+                .replicas
+                .get(&subring)
+                .filter(|v| !v.is_expired(now))
+                .or_else(|| self.dht.local_cache_get(subring)),
+        };
+        let Some(vnode) = vnode.filter(|v| v.kind == VNodeType::Subring) else {
+            return Ok(None);
+        };
+        Ok(Some(Subring::try_from(vnode)?.members))
+    }
+
+    /// Deliver the broadcast if current node is a member of subring, and forward it to
+    /// the ones of `msg.members` that are known as members too, so that a sender cannot
+    /// make members forward to nodes out of the subring.
+    fn subring_broadcast_events(
+        &self,
+        msg: &SubringBroadcast,
+        known: &[Did],
+    ) -> Vec<MessageHandlerEvent> {
+        if !known.contains(&self.dht.did) {
+            tracing::warn!("Drop broadcast of subring {} not joined", msg.subring);
+            return vec![];
+        }
+        let members = msg
+            .members
+            .iter()
+            .filter(|m| **m != self.dht.did && known.contains(m))
+            .copied()
+            .collect();
+        vec![MessageHandlerEvent::DeliverSubringMessage(
+            SubringBroadcast {
+                subring: msg.subring,
+                members,
+                payload: msg.payload.clone(),
+            },
+        )]
+    }
+
+    /// Cache a found subring by its did, and release the broadcasts parked for it.
+    pub(super) fn release_subring_broadcasts(
+        &self,
+        vnode: &VirtualNode,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        // The vnode found may be an affine copy, the subring did is the unrotated one.
+        let subring = Subring::try_from(vnode.clone())?;
+        let did = subring.did()?;
+        self.dht.local_cache_set(vnode.clone_with_did(did));
+        Ok(self
+            .parked_broadcasts
+            .take(did)
+            .iter()
+            .flat_map(|msg| self.subring_broadcast_events(msg, &subring.members))
+            .collect())
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<SubringBroadcast> for MessageHandler {
+    /// Deliver the payload to callback, then forward to the members current node is
+    /// responsible for, split by the finger table built from them.
+    /// The members are checked against the subring known by current node. If it's unknown,
+    /// the broadcast is parked until the subring is fetched.
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &SubringBroadcast,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.dht.did != ctx.relay.destination {
+            return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
+        }
+
+        if let Some(known) = self.known_subring_members(msg.subring).await? {
+            return Ok(self.subring_broadcast_events(msg, &known));
+        }
+        self.parked_broadcasts.park(msg.clone());
+        let acts =
+            match <PeerRing as ChordStorage<_, 1>>::vnode_lookup(&self.dht, msg.subring).await? {
+                PeerRingAction::MultiActions(acts) => acts,
+                act => vec![act],
+            };
+        Ok(acts
+            .into_iter()
+            .filter_map(|act| match act {
+                PeerRingAction::RemoteAction(next, PeerRingRemoteAction::FindVNode(vid)) => {
+                    Some(MessageHandlerEvent::SendMessage(
+                        Message::SearchVNode(SearchVNode { vid }),
+                        next,
+                    ))
+                }
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use futures::lock::Mutex;
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::ecc::tests::gen_ordered_keys;
+    use crate::swarm::callback::SwarmCallback;
+    use crate::tests::default::prepare_node;
+    use crate::tests::manually_establish_connection;
+
+    struct SubringMessageCallback {
+        messages: Mutex<Vec<Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl SwarmCallback for SubringMessageCallback {
+        async fn on_inbound(
+            &self,
+            payload: &MessagePayload,
+        ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+            if let Message::CustomMessage(msg) = payload.transaction.data()? {
+                self.messages.lock().await.push(msg.0);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subring_membership_and_broadcast() -> Result<()> {
+        let name = "test_subring";
+        let timeout = Duration::from_secs(3);
+        let mut nodes = vec![];
+        let mut callbacks = vec![];
+        for key in gen_ordered_keys(3) {
+            let (node, _path) = prepare_node(key).await;
+            let cb = Arc::new(SubringMessageCallback {
+                messages: Mutex::new(vec![]),
+            });
+            node.set_callback(cb.clone()).unwrap();
+            nodes.push(node);
+            callbacks.push(cb);
+        }
+        manually_establish_connection(&nodes[0], &nodes[1]).await;
+        manually_establish_connection(&nodes[1], &nodes[2]).await;
+        manually_establish_connection(&nodes[0], &nodes[2]).await;
+        for node in nodes.iter() {
+            let n = node.clone();
+            tokio::spawn(async move { n.listen().await });
+        }
+        sleep(Duration::from_secs(3)).await;
+
+        for node in nodes.iter() {
+            <Swarm as SubringInterface<1>>::subring_join(node, name).await?;
+            sleep(Duration::from_secs(1)).await;
+        }
+        let mut dids: Vec<Did> = nodes.iter().map(|n| n.did()).collect();
+        dids.sort();
+        let members =
+            <Swarm as SubringInterface<1>>::subring_members(&nodes[0], name, timeout).await?;
+        assert_eq!(members, dids);
+
+        // All members except sender receive the message.
+        let sent =
+            <Swarm as SubringInterface<1>>::send_to_subring(&nodes[0], name, b"hello", timeout)
+                .await?;
+        assert_eq!(sent, 2);
+        sleep(Duration::from_secs(3)).await;
+        assert!(callbacks[0].messages.lock().await.is_empty());
+        for cb in callbacks.iter().skip(1) {
+            assert_eq!(cb.messages.lock().await.as_slice(), &[b"hello".to_vec()]);
+        }
+
+        // Left member does not receive the message anymore.
+        <Swarm as SubringInterface<1>>::subring_leave(&nodes[2], name).await?;
+        sleep(Duration::from_secs(1)).await;
+        let members =
+            <Swarm as SubringInterface<1>>::subring_members(&nodes[1], name, timeout).await?;
+        assert!(!members.contains(&nodes[2].did()));
+        assert_eq!(members.len(), 2);
+
+        let sent =
+            <Swarm as SubringInterface<1>>::send_to_subring(&nodes[1], name, b"bye", timeout)
+                .await?;
+        assert_eq!(sent, 1);
+        sleep(Duration::from_secs(3)).await;
+        assert_eq!(callbacks[0].messages.lock().await.as_slice(), &[
+            b"bye".to_vec()
+        ]);
+        assert_eq!(callbacks[2].messages.lock().await.len(), 1);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_subring_broadcast_only_to_known_members() -> Result<()> {
+        let keys = gen_ordered_keys(3);
+        let (node, _path) = prepare_node(keys[0]).await;
+        let member: Did = keys[1].address().into();
+        let stranger: Did = keys[2].address().into();
+        let handler = MessageHandler::new(node.dht());
+
+        // Leaving a subring not existed does not create it.
+        <Swarm as SubringInterface<1>>::subring_leave(&node, "test_unknown").await?;
+        let vid = VirtualNode::gen_did("test_unknown")?;
+        assert!(node.dht().storage_get(vid).await?.is_none());
+
+        let mut subring = Subring::new("test_known", node.did())?;
+        subring.join(node.did());
+        subring.join(member);
+        let msg = SubringBroadcast {
+            subring: subring.did()?,
+            members: vec![member, stranger],
+            payload: "payload".into(),
+        };
+        let forwarded = |events: Vec<MessageHandlerEvent>| {
+            events
+                .into_iter()
+                .map(|ev| match ev {
+                    MessageHandlerEvent::DeliverSubringMessage(msg) => msg.members,
+                    ev => panic!("unexpected event {:?}", ev),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Parked until the subring is found, then forwarded to known members only.
+        handler.parked_broadcasts.park(msg.clone());
+        let vnode: VirtualNode = subring.clone().try_into()?;
+        let vnode = vnode.clone_with_did(vnode.did.rotate_affine(2)[1]);
+        assert_eq!(
+            forwarded(handler.release_subring_broadcasts(&vnode)?),
+            vec![vec![member]]
+        );
+        assert!(handler.parked_broadcasts.take(msg.subring).is_empty());
+        assert_eq!(
+            handler.known_subring_members(msg.subring).await?,
+            Some(subring.members.clone())
+        );
+
+        // Nothing is delivered or forwarded by node not joined.
+        subring.leave(node.did())?;
+        assert!(handler
+            .subring_broadcast_events(&msg, &subring.members)
+            .is_empty());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
 }
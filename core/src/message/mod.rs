@@ -17,6 +17,7 @@ pub mod types;
 pub use types::*;
 
 pub mod handlers;
+pub use handlers::pubsub::PubsubInterface;
 pub use handlers::storage::ChordStorageInterface;
 pub use handlers::storage::ChordStorageInterfaceCacheChecker;
 pub use handlers::subring::SubringInterface;
@@ -166,19 +166,48 @@ impl MessagePayload {
         next_hop: Did,
         destination: Did,
     ) -> Result<Self>
+    where
+        T: Serialize,
+    {
+        Self::new_send_by(
+            data,
+            session_sk,
+            session_sk.account_did(),
+            next_hop,
+            destination,
+        )
+    }
+
+    /// Same as [MessagePayload::new_send], but sent on behalf of `origin`, which should be
+    /// one of the positions operated by the signer, see [crate::swarm::positions].
+    pub fn new_send_by<T>(
+        data: T,
+        session_sk: &SessionSk,
+        origin: Did,
+        next_hop: Did,
+        destination: Did,
+    ) -> Result<Self>
     where
         T: Serialize,
     {
         let tx_id = uuid::Uuid::new_v4();
         let transaction = Transaction::new(destination, tx_id, data, session_sk)?;
-        let relay = MessageRelay::new(
-            vec![session_sk.account_did()],
-            next_hop,
-            transaction.destination,
-        );
+        let relay = MessageRelay::new(vec![origin], next_hop, transaction.destination);
         Self::new(transaction, session_sk, relay)
     }
 
+    /// The position on the ring that sent the message. It's the origin sender if that is
+    /// operated by the signer, see [Did::is_virtual_position_of], otherwise the signer.
+    pub fn origin_position(&self) -> Did {
+        let origin = self.relay.origin_sender();
+        let signer = self.transaction.signer();
+        if origin.is_virtual_position_of(signer) {
+            origin
+        } else {
+            signer
+        }
+    }
+
     /// Deserializes a `MessagePayload` instance from the given binary data.
     pub fn from_bincode(data: &[u8]) -> Result<Self> {
         bincode::deserialize(data).map_err(Error::BincodeDeserialize)
@@ -271,7 +300,13 @@ pub trait PayloadSender {
     where
         T: Serialize + Send,
     {
-        let payload = MessagePayload::new_send(msg, self.session_sk(), next_hop, destination)?;
+        let payload = MessagePayload::new_send_by(
+            msg,
+            self.session_sk(),
+            self.dht().did,
+            next_hop,
+            destination,
+        )?;
         let tx_id = payload.transaction.tx_id;
         self.send_payload(payload).await?;
         Ok(tx_id)
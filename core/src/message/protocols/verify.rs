@@ -9,6 +9,7 @@ use crate::consts::DEFAULT_TTL_MS;
 use crate::consts::MAX_TTL_MS;
 use crate::consts::TS_OFFSET_TOLERANCE_MS;
 use crate::dht::Did;
+use crate::ecc::PublicKey;
 use crate::error::Result;
 use crate::session::Session;
 use crate::session::SessionSk;
@@ -64,6 +65,12 @@ impl MessageVerification {
             })
             .is_ok()
     }
+
+    /// Recover the public key of session which signed the data.
+    pub fn session_pubkey(&self, data: &[u8]) -> Result<PublicKey> {
+        let msg = pack_msg(data, self.ts_ms, self.ttl_ms);
+        self.session.session_pubkey(&msg, &self.sig)
+    }
 }
 
 /// This trait helps a struct with `MessageVerification` field to `verify` itself.
@@ -109,4 +116,10 @@ pub trait MessageVerificationExt {
     fn signer(&self) -> Did {
         self.verification().session.account_did()
     }
+
+    /// Get session public key of signer, which can be used to encrypt data for signer.
+    fn signer_session_pubkey(&self) -> Result<PublicKey> {
+        self.verification()
+            .session_pubkey(&self.verification_data()?)
+    }
 }
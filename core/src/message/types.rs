@@ -7,11 +7,17 @@ use serde::Deserialize;
 use serde::Serialize;
 
 use crate::chunk::Chunk;
+use crate::chunk::ChunkMeta;
+use crate::dht::vnode::VNodeDigest;
 use crate::dht::vnode::VNodeOperation;
 use crate::dht::vnode::VirtualNode;
 use crate::dht::Did;
 use crate::dht::TopoInfo;
+use crate::ecc::PublicKey;
+use crate::error::Error;
 use crate::error::Result;
+use crate::message::Encoded;
+use crate::session::SessionSk;
 
 /// The `Then` trait is used to associate a type with a "then" scenario.
 pub trait Then {
@@ -48,8 +54,10 @@ pub struct FindSuccessorSend {
 /// MessageType use to report origin node with report message.
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct FindSuccessorReport {
-    /// did of target
+    /// did of the successor
     pub did: Did,
+    /// did of target looked up by FindSuccessorSend
+    pub target: Did,
     /// handler event after processed `then` of FindSuccessorSend.
     /// Usually it will contains `then` from FindSuccessorSend,
     /// And when sender received report, it should call related handler for the event
@@ -77,6 +85,8 @@ pub enum QueryFor {
     SyncSuccessor,
     /// For stabilization
     Stabilization,
+    /// For inspecting the ring, see [crate::swarm::Swarm::inspect_ring]
+    Inspection,
 }
 
 /// MessageType for handle [crate::dht::PeerRingRemoteAction::QueryForSuccessorList]
@@ -114,6 +124,14 @@ impl QueryForTopoInfoSend {
         }
     }
 
+    /// Create new instance with QueryFor::Inspection
+    pub fn new_for_inspect(did: Did) -> Self {
+        Self {
+            did,
+            then: QueryFor::Inspection,
+        }
+    }
+
     /// response a send with QueryForTopoInfoSend
     pub fn resp(&self, info: TopoInfo) -> QueryForTopoInfoReport {
         QueryForTopoInfoReport {
@@ -145,6 +163,15 @@ pub struct LeaveDHT {
     pub did: Did,
 }
 
+/// MessageType use to notify neighbours that a node is leaving chord ring gracefully.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NotifyLeaving {
+    /// The did of leaving node
+    pub did: Did,
+    /// Suggested node to replace the leaving one, which is its predecessor or successor
+    pub replacement: Option<Did>,
+}
+
 /// MessageType use to search virtual node.
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SearchVNode {
@@ -166,10 +193,227 @@ pub struct SyncVNodeWithSuccessor {
     pub data: Vec<VirtualNode>,
 }
 
+/// MessageType use to replicate virtual nodes to successors, or restore them to the
+/// responsible node.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ReplicateVNode {
+    /// Virtual nodes paired with the dids they are stored at.
+    pub data: Vec<(Did, VirtualNode)>,
+}
+
+/// MessageType use to compare stored virtual nodes with the replicas on successors.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SyncVNodeDigestSend {
+    /// The successor of sender, sender is responsible for range (sender, range_end].
+    pub range_end: Did,
+    /// Digests of virtual nodes stored on sender.
+    pub digests: Vec<(Did, VNodeDigest)>,
+}
+
+/// MessageType report to origin with the differences of replicas.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SyncVNodeDigestReport {
+    /// Dids of missing or stale replicas, origin should send them again.
+    pub missing: Vec<Did>,
+    /// Replicas that origin is responsible for but does not have.
+    pub restore: Vec<(Did, VirtualNode)>,
+}
+
+/// MessageType report to writer when a [VNodeOperation] is rejected or failed.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OperateVNodeReport {
+    /// The did of target virtual node.
+    pub vid: Did,
+    /// The reason of failure.
+    pub error: String,
+}
+
+/// MessageType use to broadcast a [CustomMessage] to members of a subring.
+/// It's forwarded along the finger tables of members, see
+/// [split_by_finger](crate::dht::subring::split_by_finger).
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SubringBroadcast {
+    /// The did of subring.
+    pub subring: Did,
+    /// Members that receiver should forward to, excluding receiver itself.
+    /// Receiver only forwards to the ones it knows as members of subring.
+    pub members: Vec<Did>,
+    /// Encoded payload of [CustomMessage] signed by origin, whose destination is the subring.
+    pub payload: Encoded,
+}
+
+/// MessageType use to subscribe a topic, sent to the node responsible for the topic.
+/// The subscriber is the signer of message, it should be sent again to renew the lease.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SubscribeTopic {
+    /// The did of topic.
+    pub topic: Did,
+}
+
+/// MessageType use to unsubscribe a topic, sent to the node responsible for the topic.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UnsubscribeTopic {
+    /// The did of topic.
+    pub topic: Did,
+}
+
+/// MessageType use to publish data to a topic, sent to the node responsible for the topic,
+/// which will send [TopicMessage] to all subscribers.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PublishTopic {
+    /// The did of topic.
+    pub topic: Did,
+    /// Published data.
+    pub data: Encoded,
+}
+
+/// MessageType of data published to a topic, sent to subscribers.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct TopicMessage {
+    /// The did of topic.
+    pub topic: Did,
+    /// The signer of [PublishTopic].
+    pub publisher: Did,
+    /// Published data.
+    pub data: Encoded,
+    /// Encoded payload of [PublishTopic] signed by publisher, so that subscribers can
+    /// verify `publisher` and `data` without trusting the node responsible for topic.
+    pub publication: Encoded,
+}
+
+/// MessageType use to broadcast a [CustomMessage] to all nodes on the ring.
+/// The receiver forwards it to the nodes between itself and `limit`, see
+/// [PeerRing::broadcast_routes](crate::dht::PeerRing::broadcast_routes).
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RingBroadcast {
+    /// The end of range that receiver is responsible for, exclusive.
+    pub limit: Did,
+    /// How many hops it can be forwarded further.
+    pub ttl: u8,
+    /// Encoded payload of [CustomMessage] signed by origin.
+    pub payload: Encoded,
+}
+
+/// MessageType use to ask a node for the next hop of iterative lookup,
+/// see [crate::swarm::lookup].
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct QueryNextHopSend {
+    /// did of target
+    pub did: Did,
+    /// max number of next hops in the report.
+    pub count: u8,
+}
+
+/// MessageType use to report the next hop of iterative lookup to origin.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct QueryNextHopReport {
+    /// did of target
+    pub did: Did,
+    /// Result of looking up target on the reporting node.
+    pub step: LookupStep,
+}
+
+/// One step of iterative lookup, answered by [QueryNextHopReport].
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub enum LookupStep {
+    /// The successor of target is found.
+    Found(Did),
+    /// Closer nodes preceding target, the closest one comes first.
+    Next(Vec<Did>),
+}
+
+/// MessageType use to measure round-trip time of a connected peer,
+/// see [crate::swarm::Swarm::ping].
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PingSend {}
+
+/// MessageType use to answer [PingSend].
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PingReport {}
+
+/// MessageType use to announce the positions operated by sender on the ring,
+/// see [crate::swarm::positions]. The positions are derived from the signer by
+/// [Did::virtual_positions], so that the receiver can verify them.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AnnouncePositions {
+    /// Number of positions, including the did of sender.
+    pub count: u16,
+}
+
+/// MessageType use to gossip the estimation of network size with neighbours,
+/// see [crate::dht::estimate].
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct GossipNetworkSize {
+    /// Number of nodes on the ring estimated by sender.
+    pub size: u64,
+}
+
+/// MessageType use to wrap a [CustomMessage] or [EncryptedMessage] whose delivery should be
+/// acknowledged by destination, see [crate::swarm::delivery].
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AckedMessage {
+    /// The wrapped message.
+    pub message: Box<Message>,
+}
+
+/// MessageType use to acknowledge the delivery of an [AckedMessage],
+/// sent from its destination to its signer. It also acknowledges a [SyncVNodeWithSuccessor]
+/// as a report, so that a leaving node can remove the vnodes handed off.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DeliveryAck {
+    /// The tx_id of acknowledged message.
+    pub tx_id: uuid::Uuid,
+}
+
+/// MessageType use to negotiate protocol with a connected peer, sent by both sides when
+/// the connection is established, see [crate::swarm::protocol].
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ProtocolHandshake {
+    /// Protocol version of sender.
+    pub version: u32,
+    /// The lowest protocol version that sender can talk with.
+    pub min_version: u32,
+    /// Id of the network that sender belongs to.
+    pub network_id: String,
+    /// Kinds of message that sender can decode, see [Message::kind].
+    pub messages: Vec<String>,
+    /// Optional features supported by sender, such as `compression`, `encryption` and
+    /// `chunking`.
+    pub features: Vec<String>,
+}
+
+/// MessageType use to offer a stream of chunks to destination, or resume it after interrupted,
+/// see [crate::swarm::stream].
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct StreamOffer {
+    /// Meta of stream, shared by all its chunks. A stream is identified by `meta.id`.
+    pub meta: ChunkMeta,
+    /// Number of chunks in stream.
+    pub total: usize,
+}
+
+/// MessageType use to grant credits to the sender of a stream, sent by its receiver.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct StreamCredit {
+    /// The id of stream.
+    pub id: uuid::Uuid,
+    /// Number of leading chunks received in order, the stream is finished if it's total.
+    pub received: usize,
+    /// Chunks whose index is less than limit can be sent.
+    pub limit: usize,
+    /// Index of chunks below limit that are lost, which should be sent again.
+    pub missing: Vec<usize>,
+}
+
 /// MessageType use to customize message, will be handle by `custom_message` method.
 #[derive(Deserialize, Serialize, Clone)]
 pub struct CustomMessage(pub Vec<u8>);
 
+/// MessageType of encrypted [CustomMessage].
+/// It's sealed by the session public key of destination, relay nodes can only see the ciphertext.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct EncryptedMessage(pub Vec<u8>);
+
 /// MessageType enum Report contain FindSuccessorSend.
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[non_exhaustive]
@@ -228,6 +472,54 @@ pub enum Message {
     QueryForTopoInfoReport(QueryForTopoInfoReport),
     /// A chunk that can be deserialized to a payload.
     Chunk(Chunk),
+    /// Encrypted custom messages
+    EncryptedMessage(EncryptedMessage),
+    /// Remote message of a node leaving DHT gracefully
+    NotifyLeaving(NotifyLeaving),
+    /// Remote message of replicating virtual nodes
+    ReplicateVNode(ReplicateVNode),
+    /// Remote message of comparing virtual nodes with replicas
+    SyncVNodeDigestSend(SyncVNodeDigestSend),
+    /// Response of SyncVNodeDigestSend
+    SyncVNodeDigestReport(SyncVNodeDigestReport),
+    /// Response of OperateVNode when the operation is failed
+    OperateVNodeReport(OperateVNodeReport),
+    /// Remote message of broadcasting to a subring
+    SubringBroadcast(SubringBroadcast),
+    /// Remote message of subscribing a topic
+    SubscribeTopic(SubscribeTopic),
+    /// Remote message of unsubscribing a topic
+    UnsubscribeTopic(UnsubscribeTopic),
+    /// Remote message of publishing to a topic
+    PublishTopic(PublishTopic),
+    /// Data published to a subscribed topic
+    TopicMessage(TopicMessage),
+    /// Remote message of broadcasting to the whole ring
+    RingBroadcast(RingBroadcast),
+    /// Remote message of querying next hop of iterative lookup
+    QueryNextHopSend(QueryNextHopSend),
+    /// Remote message of reporting next hop of iterative lookup
+    QueryNextHopReport(QueryNextHopReport),
+    /// Direct message of measuring round-trip time
+    PingSend(PingSend),
+    /// Response of PingSend
+    PingReport(PingReport),
+    /// Direct message of announcing positions on the ring
+    AnnouncePositions(AnnouncePositions),
+    /// Direct message of gossiping estimation of network size
+    GossipNetworkSize(GossipNetworkSize),
+    /// Custom messages that should be acknowledged by destination
+    AckedMessage(AckedMessage),
+    /// Acknowledgement of AckedMessage
+    DeliveryAck(DeliveryAck),
+    /// Direct message of negotiating protocol with a connected peer
+    ProtocolHandshake(ProtocolHandshake),
+    /// Remote message of offering or resuming a stream
+    StreamOffer(StreamOffer),
+    /// A chunk of stream, identified by its index
+    StreamChunk(Chunk),
+    /// Response of StreamOffer and StreamChunk, granting credits to sender
+    StreamCredit(StreamCredit),
 }
 
 impl std::fmt::Display for Message {
@@ -236,6 +528,66 @@ impl std::fmt::Display for Message {
     }
 }
 
+/// Implement [Message::kind] and [Message::KINDS] by names of variants.
+macro_rules! message_kinds {
+    ($($kind:ident),* $(,)?) => {
+        impl Message {
+            /// Kinds of message that current node can decode, see [Message::kind].
+            pub const KINDS: &'static [&'static str] = &[$(stringify!($kind)),*];
+
+            /// Kind of message, which is the name of its variant.
+            pub fn kind(&self) -> &'static str {
+                match self {
+                    $(Message::$kind(_) => stringify!($kind),)*
+                }
+            }
+        }
+    };
+}
+
+message_kinds!(
+    JoinDHT,
+    LeaveDHT,
+    ConnectNodeSend,
+    ConnectNodeReport,
+    FindSuccessorSend,
+    FindSuccessorReport,
+    NotifyPredecessorSend,
+    NotifyPredecessorReport,
+    SearchVNode,
+    FoundVNode,
+    OperateVNode,
+    SyncVNodeWithSuccessor,
+    CustomMessage,
+    QueryForTopoInfoSend,
+    QueryForTopoInfoReport,
+    Chunk,
+    EncryptedMessage,
+    NotifyLeaving,
+    ReplicateVNode,
+    SyncVNodeDigestSend,
+    SyncVNodeDigestReport,
+    OperateVNodeReport,
+    SubringBroadcast,
+    SubscribeTopic,
+    UnsubscribeTopic,
+    PublishTopic,
+    TopicMessage,
+    RingBroadcast,
+    QueryNextHopSend,
+    QueryNextHopReport,
+    PingSend,
+    PingReport,
+    AnnouncePositions,
+    GossipNetworkSize,
+    AckedMessage,
+    DeliveryAck,
+    ProtocolHandshake,
+    StreamOffer,
+    StreamChunk,
+    StreamCredit,
+);
+
 impl Message {
     /// Wrap a data of message into CustomMessage.
     pub fn custom(msg: &[u8]) -> Result<Message> {
@@ -250,3 +602,24 @@ impl std::fmt::Debug for CustomMessage {
             .finish()
     }
 }
+
+impl EncryptedMessage {
+    /// Encrypt data of [CustomMessage] with session public key of destination.
+    pub fn seal(msg: &[u8], pubkey: &PublicKey) -> Result<Self> {
+        let data = ecies::encrypt(&pubkey.0, msg).map_err(Error::MessageEncryptionFailed)?;
+        Ok(Self(data))
+    }
+
+    /// Decrypt to [CustomMessage] with session_sk of destination.
+    pub fn open(&self, session_sk: &SessionSk) -> Result<CustomMessage> {
+        Ok(CustomMessage(session_sk.decrypt(&self.0)?))
+    }
+}
+
+impl std::fmt::Debug for EncryptedMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedMessage")
+            .field("size", &self.0.len())
+            .finish()
+    }
+}
@@ -13,6 +13,7 @@ pub use crate::message;
 pub use crate::message::ChordStorageInterface;
 pub use crate::message::ChordStorageInterfaceCacheChecker;
 pub use crate::message::MessageRelay;
+pub use crate::message::PubsubInterface;
 pub use crate::message::SubringInterface;
 pub use crate::storage::PersistenceStorage;
 pub use crate::storage::PersistenceStorageReadAndWrite;
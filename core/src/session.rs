@@ -309,6 +309,21 @@ impl Session {
         Ok(())
     }
 
+    /// Recover the public key of session from a message signed by its [SessionSk].
+    /// The key can be used to encrypt data for the holder of [SessionSk].
+    pub fn session_pubkey(&self, msg: &[u8], sig: impl AsRef<[u8]>) -> Result<PublicKey> {
+        let pubkey = signers::secp256k1::recover(msg, sig)?;
+        if Did::from(pubkey.address()) != self.session_id {
+            return Err(Error::VerifySignatureFailed);
+        }
+        Ok(pubkey)
+    }
+
+    /// Get did of session, which is the address of session public key.
+    pub fn session_id(&self) -> Did {
+        self.session_id
+    }
+
     /// Get public key from session for encryption.
     pub fn account_pubkey(&self) -> Result<PublicKey> {
         let auth_bytes = self.pack();
@@ -365,6 +380,16 @@ impl SessionSk {
         self.session.account_did()
     }
 
+    /// Get public key of session, which can be used to encrypt data for this session.
+    pub fn session_pubkey(&self) -> PublicKey {
+        self.sk.pubkey()
+    }
+
+    /// Decrypt data which was encrypted by public key of session.
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        ecies::decrypt(&self.sk.ser(), data).map_err(Error::MessageDecryptionFailed)
+    }
+
     /// Dump session_sk to string, allowing user to save it in a config file.
     /// It can be restored using `SessionSk::from_str`.
     pub fn dump(&self) -> Result<String> {
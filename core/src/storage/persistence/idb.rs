@@ -164,12 +164,14 @@ where
             .await
             .map_err(Error::IDBError)?;
 
+        // Records which cannot be read as `V`, such as the ones of other types or formats,
+        // are skipped.
         Ok(entries
             .iter()
             .filter_map(|(k, v)| {
                 Some((
-                    K::from_str(k.as_string().unwrap().as_str()).ok()?,
-                    js_value::deserialize::<DataStruct<V>>(v).unwrap().data,
+                    K::from_str(k.as_string()?.as_str()).ok()?,
+                    js_value::deserialize::<DataStruct<V>>(v).ok()?.data,
                 ))
             })
             .collect::<Vec<(K, V)>>())
@@ -6,12 +6,20 @@ use std::sync::Arc;
 use std::sync::RwLock;
 
 use crate::channels::Channel;
+use crate::consts::DEFAULT_NETWORK_ID;
+use crate::consts::DEFAULT_REPLAY_WINDOW_SIZE;
+use crate::consts::MAX_VIRTUAL_POSITIONS;
+use crate::dht::Chord;
 use crate::dht::PeerRing;
 use crate::message::MessageHandler;
 use crate::session::SessionSk;
 use crate::storage::PersistenceStorage;
 use crate::swarm::callback::SharedSwarmCallback;
 use crate::swarm::callback::SwarmCallback;
+use crate::swarm::lookup::LookupMode;
+use crate::swarm::positions::VirtualPosition;
+use crate::swarm::protocol::PeerProtocols;
+use crate::swarm::replay::ReplayWindow;
 use crate::swarm::MeasureImpl;
 use crate::swarm::Swarm;
 use crate::types::channel::Channel as ChannelTrait;
@@ -25,11 +33,16 @@ pub struct SwarmBuilder {
     ice_servers: String,
     external_address: Option<String>,
     dht_succ_max: u8,
+    dht_replication_factor: u8,
     dht_storage: PersistenceStorage,
     session_sk: SessionSk,
     session_ttl: Option<usize>,
     measure: Option<MeasureImpl>,
     callback: Option<SharedSwarmCallback>,
+    replay_window_size: usize,
+    lookup_mode: LookupMode,
+    virtual_position_storages: Vec<PersistenceStorage>,
+    network_id: String,
 }
 
 impl SwarmBuilder {
@@ -39,11 +52,16 @@ impl SwarmBuilder {
             ice_servers: ice_servers.to_string(),
             external_address: None,
             dht_succ_max: 3,
+            dht_replication_factor: 0,
             dht_storage,
             session_sk,
             session_ttl: None,
             measure: None,
             callback: None,
+            replay_window_size: DEFAULT_REPLAY_WINDOW_SIZE,
+            lookup_mode: LookupMode::default(),
+            virtual_position_storages: vec![],
+            network_id: DEFAULT_NETWORK_ID.to_string(),
         }
     }
 
@@ -53,6 +71,13 @@ impl SwarmBuilder {
         self
     }
 
+    /// Sets up how many successors should keep replicas of vnodes stored on current node.
+    /// Replication is disabled by default.
+    pub fn dht_replication_factor(mut self, replication_factor: u8) -> Self {
+        self.dht_replication_factor = replication_factor;
+        self
+    }
+
     /// Sets up the external address for swarm transport.
     /// This will be used to configure the transport to listen for WebRTC connections in "HOST" mode.
     pub fn external_address(mut self, external_address: String) -> Self {
@@ -78,18 +103,71 @@ impl SwarmBuilder {
         self
     }
 
+    /// Setup the capacity of replay window, which is used to drop duplicated messages.
+    /// The same capacity is used to drop duplicated ring-wide broadcasts.
+    /// Set it to zero will disable the replay protection.
+    pub fn replay_window_size(mut self, size: usize) -> Self {
+        self.replay_window_size = size;
+        self
+    }
+
+    /// Setup the mode of looking up the successor of a did, used by connecting and
+    /// fetching virtual nodes. [LookupMode::Recursive] is used by default.
+    pub fn lookup_mode(mut self, mode: LookupMode) -> Self {
+        self.lookup_mode = mode;
+        self
+    }
+
+    /// Operate a virtual position on the ring for each given storage, besides the did of swarm.
+    /// The positions are derived from the did, and each of them keeps its own successors,
+    /// predecessor and vnodes in the storage, see [crate::swarm::positions].
+    /// At most [MAX_VIRTUAL_POSITIONS] positions are operated, including the did.
+    pub fn virtual_positions(mut self, storages: Vec<PersistenceStorage>) -> Self {
+        self.virtual_position_storages = storages;
+        self.virtual_position_storages
+            .truncate(MAX_VIRTUAL_POSITIONS as usize - 1);
+        self
+    }
+
+    /// Setup the id of network that swarm belongs to. Peers of other networks are refused
+    /// when connected, see [crate::swarm::protocol]. [DEFAULT_NETWORK_ID] is used by default.
+    pub fn network_id(mut self, network_id: String) -> Self {
+        self.network_id = network_id;
+        self
+    }
+
     /// Try build for `Swarm`.
     pub fn build(self) -> Swarm {
         let dht_did = self.session_sk.account_did();
 
-        let dht = Arc::new(PeerRing::new_with_storage(
-            dht_did,
-            self.dht_succ_max,
-            self.dht_storage,
-        ));
+        let dht = Arc::new(
+            PeerRing::new_with_storage(dht_did, self.dht_succ_max, self.dht_storage)
+                .with_replication_factor(self.dht_replication_factor),
+        );
 
         let message_handler = MessageHandler::new(dht.clone());
 
+        let virtual_positions: Vec<VirtualPosition> = self
+            .virtual_position_storages
+            .into_iter()
+            .zip(1..)
+            .map(|(storage, index)| {
+                let did = dht_did.virtual_position(index);
+                let dht = PeerRing::new_with_storage(did, self.dht_succ_max, storage)
+                    .with_replication_factor(self.dht_replication_factor);
+                VirtualPosition::new(Arc::new(dht))
+            })
+            .collect();
+
+        // Local positions know each other without connection, see [crate::swarm::positions].
+        let dhts = std::iter::once(&dht).chain(virtual_positions.iter().map(|p| &p.dht));
+        for a in dhts.clone() {
+            for b in dhts.clone() {
+                // Join a node never fails, and the remote action is not needed.
+                a.join(b.did).ok();
+            }
+        }
+
         let transport_event_channel = Channel::new();
         let transport = Box::new(Transport::new(&self.ice_servers, self.external_address));
 
@@ -101,11 +179,26 @@ impl SwarmBuilder {
         Swarm {
             transport_event_channel,
             dht,
-            measure: self.measure,
+            measure: self.measure.map(Arc::new),
             session_sk: self.session_sk,
             message_handler,
             transport,
             callback,
+            pending_requests: Default::default(),
+            pending_fetches: Default::default(),
+            deliveries: Default::default(),
+            parked_deliveries: Default::default(),
+            streams: Default::default(),
+            topic_inbox: Default::default(),
+            replay_window: Arc::new(ReplayWindow::new(self.replay_window_size)),
+            broadcast_window: Arc::new(ReplayWindow::new(self.replay_window_size)),
+            session_pubkeys: Default::default(),
+            session_pubkey_published_at: Default::default(),
+            lookup_mode: self.lookup_mode,
+            virtual_positions,
+            position_aliases: Default::default(),
+            peer_protocols: Arc::new(PeerProtocols::new(&self.network_id)),
+            relayed_chunks: Default::default(),
         }
     }
 }
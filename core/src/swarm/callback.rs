@@ -11,9 +11,18 @@ use crate::chunk::ChunkList;
 use crate::chunk::ChunkManager;
 use crate::consts::TRANSPORT_MTU;
 use crate::dht::Did;
+use crate::measure::MeasureCounter;
 use crate::message::Message;
 use crate::message::MessagePayload;
 use crate::message::MessageVerificationExt;
+use crate::message::ProtocolHandshake;
+use crate::session::SessionSk;
+use crate::swarm::delivery::DeliveryStatus;
+use crate::swarm::encryption::decrypt_payload;
+use crate::swarm::protocol::PeerProtocols;
+use crate::swarm::request::PendingRequests;
+use crate::swarm::MeasureImpl;
+use crate::swarm::ReplayWindow;
 use crate::types::channel::Channel as ChannelTrait;
 use crate::types::channel::TransportEvent;
 
@@ -39,6 +48,34 @@ pub enum SwarmEvent {
         /// The final state of the connection.
         state: WebrtcConnectionState,
     },
+    /// Indicates that the delivery status of a message sent with ack has changed,
+    /// see [crate::swarm::delivery].
+    DeliveryStatusChange {
+        /// The tx_id of first attempt of the message.
+        tx_id: uuid::Uuid,
+        /// The destination of the message.
+        destination: Did,
+        /// The new status of delivery.
+        status: DeliveryStatus,
+    },
+    /// Indicates that a peer is disconnected since its protocol is incompatible,
+    /// see [crate::swarm::protocol].
+    IncompatiblePeer {
+        /// The did of remote peer.
+        peer: Did,
+        /// Why the peer is incompatible.
+        reason: String,
+    },
+    /// Indicates that a stream is offered by a peer, whose chunks can be read by
+    /// [crate::swarm::Swarm::read_stream], see [crate::swarm::stream].
+    StreamOffered {
+        /// The id of stream.
+        id: uuid::Uuid,
+        /// The sender of stream.
+        sender: Did,
+        /// Number of chunks in stream.
+        total: usize,
+    },
 }
 
 /// Any object that implements this trait can be used as a callback for the swarm.
@@ -52,6 +89,8 @@ pub trait SwarmCallback {
 
     /// This method is invoked when a new message is received and after handling.
     /// Will not be invoked if the message is not for this node.
+    /// The [Message::EncryptedMessage] is decrypted to [Message::CustomMessage] before invoking,
+    /// and the [Message::AckedMessage] is unwrapped.
     async fn on_inbound(&self, _payload: &MessagePayload) -> Result<(), CallbackError> {
         Ok(())
     }
@@ -65,23 +104,45 @@ pub trait SwarmCallback {
 /// [InnerSwarmCallback] wraps [SharedSwarmCallback] with inner handling for a specific connection.
 pub struct InnerSwarmCallback {
     did: Did,
+    session_sk: SessionSk,
     transport_event_sender: TransportEventSender,
     callback: SharedSwarmCallback,
     chunk_list: Arc<FuturesMutex<ChunkList<TRANSPORT_MTU>>>,
+    replay_window: Arc<ReplayWindow>,
+    peer_protocols: Arc<PeerProtocols>,
+    pending_requests: PendingRequests,
+    measure: Option<Arc<MeasureImpl>>,
 }
 
 impl InnerSwarmCallback {
-    /// Create a new [InnerSwarmCallback] with the provided did, transport_event_sender and callback.
+    /// Create a new [InnerSwarmCallback] with the provided session_sk, transport_event_sender
+    /// and callback. The session_sk is used to decrypt [Message::EncryptedMessage] for
+    /// [SwarmCallback::on_inbound].
+    /// The replay_window is shared between connections to drop duplicated messages,
+    /// which will be counted by measure.
+    /// The peer_protocols is shared between connections to remember protocols negotiated
+    /// with peers, see [crate::swarm::protocol].
+    /// The pending_requests is shared with swarm to hold responses of requests back from
+    /// [SwarmCallback::on_inbound], see [crate::swarm::request].
     pub fn new(
-        did: Did,
+        session_sk: SessionSk,
         transport_event_sender: TransportEventSender,
         callback: SharedSwarmCallback,
+        replay_window: Arc<ReplayWindow>,
+        peer_protocols: Arc<PeerProtocols>,
+        pending_requests: PendingRequests,
+        measure: Option<Arc<MeasureImpl>>,
     ) -> Self {
         Self {
-            did,
+            did: session_sk.account_did(),
+            session_sk,
             transport_event_sender,
             callback,
             chunk_list: Default::default(),
+            replay_window,
+            peer_protocols,
+            pending_requests,
+            measure,
         }
     }
 
@@ -89,22 +150,66 @@ impl InnerSwarmCallback {
         &self,
         cid: &str,
         payload: &MessagePayload,
+        message: Message,
     ) -> Result<(), CallbackError> {
-        let message: Message = payload.transaction.data()?;
-
         if let Message::Chunk(msg) = message {
+            // Chunks addressed to other nodes are relayed by swarm one by one.
+            let destination = payload.transaction.destination;
+            if destination != self.did && !destination.is_virtual_position_of(self.did) {
+                return Ok(());
+            }
             if let Some(data) = self.chunk_list.lock().await.handle(msg.clone()) {
-                return self.on_message(cid, &data).await;
+                // The chunks may be relayed by other nodes after the chunker,
+                // which should be on the relay path of the message.
+                let mut inner = MessagePayload::from_bincode(&data)?;
+                inner.relay.path.extend(payload.relay.path.iter().skip(1));
+                inner.relay.next_hop = payload.relay.next_hop;
+                return self.on_message(cid, &inner.to_bincode()?).await;
             }
             return Ok(());
         };
 
-        if payload.transaction.destination == self.did {
-            self.callback.on_inbound(payload).await?;
+        // Responses are resolved by swarm for the pending requests.
+        if payload.transaction.destination == self.did && !self.pending_requests.is_pending(payload)
+        {
+            let payload = decrypt_payload(payload, &self.session_sk)?;
+            self.callback.on_inbound(&payload).await?;
         }
 
         Ok(())
     }
+
+    /// Negotiate protocol with the handshake sent by peer of the connection.
+    /// An incompatible peer will be disconnected by swarm.
+    async fn handle_handshake(
+        &self,
+        cid: &str,
+        payload: &MessagePayload,
+        handshake: &ProtocolHandshake,
+    ) -> Result<(), CallbackError> {
+        let did = payload.transaction.signer();
+        if did.to_string() != cid {
+            return Err("Handshake is not signed by peer of connection".into());
+        }
+        let Err(reason) = self.peer_protocols.negotiate(did, handshake) else {
+            return Ok(());
+        };
+
+        tracing::error!("Disconnect incompatible peer {}: {}", did, reason);
+        Channel::send(
+            &self.transport_event_sender,
+            TransportEvent::Incompatible(did),
+        )
+        .await
+        .map_err(Box::new)?;
+        self.callback
+            .on_event(&SwarmEvent::IncompatiblePeer {
+                peer: did,
+                reason: reason.clone(),
+            })
+            .await?;
+        Err(reason.into())
+    }
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -116,8 +221,30 @@ impl TransportCallback for InnerSwarmCallback {
             tracing::error!("Cannot verify msg or it's expired: {:?}", payload);
             return Err("Cannot verify msg or it's expired".into());
         }
+        if self.peer_protocols.is_refused(Did::from_str(cid)?) {
+            tracing::warn!("Drop msg from incompatible peer {}: {:?}", cid, payload);
+            return Err("Msg from incompatible peer".into());
+        }
+        let message: Message = payload.transaction.data()?;
+        if !matches!(message, Message::ProtocolHandshake(_))
+            && self.peer_protocols.requires_handshake(Did::from_str(cid)?)
+        {
+            tracing::warn!("Drop msg from peer {} without handshake: {:?}", cid, payload);
+            return Err("Msg from peer without handshake".into());
+        }
+        if !self.replay_window.check(&payload) {
+            tracing::warn!("Drop duplicated msg: {:?}", payload);
+            if let (Some(measure), Ok(did)) = (&self.measure, Did::from_str(cid)) {
+                measure.incr(did, MeasureCounter::Duplicated).await;
+            }
+            return Err("Duplicated msg".into());
+        }
         self.callback.on_validate(&payload).await?;
 
+        if let Message::ProtocolHandshake(handshake) = &message {
+            return self.handle_handshake(cid, &payload, handshake).await;
+        }
+
         Channel::send(
             &self.transport_event_sender,
             TransportEvent::DataChannelMessage(msg.into()),
@@ -125,7 +252,7 @@ impl TransportCallback for InnerSwarmCallback {
         .await
         .map_err(Box::new)?;
 
-        self.handle_payload(cid, &payload).await
+        self.handle_payload(cid, &payload, message).await
     }
 
     async fn on_peer_connection_state_change(
@@ -0,0 +1,381 @@
+#![warn(missing_docs)]
+//! Ring crawler for diagnosis.
+//!
+//! [crate::inspect::DHTInspect] shows only the view of current node. Starting from current node,
+//! [Swarm::inspect_ring] follows the first successor of each node, and queries its [TopoInfo] by
+//! [Message::QueryForTopoInfoSend], until the walk returns to current node.
+//!
+//! Invariants of correct Chord (see [crate::dht::CorrectChord]) are checked along the walk:
+//! a single ordered ring without loops, agreement of predecessor and successor list between
+//! neighbours, and full successor lists. Violations are reported as [RingAnomaly].
+
+use std::time::Duration;
+
+use num_bigint::BigUint;
+
+use crate::consts::MAX_RING_INSPECT_NODES;
+use crate::consts::RING_INSPECT_QUERY_TIMEOUT_MS;
+use crate::dht::validation;
+use crate::dht::Did;
+use crate::dht::TopoInfo;
+use crate::error::Error;
+use crate::error::Result;
+use crate::inspect::RingAnomaly;
+use crate::inspect::RingInspect;
+use crate::message::Message;
+use crate::message::MessagePayload;
+use crate::message::PayloadSender;
+use crate::message::QueryForTopoInfoSend;
+use crate::swarm::Swarm;
+
+/// Checker of ring invariants, fed with topological info of nodes along successors.
+struct RingChecker {
+    origin: Did,
+    succ_max: usize,
+    walk: Vec<(Did, TopoInfo)>,
+    complete: bool,
+    ended: bool,
+    anomalies: Vec<RingAnomaly>,
+}
+
+impl RingChecker {
+    fn new(origin: Did, succ_max: usize, info: TopoInfo) -> Self {
+        Self {
+            origin,
+            succ_max,
+            walk: vec![(origin, info)],
+            complete: false,
+            ended: false,
+            anomalies: vec![],
+        }
+    }
+
+    fn end(&mut self, anomaly: RingAnomaly) {
+        self.anomalies.push(anomaly);
+        self.ended = true;
+    }
+
+    /// The next node to visit, which is the successor of last visited node.
+    /// Returns `None` when the walk is ended, either back to origin or by an anomaly.
+    fn next(&mut self) -> Option<Did> {
+        if self.ended {
+            return None;
+        }
+
+        let (did, info) = self.walk.last()?;
+        let did = *did;
+        let Some(succ) = info.successors.first().copied() else {
+            self.end(RingAnomaly::NoSuccessor {
+                did: did.to_string(),
+            });
+            return None;
+        };
+
+        if succ == self.origin {
+            self.complete = true;
+            self.ended = true;
+            return None;
+        }
+        if self.walk.iter().any(|(v, _)| *v == succ) {
+            self.end(RingAnomaly::Loop {
+                did: did.to_string(),
+                successor: succ.to_string(),
+            });
+            return None;
+        }
+        if succ.bias(self.origin) < did.bias(self.origin) {
+            self.end(RingAnomaly::Disordered {
+                did: did.to_string(),
+                successor: succ.to_string(),
+            });
+            return None;
+        }
+        if self.walk.len() >= MAX_RING_INSPECT_NODES {
+            self.ended = true;
+            return None;
+        }
+
+        Some(succ)
+    }
+
+    /// Visit a node with its reported topological info, `None` if it's not reported.
+    fn visit(&mut self, did: Did, info: Option<TopoInfo>) {
+        let Some(info) = info else {
+            self.end(RingAnomaly::Unreachable {
+                did: did.to_string(),
+            });
+            return;
+        };
+        if !validation::is_valid_topo_info(did, &info) {
+            self.end(RingAnomaly::InvalidTopoInfo {
+                did: did.to_string(),
+            });
+            return;
+        }
+
+        if let Some((prev, prev_info)) = self.walk.last().cloned() {
+            self.check_neighbours(prev, &prev_info, did, &info);
+        }
+        self.walk.push((did, info));
+    }
+
+    /// Check the agreement between a node and its successor.
+    fn check_neighbours(&mut self, did: Did, info: &TopoInfo, succ: Did, succ_info: &TopoInfo) {
+        if succ_info.predecessor != Some(did) {
+            self.anomalies.push(RingAnomaly::PredecessorMismatch {
+                did: did.to_string(),
+                successor: succ.to_string(),
+                predecessor: succ_info.predecessor.map(|p| p.to_string()),
+            });
+        }
+
+        // The successor list of a node is its successor followed by the list of its successor.
+        let agreed = info
+            .successors
+            .iter()
+            .skip(1)
+            .zip(succ_info.successors.iter())
+            .all(|(a, b)| a == b);
+        if !agreed {
+            self.anomalies.push(RingAnomaly::SuccessorListMismatch {
+                did: did.to_string(),
+                successor: succ.to_string(),
+            });
+        }
+    }
+
+    /// Number of nodes on the ring. If the walk is incomplete, it's estimated by the
+    /// density of visited nodes on the arc from origin.
+    fn estimated_size(&self) -> u64 {
+        let n = self.walk.len() as u64;
+        if self.complete || n < 2 {
+            return n;
+        }
+
+        let (last, _) = &self.walk[self.walk.len() - 1];
+        let arc = BigUint::from(*last - self.origin);
+        let ring = BigUint::from(1u8) << 160;
+        let size = ring * (n - 1) / arc;
+        u64::try_from(size).unwrap_or(u64::MAX).max(n)
+    }
+
+    fn finish(mut self) -> RingInspect {
+        if self.complete && self.walk.len() > 1 {
+            let (last, last_info) = self.walk[self.walk.len() - 1].clone();
+            let (origin, origin_info) = self.walk[0].clone();
+            self.check_neighbours(last, &last_info, origin, &origin_info);
+        }
+
+        let size = self.estimated_size();
+        let expected = self.succ_max.min(size.saturating_sub(1) as usize);
+        for (did, info) in self.walk.iter() {
+            if info.successors.len() < expected {
+                self.anomalies.push(RingAnomaly::ShortSuccessorList {
+                    did: did.to_string(),
+                    len: info.successors.len(),
+                    expected,
+                });
+            }
+        }
+
+        RingInspect {
+            did: self.origin.to_string(),
+            nodes: self.walk.iter().map(|(did, _)| did.to_string()).collect(),
+            complete: self.complete,
+            estimated_size: size,
+            anomalies: self.anomalies,
+        }
+    }
+}
+
+impl RingInspect {
+    /// Walk the ring along successors from current node of swarm.
+    pub async fn inspect(swarm: &Swarm) -> Self {
+        let dht = swarm.dht();
+        let info = TopoInfo::try_from(dht.as_ref()).unwrap_or(TopoInfo {
+            successors: vec![],
+            predecessor: None,
+        });
+
+        let mut checker = RingChecker::new(dht.did, dht.successors().capacity(), info);
+        while let Some(did) = checker.next() {
+            let info = swarm
+                .query_topo_info(did)
+                .await
+                .map_err(|e| tracing::debug!("Failed to query TopoInfo of {}: {:?}", did, e))
+                .ok();
+            checker.visit(did, info);
+        }
+        checker.finish()
+    }
+}
+
+impl Swarm {
+    /// Query topological info of a node, the message is routed if it's not connected.
+    async fn query_topo_info(&self, did: Did) -> Result<TopoInfo> {
+        let msg = Message::QueryForTopoInfoSend(QueryForTopoInfoSend::new_for_inspect(did));
+        let next_hop = self.infer_next_hop(None, did)?;
+        let payload = MessagePayload::new_send(msg, &self.session_sk, next_hop, did)?;
+
+        // Register before sending, so that a fast report will not be missed.
+        let req = self
+            .pending_requests
+            .register(payload.transaction.tx_id, did);
+        self.send_payload(payload).await?;
+
+        let timeout = Duration::from_millis(RING_INSPECT_QUERY_TIMEOUT_MS);
+        match req.wait(timeout).await?.transaction.data()? {
+            Message::QueryForTopoInfoReport(report) => Ok(report.info),
+            msg => Err(Error::InvalidMessage(format!(
+                "unexpected report of topological info: {msg}"
+            ))),
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::dht::tests::gen_ordered_dids;
+    use crate::ecc::tests::gen_ordered_keys;
+    use crate::tests::default::prepare_node;
+    use crate::tests::manually_establish_connection;
+
+    /// Topological info of `dids[i]` on a stable ring of `dids`.
+    fn stable_info(dids: &[Did], i: usize, succ_max: usize) -> TopoInfo {
+        let n = dids.len();
+        TopoInfo {
+            successors: (1..n.min(succ_max + 1))
+                .map(|k| dids[(i + k) % n])
+                .collect(),
+            predecessor: Some(dids[(i + n - 1) % n]),
+        }
+    }
+
+    #[test]
+    fn test_check_stable_ring() {
+        let dids = gen_ordered_dids(5);
+        let mut checker = RingChecker::new(dids[0], 3, stable_info(&dids, 0, 3));
+        while let Some(did) = checker.next() {
+            let i = dids.iter().position(|v| *v == did).unwrap();
+            checker.visit(did, Some(stable_info(&dids, i, 3)));
+        }
+
+        let report = checker.finish();
+        assert!(report.complete);
+        assert_eq!(report.estimated_size, 5);
+        assert_eq!(
+            report.nodes,
+            dids.iter().map(|d| d.to_string()).collect::<Vec<_>>()
+        );
+        assert_eq!(report.anomalies, vec![]);
+    }
+
+    #[test]
+    fn test_check_broken_ring() {
+        let dids = gen_ordered_dids(5);
+
+        // dids[2] lost its predecessor and a successor, and dids[3] skips over origin.
+        let mut checker = RingChecker::new(dids[0], 3, stable_info(&dids, 0, 3));
+        assert_eq!(checker.next(), Some(dids[1]));
+        checker.visit(dids[1], Some(stable_info(&dids, 1, 3)));
+        assert_eq!(checker.next(), Some(dids[2]));
+        checker.visit(
+            dids[2],
+            Some(TopoInfo {
+                successors: vec![dids[3], dids[4]],
+                predecessor: None,
+            }),
+        );
+        assert_eq!(checker.next(), Some(dids[3]));
+        checker.visit(
+            dids[3],
+            Some(TopoInfo {
+                successors: vec![dids[1], dids[2]],
+                predecessor: Some(dids[2]),
+            }),
+        );
+        assert_eq!(checker.next(), None);
+
+        let report = checker.finish();
+        assert!(!report.complete);
+        assert_eq!(report.nodes.len(), 4);
+        assert_eq!(report.anomalies, vec![
+            RingAnomaly::PredecessorMismatch {
+                did: dids[1].to_string(),
+                successor: dids[2].to_string(),
+                predecessor: None,
+            },
+            RingAnomaly::SuccessorListMismatch {
+                did: dids[2].to_string(),
+                successor: dids[3].to_string(),
+            },
+            RingAnomaly::Loop {
+                did: dids[3].to_string(),
+                successor: dids[1].to_string(),
+            },
+            RingAnomaly::ShortSuccessorList {
+                did: dids[2].to_string(),
+                len: 2,
+                expected: 3,
+            },
+            RingAnomaly::ShortSuccessorList {
+                did: dids[3].to_string(),
+                len: 2,
+                expected: 3,
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_check_unreachable() {
+        let dids = gen_ordered_dids(3);
+        let mut checker = RingChecker::new(dids[0], 3, stable_info(&dids, 0, 3));
+        assert_eq!(checker.next(), Some(dids[1]));
+        checker.visit(dids[1], None);
+        assert_eq!(checker.next(), None);
+
+        let report = checker.finish();
+        assert!(!report.complete);
+        assert!(report.estimated_size >= 1);
+        assert_eq!(report.anomalies, vec![RingAnomaly::Unreachable {
+            did: dids[1].to_string()
+        }]);
+    }
+
+    #[tokio::test]
+    async fn test_inspect_ring() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        manually_establish_connection(&node1, &node2).await;
+
+        let n1 = node1.clone();
+        let n2 = node2.clone();
+        tokio::spawn(async move { n1.listen().await });
+        tokio::spawn(async move { n2.listen().await });
+        sleep(Duration::from_secs(1)).await;
+
+        // Predecessors are set by stabilization.
+        let report = node1.inspect_ring().await;
+        assert!(report.complete);
+        assert_eq!(report.estimated_size, 2);
+        assert_eq!(report.anomalies.len(), 2);
+
+        *node1.dht().lock_predecessor()? = Some(node2.did());
+        *node2.dht().lock_predecessor()? = Some(node1.did());
+        let report = node1.inspect_ring().await;
+        assert!(report.complete);
+        assert_eq!(report.nodes, vec![
+            node1.did().to_string(),
+            node2.did().to_string()
+        ]);
+        assert_eq!(report.anomalies, vec![]);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
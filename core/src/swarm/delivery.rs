@@ -0,0 +1,443 @@
+#![warn(missing_docs)]
+//! End-to-end delivery acknowledgement of custom messages.
+//!
+//! [Swarm::send_message] only knows whether the first hop accepted the payload, a message lost
+//! in the middle of its route disappears silently. A message sent by
+//! [Swarm::send_message_with_ack] is wrapped in [Message::AckedMessage], its destination
+//! responds a signed [Message::DeliveryAck] referencing the tx_id, which is routed back to the
+//! signer as a new message since the relay path may be broken.
+//!
+//! [Swarm::wait_delivery] resends the message through an alternate next hop if no ack is
+//! received before timeout, at most [MAX_DELIVERY_ATTEMPTS] times. Every attempt is a new
+//! transaction, so the destination may receive the message more than once. The delivery is
+//! tracked by the tx_id of first attempt, its status can be queried by
+//! [Swarm::delivery_status], and changes are notified by [SwarmEvent::DeliveryStatusChange].
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use futures::future::select;
+use futures::future::Either;
+use futures::pin_mut;
+use futures_timer::Delay;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::consts::MAX_DELIVERY_ATTEMPTS;
+use crate::consts::MAX_TRACKED_DELIVERIES;
+use crate::dht::Did;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::AckedMessage;
+use crate::message::Message;
+use crate::message::MessagePayload;
+use crate::message::PayloadSender;
+use crate::swarm::callback::SwarmEvent;
+use crate::swarm::Swarm;
+
+/// Status of a message sent by [Swarm::send_message_with_ack].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    /// Waiting for the ack from destination.
+    Pending,
+    /// The ack from destination is received.
+    Delivered,
+    /// No ack is received after all attempts.
+    Failed,
+}
+
+struct Delivery {
+    destination: Did,
+    status: DeliveryStatus,
+    /// The tx_id of each attempt, including the first one.
+    attempts: Vec<uuid::Uuid>,
+    waiter: Option<oneshot::Sender<()>>,
+}
+
+#[derive(Default)]
+struct DeliveryBook {
+    deliveries: HashMap<uuid::Uuid, Delivery>,
+    /// The tx_id of each attempt, mapping to the tx_id of first attempt.
+    attempts: HashMap<uuid::Uuid, uuid::Uuid>,
+    /// The tx_id of deliveries, in order of sending.
+    queue: VecDeque<uuid::Uuid>,
+}
+
+/// Deliveries sent by swarm, keyed by the tx_id of first attempt.
+/// When it is full, the oldest delivery will be forgotten.
+#[derive(Default)]
+pub struct Deliveries {
+    inner: Arc<Mutex<DeliveryBook>>,
+}
+
+/// A delivery that was sent and is waiting for ack, see [Swarm::wait_delivery].
+/// Dropping it will stop waiting, but the ack can still be received.
+pub struct PendingDelivery {
+    /// The tx_id of first attempt, which is the id of delivery.
+    pub tx_id: uuid::Uuid,
+    /// The destination of message.
+    pub destination: Did,
+    message: Message,
+    /// Next hops that were tried.
+    hops: Vec<Did>,
+    receiver: oneshot::Receiver<()>,
+    book: Arc<Mutex<DeliveryBook>>,
+}
+
+impl Deliveries {
+    fn book(&self) -> std::sync::MutexGuard<'_, DeliveryBook> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Track a delivery by the tx_id of first attempt.
+    fn track(
+        &self,
+        tx_id: uuid::Uuid,
+        destination: Did,
+        message: Message,
+        next_hop: Did,
+    ) -> PendingDelivery {
+        let (sender, receiver) = oneshot::channel();
+        let mut book = self.book();
+        while book.queue.len() >= MAX_TRACKED_DELIVERIES {
+            let Some(id) = book.queue.pop_front() else {
+                break;
+            };
+            if let Some(delivery) = book.deliveries.remove(&id) {
+                for attempt in delivery.attempts {
+                    book.attempts.remove(&attempt);
+                }
+            }
+        }
+        book.deliveries.insert(tx_id, Delivery {
+            destination,
+            status: DeliveryStatus::Pending,
+            attempts: vec![tx_id],
+            waiter: Some(sender),
+        });
+        book.attempts.insert(tx_id, tx_id);
+        book.queue.push_back(tx_id);
+
+        PendingDelivery {
+            tx_id,
+            destination,
+            message,
+            hops: vec![next_hop],
+            receiver,
+            book: self.inner.clone(),
+        }
+    }
+
+    /// Record a new attempt of delivery.
+    fn add_attempt(&self, tx_id: uuid::Uuid, attempt: uuid::Uuid) {
+        let mut book = self.book();
+        if let Some(delivery) = book.deliveries.get_mut(&tx_id) {
+            delivery.attempts.push(attempt);
+            book.attempts.insert(attempt, tx_id);
+        }
+    }
+
+    /// Mark a pending delivery as failed. Return false if it's not pending.
+    fn fail(&self, tx_id: uuid::Uuid) -> bool {
+        match self.book().deliveries.get_mut(&tx_id) {
+            Some(delivery) if delivery.status == DeliveryStatus::Pending => {
+                delivery.status = DeliveryStatus::Failed;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Get the status of a delivery by the tx_id of first attempt.
+    /// Return None if it's not sent by [Swarm::send_message_with_ack], or already forgotten.
+    pub fn status(&self, tx_id: uuid::Uuid) -> Option<DeliveryStatus> {
+        self.book().deliveries.get(&tx_id).map(|d| d.status)
+    }
+
+    /// Mark the delivery acknowledged by [Message::DeliveryAck] in payload as delivered.
+    /// The ack should be sent by the destination of delivery, see
+    /// [MessagePayload::origin_position]. An ack received after the delivery failed still
+    /// counts, since the message did arrive.
+    /// Return the tx_id and destination of delivery if its status is changed.
+    pub fn resolve(&self, payload: &MessagePayload) -> Option<(uuid::Uuid, Did)> {
+        let Ok(Message::DeliveryAck(ack)) = payload.transaction.data() else {
+            return None;
+        };
+        let origin = payload.origin_position();
+
+        let mut book = self.book();
+        let tx_id = *book.attempts.get(&ack.tx_id)?;
+        let delivery = book.deliveries.get_mut(&tx_id)?;
+        if delivery.destination != origin || delivery.status == DeliveryStatus::Delivered {
+            return None;
+        }
+        delivery.status = DeliveryStatus::Delivered;
+        if let Some(waiter) = delivery.waiter.take() {
+            waiter.send(()).ok();
+        }
+        Some((tx_id, delivery.destination))
+    }
+}
+
+impl PendingDelivery {
+    /// Wait for the ack until timeout. Return false if timeout.
+    async fn wait_ack(&mut self, timeout: Duration) -> Result<bool> {
+        let delay = Delay::new(timeout);
+        pin_mut!(delay);
+
+        match select(&mut self.receiver, delay).await {
+            Either::Left((Ok(()), _)) => Ok(true),
+            Either::Left((Err(_), _)) => Err(Error::RequestCancelled(self.tx_id)),
+            Either::Right(_) => Ok(false),
+        }
+    }
+}
+
+impl Drop for PendingDelivery {
+    fn drop(&mut self) {
+        let mut book = self.book.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(delivery) = book.deliveries.get_mut(&self.tx_id) {
+            delivery.waiter.take();
+        }
+    }
+}
+
+impl Swarm {
+    /// Send a custom message to destination, which should acknowledge the delivery.
+    /// The message should be [Message::CustomMessage] or [Message::EncryptedMessage].
+    /// Call [Swarm::wait_delivery] with the returned [PendingDelivery] to resend it until
+    /// acknowledged.
+    pub async fn send_message_with_ack(
+        &self,
+        msg: Message,
+        destination: Did,
+    ) -> Result<PendingDelivery> {
+        let next_hop = self.infer_next_hop(None, destination)?;
+        let message = Message::AckedMessage(AckedMessage {
+            message: Box::new(msg),
+        });
+        let payload =
+            MessagePayload::new_send(message.clone(), &self.session_sk, next_hop, destination)?;
+
+        // Track before sending, so that a fast ack will not be missed.
+        let delivery =
+            self.deliveries
+                .track(payload.transaction.tx_id, destination, message, next_hop);
+        self.notify_delivery_status(delivery.tx_id, destination, DeliveryStatus::Pending)
+            .await;
+
+        // A failure of first hop will be retried by other next hops.
+        if let Err(e) = self.send_payload(payload).await {
+            tracing::warn!(
+                "Failed on sending {} to {}: {:?}",
+                delivery.tx_id,
+                next_hop,
+                e
+            );
+        }
+        Ok(delivery)
+    }
+
+    /// Wait for the ack of delivery, resend the message through an alternate next hop if
+    /// timeout. Return [DeliveryStatus::Failed] if no ack is received after
+    /// [MAX_DELIVERY_ATTEMPTS] attempts.
+    pub async fn wait_delivery(
+        &self,
+        mut delivery: PendingDelivery,
+        timeout: Duration,
+    ) -> Result<DeliveryStatus> {
+        for attempt in 1..=MAX_DELIVERY_ATTEMPTS {
+            if delivery.wait_ack(timeout).await? {
+                return Ok(DeliveryStatus::Delivered);
+            }
+            if attempt == MAX_DELIVERY_ATTEMPTS {
+                break;
+            }
+
+            let next_hop = self.alternate_next_hop(delivery.destination, &delivery.hops)?;
+            delivery.hops.push(next_hop);
+            match self
+                .send_message_by_hop(delivery.message.clone(), delivery.destination, next_hop)
+                .await
+            {
+                Ok(tx_id) => self.deliveries.add_attempt(delivery.tx_id, tx_id),
+                Err(e) => tracing::warn!(
+                    "Failed on resending {} to {}: {:?}",
+                    delivery.tx_id,
+                    next_hop,
+                    e
+                ),
+            }
+        }
+
+        if self.deliveries.fail(delivery.tx_id) {
+            self.notify_delivery_status(
+                delivery.tx_id,
+                delivery.destination,
+                DeliveryStatus::Failed,
+            )
+            .await;
+        }
+        Ok(self
+            .delivery_status(delivery.tx_id)
+            .unwrap_or(DeliveryStatus::Failed))
+    }
+
+    /// Get the status of a delivery by the tx_id returned by [Swarm::send_message_with_ack].
+    pub fn delivery_status(&self, tx_id: uuid::Uuid) -> Option<DeliveryStatus> {
+        self.deliveries.status(tx_id)
+    }
+
+    /// Choose the connected peer closest to destination which is not tried yet,
+    /// falls back to the next hop inferred by DHT if all of them are tried.
+    fn alternate_next_hop(&self, destination: Did, tried: &[Did]) -> Result<Did> {
+        match self
+            .get_connection_ids()
+            .into_iter()
+            .filter(|did| !tried.contains(did))
+            .min_by_key(|did| destination - *did)
+        {
+            Some(did) => Ok(did),
+            None => self.infer_next_hop(None, destination),
+        }
+    }
+
+    /// Notify the application that status of a delivery is changed.
+    pub(crate) async fn notify_delivery_status(
+        &self,
+        tx_id: uuid::Uuid,
+        destination: Did,
+        status: DeliveryStatus,
+    ) {
+        let event = SwarmEvent::DeliveryStatusChange {
+            tx_id,
+            destination,
+            status,
+        };
+        let result = match self.callback() {
+            Ok(callback) => callback.on_event(&event).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed on notifying {:?}: {:?}", event, e);
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use futures::lock::Mutex;
+
+    use super::*;
+    use crate::ecc::tests::gen_ordered_keys;
+    use crate::message::handlers::connection::tests::test_only_two_nodes_establish_connection;
+    use crate::message::DeliveryAck;
+    use crate::swarm::callback::SwarmCallback;
+    use crate::tests::default::prepare_node;
+
+    #[derive(Default)]
+    struct DeliveryCallback {
+        statuses: Mutex<Vec<(uuid::Uuid, DeliveryStatus)>>,
+    }
+
+    #[async_trait]
+    impl SwarmCallback for DeliveryCallback {
+        async fn on_event(
+            &self,
+            event: &SwarmEvent,
+        ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+            if let SwarmEvent::DeliveryStatusChange { tx_id, status, .. } = event {
+                self.statuses.lock().await.push((*tx_id, *status));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_message_with_ack() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        let cb1 = Arc::new(DeliveryCallback::default());
+        node1.set_callback(cb1.clone()).unwrap();
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        // Acknowledged by destination.
+        let delivery = node1
+            .send_message_with_ack(Message::custom(b"hello")?, node2.did())
+            .await?;
+        let tx_id = delivery.tx_id;
+        assert_eq!(node1.delivery_status(tx_id), Some(DeliveryStatus::Pending));
+
+        let (payload, _) = node2.listen_once().await.unwrap();
+        assert!(matches!(
+            payload.transaction.data()?,
+            Message::AckedMessage(_)
+        ));
+        let (payload, _) = node1.listen_once().await.unwrap();
+        assert!(matches!(
+            payload.transaction.data()?,
+            Message::DeliveryAck(DeliveryAck { tx_id: id }) if id == tx_id
+        ));
+        assert_eq!(
+            node1
+                .wait_delivery(delivery, Duration::from_millis(100))
+                .await?,
+            DeliveryStatus::Delivered
+        );
+        assert_eq!(
+            node1.delivery_status(tx_id),
+            Some(DeliveryStatus::Delivered)
+        );
+
+        // Not acknowledged, failed after all attempts.
+        let delivery = node1
+            .send_message_with_ack(Message::custom(b"hello")?, node2.did())
+            .await?;
+        let failed_tx_id = delivery.tx_id;
+        assert_eq!(
+            node1
+                .wait_delivery(delivery, Duration::from_millis(100))
+                .await?,
+            DeliveryStatus::Failed
+        );
+        assert_eq!(
+            node1.delivery_status(failed_tx_id),
+            Some(DeliveryStatus::Failed)
+        );
+
+        // Every attempt arrives late, and the first ack marks it delivered.
+        for _ in 0..MAX_DELIVERY_ATTEMPTS {
+            let (payload, _) = node2.listen_once().await.unwrap();
+            assert!(matches!(
+                payload.transaction.data()?,
+                Message::AckedMessage(_)
+            ));
+        }
+        for _ in 0..MAX_DELIVERY_ATTEMPTS {
+            node1.listen_once().await.unwrap();
+        }
+        assert_eq!(
+            node1.delivery_status(failed_tx_id),
+            Some(DeliveryStatus::Delivered)
+        );
+
+        assert_eq!(*cb1.statuses.lock().await, vec![
+            (tx_id, DeliveryStatus::Pending),
+            (tx_id, DeliveryStatus::Delivered),
+            (failed_tx_id, DeliveryStatus::Pending),
+            (failed_tx_id, DeliveryStatus::Failed),
+            (failed_tx_id, DeliveryStatus::Delivered),
+        ]);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
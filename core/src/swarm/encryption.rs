@@ -0,0 +1,316 @@
+#![warn(missing_docs)]
+//! End-to-end encryption of custom messages.
+//!
+//! A [Message::EncryptedMessage] is sealed by the session public key of destination, so that
+//! relay nodes in [crate::message::MessageRelay] path can only see the ciphertext.
+//! The session public key is recovered from the signature of transactions received from the
+//! peer, see [Swarm::session_pubkey_of]. For a peer that was never heard from, the key is
+//! fetched from DHT, where each node publishes a proof of its session key signed by the
+//! session, see [Swarm::publish_session_pubkey]. The vnode of proof is owned by the node, and
+//! its topic is reserved for it, see [crate::dht::vnode::RESERVED_TOPIC_PREFIXES], so others
+//! cannot write it. The proof is still verified against the did of peer, so a forged one
+//! given by the holder of vnode is ignored. The destination decrypts it to [Message::CustomMessage]
+//! before invoking [crate::swarm::callback::SwarmCallback::on_inbound].
+
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::consts::DEFAULT_SESSION_PUBKEY_FETCH_TIMEOUT_MS;
+use crate::consts::SESSION_PUBKEY_TTL_MS;
+use crate::dht::vnode::VirtualNode;
+use crate::dht::Did;
+use crate::ecc::PublicKey;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::ChordStorageInterface;
+use crate::message::Decoder;
+use crate::message::Encoded;
+use crate::message::Encoder;
+use crate::message::EncryptedMessage;
+use crate::message::Message;
+use crate::message::MessagePayload;
+use crate::message::MessageVerification;
+use crate::message::MessageVerificationExt;
+use crate::message::PayloadSender;
+use crate::session::SessionSk;
+use crate::swarm::Swarm;
+use crate::utils::get_epoch_ms;
+
+/// Topic of the vnode where a node publishes its session public key.
+/// It's reserved for the node, see [crate::dht::vnode::RESERVED_TOPIC_PREFIXES].
+fn session_pubkey_topic(did: Did) -> String {
+    format!("session_pubkey:{}", did)
+}
+
+/// Recover the session public key of `did` from a published proof, which is a
+/// [MessageVerification] of the topic signed by its session.
+fn open_session_pubkey_proof(did: Did, data: &Encoded) -> Result<PublicKey> {
+    let bytes = Vec::from_encoded(data)?;
+    let proof: MessageVerification =
+        bincode::deserialize(&bytes).map_err(Error::BincodeDeserialize)?;
+    let topic = session_pubkey_topic(did);
+    if proof.session.account_did() != did || !proof.verify(topic.as_bytes()) {
+        return Err(Error::VerifySignatureFailed);
+    }
+    proof.session_pubkey(topic.as_bytes())
+}
+
+/// Decrypt the [Message::EncryptedMessage] in payload for application.
+/// The data of returned payload is replaced by decrypted [Message::CustomMessage],
+/// so it cannot pass the verification anymore.
+/// The [Message::AckedMessage] is unwrapped before decrypting.
+/// Payloads of other messages are returned as is.
+pub fn decrypt_payload(payload: &MessagePayload, session_sk: &SessionSk) -> Result<MessagePayload> {
+    let msg = match payload.transaction.data()? {
+        Message::AckedMessage(msg) => *msg.message,
+        Message::EncryptedMessage(msg) => Message::EncryptedMessage(msg),
+        _ => return Ok(payload.clone()),
+    };
+    let msg = match msg {
+        Message::EncryptedMessage(msg) => Message::CustomMessage(msg.open(session_sk)?),
+        msg => msg,
+    };
+
+    let mut payload = payload.clone();
+    payload.transaction.data = bincode::serialize(&msg).map_err(Error::BincodeSerialize)?;
+    Ok(payload)
+}
+
+impl Swarm {
+    /// Remember the session public key of the transaction signer.
+    /// Should only be called with verified payload.
+    pub(crate) fn record_session_pubkey(&self, payload: &MessagePayload) {
+        let signer = payload.transaction.signer();
+        let session_id = payload.transaction.verification.session.session_id();
+        if let Some(pubkey) = self.session_pubkeys.get(&signer) {
+            if Did::from(pubkey.address()) == session_id {
+                return;
+            }
+        }
+
+        match payload.transaction.signer_session_pubkey() {
+            Ok(pubkey) => {
+                self.session_pubkeys.insert(signer, pubkey);
+            }
+            Err(e) => tracing::warn!("Failed to recover session pubkey of {}: {:?}", signer, e),
+        }
+    }
+
+    /// Get the session public key of a peer, which is learned from its messages.
+    pub fn session_pubkey_of(&self, did: Did) -> Option<PublicKey> {
+        self.session_pubkeys.get(&did).map(|pk| *pk)
+    }
+
+    /// Publish the session public key to DHT, so that peers can encrypt messages for current
+    /// node before hearing from it. It expires after [SESSION_PUBKEY_TTL_MS].
+    /// The vnode is owned by current node, so only it can renew the vnode.
+    pub async fn publish_session_pubkey(&self) -> Result<()> {
+        let topic = session_pubkey_topic(self.did());
+        let proof = MessageVerification::new(topic.as_bytes(), &self.session_sk)?;
+        let data = bincode::serialize(&proof)
+            .map_err(Error::BincodeSerialize)?
+            .encode()?;
+        let vnode: VirtualNode = (topic, data).try_into()?;
+        let vnode = vnode.owned_by(&self.session_sk, vec![])?;
+        let ttl = Duration::from_millis(SESSION_PUBKEY_TTL_MS);
+        <Self as ChordStorageInterface<1>>::storage_store(self, vnode.with_ttl(ttl)).await?;
+        self.session_pubkey_published_at
+            .store(get_epoch_ms() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Publish the session public key again if it's never published or half expired.
+    pub(crate) async fn renew_session_pubkey(&self) -> Result<()> {
+        let published_at = self.session_pubkey_published_at.load(Ordering::Relaxed);
+        if published_at + SESSION_PUBKEY_TTL_MS / 2 > get_epoch_ms() as u64 {
+            return Ok(());
+        }
+        self.publish_session_pubkey().await
+    }
+
+    /// Get the session public key of a peer. If it's not learned from messages, fetch the one
+    /// published by the peer from DHT until timeout.
+    pub async fn resolve_session_pubkey(&self, did: Did, timeout: Duration) -> Result<PublicKey> {
+        if let Some(pubkey) = self.session_pubkey_of(did) {
+            return Ok(pubkey);
+        }
+
+        let vid = VirtualNode::gen_did(&session_pubkey_topic(did))?;
+        let vnode = <Self as ChordStorageInterface<1>>::storage_fetch_vnode(self, vid, timeout)
+            .await?
+            .ok_or(Error::SessionPubkeyNotFound(did))?;
+        // Only the peer can write the vnode, but the holder of vnode is not trusted, so each
+        // proof is checked.
+        let pubkey = vnode
+            .data
+            .iter()
+            .rev()
+            .find_map(|data| open_session_pubkey_proof(did, data).ok())
+            .ok_or(Error::SessionPubkeyNotFound(did))?;
+        self.session_pubkeys.insert(did, pubkey);
+        Ok(pubkey)
+    }
+
+    /// Send a custom message to destination with end-to-end encryption.
+    /// Return [Error::SessionPubkeyNotFound] if the session public key of destination is
+    /// neither learned from its messages nor published, see [Swarm::resolve_session_pubkey].
+    pub async fn send_encrypted_message(&self, msg: &[u8], destination: Did) -> Result<uuid::Uuid> {
+        let timeout = Duration::from_millis(DEFAULT_SESSION_PUBKEY_FETCH_TIMEOUT_MS);
+        let pubkey = self.resolve_session_pubkey(destination, timeout).await?;
+        let msg = EncryptedMessage::seal(msg, &pubkey)?;
+        self.send_message(Message::EncryptedMessage(msg), destination)
+            .await
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use futures::lock::Mutex;
+
+    use super::*;
+    use crate::dht::Stabilization;
+    use crate::ecc::tests::gen_ordered_keys;
+    use crate::message::handlers::connection::tests::test_only_two_nodes_establish_connection;
+    use crate::message::ChordStorageInterfaceCacheChecker;
+    use crate::message::CustomMessage;
+    use crate::storage::PersistenceStorageReadAndWrite;
+    use crate::swarm::callback::SwarmCallback;
+    use crate::tests::default::prepare_node;
+
+    struct InboundCallback {
+        messages: Mutex<Vec<Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl SwarmCallback for InboundCallback {
+        async fn on_inbound(
+            &self,
+            payload: &MessagePayload,
+        ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+            if let Message::CustomMessage(CustomMessage(msg)) = payload.transaction.data()? {
+                self.messages.lock().await.push(msg);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_encrypted_message() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        let cb2 = Arc::new(InboundCallback {
+            messages: Mutex::new(vec![]),
+        });
+        node2.set_callback(cb2.clone()).unwrap();
+
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+        assert_eq!(
+            node1.session_pubkey_of(node2.did()),
+            Some(node2.session_sk().session_pubkey())
+        );
+
+        node1
+            .send_encrypted_message(b"top secret", node2.did())
+            .await?;
+        let (payload, _) = node2.listen_once().await.unwrap();
+
+        // Relay nodes can only see the ciphertext.
+        let Message::EncryptedMessage(EncryptedMessage(data)) = payload.transaction.data()? else {
+            panic!("Expect EncryptedMessage");
+        };
+        assert!(!data.windows(10).any(|w| w == b"top secret"));
+        assert!(decrypt_payload(&payload, node1.session_sk()).is_err());
+
+        // Destination get the decrypted message.
+        assert_eq!(cb2.messages.lock().await.as_slice(), &[
+            b"top secret".to_vec()
+        ]);
+
+        let unknown = crate::ecc::SecretKey::random().address().into();
+        assert!(matches!(
+            node1.send_encrypted_message(b"top secret", unknown).await,
+            Err(Error::SessionPubkeyNotFound(did)) if did == unknown
+        ));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_encrypt_with_published_session_pubkey() -> Result<()> {
+        // Pick keys that the proof falls between node1 and node2. Node3 joins by node2 only,
+        // so it stores the proof itself until stabilization hands it over to node1.
+        let keys = loop {
+            let keys = gen_ordered_keys(3);
+            let (did1, did2): (Did, Did) = (keys[0].address().into(), keys[1].address().into());
+            let did3: Did = keys[2].address().into();
+            let vid = VirtualNode::gen_did(&session_pubkey_topic(did3))?;
+            if vid != did1 && vid - did1 < did2 - did1 {
+                break keys;
+            }
+        };
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        let (node3, _path3) = prepare_node(keys[2]).await;
+        let cb3 = Arc::new(InboundCallback {
+            messages: Mutex::new(vec![]),
+        });
+        node3.set_callback(cb3.clone()).unwrap();
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+        test_only_two_nodes_establish_connection(&node2, &node3).await?;
+        for node in [&node1, &node2, &node3] {
+            let node = node.clone();
+            tokio::spawn(async move { node.listen().await });
+        }
+
+        let vid = VirtualNode::gen_did(&session_pubkey_topic(node3.did()))?;
+        node3.publish_session_pubkey().await?;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let stored: Option<VirtualNode> = node3.dht().storage.get(&vid).await?;
+        assert!(stored.is_some());
+
+        for _ in 0..2 {
+            for node in [&node1, &node2, &node3] {
+                Stabilization::new(node.clone(), 3).stabilize().await?;
+            }
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        }
+        let stored: Option<VirtualNode> = node3.dht().storage.get(&vid).await?;
+        assert!(stored.is_none());
+        let stored: Option<VirtualNode> = node1.dht().storage.get(&vid).await?;
+        assert!(stored.is_some());
+
+        // Node1 forgets the key heard from node3 when they get connected by stabilization,
+        // so the key is fetched from DHT.
+        node1.session_pubkeys.remove(&node3.did());
+        assert!(node1.session_pubkey_of(node3.did()).is_none());
+        node1
+            .send_encrypted_message(b"top secret", node3.did())
+            .await?;
+        assert_eq!(
+            node1.session_pubkey_of(node3.did()),
+            Some(node3.session_sk().session_pubkey())
+        );
+        let published = node1.storage_check_cache(vid).await.unwrap();
+        assert_eq!(published.owner, Some(node3.did()));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(cb3.messages.lock().await.as_slice(), &[
+            b"top secret".to_vec()
+        ]);
+
+        // A proof published by others is not accepted.
+        let topic = session_pubkey_topic(node2.did());
+        let proof = MessageVerification::new(topic.as_bytes(), node1.session_sk())?;
+        let data = bincode::serialize(&proof).unwrap().encode()?;
+        assert!(open_session_pubkey_proof(node2.did(), &data).is_err());
+        assert!(open_session_pubkey_proof(node1.did(), &data).is_err());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
@@ -82,6 +82,9 @@ pub trait Judegement {
 
     /// Asynchronously records that a connection has been disconnected with the provided DID.
     async fn record_disconnected(&self, did: Did);
+
+    /// Asynchronously records that the provided DID reported invalid routing information.
+    async fn record_invalid_report(&self, did: Did);
 }
 
 /// A trait that combines the `Judegement` and `ConnectionManager` traits.
@@ -140,7 +143,7 @@ impl Swarm {
         }
     }
 
-    fn callback(&self) -> Result<SharedSwarmCallback> {
+    pub(crate) fn callback(&self) -> Result<SharedSwarmCallback> {
         let inner = self
             .callback
             .read()
@@ -164,9 +167,13 @@ impl Swarm {
     /// Create new connection that will be handled by swarm.
     pub async fn new_connection(&self, did: Did) -> Result<Connection> {
         let inner_callback = InnerSwarmCallback::new(
-            self.did(),
+            self.session_sk.clone(),
             self.transport_event_channel.sender(),
             self.callback()?,
+            self.replay_window.clone(),
+            self.peer_protocols.clone(),
+            self.pending_requests.clone(),
+            self.measure.clone(),
         );
 
         let cid = did.to_string();
@@ -199,8 +206,11 @@ impl Swarm {
     }
 
     /// Get connection by did.
+    /// Positions operated by a connected peer are reached through the connection of the peer.
     pub fn get_connection(&self, did: Did) -> Option<Connection> {
-        self.transport.connection(&did.to_string()).ok()
+        self.transport
+            .connection(&self.connection_did(did).to_string())
+            .ok()
     }
 
     /// Get all connections in transport.
@@ -342,14 +352,14 @@ impl ConnectionHandshake for Swarm {
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl ConnectionManager for Swarm {
     /// Disconnect a connection. There are three steps:
-    /// 1) remove from DHT;
+    /// 1) remove from DHT, with all positions operated by the peer;
     /// 2) remove from Transport;
     /// 3) close the connection;
     async fn disconnect(&self, did: Did) -> Result<()> {
         tracing::info!("[disconnect] removing from DHT {:?}", did);
-        self.dht.remove(did)?;
+        let conn = self.remove_positions(did)?;
         self.transport
-            .close_connection(&did.to_string())
+            .close_connection(&conn.to_string())
             .await
             .map_err(|e| e.into())
     }
@@ -415,6 +425,14 @@ impl Judegement for Swarm {
         }
     }
 
+    /// Record an invalid routing report
+    async fn record_invalid_report(&self, did: Did) {
+        if let Some(measure) = &self.measure {
+            tracing::warn!("[Judgement] Record invalid report of {}", did);
+            measure.incr(did, MeasureCounter::InvalidReport).await;
+        }
+    }
+
     /// Asynchronously checks if a connection should be established with the provided DID.
     async fn should_connect(&self, did: Did) -> bool {
         self.behaviour_good(did).await
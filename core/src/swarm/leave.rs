@@ -0,0 +1,253 @@
+#![warn(missing_docs)]
+//! Graceful leaving of DHT.
+//!
+//! Before going offline, all the virtual nodes stored locally are handed off to the successor
+//! by [Message::SyncVNodeWithSuccessor], in batches of at most [VNODE_HANDOFF_BATCH_SIZE]
+//! bytes. Each position operated by the node, see [crate::swarm::positions], hands off its
+//! own range to its first successor which is not a local position. A batch is removed locally
+//! only after the successor acknowledges it by [Message::DeliveryAck], so a lost handoff keeps
+//! the data. Then the node sends [Message::NotifyLeaving] to its predecessor and successor,
+//! suggesting each other as the replacement of it, so the ring can be repaired without waiting
+//! for stabilization. Neighbours are notified even if some handoffs failed, since the node is
+//! going offline anyway.
+
+use std::time::Duration;
+
+use crate::consts::DEFAULT_HANDOFF_ACK_TIMEOUT_MS;
+use crate::consts::VNODE_HANDOFF_BATCH_SIZE;
+use crate::dht::vnode::VirtualNode;
+use crate::dht::Did;
+use crate::dht::PeerRing;
+use crate::dht::SuccessorReader;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::Message;
+use crate::message::MessagePayload;
+use crate::message::NotifyLeaving;
+use crate::message::PayloadSender;
+use crate::message::SyncVNodeWithSuccessor;
+use crate::storage::PersistenceStorageReadAndWrite;
+use crate::storage::PersistenceStorageRemove;
+use crate::swarm::Swarm;
+
+/// Split vnodes into batches of at most [VNODE_HANDOFF_BATCH_SIZE] bytes.
+/// A vnode larger than that is sent in a batch of its own.
+fn handoff_batches(items: Vec<(Did, VirtualNode)>) -> Result<Vec<Vec<(Did, VirtualNode)>>> {
+    let mut batches = vec![];
+    let mut batch = vec![];
+    let mut size = 0;
+    for item in items {
+        let len = bincode::serialized_size(&item.1).map_err(Error::BincodeSerialize)? as usize;
+        if !batch.is_empty() && size + len > VNODE_HANDOFF_BATCH_SIZE {
+            batches.push(std::mem::take(&mut batch));
+            size = 0;
+        }
+        size += len;
+        batch.push(item);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    Ok(batches)
+}
+
+impl Swarm {
+    /// Leave DHT gracefully.
+    /// Hand off virtual nodes stored by each position to its successor, then notify the
+    /// predecessor and successor.
+    /// Every step is tried even if former ones failed. Return the first error if any batch of
+    /// vnodes is not acknowledged, or any neighbour is not notified. Unacknowledged vnodes are
+    /// kept.
+    /// Connections are not closed here, the caller should close them after messages are flushed.
+    /// The swarm should be listening, so that acks can be received.
+    pub async fn leave(&self) -> Result<()> {
+        let did = self.did();
+        let predecessor = *self.dht.lock_predecessor()?;
+        let successor = self.dht.successors().list()?.first().copied();
+        let mut errors = vec![];
+
+        for dht in self.position_dhts() {
+            // Other local positions are leaving as well.
+            let successor = dht
+                .successors()
+                .list()?
+                .into_iter()
+                .find(|s| !self.is_local_position(*s));
+            match successor {
+                Some(succ) => {
+                    if let Err(e) = self.hand_off_vnodes(&dht, succ).await {
+                        tracing::error!("Failed on handing off vnodes of {}: {:?}", dht.did, e);
+                        errors.push(e);
+                    }
+                }
+                None => tracing::warn!("No successor of {} to hand off virtual nodes", dht.did),
+            }
+        }
+
+        let mut notifications = vec![];
+        if let Some(pred) = predecessor {
+            let replacement = successor.filter(|s| *s != pred);
+            notifications.push((pred, replacement));
+        }
+        if let Some(succ) = successor.filter(|s| predecessor != Some(*s)) {
+            let replacement = predecessor.filter(|p| *p != did);
+            notifications.push((succ, replacement));
+        }
+        for (neighbour, replacement) in notifications {
+            if let Err(e) = self
+                .send_direct_message(
+                    Message::NotifyLeaving(NotifyLeaving { did, replacement }),
+                    neighbour,
+                )
+                .await
+            {
+                tracing::error!("Failed on notifying {} of leaving: {:?}", neighbour, e);
+                errors.push(e);
+            }
+        }
+
+        match errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Hand off all vnodes stored by the position of `dht` to successor batch by batch,
+    /// each batch is removed after it's acknowledged.
+    async fn hand_off_vnodes(&self, dht: &PeerRing, succ: Did) -> Result<()> {
+        let items: Vec<(Did, VirtualNode)> = dht.storage.get_all().await?;
+        let timeout = Duration::from_millis(DEFAULT_HANDOFF_ACK_TIMEOUT_MS);
+        for batch in handoff_batches(items)? {
+            let data = batch.iter().map(|(_, vnode)| vnode.clone()).collect();
+            // Sent on behalf of the position, which is the previous holder of vnodes.
+            let payload = MessagePayload::new_send_by(
+                Message::SyncVNodeWithSuccessor(SyncVNodeWithSuccessor { data }),
+                &self.session_sk,
+                dht.did,
+                succ,
+                succ,
+            )?;
+            // Register before sending, so that a fast ack will not be missed.
+            let ack = self
+                .pending_requests
+                .register(payload.transaction.tx_id, succ);
+            self.send_payload(payload).await?;
+            ack.wait(timeout).await?;
+
+            for (vid, _) in batch {
+                dht.storage.remove(&vid).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dht::vnode::VNodeOperation;
+    use crate::ecc::tests::gen_ordered_keys;
+    use crate::ecc::SecretKey;
+    use crate::message::handlers::connection::tests::test_only_two_nodes_establish_connection;
+    use crate::message::Encoder;
+    use crate::tests::default::prepare_node;
+    use crate::tests::default::prepare_node_with_positions;
+    use crate::tests::manually_establish_connection;
+
+    #[test]
+    fn test_handoff_batches() -> Result<()> {
+        let items = (0..10)
+            .map(|i| {
+                let data = "x".repeat(VNODE_HANDOFF_BATCH_SIZE / 4).encode()?;
+                let vnode: VirtualNode = (format!("test_batch_{}", i), data).try_into()?;
+                Ok((vnode.did, vnode))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let batches = handoff_batches(items.clone())?;
+        assert!(batches.len() > 1);
+        for batch in batches.iter() {
+            let size: u64 = batch
+                .iter()
+                .map(|(_, v)| bincode::serialized_size(v).unwrap())
+                .sum();
+            assert!(size as usize <= VNODE_HANDOFF_BATCH_SIZE);
+        }
+        assert_eq!(batches.concat(), items);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_leave_hands_off_vnodes() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        let vnode: VirtualNode = ("test_leave".to_string(), "hello".encode()?).try_into()?;
+        let vnode = VNodeOperation::Overwrite(vnode.clone())
+            .gen_default_vnode()?
+            .operate(
+                VNodeOperation::Overwrite(vnode.owned_by(node2.session_sk(), vec![])?),
+                node2.did(),
+            )?;
+        node2.dht().storage.put(&vnode.did, &vnode).await?;
+
+        // The successor is not listening, so the handoff is not acknowledged.
+        assert!(matches!(node2.leave().await, Err(Error::RequestTimeout(_))));
+        let remained: Vec<(Did, VirtualNode)> = node2.dht().storage.get_all().await?;
+        assert_eq!(remained, vec![(vnode.did, vnode.clone())]);
+        assert!(node1.dht().successors().list()?.contains(&node2.did()));
+
+        // Neighbours are notified even if the handoff failed.
+        let n1 = node1.clone();
+        tokio::spawn(async move { n1.listen().await });
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!node1.dht().successors().list()?.contains(&node2.did()));
+
+        let n2 = node2.clone();
+        tokio::spawn(async move { n2.listen().await });
+        node2.leave().await?;
+        let remained: Vec<(Did, VirtualNode)> = node2.dht().storage.get_all().await?;
+        assert!(remained.is_empty());
+
+        // The owner is kept after handing off.
+        let stored: Option<VirtualNode> = node1.dht().storage.get(&vnode.did).await?;
+        assert_eq!(stored, Some(vnode));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_leave_hands_off_vnodes_of_positions() -> Result<()> {
+        let (node1, _path1) = prepare_node(SecretKey::random()).await;
+        let (node2, _path2) = prepare_node_with_positions(SecretKey::random(), 1).await;
+        let position = node2.positions()[1];
+        let position_dht = node2.virtual_position(position).unwrap().dht.clone();
+
+        manually_establish_connection(&node1, &node2).await;
+        for node in [&node1, &node2] {
+            let node = node.clone();
+            tokio::spawn(async move { node.listen().await });
+        }
+        tokio::time::sleep(Duration::from_secs(3)).await;
+
+        // A vnode at the position is stored by the position.
+        let vnode: VirtualNode = ("test_leave".to_string(), "hello".encode()?).try_into()?;
+        let vnode = vnode.clone_with_did(position);
+        let vnode = VNodeOperation::Overwrite(vnode.clone())
+            .gen_default_vnode()?
+            .operate(VNodeOperation::Overwrite(vnode), node2.did())?;
+        position_dht.storage.put(&vnode.did, &vnode).await?;
+
+        node2.leave().await?;
+        let remained: Vec<(Did, VirtualNode)> = position_dht.storage.get_all().await?;
+        assert!(remained.is_empty());
+        let stored: Option<VirtualNode> = node1.dht().storage.get(&vnode.did).await?;
+        assert_eq!(stored, Some(vnode));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
@@ -0,0 +1,400 @@
+#![warn(missing_docs)]
+//! Iterative lookup of successor.
+//!
+//! By default, lookups on the ring are recursive: the message is forwarded hop by hop towards
+//! target, so origin cannot see the route, and a slow or broken hop stalls the whole lookup.
+//!
+//! In iterative mode, origin asks the closest preceding nodes it knows for the next hops by
+//! [Message::QueryNextHopSend], `alpha` of them in parallel with a timeout on each hop, and
+//! walks towards target by itself until a node reports the successor of target.
+//!
+//! Reports of each hop are checked by [crate::dht::validation], nodes reporting impossible
+//! routes are recorded as offenders. To resist a lying node on the route, the lookup can be
+//! split into several paths through disjoint nodes, see [LookupConfig::disjoint_paths].
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use dashmap::DashSet;
+use futures::future::join_all;
+
+use crate::consts::DEFAULT_LOOKUP_ALPHA;
+use crate::consts::DEFAULT_LOOKUP_DISJOINT_PATHS;
+use crate::consts::DEFAULT_LOOKUP_HOP_TIMEOUT_MS;
+use crate::consts::MAX_LOOKUP_HOPS;
+use crate::dht::validation;
+use crate::dht::Chord;
+use crate::dht::Did;
+use crate::dht::PeerRingAction;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::LookupStep;
+use crate::message::Message;
+use crate::message::MessagePayload;
+use crate::message::PayloadSender;
+use crate::message::QueryNextHopSend;
+use crate::swarm::impls::Judegement;
+use crate::swarm::Swarm;
+
+/// Parameters of iterative lookup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LookupConfig {
+    /// How many nodes are queried in parallel on each hop.
+    pub alpha: usize,
+    /// How long to wait for the reports on each hop.
+    pub hop_timeout: Duration,
+    /// Lookup fails if the successor is not found after this many hops.
+    pub max_hops: usize,
+    /// How many lookups walk through disjoint nodes, their results must agree.
+    pub disjoint_paths: usize,
+}
+
+impl Default for LookupConfig {
+    fn default() -> Self {
+        Self {
+            alpha: DEFAULT_LOOKUP_ALPHA,
+            hop_timeout: Duration::from_millis(DEFAULT_LOOKUP_HOP_TIMEOUT_MS),
+            max_hops: MAX_LOOKUP_HOPS,
+            disjoint_paths: DEFAULT_LOOKUP_DISJOINT_PATHS,
+        }
+    }
+}
+
+/// How [Swarm::connect], storage fetching and storing, and fixing fingers in
+/// [crate::dht::Stabilization] look up the successor of a did.
+///
+/// Joining through a peer is still handled in the listening loop of swarm, which cannot wait
+/// for a lookup, so the peer is asked for the successor recursively. Fingers learned that way
+/// are checked again by iterative lookups in stabilization.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum LookupMode {
+    /// Messages are forwarded hop by hop towards target.
+    #[default]
+    Recursive,
+    /// Origin queries the next hops by itself, see [Swarm::lookup_iterative].
+    Iterative(LookupConfig),
+}
+
+/// Result of iterative lookup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LookupRoute {
+    /// The successor of target.
+    pub successor: Did,
+    /// Nodes walked through from origin, the last one reported the successor.
+    /// It's empty if origin knows the successor by itself.
+    pub route: Vec<Did>,
+}
+
+impl LookupRoute {
+    /// The node responsible for target, which stores its vnodes, see [crate::dht::ChordStorage].
+    /// It's the node reporting the successor, or None if origin is responsible by itself.
+    pub fn holder(&self) -> Option<Did> {
+        self.route.last().copied()
+    }
+}
+
+impl Swarm {
+    /// Mode of looking up the successor of a did, see [LookupMode].
+    pub fn lookup_mode(&self) -> &LookupMode {
+        &self.lookup_mode
+    }
+
+    /// Next hop of a message to `did` found by iterative lookup. It's `did` itself if swarm
+    /// is connected to it. Otherwise, there is no direct channel to it yet, and the message
+    /// falls back to be routed recursively by [PayloadSender::infer_next_hop].
+    pub(crate) fn lookup_next_hop(&self, did: Did) -> Result<Did> {
+        if self.is_loopback(did) || self.get_connection(did).is_some() {
+            return Ok(did);
+        }
+        self.infer_next_hop(None, did)
+    }
+
+    /// Look up the successor of `did` iteratively and return the route to it.
+    /// On each hop, the closest `alpha` nodes not queried yet are queried in parallel,
+    /// the first one that knows the successor ends the lookup, otherwise their next hops
+    /// are merged as candidates of next round.
+    ///
+    /// With `disjoint_paths` greater than one, the closest known nodes are split among
+    /// several lookups which avoid the nodes queried by each other, and all of them must
+    /// reach the successor and agree on it. Return [Error::LookupDisagreed] if they don't,
+    /// and [Error::LookupFailed] if any lookup fails to reach the successor in `max_hops`.
+    /// A node knowing fewer nodes than `disjoint_paths` starts as many lookups as it can.
+    pub async fn lookup_iterative(&self, did: Did, config: &LookupConfig) -> Result<LookupRoute> {
+        let alpha = config.alpha.max(1);
+        let paths = config.disjoint_paths.max(1);
+        let candidates = match self.dht.find_successor(did)? {
+            PeerRingAction::Some(successor) => {
+                return Ok(LookupRoute {
+                    successor,
+                    route: vec![],
+                })
+            }
+            PeerRingAction::RemoteAction(next, _) => {
+                let mut nodes = self.dht.closest_preceding_nodes(did, alpha * paths)?;
+                if nodes.is_empty() && next != self.did() {
+                    nodes.push(next);
+                }
+                nodes
+            }
+            act => return Err(Error::PeerRingUnexpectedAction(act)),
+        };
+
+        // Deal the candidates to paths in turn, so that every path starts from a close one.
+        let mut groups = vec![vec![]; paths.min(candidates.len()).max(1)];
+        let len = groups.len();
+        for (i, node) in candidates.into_iter().enumerate() {
+            groups[i % len].push(node);
+        }
+
+        let queried = DashSet::new();
+        let routes = join_all(
+            groups
+                .into_iter()
+                .map(|candidates| self.lookup_path(did, candidates, config, &queried)),
+        )
+        .await;
+
+        let routes = routes.into_iter().collect::<Result<Vec<_>>>()?;
+        let (route, others) = routes.split_first().ok_or(Error::LookupFailed(did))?;
+        if others.iter().any(|r| r.successor != route.successor) {
+            return Err(Error::LookupDisagreed(did));
+        }
+        Ok(route.clone())
+    }
+
+    /// Walk from `candidates` towards `did`, skipping nodes queried by other paths.
+    /// Paths only meet when no other candidate is left, such as on the predecessor of
+    /// `did`, which is the only node knowing its successor.
+    async fn lookup_path(
+        &self,
+        did: Did,
+        mut candidates: Vec<Did>,
+        config: &LookupConfig,
+        queried: &DashSet<Did>,
+    ) -> Result<LookupRoute> {
+        let alpha = config.alpha.max(1);
+        let mut route = vec![];
+        let mut visited = HashSet::new();
+        for _ in 0..config.max_hops {
+            candidates.retain(|n| !visited.contains(n));
+            let unqueried = candidates
+                .iter()
+                .filter(|n| queried.insert(**n))
+                .copied()
+                .collect::<Vec<_>>();
+            if !unqueried.is_empty() {
+                candidates = unqueried;
+            }
+            if candidates.is_empty() {
+                break;
+            }
+            visited.extend(candidates.iter().copied());
+
+            let reports = join_all(
+                candidates
+                    .iter()
+                    .map(|node| self.query_next_hop(*node, did, config)),
+            )
+            .await;
+
+            // Candidates are sorted closest first, so are the reports.
+            let mut closest = None;
+            let mut next = vec![];
+            for (node, report) in candidates.iter().zip(reports) {
+                match report {
+                    Ok(LookupStep::Found(successor)) => {
+                        if !validation::is_valid_successor_report(did, *node, successor) {
+                            tracing::warn!("lookup {} got invalid successor from {}", did, node);
+                            self.record_invalid_report(*node).await;
+                            continue;
+                        }
+                        route.push(*node);
+                        return Ok(LookupRoute { successor, route });
+                    }
+                    Ok(LookupStep::Next(nodes)) => {
+                        if !validation::is_valid_next_hops(did, *node, &nodes) {
+                            tracing::warn!("lookup {} got invalid next hops from {}", did, node);
+                            self.record_invalid_report(*node).await;
+                            continue;
+                        }
+                        closest.get_or_insert(*node);
+                        next.extend(nodes);
+                    }
+                    Err(e) => {
+                        tracing::debug!("lookup {} failed on {}: {:?}", did, node, e);
+                    }
+                }
+            }
+            let Some(closest) = closest else {
+                break;
+            };
+            route.push(closest);
+
+            let bound = did - self.did();
+            next.retain(|n| *n != self.did() && *n - self.did() < bound);
+            next.sort_by_key(|n| did - *n);
+            next.dedup();
+            next.retain(|n| !visited.contains(n));
+            next.truncate(alpha);
+            candidates = next;
+        }
+        Err(Error::LookupFailed(did))
+    }
+
+    async fn query_next_hop(
+        &self,
+        node: Did,
+        did: Did,
+        config: &LookupConfig,
+    ) -> Result<LookupStep> {
+        let msg = Message::QueryNextHopSend(QueryNextHopSend {
+            did,
+            count: config.alpha.min(u8::MAX as usize) as u8,
+        });
+        // Candidates learned from reports of other nodes may be unconnected, queries to them
+        // are relayed through the ring, see [Swarm::lookup_next_hop].
+        let next_hop = self.lookup_next_hop(node)?;
+        let payload = MessagePayload::new_send(msg, &self.session_sk, next_hop, node)?;
+
+        // Register before sending, so that a fast report will not be missed.
+        let req = self
+            .pending_requests
+            .register(payload.transaction.tx_id, node);
+        self.send_payload(payload).await?;
+
+        match req.wait(config.hop_timeout).await?.transaction.data()? {
+            Message::QueryNextHopReport(report) if report.did == did => Ok(report.step),
+            msg => Err(Error::InvalidMessage(format!(
+                "unexpected report of next hop: {msg}"
+            ))),
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::dht::vnode::VirtualNode;
+    use crate::ecc::tests::gen_ordered_keys;
+    use crate::message::ChordStorageInterface;
+    use crate::session::SessionSk;
+    use crate::storage::PersistenceStorage;
+    use crate::storage::PersistenceStorageReadAndWrite;
+    use crate::swarm::SwarmBuilder;
+    use crate::tests::manually_establish_connection;
+
+    async fn prepare_nodes(config: &LookupConfig) -> Result<Vec<Arc<Swarm>>> {
+        let mut nodes = vec![];
+        for key in gen_ordered_keys(4) {
+            let path = PersistenceStorage::random_path("./tmp");
+            let storage = PersistenceStorage::new_with_path(path.as_str()).await?;
+            let session_sk = SessionSk::new_with_seckey(&key)?;
+            let swarm = SwarmBuilder::new("stun://stun.l.google.com:19302", storage, session_sk)
+                .lookup_mode(LookupMode::Iterative(config.clone()))
+                .build();
+            nodes.push(Arc::new(swarm));
+        }
+        Ok(nodes)
+    }
+
+    #[tokio::test]
+    async fn test_lookup_iterative() -> Result<()> {
+        let config = LookupConfig::default();
+        let nodes = prepare_nodes(&config).await?;
+        manually_establish_connection(&nodes[0], &nodes[1]).await;
+        manually_establish_connection(&nodes[1], &nodes[2]).await;
+        manually_establish_connection(&nodes[2], &nodes[3]).await;
+        for node in nodes.iter() {
+            let n = node.clone();
+            tokio::spawn(async move { n.listen().await });
+        }
+        sleep(Duration::from_secs(3)).await;
+
+        // The successor is known by current node.
+        let route = nodes[0].lookup_iterative(nodes[1].did(), &config).await?;
+        assert_eq!(route.successor, nodes[1].did());
+        assert!(route.route.is_empty());
+
+        // The last hop is the predecessor of target.
+        let route = nodes[0].lookup_iterative(nodes[3].did(), &config).await?;
+        assert_eq!(route.successor, nodes[3].did());
+        assert_eq!(route.route.last(), Some(&nodes[2].did()));
+
+        // Disjoint lookups agree on the successor.
+        let disjoint = LookupConfig {
+            disjoint_paths: 2,
+            ..config.clone()
+        };
+        let route = nodes[0].lookup_iterative(nodes[3].did(), &disjoint).await?;
+        assert_eq!(route.successor, nodes[3].did());
+
+        // Stores are sent to the holder found by lookup.
+        let vnode = (0..)
+            .map(|i| VirtualNode::try_from(format!("lookup iterative {i}")).unwrap())
+            .find(|v| {
+                v.did
+                    .in_range(nodes[2].did(), nodes[2].did(), nodes[3].did())
+            })
+            .unwrap();
+        let vid = vnode.did;
+        let route = nodes[0].lookup_iterative(vid, &config).await?;
+        assert_eq!(route.holder(), Some(nodes[2].did()));
+        <Swarm as ChordStorageInterface<1>>::storage_store(&nodes[0], vnode).await?;
+        sleep(Duration::from_secs(3)).await;
+        let stored: Option<VirtualNode> = nodes[2].dht().storage.get(&vid).await?;
+        assert!(stored.is_some());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lookup_disjoint_paths() -> Result<()> {
+        let config = LookupConfig {
+            hop_timeout: Duration::from_secs(1),
+            disjoint_paths: 1,
+            ..Default::default()
+        };
+        let disjoint = LookupConfig {
+            disjoint_paths: 2,
+            ..config.clone()
+        };
+        let nodes = prepare_nodes(&config).await?;
+        manually_establish_connection(&nodes[0], &nodes[1]).await;
+        manually_establish_connection(&nodes[0], &nodes[2]).await;
+        manually_establish_connection(&nodes[1], &nodes[2]).await;
+        manually_establish_connection(&nodes[2], &nodes[3]).await;
+        // nodes[1] doesn't answer until it's listening.
+        for node in [&nodes[0], &nodes[2], &nodes[3]] {
+            let n = node.clone();
+            tokio::spawn(async move { n.listen().await });
+        }
+        sleep(Duration::from_secs(3)).await;
+
+        // A single path is walked through the node answering.
+        let route = nodes[0].lookup_iterative(nodes[3].did(), &config).await?;
+        assert_eq!(route.successor, nodes[3].did());
+
+        // Every disjoint path must reach the successor.
+        assert!(matches!(
+            nodes[0].lookup_iterative(nodes[3].did(), &disjoint).await,
+            Err(Error::LookupFailed(did)) if did == nodes[3].did()
+        ));
+
+        // Paths meet on the predecessor of target, and agree on the successor.
+        let n = nodes[1].clone();
+        tokio::spawn(async move { n.listen().await });
+        sleep(Duration::from_secs(3)).await;
+        let route = nodes[0].lookup_iterative(nodes[3].did(), &disjoint).await?;
+        assert_eq!(route.successor, nodes[3].did());
+        assert_eq!(route.route.last(), Some(&nodes[2].did()));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
@@ -4,38 +4,88 @@
 mod builder;
 /// Callback interface for swarm
 pub mod callback;
+/// Ring crawler of swarm
+pub mod crawl;
+/// End-to-end delivery acknowledgement of swarm
+pub mod delivery;
+/// End-to-end encryption of swarm
+pub mod encryption;
 /// Implementations of connection management traits for swarm
 pub mod impls;
+/// Graceful leaving of swarm
+pub mod leave;
+/// Iterative lookup of swarm
+pub mod lookup;
+/// Round-trip time measurement of swarm
+pub mod ping;
+/// Virtual positions of swarm
+pub mod positions;
+/// Negotiation of protocol with connected peers
+pub mod protocol;
+/// Inbox of topics subscribed by swarm
+pub mod pubsub;
+/// Replay protection of swarm
+pub mod replay;
+/// Request/response messaging of swarm
+pub mod request;
+/// Streaming transfer of swarm
+pub mod stream;
 mod types;
 
+use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
 use std::sync::RwLock;
 
 use async_recursion::async_recursion;
 use async_trait::async_trait;
 pub use builder::SwarmBuilder;
+use dashmap::DashMap;
+pub use delivery::Deliveries;
+pub use delivery::DeliveryStatus;
+pub use delivery::PendingDelivery;
+use futures::lock::Mutex as FuturesMutex;
+pub use lookup::LookupConfig;
+pub use lookup::LookupMode;
+pub use lookup::LookupRoute;
+pub use positions::VirtualPosition;
+pub use protocol::PeerProtocol;
+pub use protocol::PeerProtocols;
+pub use replay::ReplayWindow;
+pub use request::PendingFetch;
+pub use request::PendingFetches;
+pub use request::PendingRequest;
+pub use request::PendingRequests;
 use rings_derive::JudgeConnection;
 use rings_transport::core::transport::BoxedTransport;
 use rings_transport::core::transport::ConnectionInterface;
 use rings_transport::core::transport::TransportMessage;
 use rings_transport::error::Error as TransportError;
+pub use stream::Streams;
 pub use types::MeasureImpl;
 pub use types::WrappedDid;
 
 use crate::channels::Channel;
 use crate::chunk::ChunkList;
+use crate::consts::MAX_RELAY_MESSAGE_PARKED_MS;
+use crate::consts::RELAY_CHUNK_SIZE;
 use crate::consts::TRANSPORT_MAX_SIZE;
 use crate::consts::TRANSPORT_MTU;
+use crate::dht::subring::Subring;
 use crate::dht::types::Chord;
+use crate::dht::vnode::VirtualNode;
 use crate::dht::CorrectChord;
 use crate::dht::Did;
 use crate::dht::PeerRing;
+use crate::ecc::PublicKey;
 use crate::error::Error;
 use crate::error::Result;
+use crate::inspect::NetworkSizeInspect;
+use crate::inspect::RingInspect;
 use crate::inspect::SwarmInspect;
 use crate::message;
+use crate::message::types::DeliveryAck;
 use crate::message::types::NotifyPredecessorSend;
-use crate::message::ChordStorageInterface;
+use crate::message::Decoder;
 use crate::message::Message;
 use crate::message::MessageHandler;
 use crate::message::MessageHandlerEvent;
@@ -45,10 +95,13 @@ use crate::message::PayloadSender;
 use crate::session::SessionSk;
 use crate::swarm::callback::SharedSwarmCallback;
 use crate::swarm::impls::ConnectionHandshake;
+use crate::swarm::impls::Judegement;
+use crate::swarm::pubsub::TopicInbox;
 use crate::types::channel::Channel as ChannelTrait;
 use crate::types::channel::TransportEvent;
 use crate::types::Connection;
 use crate::types::ConnectionOwner;
+use crate::utils::get_epoch_ms;
 
 /// The transport and dht management.
 #[derive(JudgeConnection)]
@@ -58,11 +111,41 @@ pub struct Swarm {
     /// Reference of DHT.
     pub(crate) dht: Arc<PeerRing>,
     /// Implementationof measurement.
-    pub(crate) measure: Option<MeasureImpl>,
+    pub(crate) measure: Option<Arc<MeasureImpl>>,
     session_sk: SessionSk,
     message_handler: MessageHandler,
     transport: BoxedTransport<ConnectionOwner, TransportError>,
     callback: RwLock<SharedSwarmCallback>,
+    /// Requests waiting for responses.
+    pub(crate) pending_requests: PendingRequests,
+    /// Fetches of virtual nodes waiting for responses.
+    pub(crate) pending_fetches: PendingFetches,
+    /// Messages waiting for delivery acks, and their status.
+    pub(crate) deliveries: Deliveries,
+    /// Parked relay messages sent to their destinations and waiting for acks, keyed by tx_id.
+    pub(crate) parked_deliveries: DashMap<uuid::Uuid, (Did, VirtualNode)>,
+    /// Streams being sent and received.
+    pub(crate) streams: Streams,
+    /// Received messages of subscribed topics.
+    pub(crate) topic_inbox: TopicInbox,
+    /// Window of received transactions, used to drop duplicated messages.
+    pub(crate) replay_window: Arc<ReplayWindow>,
+    /// Window of received ring-wide and subring broadcasts, to deliver and forward them once.
+    pub(crate) broadcast_window: Arc<ReplayWindow>,
+    /// Session public keys of peers, used for end-to-end encryption.
+    pub(crate) session_pubkeys: DashMap<Did, PublicKey>,
+    /// When the session public key was published to DHT, in milliseconds.
+    pub(crate) session_pubkey_published_at: AtomicU64,
+    /// Mode of looking up the successor of a did.
+    pub(crate) lookup_mode: LookupMode,
+    /// Positions operated besides the did of swarm.
+    pub(crate) virtual_positions: Vec<VirtualPosition>,
+    /// Positions operated by connected peers, mapping to the did of their connections.
+    pub(crate) position_aliases: DashMap<Did, Did>,
+    /// Protocols negotiated with connected peers, shared with connections.
+    pub(crate) peer_protocols: Arc<PeerProtocols>,
+    /// Chunks being relayed to peers which cannot relay chunks, see [crate::swarm::protocol].
+    pub(crate) relayed_chunks: FuturesMutex<ChunkList<RELAY_CHUNK_SIZE>>,
 }
 
 impl Swarm {
@@ -92,7 +175,13 @@ impl Swarm {
                 Ok(Some(payload))
             }
             TransportEvent::Connected(did) => match self.get_connection(did) {
-                Some(_) => {
+                Some(conn) => {
+                    if let Err(e) = self.greet_peer(did, &conn).await {
+                        tracing::error!("Failed on sending handshake to {:?}: {:?}", did, e);
+                    }
+                    if let Err(e) = self.announce_positions(did).await {
+                        tracing::error!("Failed on announcing positions to {:?}: {:?}", did, e);
+                    }
                     let payload = MessagePayload::new_send(
                         Message::JoinDHT(message::JoinDHT { did }),
                         &self.session_sk,
@@ -103,7 +192,12 @@ impl Swarm {
                 }
                 None => Err(Error::SwarmMissTransport(did)),
             },
+            TransportEvent::Incompatible(did) => {
+                self.disconnect(did).await?;
+                Ok(None)
+            }
             TransportEvent::Closed(did) => {
+                self.peer_protocols.remove(did);
                 let payload = MessagePayload::new_send(
                     Message::LeaveDHT(message::LeaveDHT { did }),
                     &self.session_sk,
@@ -143,11 +237,31 @@ impl Swarm {
             tracing::error!("Cannot verify msg or it's expired: {:?}", payload);
             return None;
         }
-        let events = self.message_handler.handle_message(&payload).await;
+        self.record_session_pubkey(&payload);
+        if self.is_local_position(payload.transaction.destination) {
+            self.pending_requests.resolve(&payload);
+            self.pending_fetches.resolve(&payload);
+            if let Some((tx_id, destination)) = self.deliveries.resolve(&payload) {
+                self.notify_delivery_status(tx_id, destination, DeliveryStatus::Delivered)
+                    .await;
+            }
+            if let Err(e) =
+                crate::message::handlers::storage::handle_relay_message_ack(self, &payload).await
+            {
+                tracing::error!("Failed on draining delivered relay message: {:?}", e);
+            }
+            self.handle_stream_payload(&payload).await;
+        }
+        // Message is handled by the position which is its next hop.
+        let (handler, dht) = match self.virtual_position(payload.relay.next_hop) {
+            Some(position) => (&position.handler, position.dht.clone()),
+            None => (&self.message_handler, self.dht.clone()),
+        };
+        let events = handler.handle_message(&payload).await;
 
         match events {
             Ok(evs) => {
-                self.handle_message_handler_events(&evs)
+                self.handle_position_events(&dht, &evs)
                     .await
                     .unwrap_or_else(|e| {
                         tracing::error!(
@@ -168,27 +282,41 @@ impl Swarm {
     pub async fn handle_message_handler_event(
         &self,
         event: &MessageHandlerEvent,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        self.handle_position_event(&self.dht, event).await
+    }
+
+    /// Event handler of Swarm, on behalf of a position operated by swarm.
+    pub(crate) async fn handle_position_event(
+        &self,
+        dht: &Arc<PeerRing>,
+        event: &MessageHandlerEvent,
     ) -> Result<Vec<MessageHandlerEvent>> {
         tracing::debug!("Handle message handler event: {:?}", event);
+        let sender = self.position_sender(dht);
         match event {
             MessageHandlerEvent::Connect(did) => {
                 let did = *did;
-                if self.get_and_check_connection(did).await.is_none() && did != self.did() {
-                    self.connect(did).await?;
+                // Iterative lookup waits for reports handled by listen loop, never do it here.
+                if self.get_and_check_connection(did).await.is_none()
+                    && !self.is_local_position(did)
+                {
+                    JudgeConnection::connect(self, did).await?;
                 }
                 Ok(vec![])
             }
 
             // Notify did with self.id
             MessageHandlerEvent::Notify(did) => {
-                let msg =
-                    Message::NotifyPredecessorSend(NotifyPredecessorSend { did: self.dht.did });
+                let msg = Message::NotifyPredecessorSend(NotifyPredecessorSend { did: dht.did });
                 Ok(vec![MessageHandlerEvent::SendMessage(msg, *did)])
             }
 
             MessageHandlerEvent::ConnectVia(did, next) => {
                 let did = *did;
-                if self.get_and_check_connection(did).await.is_none() && did != self.did() {
+                if self.get_and_check_connection(did).await.is_none()
+                    && !self.is_local_position(did)
+                {
                     self.connect_via(did, *next).await?;
                 }
                 Ok(vec![])
@@ -217,54 +345,196 @@ impl Swarm {
             }
 
             MessageHandlerEvent::ForwardPayload(payload, next_hop) => {
-                if self
+                let next_hop = if self
                     .get_and_check_connection(payload.relay.destination)
                     .await
                     .is_some()
                 {
-                    self.forward_payload(payload, Some(payload.relay.destination))
-                        .await?;
+                    Some(payload.relay.destination)
                 } else {
-                    self.forward_payload(payload, *next_hop).await?;
+                    *next_hop
+                };
+                match sender.forward_payload(payload, next_hop).await {
+                    Err(Error::UnsupportedByPeer(_, feature)) if feature == "chunk-relaying" => {
+                        self.forward_chunk_by_reassembly(&sender, payload, next_hop)
+                            .await?
+                    }
+                    result => result?,
                 }
                 Ok(vec![])
             }
 
             MessageHandlerEvent::JoinDHT(ctx, did) => {
-                if cfg!(feature = "experimental") {
+                let mut events = if cfg!(feature = "experimental") {
                     let wdid: WrappedDid = WrappedDid::new(self, *did);
-                    let dht_ev = self.dht.join_then_sync(wdid).await?;
+                    let dht_ev = dht.join_then_sync(wdid).await?;
                     crate::message::handlers::dht::handle_dht_events(&dht_ev, ctx).await
                 } else {
-                    let dht_ev = self.dht.join(*did)?;
+                    let dht_ev = dht.join(*did)?;
                     crate::message::handlers::dht::handle_dht_events(&dht_ev, ctx).await
+                }?;
+                // A node joined by the did of swarm joins all the other positions as well.
+                if dht.did == self.did() {
+                    self.join_virtual_positions(ctx, *did).await?;
                 }
+                // Deliver messages parked while the joined node was offline.
+                events.extend(
+                    crate::message::handlers::storage::handle_relay_message_join(self, *did)
+                        .await?,
+                );
+                Ok(events)
             }
 
             MessageHandlerEvent::SendDirectMessage(msg, dest) => {
-                self.send_direct_message(msg.clone(), *dest).await?;
+                let tx_id = sender.send_direct_message(msg.clone(), *dest).await?;
+                if let Message::FindSuccessorSend(m) = msg {
+                    dht.successor_queries.record(tx_id, m.did);
+                }
                 Ok(vec![])
             }
 
             MessageHandlerEvent::SendMessage(msg, dest) => {
-                self.send_message(msg.clone(), *dest).await?;
+                let tx_id = sender.send_message(msg.clone(), *dest).await?;
+                if let Message::FindSuccessorSend(m) = msg {
+                    dht.successor_queries.record(tx_id, m.did);
+                }
                 Ok(vec![])
             }
 
             MessageHandlerEvent::SendReportMessage(payload, msg) => {
-                self.send_report_message(payload, msg.clone()).await?;
+                sender.send_report_message(payload, msg.clone()).await?;
                 Ok(vec![])
             }
 
             MessageHandlerEvent::ResetDestination(payload, next_hop) => {
-                self.reset_destination(payload, *next_hop).await?;
+                sender.reset_destination(payload, *next_hop).await?;
                 Ok(vec![])
             }
 
             MessageHandlerEvent::StorageStore(vnode) => {
-                <Self as ChordStorageInterface<1>>::storage_store(self, vnode.clone()).await?;
+                crate::message::handlers::storage::handle_storage_sync_store(
+                    self,
+                    dht,
+                    vnode.clone(),
+                )
+                .await?;
+                Ok(vec![])
+            }
+
+            MessageHandlerEvent::StoreRelayMessage(payload) => {
+                crate::message::handlers::storage::handle_relay_message_store(self, payload)
+                    .await?;
+                Ok(vec![])
+            }
+
+            MessageHandlerEvent::DeliverRelayMessage(vnode, found) => {
+                let callback = self.callback()?;
+                // Payloads failed to deliver are kept, and not acknowledged.
+                let mut undelivered = vec![];
+                for data in vnode.data.iter() {
+                    let payload = MessagePayload::from_encoded(data)?;
+                    // Parked payloads usually outlive their ttl, they are checked against
+                    // the max parking time instead.
+                    if !verify_signature(&payload)
+                        || is_parked_too_long(&payload)
+                        || payload.transaction.destination != self.did()
+                    {
+                        tracing::warn!("Drop invalid relay message: {:?}", payload);
+                        continue;
+                    }
+                    let payload = encryption::decrypt_payload(&payload, &self.session_sk)?;
+                    if let Err(e) = callback.on_inbound(&payload).await {
+                        tracing::error!("Failed on delivering relay message: {:?}", e);
+                        undelivered.push(data.clone());
+                    }
+                }
+                let delivered = vnode.without_data(&undelivered);
+                crate::message::handlers::storage::drain_relay_message(&self.dht, &delivered)
+                    .await?;
+                match found {
+                    Some(ctx) if undelivered.is_empty() => {
+                        Ok(vec![MessageHandlerEvent::SendReportMessage(
+                            ctx.clone(),
+                            Message::DeliveryAck(DeliveryAck {
+                                tx_id: ctx.transaction.tx_id,
+                            }),
+                        )])
+                    }
+                    _ => Ok(vec![]),
+                }
+            }
+
+            MessageHandlerEvent::DeliverTopicMessage(msg) => {
+                self.topic_inbox.deliver(dht.did, msg.clone());
+                Ok(vec![])
+            }
+
+            MessageHandlerEvent::DeliverSubringMessage(msg) => {
+                let payload = MessagePayload::from_encoded(&msg.payload)?;
+                if !payload.transaction.verify() || payload.transaction.destination != msg.subring {
+                    tracing::warn!("Drop invalid subring message: {:?}", payload);
+                    return Ok(vec![]);
+                }
+                if !self.broadcast_window.check(&payload) {
+                    tracing::debug!("Drop duplicated subring message: {:?}", payload);
+                    return Ok(vec![]);
+                }
+                if let Err(e) = self.callback()?.on_inbound(&payload).await {
+                    tracing::error!("Failed on delivering subring message: {:?}", e);
+                }
+                let events = Subring::routes(dht.did, &msg.members)
+                    .into_iter()
+                    .map(|(next, members)| {
+                        let msg = Message::SubringBroadcast(message::SubringBroadcast {
+                            subring: msg.subring,
+                            members,
+                            payload: msg.payload.clone(),
+                        });
+                        MessageHandlerEvent::SendMessage(msg, next)
+                    })
+                    .collect();
+                Ok(events)
+            }
+
+            MessageHandlerEvent::DeliverRingBroadcast(msg) => {
+                let payload = MessagePayload::from_encoded(&msg.payload)?;
+                if !payload.transaction.verify() {
+                    tracing::warn!("Drop invalid broadcast message: {:?}", payload);
+                    return Ok(vec![]);
+                }
+                if !self.broadcast_window.check(&payload) {
+                    tracing::debug!("Drop duplicated broadcast message: {:?}", payload);
+                    return Ok(vec![]);
+                }
+                if let Err(e) = self.callback()?.on_inbound(&payload).await {
+                    tracing::error!("Failed on delivering broadcast message: {:?}", e);
+                }
+                if msg.ttl == 0 {
+                    return Ok(vec![]);
+                }
+                let events = dht
+                    .broadcast_routes(msg.limit)?
+                    .into_iter()
+                    .map(|(next, limit)| {
+                        let msg = Message::RingBroadcast(message::RingBroadcast {
+                            limit,
+                            ttl: msg.ttl - 1,
+                            payload: msg.payload.clone(),
+                        });
+                        MessageHandlerEvent::SendMessage(msg, next)
+                    })
+                    .collect();
+                Ok(events)
+            }
+
+            MessageHandlerEvent::RecordInvalidReport(did) => {
+                self.record_invalid_report(*did).await;
                 Ok(vec![])
             }
+
+            MessageHandlerEvent::JoinPositions(ctx, did, count) => {
+                Ok(self.join_positions(ctx, *did, *count))
+            }
         }
     }
 
@@ -274,13 +544,24 @@ impl Swarm {
     pub async fn handle_message_handler_events(
         &self,
         events: &Vec<MessageHandlerEvent>,
+    ) -> Result<()> {
+        self.handle_position_events(&self.dht, events).await
+    }
+
+    /// Batch handle events, on behalf of a position operated by swarm.
+    #[cfg_attr(feature = "wasm", async_recursion(?Send))]
+    #[cfg_attr(not(feature = "wasm"), async_recursion)]
+    pub(crate) async fn handle_position_events(
+        &self,
+        dht: &Arc<PeerRing>,
+        events: &Vec<MessageHandlerEvent>,
     ) -> Result<()> {
         match events.as_slice() {
             [] => Ok(()),
             [x, xs @ ..] => {
-                let evs = self.handle_message_handler_event(x).await?;
-                self.handle_message_handler_events(&evs).await?;
-                self.handle_message_handler_events(&xs.to_vec()).await
+                let evs = self.handle_position_event(dht, x).await?;
+                self.handle_position_events(dht, &evs).await?;
+                self.handle_position_events(dht, &xs.to_vec()).await
             }
         }
     }
@@ -296,7 +577,27 @@ impl Swarm {
     /// Connect a given Did. If the did is already connected, return directly,
     /// else try prepare offer and establish connection by dht.
     /// This function may returns a pending connection or connected connection.
+    ///
+    /// In [LookupMode::Iterative], the did is looked up first, and the offer is sent via
+    /// the closest connected node on the route.
     pub async fn connect(&self, did: Did) -> Result<Connection> {
+        if let LookupMode::Iterative(config) = &self.lookup_mode {
+            if let Some(conn) = self.get_and_check_connection(did).await {
+                return Ok(conn);
+            }
+            let route = self.lookup_iterative(did, config).await?;
+            if route.successor != did {
+                return Err(Error::LookupFailed(did));
+            }
+            let next_hop = route
+                .route
+                .iter()
+                .rev()
+                .find(|hop| self.get_connection(**hop).is_some());
+            if let Some(next_hop) = next_hop {
+                return JudgeConnection::connect_via(self, did, *next_hop).await;
+            }
+        }
         JudgeConnection::connect(self, did).await
     }
 
@@ -309,6 +610,33 @@ impl Swarm {
     pub async fn inspect(&self) -> SwarmInspect {
         SwarmInspect::inspect(self).await
     }
+
+    /// Check the status of the whole ring, see [crawl].
+    pub async fn inspect_ring(&self) -> RingInspect {
+        RingInspect::inspect(self).await
+    }
+
+    /// Estimate the number of nodes on the ring, see [crate::dht::estimate].
+    pub fn estimate_network_size(&self) -> NetworkSizeInspect {
+        NetworkSizeInspect::inspect(&self.dht)
+    }
+}
+
+/// Check if a parked payload is signed more than [MAX_RELAY_MESSAGE_PARKED_MS] ago,
+/// or its session is expired.
+fn is_parked_too_long(payload: &MessagePayload) -> bool {
+    let verification = &payload.transaction.verification;
+    verification.session.is_expired()
+        || verification.ts_ms + MAX_RELAY_MESSAGE_PARKED_MS as u128 <= get_epoch_ms()
+}
+
+/// Check the signature of a payload carried by other message, ignoring its expiration.
+fn verify_signature(payload: &MessagePayload) -> bool {
+    payload
+        .transaction
+        .verification_data()
+        .map(|data| payload.transaction.verification.verify(&data))
+        .unwrap_or(false)
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -332,6 +660,10 @@ impl PayloadSender for Swarm {
             println!("+++++++++++++++++++++++++++++++++");
         }
 
+        if self.is_loopback(did) {
+            return self.loopback(payload).await;
+        }
+
         let conn = self
             .get_and_check_connection(did)
             .await
@@ -348,13 +680,24 @@ impl PayloadSender for Swarm {
             tracing::error!("Message is too large: {:?}", payload);
             return Err(Error::MessageTooLarge(data.len()));
         }
+        self.check_peer_protocol(did, &payload, data.len())?;
+        self.greet_peer(did, &conn).await?;
 
         let result = if data.len() > TRANSPORT_MTU {
-            let chunks = ChunkList::<TRANSPORT_MTU>::from(&data);
+            let destination = self.chunk_destination(did, &payload)?;
+            let chunks = if destination == did {
+                ChunkList::<TRANSPORT_MTU>::from(&data).to_vec()
+            } else {
+                ChunkList::<RELAY_CHUNK_SIZE>::from(&data).to_vec()
+            };
             for chunk in chunks {
-                let data =
-                    MessagePayload::new_send(Message::Chunk(chunk), &self.session_sk, did, did)?
-                        .to_bincode()?;
+                let data = MessagePayload::new_send(
+                    Message::Chunk(chunk),
+                    &self.session_sk,
+                    did,
+                    destination,
+                )?
+                .to_bincode()?;
                 conn.send_message(TransportMessage::Custom(data.to_vec()))
                     .await?;
             }
@@ -380,10 +723,23 @@ impl PayloadSender for Swarm {
     }
 }
 
+impl Swarm {
+    /// Migrate vnodes in the storage of each position, see [crate::dht::migration].
+    async fn migrate_vnodes(&self) {
+        for dht in self.position_dhts() {
+            if let Err(e) = dht.migrate_vnodes().await {
+                tracing::error!("Failed to migrate vnodes of {}: {:?}", dht.did, e);
+            }
+        }
+    }
+}
+
 #[cfg(not(feature = "wasm"))]
 impl Swarm {
     /// Listener for native envirement, It will just launch a loop.
+    /// Vnodes persisted by older versions are migrated before listening.
     pub async fn listen(self: Arc<Self>) {
+        self.migrate_vnodes().await;
         loop {
             self.listen_once().await;
         }
@@ -393,7 +749,9 @@ impl Swarm {
 #[cfg(feature = "wasm")]
 impl Swarm {
     /// Listener for browser envirement, the implementation is based on  js_sys::window.set_timeout.
+    /// Vnodes persisted by older versions are migrated before listening.
     pub async fn listen(self: Arc<Self>) {
+        self.migrate_vnodes().await;
         let func = move || {
             let this = self.clone();
             wasm_bindgen_futures::spawn_local(Box::pin(async move {
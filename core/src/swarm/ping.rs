@@ -0,0 +1,96 @@
+#![warn(missing_docs)]
+//! Round-trip time measurement of connected peers.
+//!
+//! A [Message::PingSend] is sent over the data channel of a connection, and answered by a
+//! [Message::PingReport] reusing its tx_id. The measured rtt is recorded to [PeerRing](crate::dht::PeerRing),
+//! where it's used to choose fingers by proximity, see [FingerTable::in_interval](crate::dht::FingerTable::in_interval)
+//! and [FingerTable::closest_predecessor_by_rtt](crate::dht::FingerTable::closest_predecessor_by_rtt).
+
+use std::time::Duration;
+
+use futures::future::join_all;
+
+use crate::consts::DEFAULT_PING_TIMEOUT_MS;
+use crate::dht::Did;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::Message;
+use crate::message::MessagePayload;
+use crate::message::PayloadSender;
+use crate::message::PingSend;
+use crate::swarm::Swarm;
+use crate::utils::get_epoch_ms;
+
+impl Swarm {
+    /// Measure the round-trip time in milliseconds of a connected peer,
+    /// and record it for proximity neighbor selection.
+    pub async fn ping(&self, did: Did) -> Result<u64> {
+        if self.get_connection(did).is_none() {
+            return Err(Error::SwarmMissTransport(did));
+        }
+
+        let msg = Message::PingSend(PingSend {});
+        let payload = MessagePayload::new_send(msg, &self.session_sk, did, did)?;
+
+        // Register before sending, so that a fast report will not be missed.
+        let req = self
+            .pending_requests
+            .register(payload.transaction.tx_id, did);
+        let sent_at = get_epoch_ms();
+        self.send_payload(payload).await?;
+        req.wait(Duration::from_millis(DEFAULT_PING_TIMEOUT_MS))
+            .await?;
+
+        let rtt = (get_epoch_ms() - sent_at) as u64;
+        self.dht.set_rtt(did, rtt);
+        Ok(rtt)
+    }
+
+    /// Measure the round-trip time of all connected peers in parallel.
+    /// Failures are logged and ignored.
+    pub async fn ping_connections(&self) {
+        let dids = self.get_connection_ids();
+        let results = join_all(dids.iter().map(|did| self.ping(*did))).await;
+        for (did, result) in dids.iter().zip(results) {
+            if let Err(e) = result {
+                tracing::debug!("Failed to ping {}: {:?}", did, e);
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::ecc::SecretKey;
+    use crate::tests::default::prepare_node;
+    use crate::tests::manually_establish_connection;
+
+    #[tokio::test]
+    async fn test_ping() -> Result<()> {
+        let (node1, _path1) = prepare_node(SecretKey::random()).await;
+        let (node2, _path2) = prepare_node(SecretKey::random()).await;
+        manually_establish_connection(&node1, &node2).await;
+
+        let n1 = node1.clone();
+        let n2 = node2.clone();
+        tokio::spawn(async move { n1.listen().await });
+        tokio::spawn(async move { n2.listen().await });
+        sleep(Duration::from_secs(1)).await;
+
+        let rtt = node1.ping(node2.did()).await?;
+        assert_eq!(node1.dht().rtt(node2.did()), Some(rtt));
+        assert!(node2.dht().rtt(node1.did()).is_none());
+
+        // Disconnected peer is removed from dht with its rtt.
+        node1.disconnect(node2.did()).await?;
+        assert!(node1.dht().rtt(node2.did()).is_none());
+        assert!(node1.ping(node2.did()).await.is_err());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
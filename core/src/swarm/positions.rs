@@ -0,0 +1,305 @@
+#![warn(missing_docs)]
+//! Virtual positions of swarm on the ring.
+//!
+//! A swarm occupies the position of its did on the ring by default. With few nodes, the
+//! ranges they are responsible for are very uneven. A swarm can operate more positions,
+//! see [SwarmBuilder::virtual_positions](crate::swarm::SwarmBuilder::virtual_positions).
+//! They are derived from the did by [Did::virtual_position], and each of them has its own
+//! [PeerRing], so successors, predecessor and storage are tracked separately. But they share
+//! the connections of swarm.
+//!
+//! Positions are announced to connected peers by [Message::AnnouncePositions]. The peer joins
+//! them to the DHTs of all its positions, and reaches them through the connection of swarm.
+//! A message is handled by the position which is its next hop, and messages between local
+//! positions are looped back through the event channel of swarm.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::channels::Channel;
+use crate::consts::MAX_VIRTUAL_POSITIONS;
+use crate::dht::Chord;
+use crate::dht::Did;
+use crate::dht::PeerRing;
+use crate::error::Result;
+use crate::message::handlers::dht::handle_dht_events;
+use crate::message::AnnouncePositions;
+use crate::message::Message;
+use crate::message::MessageHandler;
+use crate::message::MessageHandlerEvent;
+use crate::message::MessagePayload;
+use crate::message::PayloadSender;
+use crate::session::SessionSk;
+use crate::swarm::Swarm;
+use crate::types::channel::Channel as ChannelTrait;
+use crate::types::channel::TransportEvent;
+
+/// A position operated by swarm besides its did, see [Did::virtual_position].
+#[derive(Clone)]
+pub struct VirtualPosition {
+    /// DHT of the position, with its own successors, predecessor and storage.
+    pub dht: Arc<PeerRing>,
+    pub(crate) handler: MessageHandler,
+}
+
+impl VirtualPosition {
+    /// Create a position by its DHT.
+    pub fn new(dht: Arc<PeerRing>) -> Self {
+        Self {
+            handler: MessageHandler::new(dht.clone()),
+            dht,
+        }
+    }
+}
+
+/// [PayloadSender] on behalf of a position, so that messages are originated from it,
+/// and forwarded or reported by it.
+pub(crate) struct PositionSender<'a> {
+    swarm: &'a Swarm,
+    dht: Arc<PeerRing>,
+}
+
+impl<'a> PositionSender<'a> {
+    /// The swarm operating the position.
+    pub(crate) fn swarm(&self) -> &'a Swarm {
+        self.swarm
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl PayloadSender for PositionSender<'_> {
+    fn session_sk(&self) -> &SessionSk {
+        self.swarm.session_sk()
+    }
+
+    fn dht(&self) -> Arc<PeerRing> {
+        self.dht.clone()
+    }
+
+    async fn do_send_payload(&self, did: Did, payload: MessagePayload) -> Result<()> {
+        self.swarm.do_send_payload(did, payload).await
+    }
+}
+
+impl Swarm {
+    /// Dids of all positions operated by swarm, starting from its did.
+    pub fn positions(&self) -> Vec<Did> {
+        std::iter::once(self.did())
+            .chain(self.virtual_positions.iter().map(|p| p.dht.did))
+            .collect()
+    }
+
+    /// DHTs of all positions operated by swarm, starting from the DHT of its did.
+    pub fn position_dhts(&self) -> Vec<Arc<PeerRing>> {
+        std::iter::once(self.dht())
+            .chain(self.virtual_positions.iter().map(|p| p.dht.clone()))
+            .collect()
+    }
+
+    /// Test if a did is one of the positions operated by swarm.
+    pub fn is_local_position(&self, did: Did) -> bool {
+        did == self.did() || self.virtual_position(did).is_some()
+    }
+
+    /// Get a position operated by swarm besides its did.
+    pub fn virtual_position(&self, did: Did) -> Option<&VirtualPosition> {
+        self.virtual_positions.iter().find(|p| p.dht.did == did)
+    }
+
+    /// Did of the connection to reach a did. It's different from the did only if that is
+    /// a position operated by a connected peer.
+    pub fn connection_did(&self, did: Did) -> Did {
+        self.position_aliases.get(&did).map(|v| *v).unwrap_or(did)
+    }
+
+    pub(crate) fn position_sender(&self, dht: &Arc<PeerRing>) -> PositionSender<'_> {
+        PositionSender {
+            swarm: self,
+            dht: dht.clone(),
+        }
+    }
+
+    /// A message to local positions is looped back when swarm operates more than its did.
+    pub(crate) fn is_loopback(&self, did: Did) -> bool {
+        !self.virtual_positions.is_empty() && self.is_local_position(did)
+    }
+
+    /// Deliver a payload to local positions through the event channel of swarm.
+    pub(crate) async fn loopback(&self, payload: MessagePayload) -> Result<()> {
+        let data = payload.to_bincode()?;
+        Channel::send(
+            &self.transport_event_channel.sender(),
+            TransportEvent::DataChannelMessage(data.to_vec()),
+        )
+        .await
+    }
+
+    /// Announce positions to a connected peer.
+    /// Nothing is sent if swarm operates its did only.
+    pub(crate) async fn announce_positions(&self, did: Did) -> Result<()> {
+        if self.virtual_positions.is_empty() {
+            return Ok(());
+        }
+        let count = self.virtual_positions.len() as u16 + 1;
+        self.send_direct_message(Message::AnnouncePositions(AnnouncePositions { count }), did)
+            .await?;
+        Ok(())
+    }
+
+    /// Record the positions announced by a connected peer, which will be reached through
+    /// the connection of the peer. Return events to join them to DHT.
+    pub(crate) fn join_positions(
+        &self,
+        ctx: &MessagePayload,
+        did: Did,
+        count: u16,
+    ) -> Vec<MessageHandlerEvent> {
+        if count > MAX_VIRTUAL_POSITIONS {
+            tracing::warn!("Ignore {} positions announced by {}", count, did);
+            return vec![];
+        }
+        let positions = did.virtual_positions(count);
+        let Some(conn) = positions
+            .iter()
+            .find(|p| self.get_connection(**p).is_some())
+            .copied()
+        else {
+            tracing::warn!(
+                "Ignore positions announced by {} which is not connected",
+                did
+            );
+            return vec![];
+        };
+
+        positions
+            .into_iter()
+            .filter(|p| *p != conn && !self.is_local_position(*p))
+            .map(|p| {
+                self.position_aliases.insert(p, conn);
+                MessageHandlerEvent::JoinDHT(ctx.clone(), p)
+            })
+            .collect()
+    }
+
+    /// Join a node to DHTs of positions besides the did of swarm.
+    /// Events are handled on behalf of each position.
+    pub(crate) async fn join_virtual_positions(
+        &self,
+        ctx: &MessagePayload,
+        did: Did,
+    ) -> Result<()> {
+        for position in self.virtual_positions.iter() {
+            let act = position.dht.join(did)?;
+            let events = handle_dht_events(&act, ctx).await?;
+            self.handle_position_events(&position.dht, &events).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove a connected peer, with all the positions it operates, from DHTs of all local
+    /// positions. Return the did of its connection.
+    pub(crate) fn remove_positions(&self, did: Did) -> Result<Did> {
+        let conn = self.connection_did(did);
+        let mut dids = vec![conn];
+        self.position_aliases.retain(|p, c| {
+            if *c == conn {
+                dids.push(*p);
+            }
+            *c != conn
+        });
+        for dht in self.position_dhts() {
+            for did in dids.iter() {
+                dht.remove(*did)?;
+            }
+        }
+        Ok(conn)
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::dht::successor::SuccessorReader;
+    use crate::ecc::SecretKey;
+    use crate::tests::default::prepare_node;
+    use crate::tests::default::prepare_node_with_positions;
+    use crate::tests::manually_establish_connection;
+
+    #[tokio::test]
+    async fn test_virtual_positions() -> Result<()> {
+        let (node1, _path1) = prepare_node_with_positions(SecretKey::random(), 2).await;
+        let (node2, _path2) = prepare_node(SecretKey::random()).await;
+
+        let positions = node1.did().virtual_positions(3);
+        assert_eq!(node1.positions(), positions);
+        assert!(positions.iter().all(|p| node1.is_local_position(*p)));
+        // Local positions know each other without any connection.
+        for dht in node1.position_dhts() {
+            let mut others = positions.clone();
+            others.retain(|p| *p != dht.did);
+            assert!(others.iter().all(|p| dht.successors().contains(p).unwrap()));
+        }
+
+        manually_establish_connection(&node1, &node2).await;
+        let n1 = node1.clone();
+        let n2 = node2.clone();
+        tokio::spawn(async move { n1.listen().await });
+        tokio::spawn(async move { n2.listen().await });
+        sleep(Duration::from_secs(3)).await;
+
+        // Positions of node1 are reached through the connection of node1.
+        for p in positions.iter() {
+            assert_eq!(node2.connection_did(*p), node1.did());
+            assert!(node2.get_connection(*p).is_some());
+            assert!(node2.dht().successors().contains(p)?);
+        }
+        // All positions of node1 join node2.
+        for dht in node1.position_dhts() {
+            assert!(dht.successors().contains(&node2.did())?);
+        }
+
+        // Messages to a position are handled by that position.
+        let rtt = node2.ping(positions[2]).await?;
+        assert_eq!(node2.dht().rtt(positions[2]), Some(rtt));
+
+        node2.disconnect(positions[1]).await?;
+        assert!(node2.get_connection(node1.did()).is_none());
+        for p in positions.iter() {
+            assert_eq!(node2.connection_did(*p), *p);
+            assert!(!node2.dht().successors().contains(p)?);
+        }
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ignore_positions_of_unconnected_peer() -> Result<()> {
+        let (node1, _path1) = prepare_node_with_positions(SecretKey::random(), 1).await;
+        let did = SecretKey::random().address().into();
+        let ctx = MessagePayload::new_send(
+            Message::AnnouncePositions(AnnouncePositions { count: 2 }),
+            node1.session_sk(),
+            node1.did(),
+            node1.did(),
+        )?;
+        assert!(node1.join_positions(&ctx, did, 2).is_empty());
+        assert!(node1
+            .join_positions(&ctx, did, MAX_VIRTUAL_POSITIONS + 1)
+            .is_empty());
+        assert_eq!(
+            node1.connection_did(did.virtual_position(1)),
+            did.virtual_position(1)
+        );
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
@@ -0,0 +1,497 @@
+#![warn(missing_docs)]
+//! Negotiation of protocol with connected peers.
+//!
+//! Once a connection is connected, each side sends a [Message::ProtocolHandshake] with its
+//! protocol version, network id, kinds of message it can decode and optional features.
+//! Messages sent through the connection wait until the handshake is sent, so it always
+//! arrives first. The handshake is consumed by [crate::swarm::callback::InnerSwarmCallback] of
+//! the connection. A peer is incompatible if it belongs to another network, or the versions
+//! of both sides are out of the range that the other side can talk with. It will be
+//! disconnected and notified by [crate::swarm::callback::SwarmEvent::IncompatiblePeer], and
+//! payloads received from it fail verification until then.
+//!
+//! The network id keeps separate networks, such as staging and production, from joining each
+//! other when they share ICE servers. It's set by [crate::swarm::SwarmBuilder::network_id].
+//!
+//! For a compatible peer, the negotiated [PeerProtocol] is remembered until the connection
+//! is closed, and [Swarm] refuses to send it messages it cannot decode. Peers which never
+//! send handshake are running an older version, messages to them are not checked. Such peers
+//! know nothing of networks, so they are only accepted by nodes of [DEFAULT_NETWORK_ID].
+//! Nodes of other networks drop every message received before the handshake of the peer,
+//! which keeps peers from skipping the check of network id.
+//!
+//! Chunks of a large custom message are addressed to its destination and relayed one by one,
+//! only through peers supporting `chunk-relaying`. Otherwise the message is chunked for the
+//! connected peer, which reassembles it before relaying. A relay whose next hop doesn't support
+//! `chunk-relaying` reassembles the chunks in the same way, and chunks the message again for
+//! the next hop.
+
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use dashmap::DashSet;
+use futures::lock::Mutex as FuturesMutex;
+use rings_transport::core::transport::ConnectionInterface;
+use rings_transport::core::transport::TransportMessage;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::consts::DEFAULT_NETWORK_ID;
+use crate::consts::MIN_PROTOCOL_VERSION;
+use crate::consts::PROTOCOL_FEATURES;
+use crate::consts::PROTOCOL_VERSION;
+use crate::chunk::ChunkManager;
+use crate::consts::TRANSPORT_MTU;
+use crate::dht::Did;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::Message;
+use crate::message::MessagePayload;
+use crate::message::MessageVerificationExt;
+use crate::message::PayloadSender;
+use crate::message::ProtocolHandshake;
+use crate::swarm::positions::PositionSender;
+use crate::swarm::Swarm;
+use crate::types::Connection;
+
+/// Protocol negotiated with a connected peer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerProtocol {
+    /// Protocol version of peer.
+    pub version: u32,
+    /// Kinds of message that current node supports but the peer cannot decode.
+    pub unsupported_messages: Vec<String>,
+    /// Optional features supported by the peer.
+    pub features: Vec<String>,
+}
+
+impl ProtocolHandshake {
+    /// Handshake of current node in network.
+    pub fn new(network_id: &str) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            network_id: network_id.to_string(),
+            messages: Message::KINDS.iter().map(|k| k.to_string()).collect(),
+            features: PROTOCOL_FEATURES.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    /// Negotiate protocol with the handshake of peer.
+    /// Return the reason if the peer is incompatible.
+    pub fn negotiate(&self, network_id: &str) -> std::result::Result<PeerProtocol, String> {
+        if self.network_id != network_id {
+            return Err(format!(
+                "peer belongs to network {}, but current node belongs to {}",
+                self.network_id, network_id
+            ));
+        }
+        if self.version < MIN_PROTOCOL_VERSION {
+            return Err(format!(
+                "protocol version {} of peer is lower than {}",
+                self.version, MIN_PROTOCOL_VERSION
+            ));
+        }
+        if self.min_version > PROTOCOL_VERSION {
+            return Err(format!(
+                "peer requires protocol version {} at least, but current node is {}",
+                self.min_version, PROTOCOL_VERSION
+            ));
+        }
+
+        Ok(PeerProtocol {
+            version: self.version,
+            unsupported_messages: Message::KINDS
+                .iter()
+                .filter(|k| !self.messages.iter().any(|m| m == *k))
+                .map(|k| k.to_string())
+                .collect(),
+            features: self.features.clone(),
+        })
+    }
+}
+
+impl PeerProtocol {
+    /// Test if the peer supports an optional feature.
+    pub fn supports_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+/// Protocols negotiated with connected peers, shared by [Swarm] and its connections.
+pub struct PeerProtocols {
+    network_id: String,
+    peers: DashMap<Did, PeerProtocol>,
+    greeted: DashMap<Did, Arc<FuturesMutex<bool>>>,
+    refused: DashSet<Did>,
+}
+
+impl PeerProtocols {
+    /// Create an empty [PeerProtocols] for nodes of network.
+    pub fn new(network_id: &str) -> Self {
+        Self {
+            network_id: network_id.to_string(),
+            peers: DashMap::new(),
+            greeted: DashMap::new(),
+            refused: DashSet::new(),
+        }
+    }
+
+    /// Id of the network that current node belongs to.
+    pub fn network_id(&self) -> &str {
+        &self.network_id
+    }
+
+    /// Negotiate protocol with the handshake of a connected peer, and remember it.
+    /// Return the reason if the peer is incompatible.
+    pub fn negotiate(
+        &self,
+        did: Did,
+        handshake: &ProtocolHandshake,
+    ) -> std::result::Result<(), String> {
+        match handshake.negotiate(&self.network_id) {
+            Ok(protocol) => {
+                tracing::debug!("Negotiated protocol with {}: {:?}", did, protocol);
+                self.peers.insert(did, protocol);
+                Ok(())
+            }
+            Err(reason) => {
+                self.peers.remove(&did);
+                self.refused.insert(did);
+                Err(reason)
+            }
+        }
+    }
+
+    /// Get the protocol negotiated with a connected peer.
+    pub fn get(&self, did: Did) -> Option<PeerProtocol> {
+        self.peers.get(&did).map(|p| p.clone())
+    }
+
+    /// Test if a peer is refused for being incompatible.
+    pub fn is_refused(&self, did: Did) -> bool {
+        self.refused.contains(&did)
+    }
+
+    /// Test if messages of a peer should be dropped until its handshake is received.
+    /// Only nodes of [DEFAULT_NETWORK_ID] accept peers which never send handshake.
+    pub fn requires_handshake(&self, did: Did) -> bool {
+        self.network_id != DEFAULT_NETWORK_ID && !self.peers.contains_key(&did)
+    }
+
+    /// Get the lock of greeting a connected peer, which holds whether handshake is sent.
+    fn greeting(&self, did: Did) -> Arc<FuturesMutex<bool>> {
+        self.greeted.entry(did).or_default().clone()
+    }
+
+    /// Forget the protocol of a peer, when its connection is closed.
+    pub fn remove(&self, did: Did) {
+        self.peers.remove(&did);
+        self.greeted.remove(&did);
+        self.refused.remove(&did);
+    }
+}
+
+impl Swarm {
+    /// Send handshake through the connection of a peer, if it's not sent yet.
+    /// It's sent on connected, and messages sent before that wait for it. A failed handshake
+    /// is sent again with the next message.
+    pub(crate) async fn greet_peer(&self, did: Did, conn: &Connection) -> Result<()> {
+        let greeting = self.peer_protocols.greeting(did);
+        let mut greeted = greeting.lock().await;
+        if *greeted {
+            return Ok(());
+        }
+        let handshake = ProtocolHandshake::new(self.peer_protocols.network_id());
+        let data = MessagePayload::new_send(
+            Message::ProtocolHandshake(handshake),
+            &self.session_sk,
+            did,
+            did,
+        )?
+        .to_bincode()?;
+        conn.send_message(TransportMessage::Custom(data.to_vec()))
+            .await
+            .map_err(Error::Transport)?;
+        *greeted = true;
+        Ok(())
+    }
+
+    /// Id of the network that swarm belongs to.
+    pub fn network_id(&self) -> &str {
+        self.peer_protocols.network_id()
+    }
+
+    /// Get the protocol negotiated with a connected peer.
+    /// Return None if no handshake is received from it.
+    pub fn peer_protocol(&self, did: Did) -> Option<PeerProtocol> {
+        self.peer_protocols.get(self.connection_did(did))
+    }
+
+    /// Check if a payload of `size` bytes can be sent to a connected peer.
+    pub(crate) fn check_peer_protocol(
+        &self,
+        did: Did,
+        payload: &MessagePayload,
+        size: usize,
+    ) -> Result<()> {
+        let Some(protocol) = self.peer_protocol(did) else {
+            return Ok(());
+        };
+
+        if !protocol.unsupported_messages.is_empty() {
+            let kind = payload.transaction.data::<Message>()?.kind();
+            if protocol.unsupported_messages.iter().any(|k| k == kind) {
+                return Err(Error::UnsupportedByPeer(did, kind.to_string()));
+            }
+        }
+        if size > TRANSPORT_MTU && !protocol.supports_feature("chunking") {
+            return Err(Error::UnsupportedByPeer(did, "chunking".to_string()));
+        }
+        // A chunk addressed to another node can only be relayed by peers knowing how.
+        if payload.transaction.destination != did && !protocol.supports_feature("chunk-relaying") {
+            if let Message::Chunk(_) = payload.transaction.data::<Message>()? {
+                return Err(Error::UnsupportedByPeer(did, "chunk-relaying".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Forward a chunk addressed to another node, whose next hop cannot relay chunks.
+    /// The chunks are reassembled instead, and the message is forwarded when it's completed,
+    /// which chunks it again for the next hop.
+    pub(crate) async fn forward_chunk_by_reassembly(
+        &self,
+        sender: &PositionSender<'_>,
+        payload: &MessagePayload,
+        next_hop: Option<Did>,
+    ) -> Result<()> {
+        let Message::Chunk(chunk) = payload.transaction.data()? else {
+            return sender.forward_payload(payload, next_hop).await;
+        };
+        let Some(data) = self.relayed_chunks.lock().await.handle(chunk) else {
+            return Ok(());
+        };
+
+        let mut inner = MessagePayload::from_bincode(&data)?;
+        if !(inner.verify() && inner.transaction.verify()) {
+            return Err(Error::VerifySignatureFailed);
+        }
+        // Relays after the chunker are kept on the relay path, as the destination does.
+        inner.relay.path.extend(payload.relay.path.iter().skip(1));
+        inner.relay.next_hop = payload.relay.next_hop;
+        sender.forward_payload(&inner, next_hop).await
+    }
+
+    /// Get the destination of chunks when a large payload is sent to a connected peer.
+    ///
+    /// Custom messages are routed to the destination node opaquely, so their chunks are
+    /// addressed to it and relayed one by one, if the peer is able to relay chunks.
+    /// Chunks of other messages are addressed to the peer, which handles the reassembled
+    /// message on the way.
+    pub(crate) fn chunk_destination(&self, did: Did, payload: &MessagePayload) -> Result<Did> {
+        let relaying = self
+            .peer_protocol(did)
+            .map_or(false, |p| p.supports_feature("chunk-relaying"));
+        if !relaying {
+            return Ok(did);
+        }
+        Ok(match payload.transaction.data::<Message>()? {
+            Message::CustomMessage(_) | Message::EncryptedMessage(_) | Message::AckedMessage(_) => {
+                payload.relay.destination
+            }
+            _ => did,
+        })
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::dht::SuccessorReader;
+    use crate::ecc::tests::gen_ordered_keys;
+    use crate::ecc::SecretKey;
+    use crate::message::handlers::connection::tests::test_only_two_nodes_establish_connection;
+    use crate::message::PayloadSender;
+    use crate::session::SessionSk;
+    use crate::storage::PersistenceStorage;
+    use crate::swarm::SwarmBuilder;
+    use crate::tests::default::prepare_node;
+    use crate::tests::manually_establish_connection;
+
+    async fn prepare_node_of_network(key: SecretKey, network_id: &str) -> Arc<Swarm> {
+        let path = PersistenceStorage::random_path("./tmp");
+        let storage = PersistenceStorage::new_with_path(path.as_str())
+            .await
+            .unwrap();
+        let session_sk = SessionSk::new_with_seckey(&key).unwrap();
+        Arc::new(
+            SwarmBuilder::new("stun://stun.l.google.com:19302", storage, session_sk)
+                .network_id(network_id.to_string())
+                .build(),
+        )
+    }
+
+    #[test]
+    fn test_negotiate_protocol() {
+        let local = ProtocolHandshake::new("rings");
+        let protocol = local.negotiate("rings").unwrap();
+        assert_eq!(protocol.version, PROTOCOL_VERSION);
+        assert!(protocol.unsupported_messages.is_empty());
+        assert!(protocol.supports_feature("chunking"));
+
+        assert!(local.negotiate("staging").is_err());
+
+        let mut older = ProtocolHandshake::new("rings");
+        older
+            .messages
+            .retain(|k| k != "AckedMessage" && k != "DeliveryAck");
+        older.features.clear();
+        let protocol = older.negotiate("rings").unwrap();
+        assert_eq!(protocol.unsupported_messages, vec![
+            "AckedMessage".to_string(),
+            "DeliveryAck".to_string()
+        ]);
+        assert!(!protocol.supports_feature("chunking"));
+
+        let mut newer = ProtocolHandshake::new("rings");
+        newer.version = PROTOCOL_VERSION + 2;
+        newer.min_version = PROTOCOL_VERSION + 1;
+        assert!(newer.negotiate("rings").is_err());
+
+        let mut outdated = ProtocolHandshake::new("rings");
+        outdated.version = MIN_PROTOCOL_VERSION - 1;
+        assert!(outdated.negotiate("rings").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_protocol_on_connected() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        assert!(node1.peer_protocol(node2.did()).is_none());
+
+        // Handshakes are consumed by connections, and never show up in listen_once.
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+        let expected = PeerProtocol {
+            version: PROTOCOL_VERSION,
+            unsupported_messages: vec![],
+            features: PROTOCOL_FEATURES.iter().map(|f| f.to_string()).collect(),
+        };
+        assert_eq!(node1.peer_protocol(node2.did()), Some(expected.clone()));
+        assert_eq!(node2.peer_protocol(node1.did()), Some(expected));
+
+        // Messages unsupported by peer are refused.
+        node1
+            .peer_protocols
+            .peers
+            .alter(&node2.did(), |_, mut protocol| {
+                protocol.unsupported_messages = vec!["CustomMessage".to_string()];
+                protocol
+            });
+        assert!(matches!(
+            node1.send_message(Message::custom(b"hello")?, node2.did()).await,
+            Err(Error::UnsupportedByPeer(did, kind)) if did == node2.did() && kind == "CustomMessage"
+        ));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_greet_peer_on_connected() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        manually_establish_connection(&node1, &node2).await;
+
+        // Handshake is sent before joining dht, even if no message is sent to peer.
+        let payload = node1.poll_message().await.unwrap();
+        assert!(matches!(payload.transaction.data()?, Message::JoinDHT(_)));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(node2.peer_protocol(node1.did()).is_some());
+        assert!(node1.peer_protocol(node2.did()).is_none());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refuse_peer_of_other_network() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let node1 = prepare_node_of_network(keys[0], "staging").await;
+        let node2 = prepare_node_of_network(keys[1], "production").await;
+        assert_eq!(node1.network_id(), "staging");
+
+        manually_establish_connection(&node1, &node2).await;
+        let n1 = node1.clone();
+        let n2 = node2.clone();
+        tokio::spawn(async move { n1.listen().await });
+        tokio::spawn(async move { n2.listen().await });
+
+        // Handshake is sent on connected, then the connection is refused.
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert!(node1.get_connection(node2.did()).is_none());
+        assert!(node2.get_connection(node1.did()).is_none());
+        assert!(node1.peer_protocol(node2.did()).is_none());
+        assert!(node2.peer_protocol(node1.did()).is_none());
+        assert!(!node2.dht().successors().list()?.contains(&node1.did()));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_drop_message_before_handshake() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let node1 = prepare_node_of_network(keys[0], "staging").await;
+        let node2 = prepare_node_of_network(keys[1], "staging").await;
+        manually_establish_connection(&node1, &node2).await;
+
+        let received = |node: Arc<Swarm>| async move {
+            let mut messages = vec![];
+            while let Ok(Some((payload, _))) =
+                tokio::time::timeout(Duration::from_secs(2), node.listen_once()).await
+            {
+                messages.push(payload.transaction.data::<Message>().unwrap());
+            }
+            messages
+        };
+
+        // Skip the handshake by sending through the connection directly.
+        let data = MessagePayload::new_send(
+            Message::custom(b"hello")?,
+            &node1.session_sk,
+            node2.did(),
+            node2.did(),
+        )?
+        .to_bincode()?;
+        let conn = node1.get_connection(node2.did()).unwrap();
+        conn.send_message(TransportMessage::Custom(data.to_vec()))
+            .await
+            .unwrap();
+        assert!(!received(node2.clone())
+            .await
+            .iter()
+            .any(|m| matches!(m, Message::CustomMessage(_))));
+        assert!(node2.peer_protocol(node1.did()).is_none());
+
+        // The same message is accepted after the handshake.
+        node1.greet_peer(node2.did(), &conn).await?;
+        conn.send_message(TransportMessage::Custom(data.to_vec()))
+            .await
+            .unwrap();
+        assert!(received(node2.clone())
+            .await
+            .iter()
+            .any(|m| matches!(m, Message::CustomMessage(_))));
+        assert!(node2.peer_protocol(node1.did()).is_some());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+#![warn(missing_docs)]
+//! Inbox of subscribed topics.
+//!
+//! Messages of a topic are pushed by the node responsible for the topic as
+//! [Message::TopicMessage](crate::message::Message::TopicMessage), and buffered here
+//! until they are polled. See [PubsubInterface](crate::message::PubsubInterface).
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use futures::channel::mpsc;
+use futures::future::select;
+use futures::future::Either;
+use futures::lock::Mutex as FuturesMutex;
+use futures::pin_mut;
+use futures::StreamExt;
+use futures_timer::Delay;
+
+use crate::consts::TOPIC_INBOX_CAPACITY;
+use crate::dht::Did;
+use crate::message::TopicMessage;
+
+type TopicChannel = (
+    mpsc::Sender<TopicMessage>,
+    Arc<FuturesMutex<mpsc::Receiver<TopicMessage>>>,
+);
+
+/// Received messages of topics subscribed, keyed by did of subscriber and did of topic,
+/// since the positions of a node are different subscribers, see [crate::swarm::positions].
+#[derive(Default)]
+pub struct TopicInbox {
+    inner: DashMap<(Did, Did), TopicChannel>,
+}
+
+impl TopicInbox {
+    /// Start receiving messages of a topic by subscriber. Received messages are kept if it's
+    /// already subscribed.
+    pub fn subscribe(&self, subscriber: Did, topic: Did) {
+        self.inner.entry((subscriber, topic)).or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel(TOPIC_INBOX_CAPACITY);
+            (sender, Arc::new(FuturesMutex::new(receiver)))
+        });
+    }
+
+    /// Stop receiving messages of a topic by subscriber, and drop the received ones.
+    pub fn unsubscribe(&self, subscriber: Did, topic: Did) {
+        self.inner.remove(&(subscriber, topic));
+    }
+
+    /// List dids of topics subscribed by subscriber.
+    pub fn topics(&self, subscriber: Did) -> Vec<Did> {
+        self.inner
+            .iter()
+            .filter(|entry| entry.key().0 == subscriber)
+            .map(|entry| entry.key().1)
+            .collect()
+    }
+
+    /// Put a message sent to subscriber into the inbox of its topic.
+    /// Return false if the topic is not subscribed by subscriber or the inbox is full.
+    pub fn deliver(&self, subscriber: Did, msg: TopicMessage) -> bool {
+        let Some(mut sender) = self
+            .inner
+            .get(&(subscriber, msg.topic))
+            .map(|entry| entry.0.clone())
+        else {
+            tracing::debug!(
+                "Drop message of topic {} not subscribed by {}",
+                msg.topic,
+                subscriber
+            );
+            return false;
+        };
+        if let Err(e) = sender.try_send(msg) {
+            tracing::warn!("Drop message of topic: {:?}", e);
+            return false;
+        }
+        true
+    }
+
+    /// Take received messages of a topic by subscriber. If there is no message, wait for the
+    /// next one until timeout. Return empty list if the topic is not subscribed by subscriber.
+    pub async fn poll(&self, subscriber: Did, topic: Did, timeout: Duration) -> Vec<TopicMessage> {
+        let Some(receiver) = self
+            .inner
+            .get(&(subscriber, topic))
+            .map(|entry| entry.1.clone())
+        else {
+            return vec![];
+        };
+        let mut receiver = receiver.lock().await;
+
+        let mut messages = vec![];
+        let delay = Delay::new(timeout);
+        pin_mut!(delay);
+        match select(receiver.next(), delay).await {
+            Either::Left((Some(msg), _)) => messages.push(msg),
+            _ => return messages,
+        }
+        while let Ok(Some(msg)) = receiver.try_next() {
+            messages.push(msg);
+        }
+        messages
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dht::tests::gen_ordered_dids;
+    use crate::message::Encoder;
+
+    #[tokio::test]
+    async fn test_topic_inbox() {
+        let dids = gen_ordered_dids(4);
+        let (topic, other, publisher, subscriber) = (dids[0], dids[1], dids[2], dids[3]);
+        let inbox = TopicInbox::default();
+        let msg = |topic: Did, data: &str| TopicMessage {
+            topic,
+            publisher,
+            data: data.to_string().encode().unwrap(),
+            publication: "".into(),
+        };
+
+        assert!(!inbox.deliver(subscriber, msg(topic, "before subscribed")));
+        inbox.subscribe(subscriber, topic);
+        assert!(inbox.deliver(subscriber, msg(topic, "hello")));
+        assert!(inbox.deliver(subscriber, msg(topic, "world")));
+        assert!(!inbox.deliver(subscriber, msg(other, "other topic")));
+        assert!(!inbox.deliver(other, msg(topic, "other subscriber")));
+        assert_eq!(inbox.topics(subscriber), vec![topic]);
+        assert!(inbox.topics(other).is_empty());
+
+        let messages = inbox.poll(subscriber, topic, Duration::from_secs(1)).await;
+        assert_eq!(messages, vec![msg(topic, "hello"), msg(topic, "world")]);
+        assert!(inbox
+            .poll(subscriber, topic, Duration::from_millis(100))
+            .await
+            .is_empty());
+        assert!(inbox.poll(other, topic, Duration::ZERO).await.is_empty());
+
+        inbox.unsubscribe(subscriber, topic);
+        assert!(!inbox.deliver(subscriber, msg(topic, "after unsubscribed")));
+        assert!(inbox
+            .poll(subscriber, topic, Duration::ZERO)
+            .await
+            .is_empty());
+    }
+}
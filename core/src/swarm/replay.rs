@@ -0,0 +1,200 @@
+#![warn(missing_docs)]
+//! Replay protection of [MessagePayload].
+//!
+//! A transaction is valid within its ttl (see [crate::consts::DEFAULT_TTL_MS]), so a captured
+//! payload can be re-injected again and again in that period. The [ReplayWindow] remembers
+//! the `(signer, tx_id)` of received transactions until they expire, so that duplicates can
+//! be dropped before handling.
+
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::dht::Did;
+use crate::message::MessagePayload;
+use crate::message::MessageVerificationExt;
+use crate::utils::get_epoch_ms;
+
+type SeenKey = (Did, uuid::Uuid);
+
+#[derive(Default)]
+struct SeenSet {
+    keys: HashSet<SeenKey>,
+    /// Keys with their expiration time, in order of insertion.
+    queue: VecDeque<(u128, SeenKey)>,
+}
+
+/// A bounded, ttl aware set of `(signer, tx_id)` for received transactions.
+/// Records are only evicted after they expire. When it is full of unexpired records, new
+/// transactions are refused, since forgetting a live record would let it be replayed.
+/// A window with zero capacity is disabled and accepts everything.
+pub struct ReplayWindow {
+    capacity: usize,
+    seen: Mutex<SeenSet>,
+}
+
+impl ReplayWindow {
+    /// Create a new [ReplayWindow] which can hold `capacity` records at most.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            seen: Mutex::new(SeenSet::default()),
+        }
+    }
+
+    /// Record the payload. Return false if it was already seen and not expired, or the window
+    /// is full of unexpired records.
+    pub fn check(&self, payload: &MessagePayload) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+
+        let now = get_epoch_ms();
+        // The transaction is signed by its creator and will not change when relaying.
+        let verification = payload.transaction.verification();
+        let expires_at = verification.ts_ms + verification.ttl_ms as u128;
+        let key = (payload.transaction.signer(), payload.transaction.tx_id);
+
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        if seen.keys.contains(&key) {
+            return false;
+        }
+
+        // Evict expired records in order of insertion.
+        while let Some((exp, k)) = seen.queue.front().cloned() {
+            if exp > now {
+                break;
+            }
+            seen.queue.pop_front();
+            seen.keys.remove(&k);
+        }
+        if seen.queue.len() >= self.capacity {
+            // Records may have different ttl, find expired ones behind the oldest.
+            let SeenSet { keys, queue } = &mut *seen;
+            queue.retain(|(exp, k)| *exp > now || !keys.remove(k));
+        }
+        if seen.queue.len() >= self.capacity {
+            tracing::warn!("Replay window is full, refuse tx {}", key.1);
+            return false;
+        }
+
+        seen.keys.insert(key);
+        seen.queue.push_back((expires_at, key));
+        true
+    }
+
+    /// Number of records in the window.
+    pub fn len(&self) -> usize {
+        self.seen
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .keys
+            .len()
+    }
+
+    /// Check if the window is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecc::SecretKey;
+    use crate::message::CustomMessage;
+    use crate::message::Message;
+    use crate::session::SessionSk;
+
+    fn gen_payload(session_sk: &SessionSk) -> MessagePayload {
+        let did = SecretKey::random().address().into();
+        MessagePayload::new_send(
+            Message::CustomMessage(CustomMessage(vec![1, 2, 3])),
+            session_sk,
+            did,
+            did,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_replay_window() {
+        let session_sk = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let window = ReplayWindow::new(2);
+
+        let p1 = gen_payload(&session_sk);
+        let p2 = gen_payload(&session_sk);
+        let p3 = gen_payload(&session_sk);
+
+        assert!(window.check(&p1));
+        assert!(!window.check(&p1));
+        assert!(window.check(&p2));
+        assert!(!window.check(&p2));
+        assert_eq!(window.len(), 2);
+
+        // Unexpired records are never evicted, new ones are refused when the window is full.
+        assert!(!window.check(&p3));
+        assert_eq!(window.len(), 2);
+        assert!(!window.check(&p1));
+        assert!(!window.check(&p3));
+
+        // Expired records are evicted.
+        let mut expired = gen_payload(&session_sk);
+        expired.transaction.verification.ts_ms -= expired.transaction.verification.ttl_ms as u128;
+        let window = ReplayWindow::new(2);
+        assert!(window.check(&expired));
+        assert!(window.check(&p1));
+        assert_eq!(window.len(), 1);
+
+        // Expired records behind unexpired ones are evicted when the window is full.
+        let window = ReplayWindow::new(2);
+        assert!(window.check(&p1));
+        assert!(window.check(&expired));
+        assert!(window.check(&p2));
+        assert_eq!(window.len(), 2);
+        assert!(!window.check(&p1));
+
+        // Disabled window accepts everything.
+        let window = ReplayWindow::new(0);
+        assert!(window.check(&p1));
+        assert!(window.check(&p1));
+        assert!(window.is_empty());
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    #[tokio::test]
+    async fn test_drop_replayed_message() -> crate::error::Result<()> {
+        use std::time::Duration;
+
+        use crate::ecc::tests::gen_ordered_keys;
+        use crate::message::handlers::connection::tests::test_only_two_nodes_establish_connection;
+        use crate::message::PayloadSender;
+        use crate::tests::default::prepare_node;
+
+        let keys = gen_ordered_keys(2);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        let payload = MessagePayload::new_send(
+            Message::custom(b"hello")?,
+            node1.session_sk(),
+            node2.did(),
+            node2.did(),
+        )?;
+
+        node1.send_payload(payload.clone()).await?;
+        let (received, _) = node2.listen_once().await.unwrap();
+        assert_eq!(received.transaction.tx_id, payload.transaction.tx_id);
+
+        // Replay the same payload, it should be dropped before handling.
+        node1.send_payload(payload.clone()).await?;
+        tokio::select! {
+            _ = node2.listen_once() => unreachable!("node2 should not receive replayed message"),
+            _ = tokio::time::sleep(Duration::from_secs(3)) => {}
+        }
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
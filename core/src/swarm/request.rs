@@ -0,0 +1,423 @@
+#![warn(missing_docs)]
+//! Request/response messaging on top of [Message::CustomMessage].
+//!
+//! A request is a [Message::CustomMessage] sent by [Swarm::request]. The remote peer
+//! responds it by [Swarm::respond], which sends a report message reusing the
+//! `tx_id` of the request, or by [Swarm::respond_to] if only the `tx_id` and the requester
+//! are known. When the response arrives, the [PendingRequest] is resolved. The response is
+//! consumed by the request, so it's not passed to [crate::swarm::callback::SwarmCallback::on_inbound].
+//!
+//! Fetching of virtual nodes works in a similar way, but the [Message::FoundVNode] may be sent
+//! by any node that is holding the vnode, so [PendingFetch] is matched by the dids of vnode,
+//! which are the affine dids of a vnode stored redundantly.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use futures::channel::mpsc;
+use futures::channel::oneshot;
+use futures::future::select;
+use futures::future::Either;
+use futures::pin_mut;
+use futures::StreamExt;
+use futures_timer::Delay;
+
+use crate::dht::vnode::VirtualNode;
+use crate::dht::Did;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::Message;
+use crate::message::MessagePayload;
+use crate::message::MessageRelay;
+use crate::message::PayloadSender;
+use crate::message::Transaction;
+use crate::swarm::Swarm;
+
+type PendingMap = DashMap<uuid::Uuid, (Did, oneshot::Sender<MessagePayload>)>;
+type PendingFetchMap = DashMap<uuid::Uuid, (Vec<Did>, mpsc::UnboundedSender<VirtualNode>)>;
+
+/// Requests that are waiting for responses, keyed by tx_id.
+#[derive(Default, Clone)]
+pub struct PendingRequests {
+    inner: Arc<PendingMap>,
+}
+
+/// A request that was sent and is waiting for response.
+/// Dropping it will cancel the request.
+pub struct PendingRequest {
+    /// The tx_id of the request, which can be used to cancel it.
+    pub tx_id: uuid::Uuid,
+    receiver: oneshot::Receiver<MessagePayload>,
+    pending: Arc<PendingMap>,
+}
+
+/// Check if payload is sent by the destination of a request, which is the signer or a position
+/// operated by it, see [MessagePayload::origin_position].
+fn is_response(destination: Did, payload: &MessagePayload) -> bool {
+    destination == payload.origin_position()
+}
+
+impl PendingRequests {
+    /// Register a tx_id sent to destination, the returned [PendingRequest]
+    /// will be resolved by its response.
+    pub fn register(&self, tx_id: uuid::Uuid, destination: Did) -> PendingRequest {
+        let (sender, receiver) = oneshot::channel();
+        self.inner.insert(tx_id, (destination, sender));
+        PendingRequest {
+            tx_id,
+            receiver,
+            pending: self.inner.clone(),
+        }
+    }
+
+    /// Resolve the pending request which has the same tx_id with payload.
+    /// The payload should be sent by the destination of the request, see [is_response].
+    /// Return false if there is no such request.
+    pub fn resolve(&self, payload: &MessagePayload) -> bool {
+        let Some((_, (_, sender))) = self
+            .inner
+            .remove_if(&payload.transaction.tx_id, |_, (did, _)| {
+                is_response(*did, payload)
+            })
+        else {
+            return false;
+        };
+        sender.send(payload.clone()).is_ok()
+    }
+
+    /// Check if the payload is a response of a pending request, without resolving it.
+    /// It's checked in the same way as [PendingRequests::resolve].
+    pub fn is_pending(&self, payload: &MessagePayload) -> bool {
+        self.inner
+            .get(&payload.transaction.tx_id)
+            .map_or(false, |v| is_response(v.0, payload))
+    }
+
+    /// Cancel a pending request. Its waiter will get [Error::RequestCancelled].
+    /// Return false if the request is already resolved or not existed.
+    pub fn cancel(&self, tx_id: uuid::Uuid) -> bool {
+        self.inner.remove(&tx_id).is_some()
+    }
+}
+
+impl PendingRequest {
+    /// Wait for the response of the request.
+    /// Return [Error::RequestTimeout] if no response is received before timeout.
+    pub async fn wait(mut self, timeout: Duration) -> Result<MessagePayload> {
+        let delay = Delay::new(timeout);
+        pin_mut!(delay);
+
+        match select(&mut self.receiver, delay).await {
+            Either::Left((Ok(payload), _)) => Ok(payload),
+            Either::Left((Err(_), _)) => Err(Error::RequestCancelled(self.tx_id)),
+            Either::Right(_) => Err(Error::RequestTimeout(self.tx_id)),
+        }
+    }
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        self.pending.remove(&self.tx_id);
+    }
+}
+
+/// Fetches that are waiting for [Message::FoundVNode], keyed by a random id.
+#[derive(Default)]
+pub struct PendingFetches {
+    inner: Arc<PendingFetchMap>,
+}
+
+/// A fetch of virtual node that is waiting for [Message::FoundVNode].
+/// Dropping it will stop receiving.
+pub struct PendingFetch {
+    id: uuid::Uuid,
+    receiver: mpsc::UnboundedReceiver<VirtualNode>,
+    pending: Arc<PendingFetchMap>,
+}
+
+impl PendingFetches {
+    /// Register a fetch of vnodes `vids`, such as the affine dids of a vnode, see
+    /// [Did::rotate_affine]. The returned [PendingFetch] will receive all vnodes with any
+    /// of the dids in [Message::FoundVNode].
+    pub fn register(&self, vids: Vec<Did>) -> PendingFetch {
+        let (sender, receiver) = mpsc::unbounded();
+        let id = uuid::Uuid::new_v4();
+        self.inner.insert(id, (vids, sender));
+        PendingFetch {
+            id,
+            receiver,
+            pending: self.inner.clone(),
+        }
+    }
+
+    /// Dispatch vnodes in [Message::FoundVNode] to the pending fetches.
+    /// Payloads of other messages are ignored.
+    pub fn resolve(&self, payload: &MessagePayload) {
+        if self.inner.is_empty() {
+            return;
+        }
+        let Ok(Message::FoundVNode(msg)) = payload.transaction.data() else {
+            return;
+        };
+        for vnode in msg.data {
+            for entry in self.inner.iter() {
+                let (vids, sender) = entry.value();
+                if vids.contains(&vnode.did) {
+                    sender.unbounded_send(vnode.clone()).ok();
+                }
+            }
+        }
+    }
+}
+
+impl PendingFetch {
+    /// Collect vnodes until `expected` ones are received, or timeout.
+    pub async fn collect(mut self, expected: usize, timeout: Duration) -> Vec<VirtualNode> {
+        let mut found = vec![];
+        if expected == 0 {
+            return found;
+        }
+
+        let delay = Delay::new(timeout);
+        pin_mut!(delay);
+        while found.len() < expected {
+            match select(self.receiver.next(), &mut delay).await {
+                Either::Left((Some(vnode), _)) => found.push(vnode),
+                _ => break,
+            }
+        }
+        found
+    }
+}
+
+impl Drop for PendingFetch {
+    fn drop(&mut self) {
+        self.pending.remove(&self.id);
+    }
+}
+
+impl Swarm {
+    /// Send a custom message to destination as a request.
+    /// The returned [PendingRequest] will be resolved by the response from destination.
+    pub async fn request(&self, msg: &[u8], destination: Did) -> Result<PendingRequest> {
+        let next_hop = self.infer_next_hop(None, destination)?;
+        let payload = MessagePayload::new_send(
+            Message::custom(msg)?,
+            &self.session_sk,
+            next_hop,
+            destination,
+        )?;
+
+        // Register before sending, so that a fast response will not be missed.
+        let req = self
+            .pending_requests
+            .register(payload.transaction.tx_id, destination);
+        self.send_payload(payload).await?;
+
+        Ok(req)
+    }
+
+    /// Cancel a pending request by its tx_id.
+    /// Return false if the request is already resolved or not existed.
+    pub fn cancel_request(&self, tx_id: uuid::Uuid) -> bool {
+        self.pending_requests.cancel(tx_id)
+    }
+
+    /// Respond a request payload with custom message data.
+    /// The response will be sent as a report message with the same tx_id.
+    pub async fn respond(&self, req: &MessagePayload, msg: &[u8]) -> Result<()> {
+        self.send_report_message(req, Message::custom(msg)?).await
+    }
+
+    /// Respond the request `tx_id` sent by `destination` with custom message data.
+    /// Unlike [Swarm::respond], the response is routed by DHT, since the relay path of request
+    /// is unknown.
+    pub async fn respond_to(&self, destination: Did, tx_id: uuid::Uuid, msg: &[u8]) -> Result<()> {
+        let next_hop = self.infer_next_hop(None, destination)?;
+        let transaction =
+            Transaction::new(destination, tx_id, Message::custom(msg)?, &self.session_sk)?;
+        let relay = MessageRelay::new(vec![self.did()], next_hop, destination);
+        let payload = MessagePayload::new(transaction, &self.session_sk, relay)?;
+        self.send_payload(payload).await
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use futures::lock::Mutex;
+
+    use super::*;
+    use crate::ecc::tests::gen_ordered_keys;
+    use crate::message::handlers::connection::tests::test_only_two_nodes_establish_connection;
+    use crate::message::CustomMessage;
+    use crate::message::FoundVNode;
+    use crate::swarm::callback::SwarmCallback;
+    use crate::tests::default::prepare_node;
+
+    #[tokio::test]
+    async fn test_request_and_respond() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        // Resolved by response.
+        let req = node1.request(b"ping", node2.did()).await?;
+        let tx_id = req.tx_id;
+        let (payload, _) = node2.listen_once().await.unwrap();
+        assert_eq!(payload.transaction.tx_id, tx_id);
+        node2.respond(&payload, b"pong").await?;
+        node1.listen_once().await.unwrap();
+
+        let resp = req.wait(Duration::from_secs(1)).await?;
+        assert_eq!(resp.transaction.tx_id, tx_id);
+        assert!(matches!(
+            resp.transaction.data()?,
+            Message::CustomMessage(CustomMessage(data)) if data == b"pong"
+        ));
+        assert!(!node1.cancel_request(tx_id));
+
+        // No response.
+        let req = node1.request(b"ping", node2.did()).await?;
+        let tx_id = req.tx_id;
+        node2.listen_once().await.unwrap();
+        assert!(matches!(
+            req.wait(Duration::from_millis(100)).await,
+            Err(Error::RequestTimeout(id)) if id == tx_id
+        ));
+        assert!(!node1.cancel_request(tx_id));
+
+        // Cancelled.
+        let req = node1.request(b"ping", node2.did()).await?;
+        let tx_id = req.tx_id;
+        node2.listen_once().await.unwrap();
+        assert!(node1.cancel_request(tx_id));
+        assert!(matches!(
+            req.wait(Duration::from_secs(1)).await,
+            Err(Error::RequestCancelled(id)) if id == tx_id
+        ));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_response_from_position() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        let position = node2.did().virtual_position(1);
+
+        let response = |origin: Did, tx_id: uuid::Uuid| -> Result<MessagePayload> {
+            let transaction = Transaction::new(
+                node1.did(),
+                tx_id,
+                Message::custom(b"pong")?,
+                node2.session_sk(),
+            )?;
+            let relay = MessageRelay::new(vec![origin], node1.did(), node1.did());
+            MessagePayload::new(transaction, node2.session_sk(), relay)
+        };
+
+        // A request to a position is answered by that position, not by its signer.
+        let pending = PendingRequests::default();
+        let tx_id = uuid::Uuid::new_v4();
+        let req = pending.register(tx_id, position);
+        let from_signer = response(node2.did(), tx_id)?;
+        assert!(!pending.is_pending(&from_signer));
+        assert!(!pending.resolve(&from_signer));
+
+        let from_position = response(position, tx_id)?;
+        assert!(pending.is_pending(&from_position));
+        assert!(pending.resolve(&from_position));
+        assert!(!pending.is_pending(&from_position));
+        assert_eq!(req.wait(Duration::from_secs(1)).await?, from_position);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_fetch_of_affine_vnodes() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+
+        let vnode: VirtualNode = "test_affine_fetch".to_string().try_into()?;
+        let vids = vnode.did.rotate_affine(3);
+        let found = |did: Did| -> Result<MessagePayload> {
+            let mut copy = vnode.clone();
+            copy.did = did;
+            let msg = Message::FoundVNode(FoundVNode { data: vec![copy] });
+            MessagePayload::new_send(msg, node2.session_sk(), node1.did(), node1.did())
+        };
+
+        // Every affine copy resolves the fetch, others are ignored.
+        let pending = PendingFetches::default();
+        let fetch = pending.register(vids.clone());
+        pending.resolve(&found(VirtualNode::gen_did("test_other_fetch")?)?);
+        for vid in vids.iter() {
+            pending.resolve(&found(*vid)?);
+        }
+        let collected = fetch.collect(vids.len() + 1, Duration::from_millis(100)).await;
+        assert_eq!(
+            collected.iter().map(|v| v.did).collect::<Vec<_>>(),
+            vids
+        );
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct InboundCallback {
+        inbound: Mutex<Vec<uuid::Uuid>>,
+    }
+
+    #[async_trait]
+    impl SwarmCallback for InboundCallback {
+        async fn on_inbound(
+            &self,
+            payload: &MessagePayload,
+        ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+            if let Message::CustomMessage(_) = payload.transaction.data()? {
+                self.inbound.lock().await.push(payload.transaction.tx_id);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_respond_to_request_by_tx_id() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        let cb1 = Arc::new(InboundCallback::default());
+        let cb2 = Arc::new(InboundCallback::default());
+        node1.set_callback(cb1.clone()).unwrap();
+        node2.set_callback(cb2.clone()).unwrap();
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        let req = node1.request(b"ping", node2.did()).await?;
+        let tx_id = req.tx_id;
+        node2.listen_once().await.unwrap();
+        assert_eq!(cb2.inbound.lock().await.as_slice(), &[tx_id]);
+
+        node2.respond_to(node1.did(), tx_id, b"pong").await?;
+        node1.listen_once().await.unwrap();
+        let resp = req.wait(Duration::from_secs(1)).await?;
+        assert!(matches!(
+            resp.transaction.data()?,
+            Message::CustomMessage(CustomMessage(data)) if data == b"pong"
+        ));
+
+        // The response is consumed by the request.
+        assert!(cb1.inbound.lock().await.is_empty());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
@@ -0,0 +1,684 @@
+#![warn(missing_docs)]
+//! Streaming transfer of large data on top of [Chunk].
+//!
+//! Chunks of a message are reassembled in memory before delivery, so its size is limited by
+//! [crate::consts::TRANSPORT_MAX_SIZE]. A stream is sent by [Swarm::send_stream] chunk by
+//! chunk, which are read from a [ChunkSource] on demand, and its destination reads them in
+//! order by [Swarm::read_stream], so neither side holds the whole data.
+//!
+//! The sender offers the stream by [Message::StreamOffer], then sends [Message::StreamChunk]
+//! within the credits granted by [Message::StreamCredit] of receiver. The receiver grants more
+//! credits as chunks are read, so at most [DEFAULT_STREAM_WINDOW] chunks are buffered. If the
+//! next chunk is not received before timeout, the receiver requests the missing chunks by
+//! index. If no credit is received before timeout, the sender offers the stream again.
+//!
+//! A stream is identified by the id of its [ChunkMeta]. An interrupted transfer is resumed by
+//! sending the stream again with the same meta, the receiver responds how many chunks it
+//! already received, which are skipped by sender.
+//!
+//! The receiver holds at most [MAX_INCOMING_STREAMS] unfinished streams, and
+//! [MAX_INCOMING_STREAMS_PER_PEER] of them from the same sender, further offers are refused.
+//! Streams idle for [INCOMING_STREAM_IDLE_TIMEOUT_MS] are dropped to make room.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::channel::oneshot;
+use futures::future::select;
+use futures::future::Either;
+use futures::pin_mut;
+use futures::StreamExt;
+use futures_timer::Delay;
+
+use crate::chunk::Chunk;
+use crate::chunk::ChunkMeta;
+use crate::chunk::ChunkSource;
+use crate::consts::DEFAULT_STREAM_WINDOW;
+use crate::consts::INCOMING_STREAM_IDLE_TIMEOUT_MS;
+use crate::consts::MAX_INCOMING_STREAMS;
+use crate::consts::MAX_INCOMING_STREAMS_PER_PEER;
+use crate::consts::MAX_STREAM_ATTEMPTS;
+use crate::consts::MAX_TRACKED_STREAMS;
+use crate::consts::STREAM_CHUNK_SIZE;
+use crate::dht::Did;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::Message;
+use crate::message::MessagePayload;
+use crate::message::PayloadSender;
+use crate::message::StreamCredit;
+use crate::message::StreamOffer;
+use crate::swarm::callback::SwarmEvent;
+use crate::swarm::Swarm;
+use crate::utils::get_epoch_ms;
+
+struct IncomingStream {
+    sender: Did,
+    total: usize,
+    /// Index of next chunk to be read.
+    read: usize,
+    /// Chunks whose index is less than limit are granted to sender.
+    limit: usize,
+    /// Chunks received but not read, keyed by index.
+    buffer: BTreeMap<usize, Bytes>,
+    waiter: Option<oneshot::Sender<()>>,
+    /// Timestamp in milliseconds of the last offer, chunk or read.
+    active_at: u128,
+}
+
+impl IncomingStream {
+    fn new(sender: Did, total: usize) -> Self {
+        Self {
+            sender,
+            total,
+            read: 0,
+            limit: total.min(DEFAULT_STREAM_WINDOW),
+            buffer: BTreeMap::new(),
+            waiter: None,
+            active_at: get_epoch_ms(),
+        }
+    }
+
+    fn is_idle(&self, now: u128) -> bool {
+        self.active_at + INCOMING_STREAM_IDLE_TIMEOUT_MS as u128 <= now
+    }
+
+    /// Number of leading chunks received, including the ones already read.
+    fn received(&self) -> usize {
+        let mut received = self.read;
+        while self.buffer.contains_key(&received) {
+            received += 1;
+        }
+        received
+    }
+
+    /// Credit of current state. Chunks not received below limit are reported as missing
+    /// if `resend` is true.
+    fn credit(&self, id: uuid::Uuid, resend: bool) -> StreamCredit {
+        let missing = if resend {
+            (self.read..self.limit)
+                .filter(|i| !self.buffer.contains_key(i))
+                .collect()
+        } else {
+            vec![]
+        };
+        StreamCredit {
+            id,
+            received: self.received(),
+            limit: self.limit,
+            missing,
+        }
+    }
+
+    /// Grant more credits when half of the window is read.
+    fn grant(&mut self, id: uuid::Uuid) -> Option<StreamCredit> {
+        if self.limit >= self.total || self.limit - self.read > DEFAULT_STREAM_WINDOW / 2 {
+            return None;
+        }
+        self.limit = self.total.min(self.read + DEFAULT_STREAM_WINDOW);
+        Some(self.credit(id, false))
+    }
+}
+
+#[derive(Default)]
+struct StreamBook {
+    incoming: HashMap<uuid::Uuid, IncomingStream>,
+    /// Streams that are read completely, with their number of chunks.
+    finished: HashMap<uuid::Uuid, usize>,
+    /// The id of finished streams, in order of finishing.
+    queue: VecDeque<uuid::Uuid>,
+    /// Outgoing streams waiting for credits, with their destinations.
+    outgoing: HashMap<uuid::Uuid, (Did, mpsc::UnboundedSender<StreamCredit>)>,
+}
+
+impl StreamBook {
+    fn finish(&mut self, id: uuid::Uuid) {
+        let Some(stream) = self.incoming.remove(&id) else {
+            return;
+        };
+        while self.queue.len() >= MAX_TRACKED_STREAMS {
+            let Some(id) = self.queue.pop_front() else {
+                break;
+            };
+            self.finished.remove(&id);
+        }
+        self.finished.insert(id, stream.total);
+        self.queue.push_back(id);
+    }
+
+    /// Drop idle incoming streams, then check if a new stream from sender can be accepted.
+    fn admit(&mut self, sender: Did) -> bool {
+        let now = get_epoch_ms();
+        self.incoming.retain(|id, stream| {
+            let idle = stream.is_idle(now);
+            if idle {
+                tracing::warn!("Drop idle stream {} from {}", id, stream.sender);
+            }
+            !idle
+        });
+        let from_sender = self
+            .incoming
+            .values()
+            .filter(|s| s.sender == sender)
+            .count();
+        self.incoming.len() < MAX_INCOMING_STREAMS && from_sender < MAX_INCOMING_STREAMS_PER_PEER
+    }
+}
+
+/// Next chunk of an incoming stream, see [Streams::next_chunk].
+enum NextChunk {
+    /// Data of chunk, with the credit that should be granted to sender.
+    Data(Bytes, Option<(Did, StreamCredit)>),
+    /// The stream is read completely.
+    Finished,
+    /// The chunk is not received yet.
+    Waiting(oneshot::Receiver<()>),
+}
+
+/// Streams sent and received by swarm, keyed by the id of stream.
+/// When it is full, the oldest finished stream will be forgotten, and new incoming streams
+/// are refused until others finish or become idle.
+#[derive(Default)]
+pub struct Streams {
+    inner: Arc<Mutex<StreamBook>>,
+}
+
+/// A stream that is being sent, and receiving credits from its destination.
+/// Dropping it will stop receiving.
+struct OutgoingStream {
+    id: uuid::Uuid,
+    receiver: mpsc::UnboundedReceiver<StreamCredit>,
+    book: Arc<Mutex<StreamBook>>,
+}
+
+impl Streams {
+    fn book(&self) -> std::sync::MutexGuard<'_, StreamBook> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Receive credits of a stream sent to destination.
+    fn open(&self, id: uuid::Uuid, destination: Did) -> OutgoingStream {
+        let (sender, receiver) = mpsc::unbounded();
+        self.book().outgoing.insert(id, (destination, sender));
+        OutgoingStream {
+            id,
+            receiver,
+            book: self.inner.clone(),
+        }
+    }
+
+    /// Accept a stream offered by sender, or resume it. Return the credit that should be
+    /// responded, and whether the stream is new. Offers of the same id from other senders,
+    /// or new streams exceeding the limits, are ignored.
+    fn offer(&self, sender: Did, offer: &StreamOffer) -> Option<(StreamCredit, bool)> {
+        let id = offer.meta.id;
+        let mut book = self.book();
+        if let Some(total) = book.finished.get(&id) {
+            return Some((
+                StreamCredit {
+                    id,
+                    received: *total,
+                    limit: *total,
+                    missing: vec![],
+                },
+                false,
+            ));
+        }
+        if let Some(stream) = book.incoming.get_mut(&id) {
+            if stream.sender != sender {
+                return None;
+            }
+            stream.active_at = get_epoch_ms();
+            return Some((stream.credit(id, true), false));
+        }
+        if !book.admit(sender) {
+            tracing::warn!("Refuse stream {} from {}, too many streams", id, sender);
+            return None;
+        }
+        let stream = IncomingStream::new(sender, offer.total);
+        let credit = stream.credit(id, false);
+        book.incoming.insert(id, stream);
+        Some((credit, true))
+    }
+
+    /// Buffer a chunk of stream until it's read. Chunks out of the granted window are dropped.
+    /// Return the credit that should be responded when all chunks are received.
+    fn receive(&self, sender: Did, chunk: Chunk) -> Option<StreamCredit> {
+        let id = chunk.meta.id;
+        let [index, total] = chunk.chunk;
+        let mut book = self.book();
+        let stream = book.incoming.get_mut(&id)?;
+        if stream.sender != sender
+            || stream.total != total
+            || index < stream.read
+            || index >= stream.limit
+        {
+            return None;
+        }
+
+        let completed = stream.received() == total;
+        stream.active_at = get_epoch_ms();
+        stream.buffer.insert(index, chunk.data);
+        if index == stream.read {
+            if let Some(waiter) = stream.waiter.take() {
+                waiter.send(()).ok();
+            }
+        }
+        (!completed && stream.received() == total).then(|| stream.credit(id, false))
+    }
+
+    /// Dispatch a credit to the outgoing stream, it should be sent by the destination.
+    fn credit(&self, origin: Did, credit: StreamCredit) {
+        if let Some((destination, sender)) = self.book().outgoing.get(&credit.id) {
+            if *destination == origin {
+                sender.unbounded_send(credit).ok();
+            }
+        }
+    }
+
+    /// Take the next chunk of an incoming stream, or a receiver notified when it arrives.
+    fn next_chunk(&self, id: uuid::Uuid) -> Result<NextChunk> {
+        let mut book = self.book();
+        if book.finished.contains_key(&id) {
+            return Ok(NextChunk::Finished);
+        }
+        let stream = book
+            .incoming
+            .get_mut(&id)
+            .ok_or(Error::StreamNotFound(id))?;
+
+        let Some(data) = stream.buffer.remove(&stream.read) else {
+            if stream.read >= stream.total {
+                book.finish(id);
+                return Ok(NextChunk::Finished);
+            }
+            let (sender, receiver) = oneshot::channel();
+            stream.waiter = Some(sender);
+            return Ok(NextChunk::Waiting(receiver));
+        };
+        stream.read += 1;
+        stream.active_at = get_epoch_ms();
+        let credit = stream.grant(id).map(|c| (stream.sender, c));
+        if stream.read >= stream.total {
+            book.finish(id);
+        }
+        Ok(NextChunk::Data(data, credit))
+    }
+
+    /// Credit reporting the missing chunks of an incoming stream, with its sender.
+    fn missing(&self, id: uuid::Uuid) -> Option<(Did, StreamCredit)> {
+        let book = self.book();
+        let stream = book.incoming.get(&id)?;
+        Some((stream.sender, stream.credit(id, true)))
+    }
+}
+
+impl OutgoingStream {
+    /// Wait for the next credit until timeout. Return None if timeout.
+    async fn wait(&mut self, timeout: Duration) -> Result<Option<StreamCredit>> {
+        let delay = Delay::new(timeout);
+        pin_mut!(delay);
+
+        match select(self.receiver.next(), delay).await {
+            Either::Left((Some(credit), _)) => Ok(Some(credit)),
+            Either::Left((None, _)) => Err(Error::StreamNotFound(self.id)),
+            Either::Right(_) => Ok(None),
+        }
+    }
+}
+
+impl Drop for OutgoingStream {
+    fn drop(&mut self) {
+        let mut book = self.book.lock().unwrap_or_else(|e| e.into_inner());
+        book.outgoing.remove(&self.id);
+    }
+}
+
+impl Swarm {
+    /// Send a stream of chunks read from source to destination, identified by the id of meta.
+    /// Each chunk should be no larger than [STREAM_CHUNK_SIZE]. Return when all chunks are
+    /// received by destination, or [Error::StreamTimeout] if no more credit is granted after
+    /// [MAX_STREAM_ATTEMPTS] offers. An interrupted stream can be resumed by calling it
+    /// again with the same meta.
+    pub async fn send_stream<S>(
+        &self,
+        destination: Did,
+        meta: ChunkMeta,
+        source: &S,
+        timeout: Duration,
+    ) -> Result<()>
+    where
+        S: ChunkSource + ?Sized,
+    {
+        let total = source.total();
+        let offer = Message::StreamOffer(StreamOffer { meta, total });
+        let mut outgoing = self.streams.open(meta.id, destination);
+        self.send_message(offer.clone(), destination).await?;
+
+        // Index of next chunk that has never been sent.
+        let mut next = 0;
+        let mut timeouts = 0;
+        loop {
+            let Some(credit) = outgoing.wait(timeout).await? else {
+                timeouts += 1;
+                if timeouts >= MAX_STREAM_ATTEMPTS {
+                    return Err(Error::StreamTimeout(meta.id));
+                }
+                tracing::warn!("Offer stream {} to {} again", meta.id, destination);
+                if let Err(e) = self.send_message(offer.clone(), destination).await {
+                    tracing::warn!("Failed on offering stream {}: {:?}", meta.id, e);
+                }
+                continue;
+            };
+            if credit.received >= total {
+                return Ok(());
+            }
+            // A credit responding the offer again grants nothing, if receiver is not reading.
+            if credit.limit > next || !credit.missing.is_empty() {
+                timeouts = 0;
+            }
+
+            // Chunks received by destination are skipped, when the stream is resumed.
+            next = next.max(credit.received);
+            let limit = credit.limit.min(total);
+            let resent = credit.missing.into_iter().filter(|i| *i < next);
+            for index in resent.chain(next..limit) {
+                self.send_stream_chunk(destination, meta, source, [index, total])
+                    .await?;
+            }
+            next = next.max(limit);
+        }
+    }
+
+    async fn send_stream_chunk<S>(
+        &self,
+        destination: Did,
+        meta: ChunkMeta,
+        source: &S,
+        chunk: [usize; 2],
+    ) -> Result<()>
+    where
+        S: ChunkSource + ?Sized,
+    {
+        let data = source.read(chunk[0]).await?;
+        if data.len() > STREAM_CHUNK_SIZE {
+            return Err(Error::MessageTooLarge(data.len()));
+        }
+        let msg = Message::StreamChunk(Chunk { chunk, data, meta });
+        // A lost chunk will be requested again by destination.
+        if let Err(e) = self.send_message(msg, destination).await {
+            tracing::warn!(
+                "Failed on sending chunk {:?} of {}: {:?}",
+                chunk,
+                meta.id,
+                e
+            );
+        }
+        Ok(())
+    }
+
+    /// Read the next chunk of a stream offered to current node, see
+    /// [SwarmEvent::StreamOffered]. Return None if all chunks are read. If the chunk is not
+    /// received before timeout, missing chunks are requested again, at most
+    /// [MAX_STREAM_ATTEMPTS] times before [Error::StreamTimeout].
+    pub async fn read_stream(&self, id: uuid::Uuid, timeout: Duration) -> Result<Option<Bytes>> {
+        let mut timeouts = 0;
+        loop {
+            let receiver = match self.streams.next_chunk(id)? {
+                NextChunk::Data(data, credit) => {
+                    if let Some((sender, credit)) = credit {
+                        self.send_stream_credit(sender, credit).await;
+                    }
+                    return Ok(Some(data));
+                }
+                NextChunk::Finished => return Ok(None),
+                NextChunk::Waiting(receiver) => receiver,
+            };
+
+            let delay = Delay::new(timeout);
+            pin_mut!(delay);
+            if let Either::Right(_) = select(receiver, delay).await {
+                timeouts += 1;
+                if timeouts >= MAX_STREAM_ATTEMPTS {
+                    return Err(Error::StreamTimeout(id));
+                }
+                if let Some((sender, credit)) = self.streams.missing(id) {
+                    tracing::warn!("Request missing chunks of stream {}", id);
+                    self.send_stream_credit(sender, credit).await;
+                }
+            }
+        }
+    }
+
+    async fn send_stream_credit(&self, sender: Did, credit: StreamCredit) {
+        let id = credit.id;
+        if let Err(e) = self
+            .send_message(Message::StreamCredit(credit), sender)
+            .await
+        {
+            tracing::warn!("Failed on granting credit of stream {}: {:?}", id, e);
+        }
+    }
+
+    /// Handle messages of stream sent to current node.
+    pub(crate) async fn handle_stream_payload(&self, payload: &MessagePayload) {
+        let origin = payload.origin_position();
+        let credit = match payload.transaction.data() {
+            Ok(Message::StreamOffer(offer)) => match self.streams.offer(origin, &offer) {
+                Some((credit, true)) => {
+                    self.notify_stream_offered(offer.meta.id, origin, offer.total)
+                        .await;
+                    Some(credit)
+                }
+                Some((credit, false)) => Some(credit),
+                None => None,
+            },
+            Ok(Message::StreamChunk(chunk)) => self.streams.receive(origin, chunk),
+            Ok(Message::StreamCredit(credit)) => {
+                self.streams.credit(origin, credit);
+                None
+            }
+            _ => None,
+        };
+        if let Some(credit) = credit {
+            self.send_stream_credit(origin, credit).await;
+        }
+    }
+
+    async fn notify_stream_offered(&self, id: uuid::Uuid, sender: Did, total: usize) {
+        let event = SwarmEvent::StreamOffered { id, sender, total };
+        let result = match self.callback() {
+            Ok(callback) => callback.on_event(&event).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed on notifying {:?}: {:?}", event, e);
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::chunk::ChunkList;
+    use crate::dht::tests::gen_ordered_dids;
+    use crate::ecc::tests::gen_ordered_keys;
+    use crate::ecc::SecretKey;
+    use crate::message::handlers::connection::tests::test_only_two_nodes_establish_connection;
+    use crate::tests::default::prepare_node;
+
+    /// A source that fails on reading chunks after `until`.
+    struct InterruptedSource {
+        chunks: ChunkList<1024>,
+        until: usize,
+    }
+
+    #[async_trait]
+    impl ChunkSource for InterruptedSource {
+        fn total(&self) -> usize {
+            self.chunks.total()
+        }
+
+        async fn read(&self, index: usize) -> Result<Bytes> {
+            if index >= self.until {
+                return Err(Error::InvalidChunkIndex(index));
+            }
+            self.chunks.read(index).await
+        }
+    }
+
+    async fn read_chunks(
+        node: &Swarm,
+        id: uuid::Uuid,
+        count: usize,
+        timeout: Duration,
+    ) -> Result<Vec<Bytes>> {
+        let mut received = vec![];
+        while received.len() < count {
+            match node.read_stream(id, timeout).await {
+                Ok(Some(data)) => received.push(data),
+                Ok(None) => break,
+                // Wait for the offer.
+                Err(Error::StreamNotFound(_)) => tokio::time::sleep(timeout / 10).await,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(received)
+    }
+
+    #[test]
+    fn test_request_missing_chunks() {
+        let dids = gen_ordered_dids(2);
+        let streams = Streams::default();
+        let data = "hello".repeat(1024).into();
+        let chunks = ChunkList::<32>::from(&data).to_vec();
+        let meta = chunks[0].meta;
+        let total = chunks.len();
+
+        let offer = StreamOffer { meta, total };
+        let (credit, offered) = streams.offer(dids[0], &offer).unwrap();
+        assert!(offered);
+        assert_eq!(credit.limit, DEFAULT_STREAM_WINDOW);
+        assert!(streams.offer(dids[1], &offer).is_none());
+
+        // Chunks from other senders, or out of window, are dropped.
+        assert!(streams.receive(dids[1], chunks[0].clone()).is_none());
+        streams.receive(dids[0], chunks[DEFAULT_STREAM_WINDOW].clone());
+        streams.receive(dids[0], chunks[0].clone());
+        streams.receive(dids[0], chunks[2].clone());
+        let (sender, credit) = streams.missing(meta.id).unwrap();
+        assert_eq!(sender, dids[0]);
+        assert_eq!(credit.received, 1);
+        assert_eq!(credit.missing[..2], [1, 3]);
+        assert_eq!(credit.missing.len(), DEFAULT_STREAM_WINDOW - 2);
+
+        assert!(matches!(
+            streams.next_chunk(meta.id),
+            Ok(NextChunk::Data(d, None)) if d == chunks[0].data
+        ));
+        assert!(matches!(
+            streams.next_chunk(meta.id),
+            Ok(NextChunk::Waiting(_))
+        ));
+
+        // Offer again to resume, the missing chunks are responded.
+        let (credit, offered) = streams.offer(dids[0], &offer).unwrap();
+        assert!(!offered);
+        assert_eq!(credit.received, 1);
+        assert_eq!(credit.missing[0], 1);
+    }
+
+    #[test]
+    fn test_limit_incoming_streams() {
+        let dids = gen_ordered_dids(2);
+        let streams = Streams::default();
+        let offer = || StreamOffer {
+            meta: ChunkMeta::default(),
+            total: 1,
+        };
+
+        // Streams from the same sender are limited.
+        for _ in 0..MAX_INCOMING_STREAMS_PER_PEER {
+            assert!(streams.offer(dids[0], &offer()).is_some());
+        }
+        let refused = offer();
+        assert!(streams.offer(dids[0], &refused).is_none());
+        assert!(streams.next_chunk(refused.meta.id).is_err());
+        assert!(streams.offer(dids[1], &offer()).is_some());
+
+        // Streams from all senders are limited.
+        for i in 0..MAX_INCOMING_STREAMS {
+            let sender = SecretKey::random().address().into();
+            let accepted = streams.offer(sender, &offer()).is_some();
+            assert_eq!(
+                accepted,
+                i < MAX_INCOMING_STREAMS - MAX_INCOMING_STREAMS_PER_PEER - 1
+            );
+        }
+
+        // Idle streams are dropped to make room.
+        for stream in streams.book().incoming.values_mut() {
+            stream.active_at -= INCOMING_STREAM_IDLE_TIMEOUT_MS as u128;
+        }
+        assert!(streams.offer(dids[0], &offer()).is_some());
+        assert_eq!(streams.book().incoming.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_stream() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (node1, _path1) = prepare_node(keys[0]).await;
+        let (node2, _path2) = prepare_node(keys[1]).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+        let n1 = node1.clone();
+        let n2 = node2.clone();
+        tokio::spawn(async move { n1.listen().await });
+        tokio::spawn(async move { n2.listen().await });
+
+        // More chunks than the window, so that credits are granted while reading.
+        let data: Bytes = (0..200 * 1024).map(|i| (i % 251) as u8).collect();
+        let chunks = ChunkList::<1024>::from(&data);
+        let meta = chunks.as_vec()[0].meta;
+        let timeout = Duration::from_secs(3);
+
+        // Sender is interrupted after the receiver read 70 chunks and granted more.
+        let interrupted = InterruptedSource {
+            chunks: chunks.clone(),
+            until: 100,
+        };
+        let send = node1.send_stream(node2.did(), meta, &interrupted, timeout);
+        let (sent, head) = futures::join!(send, read_chunks(&node2, meta.id, 70, timeout));
+        assert!(
+            matches!(sent, Err(Error::InvalidChunkIndex(100))),
+            "{:?}",
+            sent
+        );
+        let head = head?;
+        assert_eq!(head.len(), 70);
+
+        // Resume the stream with the same meta, received chunks are skipped.
+        let send = node1.send_stream(node2.did(), meta, &chunks, timeout);
+        let (sent, tail) = futures::join!(send, read_chunks(&node2, meta.id, usize::MAX, timeout));
+        sent?;
+        let received: Bytes = head.into_iter().chain(tail?).flatten().collect();
+        assert_eq!(received, data);
+
+        // Offering a finished stream is responded as finished.
+        node1
+            .send_stream(node2.did(), meta, &chunks, timeout)
+            .await?;
+        assert!(node2.read_stream(meta.id, timeout).await?.is_none());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
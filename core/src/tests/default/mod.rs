@@ -10,6 +10,10 @@ use crate::swarm::Swarm;
 use crate::swarm::SwarmBuilder;
 
 mod test_message_handler;
+#[cfg(feature = "dummy")]
+mod test_network_size;
+#[cfg(feature = "dummy")]
+mod test_replication;
 mod test_stabilization;
 
 pub async fn prepare_node(key: SecretKey) -> (Arc<Swarm>, String) {
@@ -28,6 +32,35 @@ pub async fn prepare_node(key: SecretKey) -> (Arc<Swarm>, String) {
     (swarm, path)
 }
 
+pub async fn prepare_node_with_positions(key: SecretKey, count: usize) -> (Arc<Swarm>, String) {
+    let stun = "stun://stun.l.google.com:19302";
+    let path = PersistenceStorage::random_path("./tmp");
+    let storage = PersistenceStorage::new_with_path(path.as_str())
+        .await
+        .unwrap();
+    let mut storages = vec![];
+    for _ in 0..count {
+        let path = PersistenceStorage::random_path("./tmp");
+        storages.push(
+            PersistenceStorage::new_with_path(path.as_str())
+                .await
+                .unwrap(),
+        );
+    }
+
+    let session_sk = SessionSk::new_with_seckey(&key).unwrap();
+    let swarm = Arc::new(
+        SwarmBuilder::new(stun, storage, session_sk)
+            .virtual_positions(storages)
+            .build(),
+    );
+
+    println!("key: {:?}", key.to_string());
+    println!("did: {:?}", swarm.did());
+
+    (swarm, path)
+}
+
 pub async fn gen_pure_dht(did: Did) -> Result<PeerRing> {
     let db_path = PersistenceStorage::random_path("./tmp");
     let db = PersistenceStorage::new_with_path(db_path.as_str()).await?;
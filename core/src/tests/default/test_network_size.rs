@@ -0,0 +1,92 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+use crate::dht::Stabilization;
+use crate::dht::SuccessorReader;
+use crate::ecc::tests::gen_ordered_keys;
+use crate::error::Result;
+use crate::session::SessionSk;
+use crate::storage::PersistenceStorage;
+use crate::swarm::Swarm;
+use crate::swarm::SwarmBuilder;
+use crate::tests::manually_establish_connection;
+
+/// Messages are delayed randomly by dummy transport, and handled one by one,
+/// so the ring may take a while to settle down.
+const POLL_TIMES: usize = 60;
+
+async fn prepare_ring(size: usize, succ_max: u8) -> Vec<(Arc<Swarm>, JoinHandle<()>)> {
+    let mut nodes = vec![];
+    for key in gen_ordered_keys(size) {
+        let path = PersistenceStorage::random_path("./tmp");
+        let storage = PersistenceStorage::new_with_path(path.as_str())
+            .await
+            .unwrap();
+        let session_sk = SessionSk::new_with_seckey(&key).unwrap();
+        let swarm = SwarmBuilder::new("stun://stun.l.google.com:19302", storage, session_sk)
+            .dht_succ_max(succ_max)
+            .build();
+        nodes.push(Arc::new(swarm));
+    }
+
+    for (i, node) in nodes.iter().enumerate() {
+        for other in nodes.iter().skip(i + 1) {
+            manually_establish_connection(node, other).await;
+        }
+    }
+
+    nodes
+        .into_iter()
+        .map(|node| {
+            let n = node.clone();
+            (node, tokio::spawn(async move { n.listen().await }))
+        })
+        .collect()
+}
+
+/// Stabilize until every node knows its neighbours and their estimations.
+async fn stabilize_until_gossiped(nodes: &[Arc<Swarm>]) -> Result<bool> {
+    for _ in 0..POLL_TIMES {
+        for node in nodes {
+            Stabilization::new(node.clone(), 3).stabilize().await?;
+        }
+        sleep(Duration::from_secs(1)).await;
+        let mut gossiped = true;
+        for node in nodes {
+            let dht = node.dht();
+            gossiped &= dht.lock_predecessor()?.is_some()
+                && dht.successors().list()?.len() == nodes.len() - 1
+                && dht.neighbour_size_estimates()?.len() == nodes.len() - 1;
+        }
+        if gossiped {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[tokio::test]
+async fn test_estimate_size_of_known_rings() -> Result<()> {
+    for size in [2, 3, 6] {
+        let nodes = prepare_ring(size, size as u8).await;
+        let swarms: Vec<Arc<Swarm>> = nodes.iter().map(|(node, _)| node.clone()).collect();
+        assert!(stabilize_until_gossiped(&swarms).await?);
+
+        for node in swarms.iter() {
+            let estimate = node.estimate_network_size();
+            assert_eq!(estimate.local, size as u64);
+            assert_eq!(estimate.estimate, size as u64);
+            assert!(estimate.neighbours.iter().all(|(_, s)| *s == size as u64));
+        }
+
+        for (_, handle) in nodes {
+            handle.abort();
+        }
+    }
+
+    tokio::fs::remove_dir_all("./tmp").await.ok();
+    Ok(())
+}
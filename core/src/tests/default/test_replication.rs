@@ -0,0 +1,170 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+use crate::dht::vnode::VirtualNode;
+use crate::dht::ChordStorageReplica;
+use crate::dht::Did;
+use crate::dht::Stabilization;
+use crate::dht::SuccessorReader;
+use crate::ecc::tests::gen_ordered_keys;
+use crate::error::Result;
+use crate::message::ChordStorageInterface;
+use crate::session::SessionSk;
+use crate::storage::PersistenceStorage;
+use crate::storage::PersistenceStorageReadAndWrite;
+use crate::swarm::Swarm;
+use crate::swarm::SwarmBuilder;
+use crate::tests::manually_establish_connection;
+
+const NODES: usize = 6;
+const REPLICATION_FACTOR: u8 = 3;
+/// Messages are delayed randomly by dummy transport, and handled one by one,
+/// so the ring may take a while to settle down.
+const POLL_TIMES: usize = 60;
+
+async fn prepare_ring() -> Vec<(Arc<Swarm>, JoinHandle<()>)> {
+    let mut nodes = vec![];
+    for key in gen_ordered_keys(NODES) {
+        let path = PersistenceStorage::random_path("./tmp");
+        let storage = PersistenceStorage::new_with_path(path.as_str())
+            .await
+            .unwrap();
+        let session_sk = SessionSk::new_with_seckey(&key).unwrap();
+        let swarm = SwarmBuilder::new("stun://stun.l.google.com:19302", storage, session_sk)
+            .dht_replication_factor(REPLICATION_FACTOR)
+            .build();
+        nodes.push(Arc::new(swarm));
+    }
+
+    for (i, node) in nodes.iter().enumerate() {
+        for other in nodes.iter().skip(i + 1) {
+            manually_establish_connection(node, other).await;
+        }
+    }
+
+    let nodes: Vec<(Arc<Swarm>, JoinHandle<()>)> = nodes
+        .into_iter()
+        .map(|node| {
+            let n = node.clone();
+            (node, tokio::spawn(async move { n.listen().await }))
+        })
+        .collect();
+
+    // Wait until successor lists of all nodes are settled.
+    let dids: Vec<Did> = nodes.iter().map(|(node, _)| node.did()).collect();
+    for _ in 0..POLL_TIMES {
+        sleep(Duration::from_secs(1)).await;
+        let settled = nodes.iter().enumerate().all(|(i, (node, _))| {
+            let expected: Vec<Did> = (1..=REPLICATION_FACTOR as usize)
+                .map(|j| dids[(i + j) % NODES])
+                .collect();
+            node.dht().successors().list().unwrap() == expected
+        });
+        if settled {
+            break;
+        }
+    }
+    nodes
+}
+
+/// Index of the node responsible for vid, which is the closest predecessor of vid.
+fn responsible(dids: &[Did], vid: Did) -> usize {
+    (0..dids.len()).min_by_key(|i| vid - dids[*i]).unwrap()
+}
+
+async fn stored(node: &Swarm, vid: Did) -> Result<Option<VirtualNode>> {
+    node.dht().storage.get(&vid).await
+}
+
+async fn stabilize(nodes: &[Arc<Swarm>]) -> Result<()> {
+    for node in nodes {
+        Stabilization::new(node.clone(), 3).stabilize().await?;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_data_survives_losing_consecutive_nodes() -> Result<()> {
+    for offset in 0..NODES {
+        let nodes = prepare_ring().await;
+        let dids: Vec<Did> = nodes.iter().map(|(node, _)| node.did()).collect();
+
+        let vnode: VirtualNode = format!("replication test {}", offset).try_into()?;
+        let vid = vnode.did;
+        let owner = responsible(&dids, vid);
+        let replica_holders: Vec<usize> = (1..=REPLICATION_FACTOR as usize)
+            .map(|i| (owner + i) % NODES)
+            .collect();
+
+        // Stored on responsible node, and replicated to its successors.
+        let data = vnode.data.clone();
+        let vnode = vnode.owned_by(nodes[0].0.session_sk(), vec![])?;
+        <Swarm as ChordStorageInterface<1>>::storage_store(&nodes[0].0, vnode).await?;
+        let mut replicated = None;
+        for _ in 0..POLL_TIMES {
+            sleep(Duration::from_secs(1)).await;
+            replicated = stored(&nodes[owner].0, vid).await?.filter(|v| {
+                replica_holders
+                    .iter()
+                    .all(|i| nodes[*i].0.dht().replicas.get(&vid).as_ref() == Some(v))
+            });
+            if replicated.is_some() {
+                break;
+            }
+        }
+        let vnode = replicated.expect("vnode should be replicated");
+        assert_eq!(vnode.data, data);
+        assert_eq!(vnode.owner, Some(dids[0]));
+        assert_eq!(vnode.version.writer, Some(dids[0]));
+
+        // Lose r - 1 consecutive nodes, starting from offset to the responsible node.
+        let lost: Vec<usize> = (0..REPLICATION_FACTOR as usize - 1)
+            .map(|i| (owner + offset + i) % NODES)
+            .collect();
+        for i in lost.iter() {
+            nodes[*i].1.abort();
+        }
+        let survivors: Vec<Arc<Swarm>> = nodes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !lost.contains(i))
+            .map(|(_, (node, _))| node.clone())
+            .collect();
+        for node in survivors.iter() {
+            for i in lost.iter() {
+                node.disconnect(dids[*i]).await?;
+            }
+        }
+
+        // Stabilize, which runs anti-entropy, until the new responsible node holds the data
+        // and its replica targets hold replicas again.
+        let survivor_dids: Vec<Did> = survivors.iter().map(|node| node.did()).collect();
+        let new_owner = &survivors[responsible(&survivor_dids, vid)];
+        let mut repaired = false;
+        for _ in 0..POLL_TIMES {
+            stabilize(&survivors).await?;
+            sleep(Duration::from_secs(1)).await;
+            let targets = new_owner.dht().replica_targets()?;
+            repaired = stored(new_owner, vid).await? == Some(vnode.clone())
+                && !targets.is_empty()
+                && survivors
+                    .iter()
+                    .filter(|node| targets.contains(&node.did()))
+                    .all(|node| node.dht().replicas.get(&vid) == Some(vnode.clone()));
+            if repaired {
+                break;
+            }
+        }
+        assert!(repaired);
+
+        for (_, handle) in nodes {
+            handle.abort();
+        }
+    }
+
+    tokio::fs::remove_dir_all("./tmp").await.ok();
+    Ok(())
+}
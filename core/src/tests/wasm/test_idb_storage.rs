@@ -7,6 +7,12 @@ use serde_json::Value as JsonValue;
 use wasm_bindgen::JsValue;
 use wasm_bindgen_test::wasm_bindgen_test;
 
+use crate::dht::vnode::VNodeType;
+use crate::dht::vnode::VirtualNode;
+use crate::dht::Did;
+use crate::dht::PeerRing;
+use crate::ecc::SecretKey;
+use crate::message::Encoded;
 use crate::storage::persistence::idb::IDBStorageBasic;
 use crate::storage::persistence::IDBStorage;
 use crate::storage::persistence::PersistenceStorageOperation;
@@ -256,3 +262,35 @@ async fn test_idb_total_size() {
         "indexedDB is not empty"
     );
 }
+
+/// A [VirtualNode] of format version 0, see [crate::dht::migration].
+#[derive(Serialize, Deserialize, Debug)]
+struct TestVirtualNodeV0 {
+    did: Did,
+    data: Vec<Encoded>,
+    kind: VNodeType,
+}
+
+#[wasm_bindgen_test]
+async fn test_migrate_v0_vnode() {
+    let instance = create_db_instance(16).await;
+    let legacy: VirtualNode = "test_migrate_idb".to_string().try_into().unwrap();
+    let v0 = TestVirtualNodeV0 {
+        did: legacy.did,
+        data: legacy.data.clone(),
+        kind: legacy.kind,
+    };
+    instance.put(&legacy.did, &v0).await.unwrap();
+    // Records of other types are skipped instead of panicking.
+    instance
+        .put(&"test_other".to_string(), &serde_json::json!("test"))
+        .await
+        .unwrap();
+
+    let did = SecretKey::random().address().into();
+    let dht = PeerRing::new_with_storage(did, 3, instance);
+    dht.migrate_vnodes().await.unwrap();
+    assert_eq!(dht.storage_get(legacy.did).await.unwrap(), Some(legacy));
+
+    dht.storage.clear().await.unwrap();
+}
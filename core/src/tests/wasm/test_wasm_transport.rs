@@ -1,4 +1,3 @@
-use std::str::FromStr;
 use std::sync::Arc;
 
 use rings_transport::core::transport::ConnectionInterface;
@@ -10,11 +9,15 @@ use wasm_bindgen_test::*;
 
 use super::prepare_node;
 use crate::channels::Channel as CbChannel;
-use crate::dht::Did;
+use crate::consts::DEFAULT_NETWORK_ID;
+use crate::consts::DEFAULT_REPLAY_WINDOW_SIZE;
 use crate::ecc::SecretKey;
 use crate::error::Result;
+use crate::session::SessionSk;
 use crate::swarm::callback::InnerSwarmCallback;
 use crate::swarm::callback::SwarmCallback;
+use crate::swarm::PeerProtocols;
+use crate::swarm::ReplayWindow;
 use crate::tests::manually_establish_connection;
 use crate::types::channel::Channel;
 use crate::types::channel::TransportEvent;
@@ -43,9 +46,13 @@ async fn prepare_transport(channel: Option<Arc<CbChannel<TransportEvent>>>) -> T
     };
     let trans = Transport::new("stun://stun.l.google.com:19302", None);
     let callback = InnerSwarmCallback::new(
-        Did::from_str("0x11E807fcc88dD319270493fB2e822e388Fe36ab0").unwrap(),
+        SessionSk::new_with_seckey(&SecretKey::random()).unwrap(),
         ch.sender(),
         Arc::new(DefaultCallback {}),
+        Arc::new(ReplayWindow::new(DEFAULT_REPLAY_WINDOW_SIZE)),
+        Arc::new(PeerProtocols::new(DEFAULT_NETWORK_ID)),
+        Default::default(),
+        None,
     );
     trans
         .new_connection("test", Box::new(callback))
@@ -10,6 +10,8 @@ pub enum TransportEvent {
     Connected(Did),
     DataChannelMessage(Vec<u8>),
     Closed(Did),
+    /// The peer is incompatible and should be disconnected, see [crate::swarm::protocol].
+    Incompatible(Did),
 }
 
 /// Channel trant implement methods.
@@ -11,6 +11,9 @@ pub fn impl_measure_behaviour_traits(ast: &syn::DeriveInput) -> proc_macro2::Tok
     #[cfg_attr(feature = "node", async_trait)]
     #[cfg_attr(feature = "browser", async_trait(?Send))]
     impl<const T: i16> ConnectBehaviour<T> for #name {}
+    #[cfg_attr(feature = "node", async_trait)]
+    #[cfg_attr(feature = "browser", async_trait(?Send))]
+    impl<const T: i16> RoutingBehaviour<T> for #name {}
     };
 
     #[cfg(not(feature = "core_crate"))]
@@ -18,6 +21,7 @@ pub fn impl_measure_behaviour_traits(ast: &syn::DeriveInput) -> proc_macro2::Tok
     use rings_core::measure::measure::MessageRecvBehaviour;
     use rings_core::measure::measure::MessageSendBehaviour;
     use rings_core::measure::measure::ConnectBehaviour;
+    use rings_core::measure::measure::RoutingBehaviour;
 
     #impl_token
     }
@@ -27,6 +31,7 @@ pub fn impl_measure_behaviour_traits(ast: &syn::DeriveInput) -> proc_macro2::Tok
     use crate::measure::measure::MessageRecvBehaviour;
     use crate::measure::measure::MessageSendBehaviour;
     use crate::measure::measure::ConnectBehaviour;
+    use crate::measure::measure::RoutingBehaviour;
 
     #impl_token
     }
@@ -15,6 +15,7 @@ use futures_timer::Delay;
 use rings_node::backend::native::BackendConfig;
 use rings_node::backend::native::BackendContext;
 use rings_node::backend::Backend;
+use rings_node::consts::SERVICE_REGISTER_INTERVAL_MS;
 use rings_node::logging::init_logging;
 use rings_node::logging::LogLevel;
 use rings_node::measure::PeriodicMeasure;
@@ -63,6 +64,8 @@ enum Command {
     Send(SendCommand),
     #[command(about = "Registers or looks up a service on the network.", subcommand)]
     Service(ServiceCommand),
+    #[command(about = "Joins, leaves or broadcasts to a subring.", subcommand)]
+    Subring(SubringCommand),
     #[command(
         about = "Show information of swarm. Include transport table, successors, predecessor, and finger table."
     )]
@@ -135,6 +138,13 @@ struct RunCommand {
     #[arg(long, help = "external ip address", env)]
     pub external_ip: Option<String>,
 
+    #[arg(
+        long,
+        help = "Id of the network to join, nodes of other networks are refused. If not provided, use network_id in config file or rings",
+        env
+    )]
+    pub network_id: Option<String>,
+
     #[arg(
         long,
         help = "Storage files location. If not provided, use storage.path in config file or ~/.local/share/rings",
@@ -308,6 +318,8 @@ enum SendCommand {
     PlainText(SendPlainTextCommand),
     #[command(about = "Sends a custom message.")]
     Custom(SendCustomMessageCommand),
+    #[command(about = "Broadcasts a simple text message to all nodes on the ring.")]
+    Broadcast(SendBroadcastCommand),
 }
 
 #[derive(Args, Debug)]
@@ -372,6 +384,13 @@ struct SendCustomMessageCommand {
     data: String,
 }
 
+#[derive(Args, Debug)]
+struct SendBroadcastCommand {
+    #[command(flatten)]
+    client_args: ClientArgs,
+    text: String,
+}
+
 #[derive(Subcommand, Debug)]
 #[command(rename_all = "kebab-case")]
 enum ServiceCommand {
@@ -395,10 +414,56 @@ struct ServiceLookupCommand {
     name: String,
 }
 
+#[derive(Subcommand, Debug)]
+#[command(rename_all = "kebab-case")]
+enum SubringCommand {
+    #[command(about = "Joins a subring.")]
+    Join(SubringNameCommand),
+    #[command(about = "Leaves a subring.")]
+    Leave(SubringNameCommand),
+    #[command(about = "Lists members of a subring.")]
+    Members(SubringNameCommand),
+    #[command(about = "Sends a simple text message to all members of a subring.")]
+    Send(SubringSendCommand),
+}
+
+#[derive(Args, Debug)]
+struct SubringNameCommand {
+    #[command(flatten)]
+    client_args: ClientArgs,
+
+    name: String,
+}
+
+#[derive(Args, Debug)]
+struct SubringSendCommand {
+    #[command(flatten)]
+    client_args: ClientArgs,
+
+    name: String,
+
+    text: String,
+}
+
 #[derive(Args, Debug)]
 struct InspectCommand {
     #[command(flatten)]
     client_args: ClientArgs,
+
+    #[command(subcommand)]
+    target: Option<InspectTarget>,
+}
+
+#[derive(Subcommand, Debug)]
+enum InspectTarget {
+    #[command(
+        about = "Walks the ring along successors and checks its invariants, reports anomalies and estimated ring size."
+    )]
+    Ring,
+    #[command(
+        about = "Estimates the number of nodes on the ring by the density of neighbours, refined by their estimations."
+    )]
+    NetworkSize,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -414,6 +479,9 @@ async fn daemon_run(args: RunCommand) -> anyhow::Result<()> {
     if let Some(stabilize_timeout) = args.stabilize_timeout {
         c.stabilize_timeout = stabilize_timeout;
     }
+    if let Some(network_id) = args.network_id {
+        c.network_id = network_id;
+    }
     if let Some(http_addr) = args.http_addr {
         c.http_addr = http_addr;
     }
@@ -458,15 +526,46 @@ async fn daemon_run(args: RunCommand) -> anyhow::Result<()> {
     processor.swarm.set_callback(backend).unwrap();
 
     let processor_clone = processor.clone();
-    let _ = futures::join!(
-        processor.listen(),
-        service_loop_register(&processor, backend_service_names),
-        run_http_api(c.http_addr, processor_clone),
-    );
+    // Keep listening while leaving, so that the handoff of vnodes can be acknowledged.
+    let shutdown = async {
+        if let Err(e) = shutdown_signal().await {
+            tracing::error!("Failed on waiting for shutdown signal: {:?}", e);
+        }
+        println!("Leaving...");
+    };
+    tokio::select! {
+        _ = async {
+            futures::join!(
+                service_loop_register(&processor, backend_service_names),
+                reconnect(&processor, c.seeds.clone()),
+                run_http_api(c.http_addr, processor_clone),
+            )
+        } => {}
+        r = processor.listen_until_leave(shutdown) => r?,
+    }
 
     Ok(())
 }
 
+#[cfg(unix)]
+async fn shutdown_signal() -> anyhow::Result<()> {
+    use tokio::signal::unix::signal;
+    use tokio::signal::unix::SignalKind;
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        r = tokio::signal::ctrl_c() => r?,
+        _ = sigterm.recv() => {}
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> anyhow::Result<()> {
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
 async fn pubsub_run(client_args: ClientArgs, topic: String) -> anyhow::Result<()> {
     let mut stdin = io::BufReader::new(io::stdin()).lines();
 
@@ -600,6 +699,15 @@ async fn main() -> anyhow::Result<()> {
                 .display();
             Ok(())
         }
+        Command::Send(SendCommand::Broadcast(args)) => {
+            args.client_args
+                .new_client()
+                .await?
+                .broadcast(args.text.as_str())
+                .await?
+                .display();
+            Ok(())
+        }
         Command::Service(ServiceCommand::Register(args)) => {
             args.client_args
                 .new_client()
@@ -618,6 +726,42 @@ async fn main() -> anyhow::Result<()> {
                 .display();
             Ok(())
         }
+        Command::Subring(SubringCommand::Join(args)) => {
+            args.client_args
+                .new_client()
+                .await?
+                .subring_join(args.name.as_str())
+                .await?
+                .display();
+            Ok(())
+        }
+        Command::Subring(SubringCommand::Leave(args)) => {
+            args.client_args
+                .new_client()
+                .await?
+                .subring_leave(args.name.as_str())
+                .await?
+                .display();
+            Ok(())
+        }
+        Command::Subring(SubringCommand::Members(args)) => {
+            args.client_args
+                .new_client()
+                .await?
+                .subring_members(args.name.as_str())
+                .await?
+                .display();
+            Ok(())
+        }
+        Command::Subring(SubringCommand::Send(args)) => {
+            args.client_args
+                .new_client()
+                .await?
+                .send_to_subring(args.name.as_str(), args.text.as_str())
+                .await?
+                .display();
+            Ok(())
+        }
         Command::Init(args) => {
             let session_sk_path = args.session_args.new_session_then_write_to_fs()?;
             let config = config::Config::new(session_sk_path);
@@ -630,12 +774,12 @@ async fn main() -> anyhow::Result<()> {
             Ok(())
         }
         Command::Inspect(args) => {
-            args.client_args
-                .new_client()
-                .await?
-                .inspect()
-                .await?
-                .display();
+            let client = args.client_args.new_client().await?;
+            match args.target {
+                Some(InspectTarget::Ring) => client.inspect_ring().await?.display(),
+                Some(InspectTarget::NetworkSize) => client.estimate_network_size().await?.display(),
+                None => client.inspect().await?.display(),
+            }
             Ok(())
         }
     }
@@ -654,9 +798,16 @@ async fn register_services(processor: &Processor, names: Vec<String>) -> anyhow:
     Ok(())
 }
 
+async fn reconnect(processor: &Processor, seeds: Vec<String>) {
+    match processor.reconnect(&seeds).await {
+        Ok(dids) => println!("Reconnected {} nodes", dids.len()),
+        Err(e) => eprintln!("Error: {}", e),
+    }
+}
+
 async fn service_loop_register(processor: &Processor, names: Vec<String>) {
     loop {
-        let timeout = Delay::new(Duration::from_secs(30)).fuse();
+        let timeout = Delay::new(Duration::from_millis(SERVICE_REGISTER_INTERVAL_MS)).fuse();
         pin_mut!(timeout);
         select! {
             _ = timeout => register_services(processor, names.clone()).await.unwrap_or_else(|e| eprintln!("Error: {}", e)),
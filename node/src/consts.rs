@@ -3,11 +3,27 @@ use crate::prelude::rings_core::consts::*;
 pub const BACKEND_MTU: usize = TRANSPORT_MAX_SIZE - TRANSPORT_MTU;
 /// Redundant setting of vnode data storage
 pub const DATA_REDUNDANT: u16 = 6;
+/// Number of successors keeping replicas of vnode data
+pub const DATA_REPLICATION_FACTOR: u8 = 2;
 /// Connect Behaviour
 pub const CONNECT_FAILED_LIMIT: i16 = 3;
 /// Message Send Behaviour
 pub const MSG_SEND_FAILED_LIMIT: i16 = 10;
 /// Message Received Behaviour
 pub const MSG_RECV_FAILED_LIMIT: i16 = 10;
+/// Routing Behaviour
+pub const INVALID_REPORT_LIMIT: i16 = 3;
 /// Timeout for proxied TCP connections
 pub const TCP_SERVER_TIMEOUT: u64 = 30;
+/// Default timeout in milliseconds for waiting the response of a peer request
+pub const DEFAULT_REQUEST_PEER_TIMEOUT_MS: u64 = 30000;
+/// Default timeout in milliseconds for waiting vnodes of a DHT fetch
+pub const DEFAULT_FETCH_VNODE_TIMEOUT_MS: u64 = 5000;
+/// Time in milliseconds to wait for messages of leaving to be sent before closing connections
+pub const LEAVE_GRACE_PERIOD_MS: u64 = 1000;
+/// Timeout in milliseconds for long polling messages of a subscribed topic
+pub const TOPIC_POLL_TIMEOUT_MS: u64 = 10000;
+/// Interval in milliseconds of refreshing registered services on DHT
+pub const SERVICE_REGISTER_INTERVAL_MS: u64 = 30000;
+/// Time in milliseconds a service registration lives without being refreshed
+pub const SERVICE_REGISTRATION_TTL_MS: u64 = 3 * SERVICE_REGISTER_INTERVAL_MS;
@@ -63,10 +63,18 @@ pub enum Error {
     ConnectError(rings_core::error::Error) = 600,
     #[error("Send message error: {0}")]
     SendMessage(rings_core::error::Error) = 601,
+    #[error("Request peer error: {0}")]
+    RequestPeer(rings_core::error::Error) = 602,
     #[error("vnode action error: {0}")]
     VNodeError(rings_core::error::Error) = 603,
     #[error("service register action error: {0}")]
     ServiceRegisterError(rings_core::error::Error) = 604,
+    #[error("subring action error: {0}")]
+    SubringError(rings_core::error::Error) = 605,
+    #[error("pubsub action error: {0}")]
+    PubsubError(rings_core::error::Error) = 606,
+    #[error("Delivery of message not found: {0}")]
+    DeliveryNotFound(rings_core::prelude::uuid::Uuid) = 607,
     #[error("JsError: {0}")]
     JsError(String) = 700,
     #[error("Invalid message")]
@@ -143,6 +143,10 @@ pub fn methods() -> Vec<(Method, MethodFnBox)> {
         (Method::Disconnect, pin!(server::close_connection)),
         (Method::SendTo, pin!(server::send_raw_message)),
         (Method::SendCustomMessage, pin!(server::send_custom_message)),
+        (Method::GetMessageStatus, pin!(server::get_message_status)),
+        (Method::RequestPeer, pin!(server::request_peer)),
+        (Method::RespondPeer, pin!(server::respond_peer)),
+        (Method::Broadcast, pin!(server::broadcast)),
         (
             Method::SendBackendMessage,
             pin!(server::send_backend_message),
@@ -155,9 +159,23 @@ pub fn methods() -> Vec<(Method, MethodFnBox)> {
             Method::FetchMessagesOfTopic,
             pin!(server::fetch_messages_of_topic),
         ),
+        (Method::SubscribeTopic, pin!(server::subscribe_topic)),
+        (Method::UnsubscribeTopic, pin!(server::unsubscribe_topic)),
+        (Method::PollTopicMessages, pin!(server::poll_topic_messages)),
+        (Method::FetchVNode, pin!(server::fetch_vnode)),
         (Method::RegisterService, pin!(server::register_service)),
+        (Method::UnregisterService, pin!(server::unregister_service)),
         (Method::LookupService, pin!(server::lookup_service)),
+        (Method::SubringJoin, pin!(server::subring_join)),
+        (Method::SubringLeave, pin!(server::subring_leave)),
+        (Method::SubringMembers, pin!(server::subring_members)),
+        (Method::SendToSubring, pin!(server::send_to_subring)),
         (Method::NodeInfo, pin!(server::node_info)),
+        (Method::InspectRing, pin!(server::inspect_ring)),
+        (
+            Method::EstimateNetworkSize,
+            pin!(server::estimate_network_size),
+        ),
         (Method::NodeDid, pin!(server::node_did)),
     ]
 }
@@ -4,6 +4,7 @@
 use std::collections::HashSet;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures::future::join_all;
 use rings_core::swarm::impls::ConnectionHandshake;
@@ -11,6 +12,8 @@ use rings_transport::core::transport::ConnectionInterface;
 use serde_json::Value;
 
 use crate::backend::types::BackendMessage;
+use crate::consts::DEFAULT_FETCH_VNODE_TIMEOUT_MS;
+use crate::consts::DEFAULT_REQUEST_PEER_TIMEOUT_MS;
 use crate::error::Error as ServerError;
 use crate::prelude::jsonrpc_core::Error;
 use crate::prelude::jsonrpc_core::ErrorCode;
@@ -21,7 +24,9 @@ use crate::prelude::rings_core::message::Decoder;
 use crate::prelude::rings_core::message::Encoded;
 use crate::prelude::rings_core::message::Encoder;
 use crate::prelude::rings_core::message::MessagePayload;
+use crate::prelude::rings_core::prelude::uuid;
 use crate::prelude::rings_core::prelude::vnode::VirtualNode;
+use crate::prelude::rings_core::utils::get_epoch_ms;
 use crate::prelude::rings_rpc;
 use crate::prelude::rings_rpc::response::Peer;
 use crate::processor::Processor;
@@ -113,6 +118,19 @@ pub(crate) async fn node_info(_: Params, meta: RpcMeta) -> Result<Value> {
     serde_json::to_value(node_info).map_err(|_| Error::new(ErrorCode::ParseError))
 }
 
+/// Walk the ring along successors and check its invariants.
+pub(crate) async fn inspect_ring(_: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let report = meta.processor.inspect_ring().await;
+    serde_json::to_value(report).map_err(|_| Error::new(ErrorCode::ParseError))
+}
+
+/// Estimate the number of nodes on the ring.
+pub(crate) async fn estimate_network_size(_: Params, meta: RpcMeta) -> Result<Value> {
+    let estimate = meta.processor.estimate_network_size();
+    serde_json::to_value(estimate).map_err(|_| Error::new(ErrorCode::ParseError))
+}
+
 pub(crate) async fn node_did(_: Params, meta: RpcMeta) -> Result<Value> {
     let did = meta.processor.did();
     serde_json::to_value(did).map_err(|_| Error::new(ErrorCode::ParseError))
@@ -307,6 +325,7 @@ pub(crate) async fn send_raw_message(params: Params, meta: RpcMeta) -> Result<Va
 /// * Params
 ///   - destination:  destination did
 ///   - data: base64 of [u8]
+///   - ack: optional, whether the destination should acknowledge the delivery, default false
 pub(crate) async fn send_custom_message(params: Params, meta: RpcMeta) -> Result<Value> {
     meta.require_authed()?;
     let params: Vec<serde_json::Value> = params.parse()?;
@@ -322,8 +341,68 @@ pub(crate) async fn send_custom_message(params: Params, meta: RpcMeta) -> Result
         .as_str()
         .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
 
+    let ack = match params.get(2) {
+        Some(v) => v
+            .as_bool()
+            .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?,
+        None => false,
+    };
+
+    let data = base64::decode(data).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+    let tx_id = if ack {
+        meta.processor
+            .send_message_with_ack(destination, &data)
+            .await?
+    } else {
+        meta.processor.send_message(destination, &data).await?
+    };
+
+    Ok(
+        serde_json::to_value(rings_rpc::response::SendMessageResponse::from(
+            tx_id.to_string(),
+        ))
+        .unwrap(),
+    )
+}
+
+/// get delivery status of a custom message sent with ack
+/// * Params
+///   - tx_id: tx_id returned by sendCustomMessage
+pub(crate) async fn get_message_status(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let tx_id = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let tx_id = uuid::Uuid::from_str(tx_id).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+
+    let status = meta.processor.message_status(tx_id)?;
+
+    Ok(
+        serde_json::to_value(rings_rpc::response::MessageStatusResponse {
+            tx_id: tx_id.to_string(),
+            status,
+        })
+        .unwrap(),
+    )
+}
+
+/// broadcast custom message to all nodes on the ring
+/// * Params
+///   - data: base64 of [u8]
+pub(crate) async fn broadcast(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let data = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+
     let data = base64::decode(data).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
-    let tx_id = meta.processor.send_message(destination, &data).await?;
+    let tx_id = meta.processor.broadcast(&data).await?;
 
     Ok(
         serde_json::to_value(rings_rpc::response::SendMessageResponse::from(
@@ -333,6 +412,80 @@ pub(crate) async fn send_custom_message(params: Params, meta: RpcMeta) -> Result
     )
 }
 
+/// send custom message to specifice destination as a request and wait for its response
+/// * Params
+///   - destination:  destination did
+///   - data: base64 of [u8]
+///   - timeout_ms: optional timeout of waiting response in milliseconds
+pub(crate) async fn request_peer(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let destination = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+
+    let data = params
+        .get(1)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+
+    let timeout_ms = match params.get(2) {
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?,
+        None => DEFAULT_REQUEST_PEER_TIMEOUT_MS,
+    };
+
+    let data = base64::decode(data).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+    let (tx_id, resp) = meta
+        .processor
+        .request_peer(destination, &data, Duration::from_millis(timeout_ms))
+        .await?;
+
+    Ok(
+        serde_json::to_value(rings_rpc::response::RequestPeerResponse {
+            tx_id: tx_id.to_string(),
+            data: base64::encode(resp),
+        })
+        .unwrap(),
+    )
+}
+
+/// respond a request sent by peer, see [request_peer]
+/// * Params
+///   - destination:  did of the requester
+///   - tx_id: tx_id of the request
+///   - data: base64 of [u8]
+pub(crate) async fn respond_peer(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let destination = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let tx_id = params
+        .get(1)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let tx_id = uuid::Uuid::from_str(tx_id).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+    let data = params
+        .get(2)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+
+    let data = base64::decode(data).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+    meta.processor
+        .respond_peer(destination, tx_id, &data)
+        .await?;
+    Ok(serde_json::json!({}))
+}
+
 /// send custom message to specifice destination
 /// * Params
 ///   - destination:  destination did
@@ -370,7 +523,7 @@ pub(crate) async fn publish_message_to_topic(params: Params, meta: RpcMeta) -> R
         .encode()
         .map_err(|_| Error::new(ErrorCode::InvalidParams))?;
 
-    meta.processor.storage_append_data(topic, data).await?;
+    meta.processor.topic_publish(topic, data).await?;
 
     Ok(serde_json::json!({}))
 }
@@ -391,8 +544,10 @@ pub(crate) async fn fetch_messages_of_topic(params: Params, meta: RpcMeta) -> Re
 
     let vid = VirtualNode::gen_did(topic).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
 
-    meta.processor.storage_fetch(vid).await?;
-    let result = meta.processor.storage_check_cache(vid).await;
+    let result = meta
+        .processor
+        .storage_fetch_vnode(vid, Duration::from_millis(DEFAULT_FETCH_VNODE_TIMEOUT_MS))
+        .await?;
 
     if let Some(vnode) = result {
         let messages = vnode
@@ -408,6 +563,97 @@ pub(crate) async fn fetch_messages_of_topic(params: Params, meta: RpcMeta) -> Re
     }
 }
 
+/// subscribe a topic, published messages will be pushed to current node
+/// * Params
+///   - topic: name of topic
+pub(crate) async fn subscribe_topic(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let topic = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    meta.processor.topic_subscribe(topic).await?;
+    Ok(serde_json::json!({}))
+}
+
+/// unsubscribe a topic
+/// * Params
+///   - topic: name of topic
+pub(crate) async fn unsubscribe_topic(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let topic = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    meta.processor.topic_unsubscribe(topic).await?;
+    Ok(serde_json::json!({}))
+}
+
+/// take messages pushed to a subscribed topic, wait for the next one if there is none
+/// * Params
+///   - topic: name of topic
+///   - timeout_ms: optional timeout of waiting messages in milliseconds
+pub(crate) async fn poll_topic_messages(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let topic = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+
+    let timeout_ms = match params.get(1) {
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?,
+        None => DEFAULT_FETCH_VNODE_TIMEOUT_MS,
+    };
+
+    let messages = meta
+        .processor
+        .topic_poll(topic, Duration::from_millis(timeout_ms))
+        .await?
+        .iter()
+        .map(|msg| msg.data.decode())
+        .filter_map(|v| v.ok())
+        .collect::<Vec<String>>();
+    Ok(serde_json::json!(messages))
+}
+
+/// fetch virtual node from DHT and wait for it
+/// * Params
+///   - did: did of virtual node
+///   - timeout_ms: optional timeout of waiting virtual node in milliseconds
+pub(crate) async fn fetch_vnode(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let did = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let did = Did::from_str(did).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+
+    let timeout_ms = match params.get(1) {
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?,
+        None => DEFAULT_FETCH_VNODE_TIMEOUT_MS,
+    };
+
+    let result = meta
+        .processor
+        .storage_fetch_vnode(did, Duration::from_millis(timeout_ms))
+        .await?;
+    serde_json::to_value(result)
+        .map_err(ServerError::SerdeJsonError)
+        .map_err(Error::from)
+}
+
 pub(crate) async fn register_service(params: Params, meta: RpcMeta) -> Result<Value> {
     meta.require_authed()?;
     let params: Vec<serde_json::Value> = params.parse()?;
@@ -416,7 +662,32 @@ pub(crate) async fn register_service(params: Params, meta: RpcMeta) -> Result<Va
         .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
         .as_str()
         .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
-    meta.processor.register_service(name).await?;
+    match params.get(1) {
+        Some(ttl_ms) => {
+            let ttl_ms = ttl_ms
+                .as_u64()
+                .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+            meta.processor
+                .register_service_with_ttl(name, Duration::from_millis(ttl_ms))
+                .await?
+        }
+        None => meta.processor.register_service(name).await?,
+    }
+    Ok(serde_json::json!({}))
+}
+
+/// unregister a service registered by current node
+/// * Params
+///   - name: name of service
+pub(crate) async fn unregister_service(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let name = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    meta.processor.unregister_service(name).await?;
     Ok(serde_json::json!({}))
 }
 
@@ -431,12 +702,14 @@ pub(crate) async fn lookup_service(params: Params, meta: RpcMeta) -> Result<Valu
 
     let rid = VirtualNode::gen_did(name).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
 
-    meta.processor.storage_fetch(rid).await?;
-    let result = meta.processor.storage_check_cache(rid).await;
+    let result = meta
+        .processor
+        .storage_fetch_vnode(rid, Duration::from_millis(DEFAULT_FETCH_VNODE_TIMEOUT_MS))
+        .await?;
 
     if let Some(vnode) = result {
         let dids = vnode
-            .data
+            .live_data(get_epoch_ms() as u64)
             .iter()
             .map(|v| v.decode())
             .filter_map(|v| v.ok())
@@ -447,6 +720,102 @@ pub(crate) async fn lookup_service(params: Params, meta: RpcMeta) -> Result<Valu
     }
 }
 
+/// join a subring
+/// * Params
+///   - name: name of subring
+pub(crate) async fn subring_join(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let name = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    meta.processor.subring_join(name).await?;
+    Ok(serde_json::json!({}))
+}
+
+/// leave a subring
+/// * Params
+///   - name: name of subring
+pub(crate) async fn subring_leave(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let name = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    meta.processor.subring_leave(name).await?;
+    Ok(serde_json::json!({}))
+}
+
+/// list dids of subring members
+/// * Params
+///   - name: name of subring
+///   - timeout_ms: optional timeout of waiting subring in milliseconds
+pub(crate) async fn subring_members(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let name = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+
+    let timeout_ms = match params.get(1) {
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?,
+        None => DEFAULT_FETCH_VNODE_TIMEOUT_MS,
+    };
+
+    let members = meta
+        .processor
+        .subring_members(name, Duration::from_millis(timeout_ms))
+        .await?;
+    let dids = members
+        .iter()
+        .map(|did| did.to_string())
+        .collect::<Vec<String>>();
+    Ok(serde_json::json!(dids))
+}
+
+/// send custom message to all members of a subring, returns the number of receivers
+/// * Params
+///   - name: name of subring
+///   - data: base64 of [u8]
+///   - timeout_ms: optional timeout of waiting subring in milliseconds
+pub(crate) async fn send_to_subring(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let name = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+
+    let data = params
+        .get(1)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let data = base64::decode(data).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+
+    let timeout_ms = match params.get(2) {
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?,
+        None => DEFAULT_FETCH_VNODE_TIMEOUT_MS,
+    };
+
+    let sent = meta
+        .processor
+        .send_to_subring(name, &data, Duration::from_millis(timeout_ms))
+        .await?;
+    Ok(serde_json::json!(sent))
+}
+
 fn dc2p((did, conn): (Did, impl ConnectionInterface)) -> Peer {
     Peer {
         did: did.to_string(),
@@ -172,7 +172,8 @@ impl measure::BehaviourJudgement for PeriodicMeasure {
     async fn good(&self, did: Did) -> bool {
         <Self as measure::ConnectBehaviour<{crate::consts::CONNECT_FAILED_LIMIT}>>::good(self, did).await &&
 	    <Self as measure::MessageSendBehaviour<{crate::consts::MSG_SEND_FAILED_LIMIT}>>::good(self, did).await &&
-            <Self as measure::MessageRecvBehaviour<{crate::consts::MSG_RECV_FAILED_LIMIT}>>::good(self, did).await
+            <Self as measure::MessageRecvBehaviour<{crate::consts::MSG_RECV_FAILED_LIMIT}>>::good(self, did).await &&
+            <Self as measure::RoutingBehaviour<{crate::consts::INVALID_REPORT_LIMIT}>>::good(self, did).await
     }
 }
 
@@ -19,9 +19,6 @@
 use std::time::Duration;
 
 use async_stream::stream;
-use futures::pin_mut;
-use futures::select;
-use futures::FutureExt;
 use futures::Stream;
 use futures_timer::Delay;
 use serde_json::json;
@@ -29,6 +26,9 @@ use serde_json::json;
 use crate::backend::types::BackendMessage;
 use crate::backend::types::HttpRequest;
 use crate::backend::types::ServiceMessage;
+use crate::consts::TOPIC_POLL_TIMEOUT_MS;
+use crate::prelude::rings_core::inspect::NetworkSizeInspect;
+use crate::prelude::rings_core::inspect::RingInspect;
 use crate::prelude::rings_core::inspect::SwarmInspect;
 use crate::prelude::rings_core::session::SessionSk;
 use crate::prelude::rings_rpc::client::Client as RpcClient;
@@ -205,6 +205,24 @@ impl Client {
         ClientOutput::ok("Done.".into(), ())
     }
 
+    /// Broadcasts a simple text message to all nodes on the ring.
+    pub async fn broadcast(&self, text: &str) -> Output<()> {
+        let msg = BackendMessage::PlainText(text.to_string());
+
+        let data = bincode::serialize(&msg).map_err(|e| {
+            anyhow::anyhow!("Failed to serialize PlainText message to binary format: {e}",)
+        })?;
+        let data_b64 = base64::encode(&data);
+
+        let resp = self
+            .client
+            .broadcast(&data_b64)
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        ClientOutput::ok(format!("Broadcast: {}", resp.tx_id), ())
+    }
+
     /// Registers a new service with the given name.
     pub async fn register_service(&self, name: &str) -> Output<()> {
         self.client
@@ -225,6 +243,53 @@ impl Client {
         ClientOutput::ok(dids.join("\n"), ())
     }
 
+    /// Joins the subring with the given name.
+    pub async fn subring_join(&self, name: &str) -> Output<()> {
+        self.client
+            .subring_join(name)
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        ClientOutput::ok("Done.".into(), ())
+    }
+
+    /// Leaves the subring with the given name.
+    pub async fn subring_leave(&self, name: &str) -> Output<()> {
+        self.client
+            .subring_leave(name)
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        ClientOutput::ok("Done.".into(), ())
+    }
+
+    /// Lists the DIDs of members of the subring with the given name.
+    pub async fn subring_members(&self, name: &str) -> Output<()> {
+        let dids = self
+            .client
+            .subring_members(name)
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        ClientOutput::ok(dids.join("\n"), ())
+    }
+
+    /// Sends a plain text message to all members of the subring with the given name.
+    pub async fn send_to_subring(&self, name: &str, text: &str) -> Output<()> {
+        let msg = BackendMessage::PlainText(text.to_string());
+
+        let data = bincode::serialize(&msg).map_err(|e| {
+            anyhow::anyhow!("Failed to serialize PlainText message to binary format: {e}",)
+        })?;
+        let data_b64 = base64::encode(&data);
+
+        let sent = self
+            .client
+            .send_to_subring(name, &data_b64)
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        ClientOutput::ok(format!("Sent to {} members.", sent), ())
+    }
+
     /// Publishes a message to the specified topic.
     pub async fn publish_message_to_topic(&self, topic: &str, data: &str) -> Output<()> {
         self.client
@@ -235,6 +300,9 @@ impl Client {
     }
 
     /// Subscribes to the specified topic and returns a stream of messages published to the topic.
+    /// Messages kept in the topic are yielded first, then the ones pushed to the node.
+    /// The topic is subscribed before fetching the kept messages, so that no message published
+    /// in between is missed. The first pushed messages which are already fetched are skipped.
     pub async fn subscribe_topic<'a, 'b>(
         &'a self,
         topic: String,
@@ -242,29 +310,44 @@ impl Client {
     where
         'a: 'b,
     {
-        let mut index = 0;
-
         stream! {
-        loop {
-            let timeout = Delay::new(Duration::from_secs(5)).fuse();
-            pin_mut!(timeout);
-
-            select! {
-                _ = timeout => {
-                    let result = self
-                        .client
-                        .fetch_topic_messages(topic.as_str(), index)
-                        .await;
-
-                    if let Err(e) = result {
-                        tracing::error!("Failed to fetch messages of topic: {}, {}", topic, e);
-                        continue;
-                    }
-                    let messages = result.unwrap();
-                    for msg in messages.iter().cloned() {
+            while let Err(e) = self.client.subscribe_topic(topic.as_str()).await {
+                tracing::error!("Failed to subscribe topic: {}, {}", topic, e);
+                Delay::new(Duration::from_secs(5)).await;
+            }
+
+            let mut fetched = vec![];
+            match self.client.fetch_topic_messages(topic.as_str(), 0).await {
+                Ok(messages) => {
+                    for msg in messages {
+                        fetched.push(msg.clone());
                         yield msg
                     }
-                    index += messages.len();
+                }
+                Err(e) => tracing::error!("Failed to fetch messages of topic: {}, {}", topic, e),
+            }
+
+            loop {
+                let result = self
+                    .client
+                    .poll_topic_messages(topic.as_str(), Some(TOPIC_POLL_TIMEOUT_MS))
+                    .await;
+
+                match result {
+                    Ok(messages) => {
+                        for msg in messages {
+                            if let Some(i) = fetched.iter().position(|m| *m == msg) {
+                                fetched.remove(i);
+                                continue;
+                            }
+                            yield msg
+                        }
+                        // Messages published before fetching are all pushed by now.
+                        fetched.clear();
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to poll messages of topic: {}, {}", topic, e);
+                        Delay::new(Duration::from_secs(5)).await;
                     }
                 }
             }
@@ -284,6 +367,34 @@ impl Client {
 
         ClientOutput::ok(display, info.swarm)
     }
+
+    /// Walk the ring along successors from the node, and check its invariants.
+    pub async fn inspect_ring(&self) -> Output<RingInspect> {
+        let report = self
+            .client
+            .inspect_ring()
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let display =
+            serde_json::to_string_pretty(&report).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        ClientOutput::ok(display, report)
+    }
+
+    /// Estimate the number of nodes on the ring.
+    pub async fn estimate_network_size(&self) -> Output<NetworkSizeInspect> {
+        let estimate = self
+            .client
+            .estimate_network_size()
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let display =
+            serde_json::to_string_pretty(&estimate).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        ClientOutput::ok(display, estimate)
+    }
 }
 
 impl<T> ClientOutput<T> {
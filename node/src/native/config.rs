@@ -11,6 +11,7 @@ use crate::backend::native::service::ServiceConfig;
 use crate::backend::native::BackendConfig;
 use crate::error::Error;
 use crate::error::Result;
+use crate::prelude::rings_core::consts::DEFAULT_NETWORK_ID;
 use crate::prelude::rings_core::ecc::SecretKey;
 use crate::prelude::SessionSk;
 use crate::processor::ProcessorConfig;
@@ -59,10 +60,21 @@ pub struct Config {
     pub stabilize_timeout: usize,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub external_ip: Option<String>,
+    /// Id of the network that node belongs to, nodes of other networks are refused.
+    /// When there is no configuration in the YAML file,
+    /// its deserialization is equivalent to `DEFAULT_NETWORK_ID` in Rust.
+    #[serde(default = "default_network_id")]
+    pub network_id: String,
     /// When there is no configuration in the YAML file,
     /// its deserialization is equivalent to `vec![]` in Rust.
     #[serde(default)]
     pub services: Vec<ServiceConfig>,
+    /// Urls of remote rings-node jsonrpc servers, connected on startup
+    /// when no node of last routing state is reachable.
+    /// When there is no configuration in the YAML file,
+    /// its deserialization is equivalent to `vec![]` in Rust.
+    #[serde(default)]
+    pub seeds: Vec<String>,
     pub data_storage: StorageConfig,
     pub measure_storage: StorageConfig,
     /// When there is no configuration in the YAML file,
@@ -71,6 +83,10 @@ pub struct Config {
     pub extension: ExtensionConfig,
 }
 
+fn default_network_id() -> String {
+    DEFAULT_NETWORK_ID.to_string()
+}
+
 impl TryFrom<Config> for ProcessorConfigSerialized {
     type Error = Error;
     fn try_from(config: Config) -> Result<Self> {
@@ -93,20 +109,17 @@ impl TryFrom<Config> for ProcessorConfigSerialized {
             })
         };
 
-        if let Some(ext_ip) = config.external_ip {
-            Ok(Self::new_with_ext_addr(
+        let ser = if let Some(ext_ip) = config.external_ip {
+            Self::new_with_ext_addr(
                 config.ice_servers,
                 session_sk,
                 config.stabilize_timeout,
                 ext_ip,
-            ))
+            )
         } else {
-            Ok(Self::new(
-                config.ice_servers,
-                session_sk,
-                config.stabilize_timeout,
-            ))
-        }
+            Self::new(config.ice_servers, session_sk, config.stabilize_timeout)
+        };
+        Ok(ser.with_network_id(config.network_id))
     }
 }
 
@@ -139,7 +152,9 @@ impl Config {
             ice_servers: DEFAULT_ICE_SERVERS.to_string(),
             stabilize_timeout: DEFAULT_STABILIZE_TIMEOUT,
             external_ip: None,
+            network_id: default_network_id(),
             services: vec![],
+            seeds: vec![],
             data_storage: DEFAULT_DATA_STORAGE_CONFIG.clone(),
             measure_storage: DEFAULT_MEASURE_STORAGE_CONFIG.clone(),
             extension: ExtensionConfig::default(),
@@ -205,5 +220,7 @@ measure_storage:
         let cfg: Config = serde_yaml::from_str(yaml).unwrap();
         assert_eq!(cfg.extension, ExtensionConfig::default());
         assert_eq!(cfg.services, vec![]);
+        assert!(cfg.seeds.is_empty());
+        assert_eq!(cfg.network_id, DEFAULT_NETWORK_ID);
     }
 }
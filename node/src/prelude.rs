@@ -25,6 +25,7 @@ pub use self::rings_core::prelude::ChordStorageInterfaceCacheChecker;
 pub use self::rings_core::prelude::MessageRelay;
 pub use self::rings_core::prelude::PersistenceStorage;
 pub use self::rings_core::prelude::PersistenceStorageReadAndWrite;
+pub use self::rings_core::prelude::PubsubInterface;
 pub use self::rings_core::prelude::SubringInterface;
 pub use self::rings_core::session::Session;
 pub use self::rings_core::session::SessionSk;
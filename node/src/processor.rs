@@ -4,8 +4,13 @@
 
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
+use futures::future::join_all;
+use futures::future::select;
+use futures::future::Either;
 use futures::future::Join;
+use futures::pin_mut;
 use futures::Future;
 use rings_core::message::MessagePayload;
 use rings_core::swarm::impls::ConnectionHandshake;
@@ -15,21 +20,31 @@ use serde::Serialize;
 
 use crate::backend::types::BackendMessage;
 use crate::consts::DATA_REDUNDANT;
+use crate::consts::DATA_REPLICATION_FACTOR;
+use crate::consts::LEAVE_GRACE_PERIOD_MS;
+use crate::consts::SERVICE_REGISTRATION_TTL_MS;
 use crate::error::Error;
 use crate::error::Result;
 use crate::measure::PeriodicMeasure;
 use crate::prelude::jsonrpc_client::SimpleClient;
 use crate::prelude::jsonrpc_core;
+use crate::prelude::rings_core::consts::DEFAULT_DELIVERY_ACK_TIMEOUT_MS;
+use crate::prelude::rings_core::consts::DEFAULT_NETWORK_ID;
 use crate::prelude::rings_core::dht::Did;
 use crate::prelude::rings_core::dht::Stabilization;
 use crate::prelude::rings_core::dht::TStabilize;
+use crate::prelude::rings_core::inspect::NetworkSizeInspect;
+use crate::prelude::rings_core::inspect::RingInspect;
+use crate::prelude::rings_core::message::CustomMessage;
 use crate::prelude::rings_core::message::Decoder;
 use crate::prelude::rings_core::message::Encoded;
 use crate::prelude::rings_core::message::Encoder;
 use crate::prelude::rings_core::message::Message;
 use crate::prelude::rings_core::message::PayloadSender;
+use crate::prelude::rings_core::message::TopicMessage;
 use crate::prelude::rings_core::prelude::uuid;
 use crate::prelude::rings_core::storage::PersistenceStorage;
+use crate::prelude::rings_core::swarm::DeliveryStatus;
 use crate::prelude::rings_core::swarm::MeasureImpl;
 use crate::prelude::rings_core::swarm::Swarm;
 use crate::prelude::rings_core::swarm::SwarmBuilder;
@@ -39,7 +54,9 @@ use crate::prelude::vnode;
 use crate::prelude::wasm_export;
 use crate::prelude::ChordStorageInterface;
 use crate::prelude::ChordStorageInterfaceCacheChecker;
+use crate::prelude::PubsubInterface;
 use crate::prelude::SessionSk;
+use crate::prelude::SubringInterface;
 
 /// ProcessorConfig is usually serialized as json or yaml.
 /// There is a `from_config` method in [ProcessorBuilder] used to initialize the Builder with a serialized ProcessorConfig.
@@ -54,6 +71,8 @@ pub struct ProcessorConfig {
     session_sk: SessionSk,
     /// Stabilization timeout.
     stabilize_timeout: usize,
+    /// Id of the network that node belongs to.
+    network_id: String,
 }
 
 #[wasm_export]
@@ -65,6 +84,7 @@ impl ProcessorConfig {
             external_address: None,
             session_sk,
             stabilize_timeout,
+            network_id: DEFAULT_NETWORK_ID.to_string(),
         }
     }
 
@@ -80,9 +100,17 @@ impl ProcessorConfig {
             external_address: Some(external_address),
             session_sk,
             stabilize_timeout,
+            network_id: DEFAULT_NETWORK_ID.to_string(),
         }
     }
 
+    /// Set the id of network that node belongs to.
+    /// Nodes of other networks are refused.
+    pub fn with_network_id(mut self, network_id: String) -> Self {
+        self.network_id = network_id;
+        self
+    }
+
     /// Return associated [SessionSk].
     pub fn session_sk(&self) -> SessionSk {
         self.session_sk.clone()
@@ -110,6 +138,13 @@ pub struct ProcessorConfigSerialized {
     session_sk: String,
     /// An unsigned integer representing the stabilization timeout.
     stabilize_timeout: usize,
+    /// A string representing the id of network.
+    #[serde(default = "default_network_id")]
+    network_id: String,
+}
+
+fn default_network_id() -> String {
+    DEFAULT_NETWORK_ID.to_string()
 }
 
 impl ProcessorConfigSerialized {
@@ -120,6 +155,7 @@ impl ProcessorConfigSerialized {
             external_address: None,
             session_sk,
             stabilize_timeout,
+            network_id: default_network_id(),
         }
     }
 
@@ -135,8 +171,15 @@ impl ProcessorConfigSerialized {
             external_address: Some(external_address),
             session_sk,
             stabilize_timeout,
+            network_id: default_network_id(),
         }
     }
+
+    /// Set the id of network that node belongs to.
+    pub fn with_network_id(mut self, network_id: String) -> Self {
+        self.network_id = network_id;
+        self
+    }
 }
 
 impl TryFrom<ProcessorConfig> for ProcessorConfigSerialized {
@@ -147,6 +190,7 @@ impl TryFrom<ProcessorConfig> for ProcessorConfigSerialized {
             external_address: ins.external_address.clone(),
             session_sk: ins.session_sk.dump()?,
             stabilize_timeout: ins.stabilize_timeout,
+            network_id: ins.network_id,
         })
     }
 }
@@ -159,6 +203,7 @@ impl TryFrom<ProcessorConfigSerialized> for ProcessorConfig {
             external_address: ins.external_address.clone(),
             session_sk: SessionSk::from_str(&ins.session_sk)?,
             stabilize_timeout: ins.stabilize_timeout,
+            network_id: ins.network_id.clone(),
         })
     }
 }
@@ -199,6 +244,7 @@ pub struct ProcessorBuilder {
     storage: Option<PersistenceStorage>,
     measure: Option<MeasureImpl>,
     stabilize_timeout: usize,
+    network_id: String,
 }
 
 /// Processor for rings-node jsonrpc server
@@ -227,6 +273,7 @@ impl ProcessorBuilder {
             storage: None,
             measure: None,
             stabilize_timeout: config.stabilize_timeout,
+            network_id: config.network_id.clone(),
         })
     }
 
@@ -253,7 +300,9 @@ impl ProcessorBuilder {
             .storage
             .expect("Please set storage by `storage()` method");
 
-        let mut swarm_builder = SwarmBuilder::new(&self.ice_servers, storage, self.session_sk);
+        let mut swarm_builder = SwarmBuilder::new(&self.ice_servers, storage, self.session_sk)
+            .dht_replication_factor(DATA_REPLICATION_FACTOR)
+            .network_id(self.network_id);
 
         if let Some(external_address) = self.external_address {
             swarm_builder = swarm_builder.external_address(external_address);
@@ -283,6 +332,23 @@ impl Processor {
 
         futures::future::join(message_listener, stabilization)
     }
+
+    /// Listen processor message until `shutdown` is resolved, then leave the network.
+    /// The message listener keeps running while leaving, so that the acks of handing off
+    /// vnodes can be received, see [Processor::leave].
+    pub async fn listen_until_leave(&self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let listen = self.listen();
+        let leave = async {
+            shutdown.await;
+            self.leave().await
+        };
+        pin_mut!(listen);
+        pin_mut!(leave);
+        match select(listen, leave).await {
+            Either::Left(_) => Ok(()),
+            Either::Right((result, _)) => result,
+        }
+    }
 }
 
 impl Processor {
@@ -340,10 +406,72 @@ impl Processor {
             .accept_answer(answer_payload)
             .await
             .map_err(Error::AcceptAnswer)?;
+        self.swarm.dht().set_endpoint(did, peer_url);
 
         Ok(did)
     }
 
+    /// Reconnect to the ring after restart.
+    /// Nodes in the persisted [RoutingSnapshot](rings_core::dht::RoutingSnapshot) are connected
+    /// via their last known endpoints, then the others are connected through the ring.
+    /// If none of them is reachable, fall back to `seeds`, which are urls of remote rings-node
+    /// jsonrpc servers.
+    /// Return dids of connected nodes.
+    pub async fn reconnect(&self, seeds: &[String]) -> Result<Vec<Did>> {
+        let snapshot = self
+            .swarm
+            .dht()
+            .load_routing_snapshot()
+            .await
+            .map_err(Error::Storage)?;
+
+        let mut connected = vec![];
+        if let Some(snapshot) = snapshot {
+            tracing::info!(
+                "reconnect {} nodes of routing snapshot",
+                snapshot.dids().len()
+            );
+            let results = join_all(
+                snapshot
+                    .endpoints
+                    .values()
+                    .map(|url| self.connect_peer_via_http(url)),
+            )
+            .await;
+            connected.extend(results.into_iter().filter_map(|r| {
+                r.map_err(|e| tracing::warn!("reconnect via endpoint failed: {:?}", e))
+                    .ok()
+            }));
+
+            if !connected.is_empty() {
+                let rest = snapshot
+                    .dids()
+                    .into_iter()
+                    .filter(|did| *did != self.did() && !connected.contains(did))
+                    .collect::<Vec<_>>();
+                let results =
+                    join_all(rest.iter().map(|did| self.connect_with_did(*did, false))).await;
+                for (did, r) in rest.into_iter().zip(results) {
+                    match r {
+                        Ok(_) => connected.push(did),
+                        Err(e) => tracing::warn!("reconnect {} failed: {:?}", did, e),
+                    }
+                }
+            }
+        }
+
+        if connected.is_empty() && !seeds.is_empty() {
+            tracing::info!("no node of routing snapshot is reachable, connect seeds");
+            let results = join_all(seeds.iter().map(|url| self.connect_peer_via_http(url))).await;
+            connected.extend(results.into_iter().filter_map(|r| {
+                r.map_err(|e| tracing::warn!("connect seed failed: {:?}", e))
+                    .ok()
+            }));
+        }
+
+        Ok(connected)
+    }
+
     /// Connect peer with web3 did.
     /// There are 3 peers: PeerA, PeerB, PeerC.
     /// 1. PeerA has a connection with PeerB.
@@ -380,6 +508,23 @@ impl Processor {
         futures::future::join_all(close_async).await;
     }
 
+    /// Leave the network gracefully.
+    /// Neighbours are notified and stored vnodes are handed off to successor,
+    /// then all connections are closed after a grace period.
+    pub async fn leave(&self) -> Result<()> {
+        tracing::info!("leave, did: {}", self.swarm.did());
+        self.swarm.leave().await.map_err(Error::Swarm)?;
+        // Wait for data channels to flush messages before closing them.
+        #[cfg(feature = "node")]
+        tokio::time::sleep(Duration::from_millis(LEAVE_GRACE_PERIOD_MS)).await;
+        #[cfg(feature = "browser")]
+        rings_core::utils::js_utils::window_sleep(LEAVE_GRACE_PERIOD_MS as i32)
+            .await
+            .ok();
+        self.disconnect_all().await;
+        Ok(())
+    }
+
     /// Send custom message to a did.
     pub async fn send_message(&self, destination: &str, msg: &[u8]) -> Result<uuid::Uuid> {
         tracing::info!(
@@ -397,6 +542,125 @@ impl Processor {
             .map_err(Error::SendMessage)
     }
 
+    /// Broadcast custom message to all nodes on the ring.
+    pub async fn broadcast(&self, msg: &[u8]) -> Result<uuid::Uuid> {
+        tracing::info!("broadcast, message size: {:?}", msg.len());
+        self.swarm.broadcast(msg).await.map_err(Error::SendMessage)
+    }
+
+    /// Send custom message to a did with end-to-end encryption.
+    /// The session public key of destination is learned from its messages,
+    /// so it will fail if there is no message received from destination.
+    pub async fn send_encrypted_message(
+        &self,
+        destination: &str,
+        msg: &[u8],
+    ) -> Result<uuid::Uuid> {
+        tracing::info!(
+            "send_encrypted_message, destination: {}, message size: {:?}",
+            destination,
+            msg.len(),
+        );
+        let destination = Did::from_str(destination).map_err(|_| Error::InvalidDid)?;
+
+        self.swarm
+            .send_encrypted_message(msg, destination)
+            .await
+            .map_err(Error::SendMessage)
+    }
+
+    /// Send custom message to a did, which should acknowledge the delivery.
+    /// The message is resent in background until acknowledged or all attempts failed,
+    /// its status can be queried by [Processor::message_status].
+    pub async fn send_message_with_ack(&self, destination: &str, msg: &[u8]) -> Result<uuid::Uuid> {
+        tracing::info!(
+            "send_message_with_ack, destination: {}, message size: {:?}",
+            destination,
+            msg.len(),
+        );
+        let destination = Did::from_str(destination).map_err(|_| Error::InvalidDid)?;
+
+        let msg = Message::custom(msg).map_err(Error::SendMessage)?;
+        let delivery = self
+            .swarm
+            .send_message_with_ack(msg, destination)
+            .await
+            .map_err(Error::SendMessage)?;
+        let tx_id = delivery.tx_id;
+
+        let swarm = self.swarm.clone();
+        let wait = async move {
+            let timeout = Duration::from_millis(DEFAULT_DELIVERY_ACK_TIMEOUT_MS);
+            if let Err(e) = swarm.wait_delivery(delivery, timeout).await {
+                tracing::error!("Failed on waiting delivery of {}: {:?}", tx_id, e);
+            }
+        };
+        #[cfg(feature = "node")]
+        tokio::spawn(wait);
+        #[cfg(feature = "browser")]
+        wasm_bindgen_futures::spawn_local(wait);
+
+        Ok(tx_id)
+    }
+
+    /// Get delivery status of a message sent by [Processor::send_message_with_ack].
+    pub fn message_status(&self, tx_id: uuid::Uuid) -> Result<DeliveryStatus> {
+        self.swarm
+            .delivery_status(tx_id)
+            .ok_or(Error::DeliveryNotFound(tx_id))
+    }
+
+    /// Send custom message to a did as a request, and wait for the response with same tx_id.
+    /// The remote peer should respond it by [Swarm::respond].
+    /// Return the tx_id and the data of response.
+    pub async fn request_peer(
+        &self,
+        destination: &str,
+        msg: &[u8],
+        timeout: Duration,
+    ) -> Result<(uuid::Uuid, Vec<u8>)> {
+        tracing::info!(
+            "request_peer, destination: {}, message size: {:?}",
+            destination,
+            msg.len(),
+        );
+        let destination = Did::from_str(destination).map_err(|_| Error::InvalidDid)?;
+
+        let req = self
+            .swarm
+            .request(msg, destination)
+            .await
+            .map_err(Error::SendMessage)?;
+        let tx_id = req.tx_id;
+
+        let resp = req.wait(timeout).await.map_err(Error::RequestPeer)?;
+        match resp.transaction.data().map_err(Error::RequestPeer)? {
+            Message::CustomMessage(CustomMessage(data)) => Ok((tx_id, data)),
+            _ => Err(Error::InvalidMessage),
+        }
+    }
+
+    /// Respond the request `tx_id` sent by `destination` with custom message data.
+    /// The tx_id is the one of payload received by [rings_core::swarm::callback::SwarmCallback::on_inbound].
+    pub async fn respond_peer(
+        &self,
+        destination: &str,
+        tx_id: uuid::Uuid,
+        msg: &[u8],
+    ) -> Result<()> {
+        tracing::info!(
+            "respond_peer, destination: {}, tx_id: {}, message size: {:?}",
+            destination,
+            tx_id,
+            msg.len(),
+        );
+        let destination = Did::from_str(destination).map_err(|_| Error::InvalidDid)?;
+        self.swarm
+            .respond_to(destination, tx_id, msg)
+            .await
+            .map_err(Error::SendMessage)
+    }
+
     /// Send custom message to a did.
     pub async fn send_backend_message(
         &self,
@@ -420,6 +684,29 @@ impl Processor {
             .map_err(Error::VNodeError)
     }
 
+    /// fetch virtual node from DHT and wait for it until timeout.
+    /// The latest one of all redundant copies is returned.
+    pub async fn storage_fetch_vnode(
+        &self,
+        did: Did,
+        timeout: Duration,
+    ) -> Result<Option<vnode::VirtualNode>> {
+        <Swarm as ChordStorageInterface<DATA_REDUNDANT>>::storage_fetch_vnode(
+            &self.swarm,
+            did,
+            timeout,
+        )
+        .await
+        .map_err(Error::VNodeError)
+    }
+
+    /// remove virtual node owned by current node, or the entries written by current node, from DHT
+    pub async fn storage_remove(&self, did: Did) -> Result<()> {
+        <Swarm as ChordStorageInterface<DATA_REDUNDANT>>::storage_remove(&self.swarm, did)
+            .await
+            .map_err(Error::VNodeError)
+    }
+
     /// store virtual node on DHT
     pub async fn storage_store(&self, vnode: vnode::VirtualNode) -> Result<()> {
         <Swarm as ChordStorageInterface<DATA_REDUNDANT>>::storage_store(&self.swarm, vnode)
@@ -438,8 +725,15 @@ impl Processor {
         .map_err(Error::VNodeError)
     }
 
-    /// register service
+    /// register service, the registration expires after [SERVICE_REGISTRATION_TTL_MS]
+    /// unless it's registered again.
     pub async fn register_service(&self, name: &str) -> Result<()> {
+        self.register_service_with_ttl(name, Duration::from_millis(SERVICE_REGISTRATION_TTL_MS))
+            .await
+    }
+
+    /// register service with the ttl of registration
+    pub async fn register_service_with_ttl(&self, name: &str, ttl: Duration) -> Result<()> {
         let encoded_did = self
             .did()
             .to_string()
@@ -449,18 +743,103 @@ impl Processor {
             &self.swarm,
             name,
             encoded_did,
+            Some(ttl),
         )
         .await
         .map_err(Error::ServiceRegisterError)
     }
 
+    /// unregister service by expiring the registration of current node.
+    /// The registrations of other nodes are written by them, so they are not affected.
+    pub async fn unregister_service(&self, name: &str) -> Result<()> {
+        self.register_service_with_ttl(name, Duration::ZERO).await
+    }
+
+    /// join a subring
+    pub async fn subring_join(&self, name: &str) -> Result<()> {
+        <Swarm as SubringInterface<DATA_REDUNDANT>>::subring_join(&self.swarm, name)
+            .await
+            .map_err(Error::SubringError)
+    }
+
+    /// leave a subring
+    pub async fn subring_leave(&self, name: &str) -> Result<()> {
+        <Swarm as SubringInterface<DATA_REDUNDANT>>::subring_leave(&self.swarm, name)
+            .await
+            .map_err(Error::SubringError)
+    }
+
+    /// list members of a subring, wait for the subring until timeout.
+    pub async fn subring_members(&self, name: &str, timeout: Duration) -> Result<Vec<Did>> {
+        <Swarm as SubringInterface<DATA_REDUNDANT>>::subring_members(&self.swarm, name, timeout)
+            .await
+            .map_err(Error::SubringError)
+    }
+
+    /// send custom message to all members of a subring except current node.
+    /// Return the number of members the message is sent to.
+    pub async fn send_to_subring(
+        &self,
+        name: &str,
+        msg: &[u8],
+        timeout: Duration,
+    ) -> Result<usize> {
+        <Swarm as SubringInterface<DATA_REDUNDANT>>::send_to_subring(
+            &self.swarm,
+            name,
+            msg,
+            timeout,
+        )
+        .await
+        .map_err(Error::SubringError)
+    }
+
+    /// subscribe a topic, published messages will be pushed to current node.
+    pub async fn topic_subscribe(&self, topic: &str) -> Result<()> {
+        <Swarm as PubsubInterface<DATA_REDUNDANT>>::topic_subscribe(&self.swarm, topic)
+            .await
+            .map_err(Error::PubsubError)
+    }
+
+    /// unsubscribe a topic.
+    pub async fn topic_unsubscribe(&self, topic: &str) -> Result<()> {
+        <Swarm as PubsubInterface<DATA_REDUNDANT>>::topic_unsubscribe(&self.swarm, topic)
+            .await
+            .map_err(Error::PubsubError)
+    }
+
+    /// publish data to subscribers of a topic, and append it to the topic vnode.
+    pub async fn topic_publish(&self, topic: &str, data: Encoded) -> Result<()> {
+        <Swarm as PubsubInterface<DATA_REDUNDANT>>::topic_publish(&self.swarm, topic, data)
+            .await
+            .map_err(Error::PubsubError)
+    }
+
+    /// take received messages of a subscribed topic, wait for the next one until timeout.
+    pub async fn topic_poll(&self, topic: &str, timeout: Duration) -> Result<Vec<TopicMessage>> {
+        <Swarm as PubsubInterface<DATA_REDUNDANT>>::topic_poll(&self.swarm, topic, timeout)
+            .await
+            .map_err(Error::PubsubError)
+    }
+
     /// get node info
     pub async fn get_node_info(&self) -> Result<response::NodeInfo> {
         Ok(response::NodeInfo {
             version: crate::util::build_version(),
             swarm: self.swarm.inspect().await,
+            network_size: self.swarm.estimate_network_size(),
         })
     }
+
+    /// Estimate the number of nodes on the ring.
+    pub fn estimate_network_size(&self) -> NetworkSizeInspect {
+        self.swarm.estimate_network_size()
+    }
+
+    /// Walk the ring along successors and check its invariants.
+    pub async fn inspect_ring(&self) -> RingInspect {
+        self.swarm.inspect_ring().await
+    }
 }
 
 #[cfg(test)]
@@ -471,6 +850,9 @@ mod test {
     use rings_transport::core::transport::WebrtcConnectionState;
 
     use super::*;
+    use crate::prelude::rings_core::dht::SuccessorWriter;
+    use crate::prelude::rings_core::message::MessageVerificationExt;
+    use crate::prelude::rings_core::utils::get_epoch_ms;
     use crate::prelude::*;
     use crate::tests::native::prepare_processor;
 
@@ -485,6 +867,57 @@ mod test {
         tokio::fs::remove_dir_all(path).await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_processor_reconnect_unreachable() {
+        let peer_did: Did = SecretKey::random().address().into();
+        let (processor, path) = prepare_processor().await;
+        assert!(processor.reconnect(&[]).await.unwrap().is_empty());
+
+        let dht = processor.swarm.dht();
+        dht.successors().update(peer_did).unwrap();
+        dht.set_endpoint(peer_did, "http://127.0.0.1:1");
+        dht.store_routing_snapshot().await.unwrap();
+        dht.remove(peer_did).unwrap();
+
+        // Neither the node of snapshot nor the seed is reachable.
+        let seeds = vec!["http://127.0.0.1:2".to_string()];
+        assert!(processor.reconnect(&seeds).await.unwrap().is_empty());
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_processor_service_registration_expires() {
+        let (processor, path) = prepare_processor().await;
+        let rid = vnode::VirtualNode::gen_did("test_service").unwrap();
+        let did = processor.did().to_string().encode().unwrap();
+        let lookup = || async {
+            let now = get_epoch_ms() as u64;
+            processor
+                .storage_fetch_vnode(rid, Duration::from_secs(1))
+                .await
+                .unwrap()
+                .map(|v| v.live_data(now))
+                .unwrap_or_default()
+        };
+
+        processor
+            .register_service_with_ttl("test_service", Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(lookup().await, vec![did.clone()]);
+
+        // Registration expires unless refreshed.
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(lookup().await.is_empty());
+
+        processor.register_service("test_service").await.unwrap();
+        assert_eq!(lookup().await, vec![did]);
+        processor.unregister_service("test_service").await.unwrap();
+        assert!(lookup().await.is_empty());
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
     struct SwarmCallbackInstance {
         pub msgs: Mutex<Vec<String>>,
     }
@@ -619,4 +1052,110 @@ mod test {
         tokio::fs::remove_dir_all(path1).await.unwrap();
         tokio::fs::remove_dir_all(path2).await.unwrap();
     }
+
+    struct RequestCallback {
+        pub requests: Mutex<Vec<(Did, uuid::Uuid)>>,
+    }
+
+    #[async_trait]
+    impl SwarmCallback for RequestCallback {
+        async fn on_inbound(
+            &self,
+            payload: &MessagePayload,
+        ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+            if let Message::CustomMessage(_) = payload.transaction.data().map_err(Box::new)? {
+                self.requests
+                    .lock()
+                    .await
+                    .push((payload.transaction.signer(), payload.transaction.tx_id));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_processor_request_and_respond_peer() {
+        let callback1 = Arc::new(RequestCallback {
+            requests: Mutex::new(Vec::new()),
+        });
+        let callback2 = Arc::new(RequestCallback {
+            requests: Mutex::new(Vec::new()),
+        });
+        let (p1, path1) = prepare_processor().await;
+        let (p2, path2) = prepare_processor().await;
+        p1.swarm.set_callback(callback1.clone()).unwrap();
+        p2.swarm.set_callback(callback2.clone()).unwrap();
+
+        let swarm1 = p1.swarm.clone();
+        let swarm2 = p2.swarm.clone();
+        tokio::spawn(async { swarm1.listen().await });
+        tokio::spawn(async { swarm2.listen().await });
+
+        let (conn1, offer) = p1.swarm.create_offer(p2.did()).await.unwrap();
+        let (_, answer) = p2.swarm.answer_offer(offer).await.unwrap();
+        p1.swarm.accept_answer(answer).await.unwrap();
+        conn1.webrtc_wait_for_data_channel_open().await.unwrap();
+        tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+
+        let did2 = p2.did().to_string();
+        let request = p1.request_peer(&did2, b"ping", Duration::from_secs(5));
+        let respond = async {
+            loop {
+                let req = callback2.requests.lock().await.pop();
+                if let Some((requester, tx_id)) = req {
+                    p2.respond_peer(&requester.to_string(), tx_id, b"pong")
+                        .await
+                        .unwrap();
+                    return tx_id;
+                }
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            }
+        };
+        let (resp, responded) = futures::join!(request, respond);
+        let (tx_id, data) = resp.unwrap();
+        assert_eq!(tx_id, responded);
+        assert_eq!(data, b"pong");
+
+        // The response is not taken as an inbound message.
+        assert!(callback1.requests.lock().await.is_empty());
+
+        tokio::fs::remove_dir_all(path1).await.unwrap();
+        tokio::fs::remove_dir_all(path2).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_processor_leave_while_listening() {
+        let (p1, path1) = prepare_processor().await;
+        let (p2, path2) = prepare_processor().await;
+
+        let swarm1 = p1.swarm.clone();
+        tokio::spawn(async { swarm1.listen().await });
+        let (conn1, offer) = p1.swarm.create_offer(p2.did()).await.unwrap();
+        let (_, answer) = p2.swarm.answer_offer(offer).await.unwrap();
+        p1.swarm.accept_answer(answer).await.unwrap();
+
+        let vnode: vnode::VirtualNode = "test_processor_leave".to_string().try_into().unwrap();
+        p2.swarm
+            .dht()
+            .storage
+            .put(&vnode.did, &vnode)
+            .await
+            .unwrap();
+
+        // The vnode is handed off while p2 is still listening.
+        let shutdown = async {
+            conn1.webrtc_wait_for_data_channel_open().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(3)).await;
+        };
+        p2.listen_until_leave(shutdown).await.unwrap();
+        let stored: Option<vnode::VirtualNode> =
+            p1.swarm.dht().storage.get(&vnode.did).await.unwrap();
+        assert_eq!(stored, Some(vnode.clone()));
+        let remained: Option<vnode::VirtualNode> =
+            p2.swarm.dht().storage.get(&vnode.did).await.unwrap();
+        assert_eq!(remained, None);
+
+        tokio::fs::remove_dir_all(path1).await.unwrap();
+        tokio::fs::remove_dir_all(path2).await.unwrap();
+    }
 }
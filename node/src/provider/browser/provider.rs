@@ -13,6 +13,7 @@ use rings_core::dht::Did;
 use rings_core::ecc::PublicKey;
 use rings_core::prelude::vnode;
 use rings_core::prelude::vnode::VirtualNode;
+use rings_core::utils::get_epoch_ms;
 use rings_core::utils::js_utils;
 use rings_core::utils::js_value;
 use rings_derive::wasm_export;
@@ -508,7 +509,7 @@ impl Provider {
 
             if let Some(vnode) = result {
                 let dids = vnode
-                    .data
+                    .live_data(get_epoch_ms() as u64)
                     .iter()
                     .map(|v| v.decode())
                     .filter_map(|v| v.ok())
@@ -1,5 +1,8 @@
 //! rings-rpc client
 
+use rings_core::dht::vnode::VirtualNode;
+use rings_core::inspect::NetworkSizeInspect;
+use rings_core::inspect::RingInspect;
 use rings_core::session::SessionSk;
 use serde_json::json;
 use serde_json::Value;
@@ -130,6 +133,81 @@ impl Client {
         serde_json::from_value(result).map_err(|_| Error::DecodeError)
     }
 
+    /// Sends a custom message to the specified peer, which should acknowledge the delivery.
+    /// The delivery status can be queried by [Client::get_message_status].
+    pub async fn send_custom_message_with_ack(
+        &self,
+        did: &str,
+        data_b64: &str,
+    ) -> Result<response::SendMessageResponse> {
+        let result = self
+            .client
+            .call_method(
+                Method::SendCustomMessage.as_str(),
+                Params::Array(vec![json!(did), json!(data_b64), json!(true)]),
+            )
+            .await
+            .map_err(Error::RpcError)?;
+        serde_json::from_value(result).map_err(|_| Error::DecodeError)
+    }
+
+    /// Gets the delivery status of a custom message sent with ack.
+    pub async fn get_message_status(&self, tx_id: &str) -> Result<response::MessageStatusResponse> {
+        let result = self
+            .client
+            .call_method(
+                Method::GetMessageStatus.as_str(),
+                Params::Array(vec![json!(tx_id)]),
+            )
+            .await
+            .map_err(Error::RpcError)?;
+        serde_json::from_value(result).map_err(|_| Error::DecodeError)
+    }
+
+    /// Broadcasts a custom message to all nodes on the ring.
+    pub async fn broadcast(&self, data_b64: &str) -> Result<response::SendMessageResponse> {
+        let result = self
+            .client
+            .call_method(
+                Method::Broadcast.as_str(),
+                Params::Array(vec![json!(data_b64)]),
+            )
+            .await
+            .map_err(Error::RpcError)?;
+        serde_json::from_value(result).map_err(|_| Error::DecodeError)
+    }
+
+    /// Sends a custom message to the specified peer and waits for its response.
+    pub async fn request_peer(
+        &self,
+        did: &str,
+        data_b64: &str,
+        timeout_ms: Option<u64>,
+    ) -> Result<response::RequestPeerResponse> {
+        let mut params = vec![json!(did), json!(data_b64)];
+        if let Some(timeout_ms) = timeout_ms {
+            params.push(json!(timeout_ms));
+        }
+        let result = self
+            .client
+            .call_method(Method::RequestPeer.as_str(), Params::Array(params))
+            .await
+            .map_err(Error::RpcError)?;
+        serde_json::from_value(result).map_err(|_| Error::DecodeError)
+    }
+
+    /// Responds the request `tx_id` sent by the specified peer.
+    pub async fn respond_peer(&self, did: &str, tx_id: &str, data_b64: &str) -> Result<()> {
+        self.client
+            .call_method(
+                Method::RespondPeer.as_str(),
+                Params::Array(vec![json!(did), json!(tx_id), json!(data_b64)]),
+            )
+            .await
+            .map_err(Error::RpcError)?;
+        Ok(())
+    }
+
     /// Registers a new service with the given name.
     pub async fn register_service(&self, name: &str) -> Result<()> {
         self.client
@@ -142,6 +220,31 @@ impl Client {
         Ok(())
     }
 
+    /// Registers a new service with the given name, the registration expires after `ttl_ms`
+    /// unless it's registered again.
+    pub async fn register_service_with_ttl(&self, name: &str, ttl_ms: u64) -> Result<()> {
+        self.client
+            .call_method(
+                Method::RegisterService.as_str(),
+                Params::Array(vec![json!(name), json!(ttl_ms)]),
+            )
+            .await
+            .map_err(Error::RpcError)?;
+        Ok(())
+    }
+
+    /// Unregisters the service with the given name.
+    pub async fn unregister_service(&self, name: &str) -> Result<()> {
+        self.client
+            .call_method(
+                Method::UnregisterService.as_str(),
+                Params::Array(vec![json!(name)]),
+            )
+            .await
+            .map_err(Error::RpcError)?;
+        Ok(())
+    }
+
     /// Looks up the DIDs of services registered with the given name.
     pub async fn lookup_service(&self, name: &str) -> Result<Vec<String>> {
         let resp = self
@@ -156,6 +259,59 @@ impl Client {
         serde_json::from_value(resp).map_err(|_| Error::DecodeError)
     }
 
+    /// Joins the subring with the given name.
+    pub async fn subring_join(&self, name: &str) -> Result<()> {
+        self.client
+            .call_method(
+                Method::SubringJoin.as_str(),
+                Params::Array(vec![json!(name)]),
+            )
+            .await
+            .map_err(Error::RpcError)?;
+        Ok(())
+    }
+
+    /// Leaves the subring with the given name.
+    pub async fn subring_leave(&self, name: &str) -> Result<()> {
+        self.client
+            .call_method(
+                Method::SubringLeave.as_str(),
+                Params::Array(vec![json!(name)]),
+            )
+            .await
+            .map_err(Error::RpcError)?;
+        Ok(())
+    }
+
+    /// Lists the DIDs of members of the subring with the given name.
+    pub async fn subring_members(&self, name: &str) -> Result<Vec<String>> {
+        let resp = self
+            .client
+            .call_method(
+                Method::SubringMembers.as_str(),
+                Params::Array(vec![json!(name)]),
+            )
+            .await
+            .map_err(Error::RpcError)?;
+
+        serde_json::from_value(resp).map_err(|_| Error::DecodeError)
+    }
+
+    /// Sends a custom message to all members of the subring with the given name.
+    /// Returns the number of members the message is sent to.
+    pub async fn send_to_subring(&self, name: &str, data: &str) -> Result<usize> {
+        let resp = self
+            .client
+            .call_method(
+                Method::SendToSubring.as_str(),
+                Params::Array(vec![json!(name), json!(data)]),
+            )
+            .await
+            .map_err(Error::RpcError)?;
+
+        serde_json::from_value(resp).map_err(|_| Error::DecodeError)
+    }
+
     /// Publishes a message to the specified topic.
     pub async fn publish_message_to_topic(&self, topic: &str, data: &str) -> Result<()> {
         self.client
@@ -181,6 +337,69 @@ impl Client {
         serde_json::from_value(resp).map_err(|_| Error::DecodeError)
     }
 
+    /// Subscribes the specified topic, published messages will be pushed to the node.
+    pub async fn subscribe_topic(&self, topic: &str) -> Result<()> {
+        self.client
+            .call_method(
+                Method::SubscribeTopic.as_str(),
+                Params::Array(vec![json!(topic)]),
+            )
+            .await
+            .map_err(Error::RpcError)?;
+        Ok(())
+    }
+
+    /// Unsubscribes the specified topic.
+    pub async fn unsubscribe_topic(&self, topic: &str) -> Result<()> {
+        self.client
+            .call_method(
+                Method::UnsubscribeTopic.as_str(),
+                Params::Array(vec![json!(topic)]),
+            )
+            .await
+            .map_err(Error::RpcError)?;
+        Ok(())
+    }
+
+    /// Takes messages pushed to the subscribed topic.
+    /// Waits for the next one until timeout if there is none.
+    pub async fn poll_topic_messages(
+        &self,
+        topic: &str,
+        timeout_ms: Option<u64>,
+    ) -> Result<Vec<String>> {
+        let mut params = vec![json!(topic)];
+        if let Some(timeout_ms) = timeout_ms {
+            params.push(json!(timeout_ms));
+        }
+        let resp = self
+            .client
+            .call_method(Method::PollTopicMessages.as_str(), Params::Array(params))
+            .await
+            .map_err(Error::RpcError)?;
+
+        serde_json::from_value(resp).map_err(|_| Error::DecodeError)
+    }
+
+    /// Fetches a virtual node from DHT, and waits for it until timeout.
+    /// Returns None if the virtual node is not found.
+    pub async fn fetch_vnode(
+        &self,
+        did: &str,
+        timeout_ms: Option<u64>,
+    ) -> Result<Option<VirtualNode>> {
+        let mut params = vec![json!(did)];
+        if let Some(timeout_ms) = timeout_ms {
+            params.push(json!(timeout_ms));
+        }
+        let resp = self
+            .client
+            .call_method(Method::FetchVNode.as_str(), Params::Array(params))
+            .await
+            .map_err(Error::RpcError)?;
+        serde_json::from_value(resp).map_err(|_| Error::DecodeError)
+    }
+
     /// Query for swarm inspect info.
     pub async fn inspect(&self) -> Result<response::NodeInfo> {
         let resp = self
@@ -190,4 +409,24 @@ impl Client {
             .map_err(Error::RpcError)?;
         serde_json::from_value(resp).map_err(|_| Error::DecodeError)
     }
+
+    /// Walk the ring along successors from the node, and check its invariants.
+    pub async fn inspect_ring(&self) -> Result<RingInspect> {
+        let resp = self
+            .client
+            .call_method(Method::InspectRing.as_str(), Params::None)
+            .await
+            .map_err(Error::RpcError)?;
+        serde_json::from_value(resp).map_err(|_| Error::DecodeError)
+    }
+
+    /// Estimate the number of nodes on the ring.
+    pub async fn estimate_network_size(&self) -> Result<NetworkSizeInspect> {
+        let resp = self
+            .client
+            .call_method(Method::EstimateNetworkSize.as_str(), Params::None)
+            .await
+            .map_err(Error::RpcError)?;
+        serde_json::from_value(resp).map_err(|_| Error::DecodeError)
+    }
 }
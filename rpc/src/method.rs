@@ -26,18 +26,48 @@ pub enum Method {
     Disconnect,
     /// SendCustomMessage,
     SendCustomMessage,
+    /// Get delivery status of a custom message sent with ack
+    GetMessageStatus,
+    /// Send custom message to peer and wait for its response
+    RequestPeer,
+    /// Respond a request sent by peer
+    RespondPeer,
+    /// Broadcast custom message to all nodes on the ring
+    Broadcast,
     /// SendBackendMessage
     SendBackendMessage,
-    /// Append data to topic
+    /// Publish data to subscribers of topic, and append it to topic
     PublishMessageToTopic,
     /// Fetch data of topic
     FetchMessagesOfTopic,
+    /// Subscribe topic
+    SubscribeTopic,
+    /// Unsubscribe topic
+    UnsubscribeTopic,
+    /// Poll messages pushed to subscribed topic
+    PollTopicMessages,
+    /// Fetch virtual node from DHT and wait for it
+    FetchVNode,
     /// Register service
     RegisterService,
+    /// Unregister service
+    UnregisterService,
     /// Lookup service
     LookupService,
+    /// Join subring
+    SubringJoin,
+    /// Leave subring
+    SubringLeave,
+    /// List members of subring
+    SubringMembers,
+    /// Send custom message to all members of subring
+    SendToSubring,
     /// Retrieve Node info
     NodeInfo,
+    /// Walk the ring along successors and check its invariants
+    InspectRing,
+    /// Estimate the number of nodes on the ring
+    EstimateNetworkSize,
     /// Retrieve Node DID
     NodeDid,
 }
@@ -56,12 +86,27 @@ impl Method {
             Method::Disconnect => "disconnect",
             Method::AcceptAnswer => "acceptAnswer",
             Method::SendCustomMessage => "sendCustomMessage",
+            Method::GetMessageStatus => "getMessageStatus",
+            Method::RequestPeer => "requestPeer",
+            Method::RespondPeer => "respondPeer",
+            Method::Broadcast => "broadcast",
             Method::SendBackendMessage => "sendBackendMessage",
             Method::PublishMessageToTopic => "publishMessageToTopic",
             Method::FetchMessagesOfTopic => "fetchMessagesOfTopic",
+            Method::SubscribeTopic => "subscribeTopic",
+            Method::UnsubscribeTopic => "unsubscribeTopic",
+            Method::PollTopicMessages => "pollTopicMessages",
+            Method::FetchVNode => "fetchVNode",
             Method::RegisterService => "registerService",
+            Method::UnregisterService => "unregisterService",
             Method::LookupService => "lookupService",
+            Method::SubringJoin => "subringJoin",
+            Method::SubringLeave => "subringLeave",
+            Method::SubringMembers => "subringMembers",
+            Method::SendToSubring => "sendToSubring",
             Method::NodeInfo => "nodeInfo",
+            Method::InspectRing => "inspectRing",
+            Method::EstimateNetworkSize => "estimateNetworkSize",
             Method::NodeDid => "nodeDid",
         }
     }
@@ -89,11 +134,26 @@ impl TryFrom<&str> for Method {
             "acceptAnswer" => Self::AcceptAnswer,
             "sendBackendMessage" => Self::SendBackendMessage,
             "sendCustomMessage" => Self::SendCustomMessage,
+            "getMessageStatus" => Self::GetMessageStatus,
+            "requestPeer" => Self::RequestPeer,
+            "respondPeer" => Self::RespondPeer,
+            "broadcast" => Self::Broadcast,
             "publishMessageToTopic" => Method::PublishMessageToTopic,
             "fetchMessagesOfTopic" => Method::FetchMessagesOfTopic,
+            "subscribeTopic" => Method::SubscribeTopic,
+            "unsubscribeTopic" => Method::UnsubscribeTopic,
+            "pollTopicMessages" => Method::PollTopicMessages,
+            "fetchVNode" => Method::FetchVNode,
             "registerService" => Method::RegisterService,
+            "unregisterService" => Method::UnregisterService,
             "lookupService" => Method::LookupService,
+            "subringJoin" => Method::SubringJoin,
+            "subringLeave" => Method::SubringLeave,
+            "subringMembers" => Method::SubringMembers,
+            "sendToSubring" => Method::SendToSubring,
             "nodeInfo" => Method::NodeInfo,
+            "inspectRing" => Method::InspectRing,
+            "estimateNetworkSize" => Method::EstimateNetworkSize,
             "nodeDid" => Method::NodeDid,
             _ => return Err(Error::InvalidMethod),
         })
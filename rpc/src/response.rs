@@ -6,7 +6,9 @@ use serde_json::Value as JsonValue;
 
 use crate::error::Error;
 use crate::error::Result;
+use crate::prelude::rings_core::inspect::NetworkSizeInspect;
 use crate::prelude::rings_core::inspect::SwarmInspect;
+use crate::prelude::rings_core::swarm::DeliveryStatus;
 
 /// Peer contains transport address and state information.
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -40,6 +42,24 @@ impl From<String> for SendMessageResponse {
     }
 }
 
+/// Delivery status of a custom message sent with ack.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageStatusResponse {
+    /// tx_id of the message
+    pub tx_id: String,
+    /// one of `pending`, `delivered` and `failed`
+    pub status: DeliveryStatus,
+}
+
+/// Response of a peer request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestPeerResponse {
+    /// tx_id of the request
+    pub tx_id: String,
+    /// base64 of response data
+    pub data: String,
+}
+
 /// NodeInfo struct
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NodeInfo {
@@ -47,4 +67,7 @@ pub struct NodeInfo {
     pub version: String,
     /// swarm inspect info
     pub swarm: SwarmInspect,
+    /// estimation of network size
+    #[serde(default)]
+    pub network_size: NetworkSizeInspect,
 }